@@ -0,0 +1,36 @@
+//! Store put/get roundtrip benchmarks
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use envelope::{Envelope, Hash256, Store};
+
+fn sample_envelope(i: u64) -> Envelope {
+    Envelope::builder(Hash256::hash(b"schema:BlogPost"), vec![0u8; 1024])
+        .type_name("BlogPost")
+        .index("title", format!("Post {}", i))
+        .index("word_count", i as i64)
+        .relationship("author", Hash256::hash(b"Alice"))
+        .created_at(1708523400 + i as i64)
+        .build()
+}
+
+fn bench_put(c: &mut Criterion) {
+    c.bench_function("store_put", |b| {
+        let mut store = Store::new();
+        let mut i = 0;
+        b.iter(|| {
+            i += 1;
+            store.put(black_box(&sample_envelope(i))).unwrap()
+        })
+    });
+}
+
+fn bench_get(c: &mut Criterion) {
+    let mut store = Store::new();
+    let hash = store.put(&sample_envelope(0)).unwrap();
+    c.bench_function("store_get", |b| {
+        b.iter(|| store.get(black_box(&hash)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_put, bench_get);
+criterion_main!(benches);
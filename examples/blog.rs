@@ -67,15 +67,9 @@ fn main() {
     
     // Update the post (creates new version)
     println!("\n--- Updating post ---");
-    let updated_payload = b"Zero-copy serialization is definitely the future...".to_vec();
-    let updated_post = Envelope::builder(post_type, updated_payload)
-        .type_name("BlogPost")
-        .index("title", "Zero-Copy Dreams")
+    let updated_post = retrieved.derive()  // Links to the previous version
+        .payload(b"Zero-copy serialization is definitely the future...".to_vec())
         .index("word_count", "1600")
-        .relationship("author", author_hash)
-        .relationship("tag", rust_hash)
-        .relationship("tag", ser_hash)
-        .previous(post_hash)  // Link to previous version
         .created_at(1708609800)
         .build();
     let updated_hash = store.put(&updated_post).unwrap();
@@ -0,0 +1,118 @@
+//! Portable archives of object graphs
+//!
+//! An archive is a magic header followed by a count and the objects in
+//! the store's own encoding, each length-prefixed. Relationship targets
+//! and parents come before the objects that link them, so importing is
+//! a sequence of ordinary puts that never leaves a dangling link behind.
+//! Without a filesystem, archives are also how an in-memory store is
+//! saved and restored (in a browser, `idb` saves it to IndexedDB).
+
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::store::Store;
+use crate::wire::{self, Reader};
+use crate::Result;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// First bytes of every archive
+pub const ARCHIVE_MAGIC: &[u8; 8] = b"ENVARC01";
+
+impl Store {
+    /// Write the closure of `roots`, or every object if `None`, as an
+    /// archive; returns the number of objects written
+    pub fn export_archive(&self, roots: Option<&[Hash256]>, out: &mut impl Write) -> Result<usize> {
+        let mut hashes = match roots {
+            Some(roots) => self.closure(roots)?,
+            None => self.hashes().copied().collect(),
+        };
+        hashes.sort();
+        let hashes = self.dependency_order(&hashes)?;
+        let mut buf = ARCHIVE_MAGIC.to_vec();
+        wire::put_u32(&mut buf, hashes.len() as u32);
+        for hash in &hashes {
+            let bytes = self.stored_bytes(hash).ok_or_else(|| Error::not_found(*hash, "export_archive"))?;
+            wire::put_bytes(&mut buf, bytes);
+        }
+        out.write_all(&buf)?;
+        Ok(hashes.len())
+    }
+    
+    /// `hashes` ordered so each follows the strong relationship targets and
+    /// parents among them that it links to (cycles are broken arbitrarily)
+    fn dependency_order(&self, hashes: &[Hash256]) -> Result<Vec<Hash256>> {
+        let wanted: HashSet<_> = hashes.iter().copied().collect();
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for root in hashes {
+            let mut stack = vec![(*root, false)];
+            while let Some((hash, expanded)) = stack.pop() {
+                if expanded {
+                    order.push(hash);
+                    continue;
+                }
+                if !visited.insert(hash) {
+                    continue;
+                }
+                stack.push((hash, true));
+                let envelope = self.get_metadata(&hash)?;
+                let links = envelope.relationships.iter()
+                    .filter(|r| r.is_strong())
+                    .map(|r| r.target)
+                    .chain(envelope.parents().copied());
+                stack.extend(links.filter(|h| wanted.contains(h) && !visited.contains(h)).map(|h| (h, false)));
+            }
+        }
+        Ok(order)
+    }
+    
+    /// Put every object in an archive written by `export_archive`;
+    /// returns their hashes in archive order
+    pub fn import_archive(&mut self, input: &mut impl Read) -> Result<Vec<Hash256>> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut reader = Reader::new(&data);
+        if reader.take(ARCHIVE_MAGIC.len()).ok() != Some(ARCHIVE_MAGIC.as_slice()) {
+            return Err(Error::Serialization("not an envelope archive".to_string()));
+        }
+        let count = reader.section("header", |r| r.u32())?;
+        let mut hashes = Vec::new();
+        for i in 0..count {
+            let bytes = reader.item("object", i as usize, |r| r.bytes())?;
+            let envelope = self.deserialize(bytes)?;
+            hashes.push(self.put(&envelope)?);
+        }
+        if !reader.is_empty() {
+            return Err(reader.error("trailing bytes after the last object"));
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    
+    #[test]
+    fn test_archive_roundtrip() {
+        let mut source = Store::new();
+        let node = Hash256::hash(b"Node");
+        let leaf = source.put(&Envelope::builder(node, b"leaf".to_vec()).build()).unwrap();
+        let mid = source.put(&Envelope::builder(node, b"mid".to_vec()).relationship("leaf", leaf).build()).unwrap();
+        let root = source.put(&Envelope::builder(node, b"root".to_vec()).relationship("leaf", leaf).relationship("mid", mid).build()).unwrap();
+        source.put(&Envelope::builder(node, b"unrelated".to_vec()).build()).unwrap();
+        
+        let mut archive = Vec::new();
+        assert_eq!(source.export_archive(Some(&[root]), &mut archive).unwrap(), 3);
+        let mut copy = Store::new();
+        assert_eq!(copy.import_archive(&mut archive.as_slice()).unwrap(), vec![leaf, mid, root]);
+        assert_eq!(copy.get(&root).unwrap().payload, b"root");
+        
+        archive.clear();
+        assert_eq!(source.export_archive(None, &mut archive).unwrap(), 4);
+        let truncated = &archive[..archive.len() - 1];
+        assert!(Store::new().import_archive(&mut &truncated[..]).unwrap_err().is_corruption());
+        assert!(Store::new().import_archive(&mut &b"garbage"[..]).is_err());
+    }
+}
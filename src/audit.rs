@@ -0,0 +1,158 @@
+//! Hash-chained audit log of store mutations
+//!
+//! With auditing enabled, every put of a new object, removal, ref move
+//! and new tag made through an `IndexedStore` appends an entry envelope
+//! whose `previous` is the prior entry. Editing or dropping any entry
+//! changes the hashes after it, so the chain from the `audit/head` ref
+//! is tamper-evident, and being ordinary envelopes, entries sync and
+//! query like any other data.
+
+use crate::clock::{Clock, SystemClock};
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::Result;
+
+/// Ref pointing at the newest audit entry
+pub const AUDIT_REF: &str = "audit/head";
+
+/// Type hash of audit entry envelopes
+pub fn audit_type() -> Hash256 {
+    Hash256::hash(b"envelope.AuditEntry")
+}
+
+/// Who is recorded as acting, and when
+pub(crate) struct Auditor {
+    actor: Option<Hash256>,
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl std::fmt::Debug for Auditor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Auditor").field("actor", &self.actor).finish()
+    }
+}
+
+/// One decoded audit entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Hash of the entry envelope itself
+    pub hash: Hash256,
+    /// `put`, `delete`, `set_ref` or `tag`
+    pub op: String,
+    /// The object acted on (or pointed at, for refs and tags)
+    pub target: Hash256,
+    /// Ref or tag name
+    pub name: Option<String>,
+    pub actor: Option<Hash256>,
+    pub at: Option<i64>,
+}
+
+impl AuditEntry {
+    fn decode(hash: Hash256, envelope: &Envelope) -> Result<Self> {
+        let invalid = || Error::InvalidEnvelope(format!("malformed audit entry {}", hash));
+        let op = envelope.get_str("op").ok().flatten().ok_or_else(invalid)?.to_string();
+        let target = envelope.get_hash("target").ok().flatten().ok_or_else(invalid)?;
+        let name = envelope.get_str("name").map_err(|_| invalid())?.map(str::to_string);
+        Ok(Self { hash, op, target, name, actor: envelope.created_by, at: envelope.created_at })
+    }
+}
+
+impl IndexedStore {
+    /// Record later mutations in the audit log, as `actor`, timestamped
+    /// by the system clock
+    ///
+    /// The log continues from `audit/head`, so enabling it again after
+    /// `open` extends the same chain.
+    pub fn enable_audit(&mut self, actor: Option<Hash256>) {
+        self.enable_audit_from(actor, SystemClock);
+    }
+    
+    /// `enable_audit` with timestamps from `clock`
+    pub fn enable_audit_from<C: Clock + Send + Sync + 'static>(&mut self, actor: Option<Hash256>, clock: C) {
+        *self.auditor_mut() = Some(Auditor { actor, clock: Box::new(clock) });
+    }
+    
+    /// Stop recording mutations; existing entries stay
+    pub fn disable_audit(&mut self) {
+        *self.auditor_mut() = None;
+    }
+    
+    /// Append an entry if auditing is enabled
+    pub(crate) fn audit(&mut self, op: &str, target: Hash256, name: Option<&str>) -> Result<()> {
+        let Some(auditor) = self.auditor() else {
+            return Ok(());
+        };
+        let mut builder = Envelope::builder(audit_type(), Vec::new())
+            .type_name("AuditEntry")
+            .index("op", op)
+            .index("target", target)
+            .created_at_from(auditor.clock.as_ref());
+        if let Some(name) = name {
+            builder = builder.index("name", name);
+        }
+        if let Some(actor) = auditor.actor {
+            builder = builder.created_by(actor);
+        }
+        if let Some(head) = self.get_ref(AUDIT_REF) {
+            builder = builder.previous(head);
+        }
+        let entry = builder.build();
+        let hash = self.store_mut().put(&entry)?;
+        self.index_add(hash, &entry);
+        self.store_mut().set_ref(AUDIT_REF, hash)
+    }
+    
+    /// Audit entries, newest first
+    ///
+    /// Fails if an entry is missing or isn't an audit entry, which means
+    /// the chain was cut or tampered with.
+    pub fn audit_trail(&self) -> Result<Vec<AuditEntry>> {
+        let Some(head) = self.get_ref(AUDIT_REF) else {
+            return Ok(Vec::new());
+        };
+        self.store().history(&head)
+            .map(|version| {
+                let (hash, envelope) = version?;
+                if envelope.type_hash != audit_type() {
+                    return Err(Error::InvalidEnvelope(format!("{} is not an audit entry", hash)));
+                }
+                AuditEntry::decode(hash, &envelope)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    
+    #[test]
+    fn test_audit_trail() {
+        let mut store = IndexedStore::new();
+        let admin = Hash256::hash(b"admin");
+        let note = Hash256::hash(b"Note");
+        store.put(&Envelope::builder(note, b"before".to_vec()).build()).unwrap();
+        store.enable_audit_from(Some(admin), FixedClock(1_700_000_000));
+        
+        let hash = store.put(&Envelope::builder(note, b"hello".to_vec()).build()).unwrap();
+        store.put(&Envelope::builder(note, b"hello".to_vec()).build()).unwrap();
+        store.set_ref("notes/latest", hash).unwrap();
+        store.tag("v1", hash).unwrap();
+        store.tag("v1", hash).unwrap();
+        store.disable_audit();
+        store.put(&Envelope::builder(note, b"after".to_vec()).build()).unwrap();
+        
+        let trail = store.audit_trail().unwrap();
+        let ops: Vec<_> = trail.iter().map(|e| (e.op.as_str(), e.target, e.name.as_deref())).collect();
+        assert_eq!(ops, vec![("tag", hash, Some("v1")), ("set_ref", hash, Some("notes/latest")), ("put", hash, None)]);
+        assert!(trail.iter().all(|e| e.actor == Some(admin) && e.at == Some(1_700_000_000)));
+        assert_eq!(store.query_by_type(&audit_type()).len(), 3);
+        
+        // Deleting an entry breaks the chain
+        store.delete(&trail[1].hash, true).unwrap();
+        assert!(store.audit_trail().is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Capability-based access control
+//!
+//! A `Capability` grants operations, optionally limited to some types,
+//! to the subgraphs under some roots, or to a namespace (see
+//! `IndexedStore::namespace`). `IndexedStore::scoped` wraps the store in
+//! a handle that checks every call against one capability, so a front
+//! end can hand each tenant a handle instead of the store.
+//!
+//! A root's subgraph is its closure: strong relationships and parents.
+//! Objects written through a handle join its scope, letting a tenant
+//! write new children and then the version that links them; versions
+//! others derive from objects in scope stay out of it. The scope is
+//! computed once per handle, when first needed.
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::{Deletion, IndexedStore};
+use crate::query::Query;
+use crate::Result;
+use std::cell::OnceCell;
+use std::collections::HashSet;
+
+/// An operation a capability may grant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Read,
+    Write,
+    Delete,
+}
+
+/// Operations permitted on a scope of objects
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capability {
+    operations: HashSet<Operation>,
+    types: Option<HashSet<Hash256>>,
+    roots: Option<Vec<Hash256>>,
+    namespace: Option<String>,
+}
+
+impl Capability {
+    /// A capability that grants nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Grant an operation
+    pub fn allow(mut self, operation: Operation) -> Self {
+        self.operations.insert(operation);
+        self
+    }
+    
+    /// Only objects of these types; can be called repeatedly
+    pub fn types(mut self, types: impl IntoIterator<Item = Hash256>) -> Self {
+        self.types.get_or_insert_with(HashSet::new).extend(types);
+        self
+    }
+    
+    /// Only objects in the subgraphs under these roots
+    pub fn roots(mut self, roots: impl IntoIterator<Item = Hash256>) -> Self {
+        self.roots.get_or_insert_with(Vec::new).extend(roots);
+        self
+    }
+    
+    /// Only objects in the namespace `name`; writes go into it
+    pub fn namespace(mut self, name: impl Into<String>) -> Self {
+        self.namespace = Some(name.into());
+        self
+    }
+    
+    /// Whether the capability grants `operation` on objects of this type
+    pub fn permits(&self, operation: Operation, type_hash: &Hash256) -> bool {
+        self.operations.contains(&operation)
+            && self.types.as_ref().is_none_or(|types| types.contains(type_hash))
+    }
+}
+
+impl IndexedStore {
+    /// A handle that performs operations only as far as `capability` allows
+    pub fn scoped(&mut self, capability: Capability) -> Scoped<'_> {
+        Scoped { store: self, capability, written: HashSet::new(), scope: OnceCell::new() }
+    }
+}
+
+/// Store access restricted by a capability, from `IndexedStore::scoped`
+///
+/// Denied operations fail with `Error::Unauthorized`; reads of objects
+/// out of scope fail the same way whether or not they exist.
+#[derive(Debug)]
+pub struct Scoped<'a> {
+    store: &'a mut IndexedStore,
+    capability: Capability,
+    /// Objects put through this handle
+    written: HashSet<Hash256>,
+    /// Objects in the capability's root subgraphs and namespace, set on
+    /// first use; the inner `None` means unrestricted
+    scope: OnceCell<Option<HashSet<Hash256>>>,
+}
+
+impl Scoped<'_> {
+    /// Objects the handle may see; `None` if unrestricted
+    fn subgraph(&self) -> Result<Option<&HashSet<Hash256>>> {
+        if self.scope.get().is_none() {
+            let _ = self.scope.set(self.compute_scope()?);
+        }
+        Ok(self.scope.get().and_then(Option::as_ref))
+    }
+    
+    fn compute_scope(&self) -> Result<Option<HashSet<Hash256>>> {
+        let roots = match &self.capability.roots {
+            Some(roots) => Some(self.store.store().closure(roots)?.into_iter().collect::<HashSet<_>>()),
+            None => None,
+        };
+        let namespace = match &self.capability.namespace {
+            Some(name) => Some(self.store.namespace_members(name)?),
+            None => None,
+        };
+        let mut scope = match (roots, namespace) {
+            (Some(roots), Some(namespace)) => roots.intersection(&namespace).copied().collect(),
+            (Some(scope), None) | (None, Some(scope)) => scope,
+            (None, None) => return Ok(None),
+        };
+        scope.extend(self.written.iter().filter(|hash| self.store.contains(hash)));
+        Ok(Some(scope))
+    }
+    
+    fn check(&self, operation: Operation, hash: &Hash256, subgraph: Option<&HashSet<Hash256>>) -> Result<()> {
+        let permitted = self.store.store().stored_type(hash)
+            .is_some_and(|type_hash| self.capability.permits(operation, &type_hash))
+            && subgraph.is_none_or(|scope| scope.contains(hash));
+        match permitted {
+            true => Ok(()),
+            false => Err(Error::Unauthorized(format!("{:?} {}", operation, hash.short()))),
+        }
+    }
+    
+    /// Retrieve an object in scope
+    pub fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        self.check(Operation::Read, hash, self.subgraph()?)?;
+        self.store.get(hash)
+    }
+    
+    /// Store an envelope of a permitted type
+    ///
+    /// Under root or namespace scopes, every strong relationship target
+    /// and parent of the envelope must already be in scope, so a handle
+    /// can't pull foreign objects into view by linking to them.
+    pub fn put(&mut self, envelope: &Envelope) -> Result<Hash256> {
+        let denied = || Error::Unauthorized(format!("Write {}", envelope.type_hash.short()));
+        if !self.capability.permits(Operation::Write, &envelope.type_hash) {
+            return Err(denied());
+        }
+        if let Some(scope) = self.subgraph()? {
+            let mut links = envelope.relationships.iter()
+                .filter(|r| r.is_strong())
+                .map(|r| &r.target)
+                .chain(envelope.parents());
+            if !links.all(|target| scope.contains(target)) {
+                return Err(denied());
+            }
+        }
+        // Content already stored is deduplicated, not written, so putting
+        // a copy of a foreign object mustn't bring it into scope
+        let existed = self.store.contains(&envelope.hash());
+        let hash = match &self.capability.namespace {
+            Some(name) if !existed => self.store.namespace(name.clone())?.put(envelope)?,
+            _ => self.store.put(envelope)?,
+        };
+        if !existed {
+            self.written.insert(hash);
+            if let Some(Some(scope)) = self.scope.get_mut() {
+                scope.insert(hash);
+            }
+        }
+        Ok(hash)
+    }
+    
+    /// Delete an object in scope (see `IndexedStore::delete`)
+    pub fn delete(&mut self, hash: &Hash256, force: bool) -> Result<Deletion> {
+        self.check(Operation::Delete, hash, self.subgraph()?)?;
+        let deletion = self.store.delete(hash, force)?;
+        // What the deleted object linked to may have left the scope
+        self.scope.take();
+        Ok(deletion)
+    }
+    
+    /// Run a query, keeping only results the capability may read
+    pub fn query(&self, query: &Query) -> Result<Vec<Hash256>> {
+        let subgraph = self.subgraph()?;
+        Ok(self.store.query(query).into_iter()
+            .filter(|hash| self.check(Operation::Read, hash, subgraph).is_ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_type_scoped_capability() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let secret = Hash256::hash(b"Secret");
+        let hidden = store.put(&Envelope::builder(secret, b"key".to_vec()).build()).unwrap();
+        
+        let mut reader = store.scoped(Capability::new().allow(Operation::Read).allow(Operation::Write).types([post]));
+        let mine = reader.put(&Envelope::builder(post, b"hi".to_vec()).build()).unwrap();
+        assert_eq!(reader.get(&mine).unwrap().payload, b"hi");
+        assert!(matches!(reader.get(&hidden), Err(Error::Unauthorized(_))));
+        assert!(matches!(reader.put(&Envelope::builder(secret, vec![]).build()), Err(Error::Unauthorized(_))));
+        assert!(matches!(reader.delete(&mine, false), Err(Error::Unauthorized(_))));
+        assert_eq!(reader.query(&Query::type_is(secret).or(Query::type_is(post))).unwrap(), vec![mine]);
+    }
+    
+    #[test]
+    fn test_root_scoped_capability() {
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let other = store.put(&Envelope::builder(node, b"other tenant".to_vec()).build()).unwrap();
+        let child = store.put(&Envelope::builder(node, b"child".to_vec()).build()).unwrap();
+        let root_v1 = Envelope::builder(node, b"tenant".to_vec()).relationship("child", child).build();
+        let root = store.put(&root_v1).unwrap();
+        
+        let all = [Operation::Read, Operation::Write, Operation::Delete];
+        let capability = all.into_iter().fold(Capability::new(), Capability::allow).roots([root]);
+        let mut tenant = store.scoped(capability);
+        assert!(tenant.get(&child).is_ok());
+        assert!(tenant.get(&other).is_err());
+        
+        // New children, then a new root version linking them, stay in scope
+        let added = tenant.put(&Envelope::builder(node, b"added".to_vec()).build()).unwrap();
+        let root_v2 = tenant.put(&root_v1.derive().relationship("child", added).build()).unwrap();
+        assert!(tenant.get(&root_v2).is_ok());
+        assert!(tenant.get(&added).is_ok());
+        // Linking to another tenant's data is refused
+        let stolen = Envelope::builder(node, vec![]).relationship("steal", other).build();
+        assert!(matches!(tenant.put(&stolen), Err(Error::Unauthorized(_))));
+        assert!(tenant.delete(&other, true).is_err());
+        
+        // Versions someone else derives from the tenant's objects stay out of scope
+        let foreign = store.put(&Envelope::builder(node, b"foreign".to_vec()).relationship("leak", other).build()).unwrap();
+        let fork = store.put(&root_v1.derive().relationship("child", foreign).build()).unwrap();
+        let tenant = store.scoped(all.into_iter().fold(Capability::new(), Capability::allow).roots([root]));
+        assert!(tenant.get(&root).is_ok());
+        assert!(tenant.get(&fork).is_err() && tenant.get(&foreign).is_err() && tenant.get(&other).is_err());
+    }
+    
+    #[test]
+    fn test_namespace_scoped_capability() {
+        let mut store = IndexedStore::new();
+        let doc = Hash256::hash(b"Doc");
+        let theirs = store.namespace("b").unwrap().put(&Envelope::builder(doc, b"b".to_vec()).build()).unwrap();
+        let ours = store.namespace("a").unwrap().put(&Envelope::builder(doc, b"a".to_vec()).build()).unwrap();
+        
+        let capability = Capability::new().allow(Operation::Read).allow(Operation::Write).namespace("a");
+        let mut tenant = store.scoped(capability);
+        assert!(tenant.get(&ours).is_ok());
+        assert!(matches!(tenant.get(&theirs), Err(Error::Unauthorized(_))));
+        let added = tenant.put(&Envelope::builder(doc, vec![]).relationship("see", ours).build()).unwrap();
+        assert!(tenant.get(&added).is_ok());
+        assert!(tenant.put(&Envelope::builder(doc, vec![]).relationship("see", theirs).build()).is_err());
+        assert_eq!(tenant.query(&Query::type_is(doc)).unwrap().len(), 2);
+        assert!(store.namespace("a").unwrap().contains(&added));
+    }
+    
+    #[test]
+    fn test_duplicate_put_grants_nothing() {
+        let mut store = IndexedStore::new();
+        let doc = Hash256::hash(b"Doc");
+        let foreign = Envelope::builder(doc, b"foreign".to_vec()).build();
+        let hash = store.namespace("b").unwrap().put(&foreign).unwrap();
+        let all = [Operation::Read, Operation::Write, Operation::Delete];
+        
+        let root = store.put(&Envelope::builder(doc, b"root".to_vec()).build()).unwrap();
+        let mut tenant = store.scoped(all.into_iter().fold(Capability::new(), Capability::allow).roots([root]));
+        assert_eq!(tenant.put(&foreign).unwrap(), hash);
+        assert!(matches!(tenant.get(&hash), Err(Error::Unauthorized(_))));
+        assert!(matches!(tenant.delete(&hash, true), Err(Error::Unauthorized(_))));
+        
+        let mut tenant = store.scoped(all.into_iter().fold(Capability::new(), Capability::allow).namespace("a"));
+        assert_eq!(tenant.put(&foreign).unwrap(), hash);
+        assert!(matches!(tenant.get(&hash), Err(Error::Unauthorized(_))));
+        assert!(matches!(tenant.delete(&hash, true), Err(Error::Unauthorized(_))));
+        assert!(!store.namespace("a").unwrap().contains(&hash));
+    }
+}
@@ -0,0 +1,221 @@
+//! A minimal line editor with history and tab completion
+//!
+//! On a Unix terminal the editor puts it in raw mode while reading, and
+//! supports typing and backspace at the end of the line, up and down for
+//! history, tab to complete the last word, Ctrl-C to clear the line and
+//! Ctrl-D on an empty line to finish. Elsewhere, and when input isn't a
+//! terminal, lines are read plainly so scripts can be piped in.
+
+use std::io::{self, BufRead, Write};
+
+/// Completions for the word being typed: given the line so far and the
+/// word at its end, the words it could become
+pub type Completer<'a> = dyn Fn(&str, &str) -> Vec<String> + 'a;
+
+#[derive(Debug, Default)]
+pub struct Editor {
+    history: Vec<String>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Read one line, or `None` at end of input
+    pub fn read_line(&mut self, prompt: &str, complete: &Completer<'_>) -> io::Result<Option<String>> {
+        let line = match raw::Guard::enter() {
+            Some(_guard) => self.edit(prompt, complete)?,
+            None => {
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                Some(line.trim_end_matches(['\n', '\r']).to_string())
+            }
+        };
+        if let Some(line) = &line {
+            if !line.trim().is_empty() && self.history.last() != Some(line) {
+                self.history.push(line.clone());
+            }
+        }
+        Ok(line)
+    }
+    
+    fn edit(&mut self, prompt: &str, complete: &Completer<'_>) -> io::Result<Option<String>> {
+        let mut out = io::stdout();
+        let mut line = String::new();
+        // Position in history while browsing with up and down
+        let mut browsing = self.history.len();
+        let redraw = |out: &mut io::Stdout, line: &str| -> io::Result<()> {
+            write!(out, "\r\x1b[K{}{}", prompt, line)?;
+            out.flush()
+        };
+        redraw(&mut out, &line)?;
+        let mut pending = Vec::new();
+        loop {
+            let Some(byte) = raw::read_byte()? else {
+                return Ok(None);
+            };
+            match byte {
+                b'\r' | b'\n' => {
+                    write!(out, "\r\n")?;
+                    return Ok(Some(line));
+                }
+                // Ctrl-C
+                3 => {
+                    write!(out, "^C\r\n")?;
+                    line.clear();
+                }
+                // Ctrl-D
+                4 if line.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(None);
+                }
+                b'\t' => {
+                    let start = line.rfind(' ').map_or(0, |i| i + 1);
+                    let candidates = complete(&line[..start], &line[start..]);
+                    match candidates.as_slice() {
+                        [] => {}
+                        [only] => {
+                            line.truncate(start);
+                            line.push_str(only);
+                            line.push(' ');
+                        }
+                        several => {
+                            let prefix = common_prefix(several);
+                            if prefix.len() > line.len() - start {
+                                line.truncate(start);
+                                line.push_str(&prefix);
+                            } else {
+                                write!(out, "\r\n{}\r\n", several.join("  "))?;
+                            }
+                        }
+                    }
+                }
+                // Backspace
+                8 | 127 => {
+                    line.pop();
+                }
+                // Escape sequences: up and down browse history, others are ignored
+                0x1b => {
+                    let (Some(b'['), Some(key)) = (raw::read_byte()?, raw::read_byte()?) else {
+                        continue;
+                    };
+                    match key {
+                        b'A' if browsing > 0 => browsing -= 1,
+                        b'B' if browsing < self.history.len() => browsing += 1,
+                        _ => continue,
+                    }
+                    line = self.history.get(browsing).cloned().unwrap_or_default();
+                }
+                byte if byte >= 0x20 => {
+                    pending.push(byte);
+                    if let Ok(text) = std::str::from_utf8(&pending) {
+                        line.push_str(text);
+                        pending.clear();
+                    } else if pending.len() >= 4 {
+                        pending.clear();
+                    }
+                }
+                _ => {}
+            }
+            redraw(&mut out, &line)?;
+        }
+    }
+}
+
+/// Longest prefix every candidate shares
+fn common_prefix(candidates: &[String]) -> String {
+    let first = &candidates[0];
+    let mut len = candidates[1..].iter().fold(first.len(), |len, c| {
+        first.bytes().zip(c.bytes()).take(len).take_while(|(a, b)| a == b).count()
+    });
+    while !first.is_char_boundary(len) {
+        len -= 1;
+    }
+    first[..len].to_string()
+}
+
+#[cfg(unix)]
+mod raw {
+    use std::io::{self, Read};
+    
+    /// Restores the terminal's settings when dropped
+    pub struct Guard(libc::termios);
+    
+    impl Guard {
+        /// Switch stdin to raw mode, if it's a terminal
+        pub fn enter() -> Option<Guard> {
+            // SAFETY: termios is plain data, filled in by tcgetattr before use
+            unsafe {
+                if libc::isatty(libc::STDIN_FILENO) != 1 {
+                    return None;
+                }
+                let mut original: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                    return None;
+                }
+                let mut raw = original;
+                raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+                raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+                raw.c_cc[libc::VMIN] = 1;
+                raw.c_cc[libc::VTIME] = 0;
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                    return None;
+                }
+                Some(Guard(original))
+            }
+        }
+    }
+    
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            // SAFETY: restores settings read by tcgetattr
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+            }
+        }
+    }
+    
+    pub fn read_byte() -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match io::stdin().lock().read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod raw {
+    use std::io;
+    
+    pub struct Guard;
+    
+    impl Guard {
+        pub fn enter() -> Option<Guard> {
+            None
+        }
+    }
+    
+    pub fn read_byte() -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_common_prefix() {
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&words(&["history", "help"])), "h");
+        assert_eq!(common_prefix(&words(&["refs", "refresh"])), "ref");
+        assert_eq!(common_prefix(&words(&["go", "show"])), "");
+        assert_eq!(common_prefix(&words(&["only"])), "only");
+        // Never splits a character
+        assert_eq!(common_prefix(&words(&["café", "cafè"])), "caf");
+    }
+}
@@ -0,0 +1,366 @@
+//! Command-line inspection and editing of file-backed stores
+//!
+//! Read-only commands open stores as snapshots (see
+//! `Store::open_snapshot`), so they work while another process is
+//! writing; `put` and `import` need the store to themselves. Objects can
+//! be named by full hash, unique hash prefix, ref or tag.
+
+mod line;
+mod shell;
+
+use envelope::{parse_literal, Envelope, Error, Hash256, IndexedStore, Query, Store};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: envelope <command> <store> [args]
+
+commands:
+  cat <store> <object> [--payload]   show an envelope's metadata, or dump its payload
+  ls <store> [--type <hash|name>]     list objects, optionally of one type
+  refs <store>                        list refs and tags
+  verify <store>                      check every object and name
+  put <store> [file|-] --type <hash|name> [--content-type <mime>]
+      [--index key=value]... [--rel type=object]... [--previous <object>] [--ref <name>]
+                                      store a file or stdin as an envelope; prints its hash
+  query <store> <query>               hashes matching a query, e.g. 'type:Post words > 100'
+  export <store> [object...] [--output <file>]
+                                      write an archive of the objects' closure, or everything
+  import <store> [file|-]             store every object in an archive
+  shell <store>                       explore a store interactively";
+
+/// Flags that take no value
+const SWITCHES: [&str; 1] = ["--payload"];
+
+type CliResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("envelope: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> CliResult<ExitCode> {
+    let (Some(command), Some(dir)) = (args.first(), args.get(1)) else {
+        return Err(USAGE.into());
+    };
+    let args = Args::parse(&args[2..])?;
+    match command.as_str() {
+        "cat" => cat(&Store::open_snapshot(dir)?, &args),
+        "ls" => ls(&Store::open_snapshot(dir)?, &args),
+        "refs" => refs(&Store::open_snapshot(dir)?),
+        "verify" => verify(&Store::open_snapshot(dir)?),
+        "put" => put(&mut Store::open(dir)?, &args),
+        "query" => query(&IndexedStore::open_snapshot(dir)?, &args),
+        "export" => export(&Store::open_snapshot(dir)?, &args),
+        "import" => import(&mut Store::open(dir)?, &args),
+        "shell" => shell::run(IndexedStore::open_snapshot(dir)?),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// A command's arguments after the store
+#[derive(Debug, Default)]
+struct Args {
+    positional: Vec<String>,
+    /// `--flag value` pairs, in order
+    options: Vec<(String, String)>,
+    switches: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> CliResult<Args> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if SWITCHES.contains(&arg.as_str()) {
+                parsed.switches.push(arg.clone());
+            } else if arg.starts_with("--") {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                parsed.options.push((arg.clone(), value.clone()));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+        Ok(parsed)
+    }
+    
+    fn has(&self, switch: &str) -> bool {
+        self.switches.iter().any(|s| s == switch)
+    }
+    
+    /// Every value given for `flag`
+    fn all<'a>(&'a self, flag: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.options.iter().filter(move |(name, _)| name == flag).map(|(_, value)| value.as_str())
+    }
+    
+    /// The last value given for `flag`
+    fn value<'a>(&'a self, flag: &'a str) -> Option<&'a str> {
+        self.all(flag).last()
+    }
+}
+
+/// Split `key=value`
+fn pair<'a>(arg: &'a str, flag: &str) -> CliResult<(&'a str, &'a str)> {
+    arg.split_once('=').ok_or_else(|| format!("{} expects key=value, got '{}'", flag, arg).into())
+}
+
+/// Contents of the file named by the first positional argument, or stdin
+/// if there is none or it's `-`
+fn read_input(args: &Args) -> CliResult<Vec<u8>> {
+    let mut data = Vec::new();
+    match args.positional.first().map(String::as_str) {
+        None | Some("-") => std::io::stdin().read_to_end(&mut data)?,
+        Some(path) => File::open(path)?.read_to_end(&mut data)?,
+    };
+    Ok(data)
+}
+
+/// Find an object by full hash, ref, tag or unique hash prefix
+fn resolve(store: &Store, name: &str) -> CliResult<Hash256> {
+    if let Ok(hash) = Hash256::from_hex(name) {
+        return Ok(hash);
+    }
+    if let Some(hash) = store.get_ref(name).or_else(|| store.get_tag(name)) {
+        return Ok(hash);
+    }
+    let mut matches = store.hashes().filter(|hash| hash.to_hex().starts_with(name));
+    match (matches.next(), matches.next()) {
+        (Some(hash), None) => Ok(*hash),
+        (Some(_), Some(_)) => Err(format!("'{}' matches more than one object", name).into()),
+        _ => Err(format!("no object, ref or tag '{}'", name).into()),
+    }
+}
+
+fn cat(store: &Store, args: &Args) -> CliResult<ExitCode> {
+    let name = args.positional.first().ok_or(USAGE)?;
+    let hash = resolve(store, name)?;
+    let envelope = store.get(&hash)?;
+    if args.has("--payload") {
+        std::io::stdout().write_all(&envelope.payload)?;
+    } else {
+        print_envelope(hash, &envelope);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_envelope(hash: Hash256, envelope: &Envelope) {
+    println!("hash:         {}", hash);
+    println!("type:         {}{}", envelope.type_hash, envelope.type_name.as_ref().map(|n| format!(" ({})", n)).unwrap_or_default());
+    if let Some(content_type) = &envelope.content_type {
+        println!("content type: {}", content_type);
+    }
+    let optional = [
+        ("previous", envelope.previous.map(|h| h.to_string())),
+        ("created at", envelope.created_at.map(|t| t.to_string())),
+        ("created by", envelope.created_by.map(|h| h.to_string())),
+        ("valid from", envelope.valid_from.map(|t| t.to_string())),
+        ("valid to", envelope.valid_to.map(|t| t.to_string())),
+    ];
+    for (label, value) in optional {
+        if let Some(value) = value {
+            println!("{:<13} {}", format!("{}:", label), value);
+        }
+    }
+    for parent in &envelope.merge_parents {
+        println!("merged:       {}", parent);
+    }
+    println!("payload:      {} bytes", envelope.payload.len());
+    let mut fields: Vec<_> = envelope.index.iter().collect();
+    fields.sort_by_key(|(field, _)| *field);
+    for (field, value) in fields {
+        println!("index:        {} = {}", field, value);
+    }
+    for rel in &envelope.relationships {
+        let position = rel.position.map(|p| format!(" #{}", p)).unwrap_or_default();
+        println!("relationship: {}{} -> {} ({:?})", rel.rel_type, position, rel.target, rel.strength);
+    }
+    let mut extensions: Vec<_> = envelope.extensions.iter().collect();
+    extensions.sort_by_key(|(name, _)| *name);
+    for (name, bytes) in extensions {
+        println!("extension:    {} ({} bytes)", name, bytes.len());
+    }
+}
+
+fn ls(store: &Store, args: &Args) -> CliResult<ExitCode> {
+    let wanted = args.value("--type");
+    let mut rows = Vec::new();
+    for (hash, object) in store.iter() {
+        let header = object.header()?;
+        let type_name = header.type_name.as_deref().unwrap_or("");
+        let matches = wanted.is_none_or(|wanted| {
+            Hash256::from_hex(wanted).map_or(type_name == wanted, |type_hash| type_hash == header.type_hash)
+        });
+        if matches {
+            rows.push((hash, type_name.to_string(), object.stored_size()));
+        }
+    }
+    rows.sort();
+    for (hash, type_name, size) in rows {
+        println!("{}  {:>8}  {}", hash, size, type_name);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn refs(store: &Store) -> CliResult<ExitCode> {
+    let mut refs: Vec<_> = store.refs().collect();
+    refs.sort();
+    for (name, hash) in refs {
+        println!("ref  {}  {}", hash, name);
+    }
+    for (name, hash) in store.tags() {
+        println!("tag  {}  {}", hash, name);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(store: &Store) -> CliResult<ExitCode> {
+    let problems = store.verify();
+    for (hash, error) in &problems {
+        let kind = match error {
+            Error::NotFound { .. } => "dangling name",
+            _ => "bad object",
+        };
+        println!("{}  {}: {}", hash, kind, error);
+    }
+    println!("{} objects, {} problems", store.len(), problems.len());
+    Ok(if problems.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn put(store: &mut Store, args: &Args) -> CliResult<ExitCode> {
+    let type_arg = args.value("--type").ok_or("put needs --type")?;
+    let payload = read_input(args)?;
+    let mut builder = match Hash256::from_hex(type_arg) {
+        Ok(type_hash) => Envelope::builder(type_hash, payload),
+        // A name stands for the hash of itself
+        Err(_) => Envelope::builder(Hash256::hash(type_arg.as_bytes()), payload).type_name(type_arg),
+    };
+    if let Some(content_type) = args.value("--content-type") {
+        builder = builder.content_type(content_type);
+    }
+    for arg in args.all("--index") {
+        let (key, value) = pair(arg, "--index")?;
+        // Typed as the query language types literals
+        builder = builder.index(key, parse_literal(value));
+    }
+    for arg in args.all("--rel") {
+        let (rel_type, target) = pair(arg, "--rel")?;
+        builder = builder.relationship(rel_type, resolve(store, target)?);
+    }
+    if let Some(previous) = args.value("--previous") {
+        builder = builder.previous(resolve(store, previous)?);
+    }
+    let hash = store.put(&builder.try_build()?)?;
+    if let Some(name) = args.value("--ref") {
+        store.set_ref(name, hash)?;
+    }
+    println!("{}", hash);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn query(store: &IndexedStore, args: &Args) -> CliResult<ExitCode> {
+    if args.positional.is_empty() {
+        return Err(USAGE.into());
+    }
+    let query = Query::parse(&args.positional.join(" "))?;
+    for hash in store.query(&query) {
+        println!("{}", hash);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn export(store: &Store, args: &Args) -> CliResult<ExitCode> {
+    let roots = args.positional.iter().map(|name| resolve(store, name)).collect::<CliResult<Vec<_>>>()?;
+    let roots = (!roots.is_empty()).then_some(roots.as_slice());
+    let count = match args.value("--output") {
+        Some(path) => store.export_archive(roots, &mut File::create(path)?)?,
+        None => store.export_archive(roots, &mut std::io::stdout().lock())?,
+    };
+    eprintln!("exported {} objects", count);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn import(store: &mut Store, args: &Args) -> CliResult<ExitCode> {
+    let data = read_input(args)?;
+    let count = store.import_archive(&mut data.as_slice())?.len();
+    eprintln!("imported {} objects", count);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envelope::IndexValue;
+    
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+    
+    #[test]
+    fn test_parse_args() {
+        let args = Args::parse(&strings(&["file", "--index", "a=1", "--payload", "--index", "b=x", "--type", "Post"])).unwrap();
+        assert_eq!(args.positional, ["file"]);
+        assert!(args.has("--payload") && !args.has("--other"));
+        assert_eq!(args.all("--index").collect::<Vec<_>>(), ["a=1", "b=x"]);
+        assert_eq!(args.value("--type"), Some("Post"));
+        assert_eq!(pair("a=1", "--index").unwrap(), ("a", "1"));
+        assert_eq!(parse_literal("1"), IndexValue::Int64(1));
+        
+        // Error paths
+        assert!(Args::parse(&strings(&["--type"])).unwrap_err().to_string().contains("--type needs a value"));
+        assert!(pair("a", "--index").is_err());
+        assert!(run(&strings(&["cat"])).is_err());
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        assert!(run(&strings(&["frobnicate", dir])).is_err());
+        assert!(run(&strings(&["put", dir, "/dev/null"])).unwrap_err().to_string().contains("--type"));
+    }
+    
+    #[test]
+    fn test_resolve() {
+        let mut store = Store::new();
+        let note = Hash256::hash(b"Note");
+        let hashes: Vec<_> = (0..40u8).map(|i| store.put(&Envelope::builder(note, vec![i]).build()).unwrap()).collect();
+        store.set_ref("main", hashes[0]).unwrap();
+        store.tag("v1", hashes[1]).unwrap();
+        
+        assert_eq!(resolve(&store, "main").unwrap(), hashes[0]);
+        assert_eq!(resolve(&store, "v1").unwrap(), hashes[1]);
+        assert_eq!(resolve(&store, &hashes[2].to_hex()).unwrap(), hashes[2]);
+        let hex = hashes[3].to_hex();
+        let unique = (1..hex.len()).map(|len| &hex[..len])
+            .find(|prefix| hashes.iter().filter(|h| h.to_hex().starts_with(prefix)).count() == 1)
+            .unwrap();
+        assert_eq!(resolve(&store, unique).unwrap(), hashes[3]);
+        // The empty prefix matches every object
+        assert!(resolve(&store, "").unwrap_err().to_string().contains("more than one"));
+        assert!(resolve(&store, "nothing").unwrap_err().to_string().contains("no object"));
+    }
+    
+    #[test]
+    fn test_archive_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to, archive) = (dir.path().join("from"), dir.path().join("to"), dir.path().join("archive"));
+        let path = |path: &std::path::Path| path.to_str().unwrap().to_string();
+        let note = Hash256::hash(b"Note");
+        let (root, unrelated) = {
+            let mut store = Store::open(&from).unwrap();
+            let leaf = store.put(&Envelope::builder(note, b"leaf".to_vec()).build()).unwrap();
+            let root = store.put(&Envelope::builder(note, b"root".to_vec()).relationship("child", leaf).build()).unwrap();
+            store.set_ref("main", root).unwrap();
+            (root, store.put(&Envelope::builder(note, b"other".to_vec()).build()).unwrap())
+        };
+        
+        run(&strings(&["export", &path(&from), "main", "--output", &path(&archive)])).unwrap();
+        run(&strings(&["import", &path(&to), &path(&archive)])).unwrap();
+        let store = Store::open_snapshot(&to).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&root).unwrap().payload, b"root");
+        assert!(!store.contains(&unrelated));
+    }
+}
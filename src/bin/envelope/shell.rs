@@ -0,0 +1,208 @@
+//! `envelope shell`: an interactive session over one store
+//!
+//! The session keeps a current object to walk the graph from: `go`
+//! moves to any object, `follow` along an outgoing relationship, `back`
+//! returns. Tab completes commands, relationship types after `follow`,
+//! and refs, tags and hashes elsewhere.
+
+use super::line::Editor;
+use super::{print_envelope, resolve, CliResult};
+use envelope::{Hash256, IndexedStore, Query};
+use std::process::ExitCode;
+
+const HELP: &str = "commands:
+  go <object>              make an object current
+  show [object]            metadata of the current (or given) object
+  payload [object]         payload as text
+  links                    outgoing relationships of the current object
+  follow <type> [n]        move to the (n-th) target of a relationship
+  incoming <type>          objects linking to the current one
+  back                     return to the previous object
+  history [object]         the version chain back from an object
+  query <query>            run a query, e.g. type:Post words > 100
+  refs                     list refs and tags
+  refresh                  catch up with a writer
+  help, quit";
+
+const COMMANDS: [&str; 14] = [
+    "go", "show", "payload", "links", "follow", "incoming", "back", "history", "query", "refs", "refresh", "help", "quit", "exit",
+];
+
+struct Session {
+    store: IndexedStore,
+    current: Option<Hash256>,
+    /// Objects `back` returns to, most recent last
+    trail: Vec<Hash256>,
+}
+
+pub fn run(store: IndexedStore) -> CliResult<ExitCode> {
+    let mut session = Session { store, current: None, trail: Vec::new() };
+    let mut editor = Editor::new();
+    println!("{} objects; type help for commands", session.store.len());
+    loop {
+        let prompt = match session.current {
+            Some(hash) => format!("{}> ", hash.short()),
+            None => "> ".to_string(),
+        };
+        let Some(line) = editor.read_line(&prompt, &|before, word| session.complete(before, word))? else {
+            return Ok(ExitCode::SUCCESS);
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["quit" | "exit"] => return Ok(ExitCode::SUCCESS),
+            [command, args @ ..] => {
+                if let Err(e) = session.execute(command, args) {
+                    println!("error: {}", e);
+                }
+            }
+        }
+    }
+}
+
+impl Session {
+    /// The named object, or the current one if no name is given
+    fn target(&self, args: &[&str]) -> CliResult<Hash256> {
+        match args.first() {
+            Some(name) => resolve(self.store.store(), name),
+            None => self.current.ok_or_else(|| "no current object; use go <object>".into()),
+        }
+    }
+    
+    fn go(&mut self, hash: Hash256) -> CliResult<()> {
+        let envelope = self.store.get_metadata(&hash)?;
+        if let Some(current) = self.current.replace(hash) {
+            self.trail.push(current);
+        }
+        println!("{} {}", hash, envelope.type_name.as_deref().unwrap_or(""));
+        Ok(())
+    }
+    
+    fn execute(&mut self, command: &str, args: &[&str]) -> CliResult<()> {
+        match command {
+            "help" => println!("{}", HELP),
+            "go" if !args.is_empty() => self.go(self.target(args)?)?,
+            "show" => {
+                let hash = self.target(args)?;
+                print_envelope(hash, &self.store.get(&hash)?);
+            }
+            "payload" => println!("{}", String::from_utf8_lossy(&self.store.get(&self.target(args)?)?.payload)),
+            "links" => {
+                for rel in self.store.get_metadata(&self.target(&[])?)?.relationships {
+                    let position = rel.position.map(|p| format!(" #{}", p)).unwrap_or_default();
+                    println!("{}{} -> {}", rel.rel_type, position, rel.target);
+                }
+            }
+            "follow" if !args.is_empty() => {
+                let targets = self.store.outgoing(&self.target(&[])?, args[0])?;
+                let n: usize = args.get(1).map_or(Ok(0), |n| n.parse())?;
+                match targets.get(n) {
+                    Some((hash, _)) if targets.len() == 1 || args.len() > 1 => self.go(*hash)?,
+                    Some(_) => {
+                        for (i, (hash, envelope)) in targets.iter().enumerate() {
+                            println!("{:>3}  {} {}", i, hash, envelope.type_name.as_deref().unwrap_or(""));
+                        }
+                        println!("{} targets; follow {} <n> to pick one", targets.len(), args[0]);
+                    }
+                    None => println!("no {} target{}", args[0], if args.len() > 1 { format!(" #{}", n) } else { String::new() }),
+                }
+            }
+            "incoming" if !args.is_empty() => {
+                for (hash, envelope) in self.store.incoming(&self.target(&[])?, args[0])? {
+                    println!("{} {}", hash, envelope.type_name.as_deref().unwrap_or(""));
+                }
+            }
+            "back" => match self.trail.pop() {
+                Some(hash) => {
+                    self.current = Some(hash);
+                    println!("{}", hash);
+                }
+                None => println!("nothing to go back to"),
+            },
+            "history" => {
+                for version in self.store.store().history(&self.target(args)?) {
+                    let (hash, envelope) = version?;
+                    let at = envelope.created_at.map(|t| t.to_string()).unwrap_or_default();
+                    println!("{}  {}", hash, at);
+                }
+            }
+            "query" if !args.is_empty() => {
+                let results = self.store.query(&Query::parse(&args.join(" "))?);
+                for hash in &results {
+                    println!("{}", hash);
+                }
+                println!("{} results", results.len());
+            }
+            "refs" => {
+                let mut refs: Vec<_> = self.store.store().refs().collect();
+                refs.sort();
+                for (name, hash) in refs {
+                    println!("ref  {}  {}", hash.short(), name);
+                }
+                for (name, hash) in self.store.store().tags() {
+                    println!("tag  {}  {}", hash.short(), name);
+                }
+            }
+            "refresh" => match self.store.refresh()? {
+                true => println!("{} objects", self.store.len()),
+                false => println!("up to date"),
+            },
+            _ => println!("unknown command or missing argument; type help"),
+        }
+        Ok(())
+    }
+    
+    /// Candidates for `word`, following the text before it
+    fn complete(&self, before: &str, word: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = match before.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => COMMANDS.iter().map(|c| c.to_string()).collect(),
+            ["follow" | "incoming"] => self.current
+                .and_then(|hash| self.store.get_metadata(&hash).ok())
+                .map(|envelope| envelope.relationships.into_iter().map(|rel| rel.rel_type).collect())
+                .unwrap_or_default(),
+            ["query", ..] => Vec::new(),
+            _ => {
+                let store = self.store.store();
+                let names = store.refs().map(|(name, _)| name.to_string())
+                    .chain(store.tags().into_iter().map(|(name, _)| name.to_string()));
+                // Hashes only once a few characters narrow them down
+                let hashes = store.hashes().map(Hash256::to_hex).filter(|hex| word.len() >= 2 && hex.starts_with(word));
+                names.chain(hashes).collect()
+            }
+        };
+        candidates.retain(|c| c.starts_with(word));
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envelope::Envelope;
+    
+    #[test]
+    fn test_complete() {
+        let mut store = IndexedStore::new();
+        let note = Hash256::hash(b"Note");
+        let leaf = store.put(&Envelope::builder(note, b"leaf".to_vec()).build()).unwrap();
+        let root = store.put(&Envelope::builder(note, b"root".to_vec())
+            .relationship("child", leaf)
+            .relationship("cites", leaf)
+            .relationship("tag", leaf)
+            .build()).unwrap();
+        store.set_ref("main", root).unwrap();
+        store.set_ref("mirror", leaf).unwrap();
+        let session = Session { store, current: Some(root), trail: Vec::new() };
+        
+        assert_eq!(session.complete("", "re"), ["refresh", "refs"]);
+        assert_eq!(session.complete("follow ", "c"), ["child", "cites"]);
+        assert_eq!(session.complete("go ", "m"), ["main", "mirror"]);
+        assert!(session.complete("query ", "m").is_empty());
+        // Hashes need a couple of characters first
+        let hex = leaf.to_hex();
+        assert!(session.complete("show ", &hex[..1]).is_empty());
+        assert!(session.complete("show ", &hex[..8]).contains(&hex));
+    }
+}
@@ -0,0 +1,94 @@
+//! Bloom filter over object hashes
+//!
+//! Answers "definitely absent" without touching the objects, which is the
+//! common case when a peer offers hashes during sync. Hashes are already
+//! uniform, so bit positions come straight from their bytes via double
+//! hashing instead of rehashing.
+
+use crate::hash::Hash256;
+use std::f64::consts::LN_2;
+
+impl crate::memory::HeapSize for BloomFilter {
+    fn heap_size(&self) -> usize {
+        self.bits.heap_size()
+    }
+}
+
+/// A fixed-size Bloom filter sized for a capacity and false positive rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    probes: u32,
+    items: usize,
+    capacity: usize,
+}
+
+impl BloomFilter {
+    /// A filter for about `capacity` hashes at `false_positive_rate`
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let bits = (-(capacity as f64) * rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as usize;
+        let probes = ((bits as f64 / capacity as f64) * LN_2).round().max(1.0) as u32;
+        Self { bits: vec![0; bits.div_ceil(64)], probes, items: 0, capacity }
+    }
+    
+    /// Insertions the filter was sized for
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    
+    /// Number of insertions so far
+    pub fn len(&self) -> usize {
+        self.items
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+    
+    pub fn insert(&mut self, hash: &Hash256) {
+        for bit in self.positions(hash).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+    
+    /// False means `hash` was never inserted; true means it probably was
+    pub fn may_contain(&self, hash: &Hash256) -> bool {
+        self.positions(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+    
+    fn positions(&self, hash: &Hash256) -> impl Iterator<Item = usize> + '_ {
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let len = (self.bits.len() * 64) as u64;
+        (0..self.probes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new(1024, 0.01)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        let inserted: Vec<_> = (0..1000u32).map(|i| Hash256::hash(&i.to_le_bytes())).collect();
+        inserted.iter().for_each(|h| filter.insert(h));
+        assert!(inserted.iter().all(|h| filter.may_contain(h)));
+        assert_eq!(filter.len(), 1000);
+        
+        let false_positives = (1000..11000u32)
+            .filter(|i| filter.may_contain(&Hash256::hash(&i.to_le_bytes())))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
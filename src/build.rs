@@ -0,0 +1,222 @@
+//! Incremental index builds
+//!
+//! `build_index` registers a derived field without scanning the store up
+//! front: the field's values are computed a few objects per `build_step`
+//! while queries keep using the current index, which doesn't have the
+//! field yet. Puts and removals update the values too, so when the
+//! backlog runs out they're current; they're then posted into the live
+//! index, and the field is ready. Only the field's postings change, so
+//! views, text fields and field options set up meanwhile are kept.
+//! `build_in_background` steps a build from its own thread.
+
+use crate::envelope::{Envelope, IndexValue};
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::{IndexExtractor, IndexedStore};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// A derived field being computed for the objects stored when it started
+pub(crate) struct IndexBuild {
+    name: String,
+    extractor: IndexExtractor,
+    /// The field's value for each object computed so far
+    values: HashMap<Hash256, IndexValue>,
+    /// Objects still to add, and the same as a set for skipping ones
+    /// whose puts or removals overtook the build
+    backlog: Vec<Hash256>,
+    pending: HashSet<Hash256>,
+    total: usize,
+}
+
+impl std::fmt::Debug for IndexBuild {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexBuild")
+            .field("name", &self.name)
+            .field("values", &self.values.len())
+            .field("pending", &self.pending.len())
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+impl IndexBuild {
+    /// Compute the field for a put
+    pub(crate) fn add(&mut self, hash: Hash256, envelope: &Envelope) {
+        self.pending.remove(&hash);
+        if let Some(value) = (self.extractor)(envelope) {
+            self.values.insert(hash, value);
+        }
+    }
+    
+    /// Forget a removed object, whether or not the build reached it
+    pub(crate) fn remove(&mut self, hash: &Hash256) {
+        self.pending.remove(hash);
+        self.values.remove(hash);
+    }
+}
+
+impl IndexedStore {
+    /// Start building a derived field (see `register_index`) incrementally
+    ///
+    /// Queries don't see the field until `index_ready(name)`; call
+    /// `build_step` to make progress. Any build already running is
+    /// finished first.
+    pub fn build_index<F>(&mut self, name: impl Into<String>, extractor: F) -> Result<()>
+    where
+        F: Fn(&Envelope) -> Option<IndexValue> + Send + Sync + 'static,
+    {
+        self.finish_build()?;
+        let backlog: Vec<Hash256> = self.store().hashes().copied().collect();
+        *self.building_mut() = Some(IndexBuild {
+            name: name.into(),
+            extractor: Arc::new(extractor),
+            values: HashMap::new(),
+            pending: backlog.iter().copied().collect(),
+            total: backlog.len(),
+            backlog,
+        });
+        self.build_step(0).map(|_| ())
+    }
+    
+    /// Index up to `max_objects` more objects for the running build;
+    /// returns whether it's finished (or none was running)
+    pub fn build_step(&mut self, max_objects: usize) -> Result<bool> {
+        let Some(mut build) = self.building_mut().take() else {
+            return Ok(true);
+        };
+        let mut done = 0;
+        while done < max_objects {
+            let Some(hash) = build.backlog.pop() else {
+                break;
+            };
+            if build.pending.contains(&hash) {
+                let envelope = match self.store().get(&hash) {
+                    Ok(envelope) => envelope,
+                    Err(e) => {
+                        build.backlog.push(hash);
+                        *self.building_mut() = Some(build);
+                        return Err(e);
+                    }
+                };
+                build.add(hash, &envelope);
+                done += 1;
+            }
+        }
+        if build.pending.is_empty() {
+            self.index_mut().install_extractor(build.name, build.extractor, build.values);
+            return Ok(true);
+        }
+        *self.building_mut() = Some(build);
+        Ok(false)
+    }
+    
+    /// Run the current build, if any, to completion
+    pub fn finish_build(&mut self) -> Result<()> {
+        while !self.build_step(usize::MAX)? {}
+        Ok(())
+    }
+    
+    /// Step the running build from a new thread, `batch` objects per lock
+    ///
+    /// The lock is released between batches so other threads can read
+    /// and write meanwhile. The thread ends when the build finishes (or
+    /// none was running), or fails. Needs a target with threads (not
+    /// wasm32-unknown-unknown).
+    pub fn build_in_background(store: &Arc<Mutex<Self>>, batch: usize) -> JoinHandle<Result<()>> {
+        let store = Arc::clone(store);
+        std::thread::spawn(move || loop {
+            let mut store = store.lock().map_err(|_| Error::Storage("store lock poisoned".into()))?;
+            if store.build_step(batch.max(1))? {
+                return Ok(());
+            }
+            drop(store);
+            std::thread::yield_now();
+        })
+    }
+    
+    /// Objects indexed so far and in total, while a build is running
+    pub fn build_progress(&self) -> Option<(usize, usize)> {
+        self.building().map(|build| (build.total - build.pending.len(), build.total))
+    }
+    
+    /// Whether a derived field is registered and fully built
+    pub fn index_ready(&self, name: &str) -> bool {
+        self.index().has_extractor(name) && self.building().is_none_or(|build| build.name != name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    
+    #[test]
+    fn test_incremental_index_build() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let mut hashes: Vec<_> = (0..10i64)
+            .map(|n| store.put(&Envelope::builder(post, vec![]).index("n", n).build()).unwrap())
+            .collect();
+        let parity = |e: &Envelope| match e.index.get("n") {
+            Some(IndexValue::Int64(n)) => Some(IndexValue::from(if n % 2 == 0 { "even" } else { "odd" })),
+            _ => None,
+        };
+        
+        store.build_index("parity", parity).unwrap();
+        assert!(!store.index_ready("parity"));
+        assert!(!store.build_step(4).unwrap());
+        assert_eq!(store.build_progress(), Some((4, 10)));
+        // Not served until ready
+        assert!(store.query_by_field("parity", "even").is_empty());
+        
+        // Writes during the build are picked up
+        hashes.push(store.put(&Envelope::builder(post, vec![]).index("n", 10i64).build()).unwrap());
+        store.delete(&hashes[0], false).unwrap();
+        assert!(store.build_step(100).unwrap());
+        assert!(store.index_ready("parity"));
+        assert_eq!(store.build_progress(), None);
+        
+        let mut even: Vec<_> = [2, 4, 6, 8, 10].iter().map(|i| hashes[*i]).collect();
+        even.sort();
+        let mut found = store.query_by_field("parity", "even");
+        found.sort();
+        assert_eq!(found, even);
+        assert_eq!(store.query_by_field("parity", "odd").len(), 5);
+    }
+    
+    #[test]
+    fn test_background_build_keeps_config() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        for n in 0..50i64 {
+            store.put(&Envelope::builder(post, vec![]).index("n", n).index("title", "hello world").build()).unwrap();
+        }
+        let big = |e: &Envelope| match e.index.get("n") {
+            Some(IndexValue::Int64(n)) if *n >= 40 => Some(IndexValue::from("yes")),
+            _ => None,
+        };
+        store.build_index("big", big).unwrap();
+        store.build_step(10).unwrap();
+        // Set up while the build runs, and kept when it finishes
+        store.create_view("big", Query::field_eq("big", "yes"));
+        store.enable_text_index("title").unwrap();
+        assert!(store.index_ready("big"));
+        
+        store.build_index("small", |e: &Envelope| match e.index.get("n") {
+            Some(IndexValue::Int64(n)) if *n < 5 => Some(IndexValue::from("yes")),
+            _ => None,
+        }).unwrap();
+        store.create_view("small", Query::field_eq("small", "yes"));
+        let store = Arc::new(Mutex::new(store));
+        IndexedStore::build_in_background(&store, 7).join().unwrap().unwrap();
+        let store = store.lock().unwrap();
+        assert!(store.index_ready("small"));
+        assert_eq!(store.view("big").map(HashSet::len), Some(10));
+        assert_eq!(store.view("small").map(HashSet::len), Some(5));
+        assert!(store.index().text().is_enabled("title"));
+        assert_eq!(store.query_by_field("big", "yes").len(), 10);
+    }
+}
@@ -0,0 +1,106 @@
+//! Time sources for envelope timestamps
+//!
+//! Timestamps are Unix seconds, matching `Envelope::created_at`.
+//!
+//! `wasm32-unknown-unknown` has no system clock: there `SystemClock`
+//! reads the JavaScript clock with the `js` feature, and panics without
+//! it, so pass a `Clock` of your own to `created_at_from` instead.
+//!
+//! Durations (latency metrics, span timings, sync throttling) are
+//! measured with a `Stopwatch`, which reads the JavaScript clock there
+//! too, and measures nothing without the `js` feature rather than
+//! panicking. Such a target can't block either, so `sleep` returns at
+//! once, and throttled pulls aren't slowed down.
+
+use std::time::Duration;
+
+/// A source of the current time
+pub trait Clock {
+    /// Current time as Unix seconds
+    fn now(&self) -> i64;
+}
+
+/// The system wall clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+    fn now(&self) -> i64 {
+        (js_sys::Date::now() / 1000.0) as i64
+    }
+    
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown", feature = "js")))]
+    fn now(&self) -> i64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0)
+    }
+}
+
+/// Elapsed time since it was started, from a monotonic clock where
+/// there is one
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    started: std::time::Instant,
+    /// Milliseconds on the JavaScript clock
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+    started: f64,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            started: std::time::Instant::now(),
+            #[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+            started: js_sys::Date::now(),
+        }
+    }
+    
+    /// Time since `start`; zero with no clock to read
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        return self.started.elapsed();
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+        return Duration::from_secs_f64((js_sys::Date::now() - self.started).max(0.0) / 1000.0);
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown", not(feature = "js")))]
+        return Duration::ZERO;
+    }
+}
+
+/// Block the thread for `duration`, where threads can block
+pub(crate) fn sleep(duration: Duration) {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    std::thread::sleep(duration);
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    let _ = duration;
+}
+
+/// A clock frozen at a given time, for deterministic tests
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub i64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_stopwatch() {
+        let stopwatch = Stopwatch::start();
+        sleep(Duration::from_millis(20));
+        let first = stopwatch.elapsed();
+        assert!(first >= Duration::from_millis(20));
+        assert!(stopwatch.elapsed() >= first);
+        assert_eq!(FixedClock(42).now(), 42);
+    }
+}
@@ -0,0 +1,329 @@
+//! Differences between envelope versions
+
+use crate::envelope::{Envelope, EnvelopeBuilder, IndexValue, Relationship, Strength};
+use crate::hash::Hash256;
+use std::collections::HashMap;
+
+/// An edge whose type stayed the same but whose target changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retarget {
+    pub rel_type: String,
+    pub from: Hash256,
+    pub to: Hash256,
+}
+
+/// An edge that kept its type, position and target but whose properties
+/// or strength changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertiesChange {
+    pub rel_type: String,
+    pub target: Hash256,
+    pub position: Option<u32>,
+    /// The newer version's properties
+    pub properties: HashMap<String, IndexValue>,
+    /// The newer version's strength
+    pub strength: Strength,
+}
+
+/// Relationship changes from one envelope version to another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelationshipDiff {
+    /// Edges only in the newer version
+    pub added: Vec<Relationship>,
+    /// Edges only in the older version
+    pub removed: Vec<Relationship>,
+    /// Edges of the same type (and position) pointing somewhere new
+    pub retargeted: Vec<Retarget>,
+    /// Edges still pointing at the same target with new properties
+    pub properties_changed: Vec<PropertiesChange>,
+}
+
+impl RelationshipDiff {
+    /// Whether the two versions have identical relationships
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.retargeted.is_empty() && self.properties_changed.is_empty()
+    }
+    
+    /// Targets whose reverse-index entries are affected by this change
+    pub fn affected_targets(&self) -> Vec<Hash256> {
+        let mut targets: Vec<_> = self.added.iter().chain(&self.removed)
+            .map(|r| r.target)
+            .chain(self.retargeted.iter().flat_map(|r| [r.from, r.to]))
+            .chain(self.properties_changed.iter().map(|c| c.target))
+            .collect();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+}
+
+/// Metadata and payload changes from one envelope version to another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvelopeDiff {
+    /// New type hash, if it changed
+    pub type_hash: Option<Hash256>,
+    /// New type name, if it changed
+    pub type_name: Option<Option<String>>,
+    /// New payload content type, if it changed
+    pub content_type: Option<Option<String>>,
+    /// Index fields added or changed
+    pub index_set: HashMap<String, IndexValue>,
+    /// Index fields removed
+    pub index_removed: Vec<String>,
+    /// Extension sections added or changed
+    pub extensions_set: HashMap<String, Vec<u8>>,
+    /// Extension sections removed
+    pub extensions_removed: Vec<String>,
+    /// New validity period start, if it changed
+    pub valid_from: Option<Option<i64>>,
+    /// New validity period end, if it changed
+    pub valid_to: Option<Option<i64>>,
+    /// Relationship changes
+    pub relationships: RelationshipDiff,
+    /// New payload, if it changed
+    pub payload: Option<Vec<u8>>,
+}
+
+impl EnvelopeDiff {
+    /// Whether the two versions carry the same content
+    pub fn is_empty(&self) -> bool {
+        *self == EnvelopeDiff::default()
+    }
+    
+    /// Replay these changes onto `base`
+    ///
+    /// The builder's `previous` is `base`'s hash; `created_at` and
+    /// `created_by` are left for the caller, as with `Envelope::derive`.
+    pub fn apply(&self, base: &Envelope) -> EnvelopeBuilder {
+        let mut next = base.clone();
+        if let Some(type_hash) = self.type_hash {
+            next.type_hash = type_hash;
+        }
+        if let Some(type_name) = &self.type_name {
+            next.type_name = type_name.clone();
+        }
+        if let Some(content_type) = &self.content_type {
+            next.content_type = content_type.clone();
+        }
+        if let Some(valid_from) = self.valid_from {
+            next.valid_from = valid_from;
+        }
+        if let Some(valid_to) = self.valid_to {
+            next.valid_to = valid_to;
+        }
+        for key in &self.index_removed {
+            next.index.remove(key);
+        }
+        next.index.extend(self.index_set.clone());
+        for key in &self.extensions_removed {
+            next.extensions.remove(key);
+        }
+        next.extensions.extend(self.extensions_set.clone());
+        
+        let rels = &self.relationships;
+        for removed in &rels.removed {
+            if let Some(i) = next.relationships.iter().position(|r| r == removed) {
+                next.relationships.remove(i);
+            }
+        }
+        for retarget in &rels.retargeted {
+            let edge = next.relationships.iter_mut()
+                .find(|r| r.rel_type == retarget.rel_type && r.target == retarget.from);
+            if let Some(edge) = edge {
+                edge.target = retarget.to;
+            }
+        }
+        for change in &rels.properties_changed {
+            let edge = next.relationships.iter_mut().find(|r| {
+                r.rel_type == change.rel_type && r.target == change.target && r.position == change.position
+            });
+            if let Some(edge) = edge {
+                edge.properties = change.properties.clone();
+                edge.strength = change.strength;
+            }
+        }
+        next.relationships.extend(rels.added.iter().cloned());
+        
+        if let Some(payload) = &self.payload {
+            next.payload = payload.clone();
+        }
+        
+        next.derive().previous(base.hash())
+    }
+}
+
+impl Envelope {
+    /// Compare this envelope with a newer version
+    ///
+    /// Covers everything `apply` can replay: type, content type, validity, index,
+    /// extensions, relationships and payload. Version metadata (`previous`,
+    /// `created_at`, ...) is not part of the diff.
+    pub fn diff(&self, newer: &Envelope) -> EnvelopeDiff {
+        let (index_set, index_removed) = map_diff(&self.index, &newer.index);
+        let (extensions_set, extensions_removed) = map_diff(&self.extensions, &newer.extensions);
+        EnvelopeDiff {
+            type_hash: (self.type_hash != newer.type_hash).then_some(newer.type_hash),
+            type_name: (self.type_name != newer.type_name).then(|| newer.type_name.clone()),
+            content_type: (self.content_type != newer.content_type).then(|| newer.content_type.clone()),
+            valid_from: (self.valid_from != newer.valid_from).then_some(newer.valid_from),
+            valid_to: (self.valid_to != newer.valid_to).then_some(newer.valid_to),
+            index_set,
+            index_removed,
+            extensions_set,
+            extensions_removed,
+            relationships: self.diff_relationships(newer),
+            payload: (self.payload != newer.payload).then(|| newer.payload.clone()),
+        }
+    }
+    
+    /// Compare this envelope's relationships with a newer version's
+    ///
+    /// A removed and an added edge of the same type and position are
+    /// reported as a single properties change if they share a target,
+    /// and as a retarget otherwise.
+    pub fn diff_relationships(&self, newer: &Envelope) -> RelationshipDiff {
+        let mut removed = multiset_difference(&self.relationships, &newer.relationships);
+        let mut added = multiset_difference(&newer.relationships, &self.relationships);
+        
+        let mut retargeted = Vec::new();
+        let mut properties_changed = Vec::new();
+        removed.retain(|old| {
+            let same_slot = |new: &Relationship| new.rel_type == old.rel_type && new.position == old.position;
+            let pair = added.iter().position(|new| same_slot(new) && new.target == old.target)
+                .or_else(|| added.iter().position(same_slot));
+            match pair {
+                Some(i) => {
+                    let new = added.remove(i);
+                    if new.target == old.target {
+                        properties_changed.push(PropertiesChange {
+                            rel_type: new.rel_type,
+                            target: new.target,
+                            position: new.position,
+                            properties: new.properties,
+                            strength: new.strength,
+                        });
+                    } else {
+                        retargeted.push(Retarget {
+                            rel_type: old.rel_type.clone(),
+                            from: old.target,
+                            to: new.target,
+                        });
+                    }
+                    false
+                }
+                None => true,
+            }
+        });
+        
+        RelationshipDiff { added, removed, retargeted, properties_changed }
+    }
+}
+
+/// Entries added or changed in `newer`, and keys it no longer has (sorted)
+fn map_diff<V: Clone + PartialEq>(old: &HashMap<String, V>, newer: &HashMap<String, V>) -> (HashMap<String, V>, Vec<String>) {
+    let set = newer.iter()
+        .filter(|(k, v)| old.get(*k) != Some(*v))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    let mut removed: Vec<String> = old.keys()
+        .filter(|k| !newer.contains_key(*k))
+        .cloned()
+        .collect();
+    removed.sort();
+    (set, removed)
+}
+
+/// Edges in `a` not matched one-for-one by an equal edge in `b`
+fn multiset_difference(a: &[Relationship], b: &[Relationship]) -> Vec<Relationship> {
+    let mut unmatched: Vec<&Relationship> = b.iter().collect();
+    a.iter()
+        .filter(|rel| match unmatched.iter().position(|other| other == rel) {
+            Some(i) => {
+                unmatched.swap_remove(i);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_diff_relationships() {
+        let post_type = Hash256::hash(b"Post");
+        let alice = Hash256::hash(b"alice");
+        let bob = Hash256::hash(b"bob");
+        let rust = Hash256::hash(b"rust");
+        let wasm = Hash256::hash(b"wasm");
+        
+        let v1 = Envelope::builder(post_type, vec![])
+            .relationship("author", alice)
+            .relationship("tag", rust)
+            .build();
+        let v2 = v1.derive()
+            .remove_relationships("author")
+            .relationship("author", bob)
+            .relationship("tag", wasm)
+            .build();
+        
+        let diff = v1.diff_relationships(&v2);
+        assert_eq!(diff.retargeted, vec![Retarget { rel_type: "author".into(), from: alice, to: bob }]);
+        assert_eq!(diff.added, vec![Relationship::new("tag", wasm)]);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.affected_targets().len(), 3);
+        
+        assert!(v2.diff_relationships(&v2).is_empty());
+        
+        // New properties on the same edge aren't a retarget
+        let v3 = v2.derive()
+            .remove_relationships("author")
+            .relationships([Relationship::new("author", bob).with_property("role", "editor")])
+            .build();
+        let diff = v2.diff_relationships(&v3);
+        assert!(diff.retargeted.is_empty() && diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.properties_changed.len(), 1);
+        assert_eq!(diff.properties_changed[0].target, bob);
+        assert_eq!(diff.properties_changed[0].properties.get("role"), Some(&"editor".into()));
+        let replayed = v2.diff(&v3).apply(&v2).build();
+        assert!(v3.diff(&replayed).is_empty());
+    }
+    
+    #[test]
+    fn test_diff_apply_onto_branch() {
+        let post_type = Hash256::hash(b"Post");
+        let alice = Hash256::hash(b"alice");
+        let bob = Hash256::hash(b"bob");
+        
+        let v1 = Envelope::builder(post_type, b"body".to_vec())
+            .index("title", "Draft")
+            .index("obsolete", true)
+            .relationship("author", alice)
+            .build();
+        let v2 = v1.derive()
+            .index("title", "Final")
+            .remove_index("obsolete")
+            .remove_relationships("author")
+            .relationship("author", bob)
+            .build();
+        let diff = v1.diff(&v2);
+        assert_eq!(diff.index_removed, vec!["obsolete".to_string()]);
+        assert_eq!(diff.payload, None);
+        
+        // Replaying onto v1 itself reproduces v2's content
+        let replayed = diff.apply(&v1).build();
+        assert!(v2.diff(&replayed).is_empty());
+        assert_eq!(replayed.previous, Some(v1.hash()));
+        
+        // Replaying onto a divergent branch keeps the branch's own changes
+        let branch = v1.derive().payload(b"edited body".to_vec()).build();
+        let rebased = diff.apply(&branch).build();
+        assert_eq!(rebased.payload, b"edited body");
+        assert_eq!(rebased.index.get("title"), Some(&"Final".into()));
+        assert_eq!(rebased.relationships, vec![Relationship::new("author", bob)]);
+        assert_eq!(rebased.previous, Some(branch.hash()));
+    }
+}
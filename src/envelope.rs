@@ -1,16 +1,45 @@
 //! Core envelope types and builder
 
 use crate::hash::Hash256;
-use crate::error::Error;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{Error, ValidationError, Violation};
+use crate::wire::{self, Reader};
+use crate::Result;
 use std::collections::HashMap;
 
+/// Index keys starting with this prefix are reserved for the crate
+pub const RESERVED_KEY_PREFIX: &str = "envelope/";
+
+/// Maximum length in bytes of index keys, relationship types and type names
+pub const MAX_NAME_LEN: usize = 256;
+
+/// Maximum length in bytes of a string index value
+pub const MAX_STRING_VALUE_LEN: usize = 64 * 1024;
+
+/// Whether a relationship keeps its target alive
+///
+/// Reachability walks (closure, garbage collection) follow strong edges
+/// only, so weak "see also" links don't pin whole subgraphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strength {
+    #[default]
+    Strong,
+    Weak,
+}
+
 /// A relationship to another envelope
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Relationship {
     /// Type of relationship (e.g., "author", "parent", "contains")
     pub rel_type: String,
     /// Target envelope hash
     pub target: Hash256,
+    /// Edge properties (e.g., "weight", "since", "role")
+    pub properties: HashMap<String, IndexValue>,
+    /// Position among edges of the same type, for ordered edges like "chapter"
+    pub position: Option<u32>,
+    /// Whether the edge keeps its target reachable
+    pub strength: Strength,
 }
 
 impl Relationship {
@@ -18,19 +47,315 @@ impl Relationship {
         Self {
             rel_type: rel_type.into(),
             target,
+            properties: HashMap::new(),
+            position: None,
+            strength: Strength::Strong,
+        }
+    }
+    
+    /// Create a weak relationship, which doesn't keep its target reachable
+    pub fn weak(rel_type: impl Into<String>, target: Hash256) -> Self {
+        Self {
+            strength: Strength::Weak,
+            ..Self::new(rel_type, target)
+        }
+    }
+    
+    /// Whether reachability walks follow this edge
+    pub fn is_strong(&self) -> bool {
+        self.strength == Strength::Strong
+    }
+    
+    /// Set the edge's position among edges of the same type
+    pub fn with_position(mut self, position: u32) -> Self {
+        self.position = Some(position);
+        self
+    }
+    
+    /// Attach a property to the edge
+    pub fn with_property(mut self, key: impl Into<String>, value: impl Into<IndexValue>) -> Self {
+        self.properties.insert(key.into(), value.into());
+        self
+    }
+    
+    /// Append the canonical encoding (properties sorted by key)
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        wire::put_str(buf, &self.rel_type);
+        wire::put_hash(buf, &self.target);
+        match self.position {
+            Some(position) => {
+                buf.push(1);
+                wire::put_u32(buf, position);
+            }
+            None => buf.push(0),
+        }
+        buf.push(match self.strength {
+            Strength::Strong => 0,
+            Strength::Weak => 1,
+        });
+        
+        let mut props: Vec<_> = self.properties.iter().collect();
+        props.sort_by_key(|(k, _)| *k);
+        wire::put_u32(buf, props.len() as u32);
+        for (key, value) in props {
+            wire::put_str(buf, key);
+            value.encode(buf);
+        }
+    }
+    
+    /// Decode a relationship written by `encode`
+    pub(crate) fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let rel_type = reader.section("type", Reader::string)?;
+        let target = reader.section("target", Reader::hash)?;
+        let mut rel = Relationship::new(rel_type, target);
+        if reader.section("position", Reader::u8)? != 0 {
+            rel.position = Some(reader.section("position", Reader::u32)?);
+        }
+        if reader.section("strength", Reader::u8)? != 0 {
+            rel.strength = Strength::Weak;
+        }
+        let prop_count = reader.section("property count", Reader::u32)? as usize;
+        for i in 0..prop_count {
+            let (key, value) = reader.item("property", i, |reader| {
+                Ok((reader.section("key", Reader::string)?, reader.section("value", IndexValue::decode)?))
+            })?;
+            rel.properties.insert(key, value);
         }
+        Ok(rel)
+    }
+}
+
+impl<S: Into<String>> From<(S, Hash256)> for Relationship {
+    fn from((rel_type, target): (S, Hash256)) -> Self {
+        Relationship::new(rel_type, target)
+    }
+}
+
+/// A WGS84 location, in decimal degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+}
+
+/// `-0.0` as `0.0`: they compare equal, so they must encode (and hash) the same
+fn canonical_zero(v: f64) -> f64 {
+    if v == 0.0 { 0.0 } else { v }
+}
+
+/// Exact order of an integer and a float, NaN above every integer (below, if negative)
+fn int_float_cmp(int: i128, float: f64) -> std::cmp::Ordering {
+    use std::cmp::Ordering::*;
+    if float.is_nan() {
+        return if float.is_sign_negative() { Greater } else { Less };
+    }
+    // Beyond i128's range the float wins outright
+    if float >= 2f64.powi(127) {
+        return Less;
+    }
+    if float < -(2f64.powi(127)) {
+        return Greater;
     }
+    let whole = float.trunc();
+    int.cmp(&(whole as i128)).then(if float > whole { Less } else if float < whole { Greater } else { Equal })
 }
 
 /// Value types for index fields
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum IndexValue {
     String(String),
     Int64(i64),
+    /// 128-bit integer for large IDs and fixed-point amounts (in minor units)
+    Int128(i128),
     Float64(f64),
     Bool(bool),
     Hash(Hash256),
     Timestamp(i64),
+    /// RFC 4122 UUID, stored as its 16 raw bytes
+    Uuid([u8; 16]),
+    GeoPoint(GeoPoint),
+    /// Explicitly present but empty, as opposed to a missing field
+    Null,
+}
+
+impl IndexValue {
+    const TAG_STRING: u8 = 0;
+    const TAG_INT64: u8 = 1;
+    const TAG_FLOAT64: u8 = 2;
+    const TAG_BOOL: u8 = 3;
+    const TAG_HASH: u8 = 4;
+    const TAG_TIMESTAMP: u8 = 5;
+    const TAG_NULL: u8 = 6;
+    const TAG_INT128: u8 = 7;
+    const TAG_UUID: u8 = 8;
+    const TAG_GEO_POINT: u8 = 9;
+    
+    /// Name of the variant, e.g. `string` or `int64`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IndexValue::String(_) => "string",
+            IndexValue::Int64(_) => "int64",
+            IndexValue::Int128(_) => "int128",
+            IndexValue::Float64(_) => "float64",
+            IndexValue::Bool(_) => "bool",
+            IndexValue::Hash(_) => "hash",
+            IndexValue::Timestamp(_) => "timestamp",
+            IndexValue::Uuid(_) => "uuid",
+            IndexValue::GeoPoint(_) => "geo_point",
+            IndexValue::Null => "null",
+        }
+    }
+    
+    /// Append the canonical (tagged) encoding of this value
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            IndexValue::String(s) => {
+                buf.push(Self::TAG_STRING);
+                wire::put_str(buf, s);
+            }
+            IndexValue::Int64(v) => {
+                buf.push(Self::TAG_INT64);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            IndexValue::Int128(v) => {
+                // Big-endian with the sign bit flipped, so byte order matches numeric order
+                buf.push(Self::TAG_INT128);
+                buf.extend_from_slice(&((*v as u128) ^ (1 << 127)).to_be_bytes());
+            }
+            IndexValue::Float64(v) => {
+                buf.push(Self::TAG_FLOAT64);
+                buf.extend_from_slice(&canonical_zero(*v).to_le_bytes());
+            }
+            IndexValue::Bool(v) => {
+                buf.push(Self::TAG_BOOL);
+                buf.push(*v as u8);
+            }
+            IndexValue::Hash(h) => {
+                buf.push(Self::TAG_HASH);
+                wire::put_hash(buf, h);
+            }
+            IndexValue::Timestamp(v) => {
+                buf.push(Self::TAG_TIMESTAMP);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            IndexValue::Uuid(bytes) => {
+                buf.push(Self::TAG_UUID);
+                buf.extend_from_slice(bytes);
+            }
+            IndexValue::GeoPoint(p) => {
+                buf.push(Self::TAG_GEO_POINT);
+                buf.extend_from_slice(&canonical_zero(p.lat).to_le_bytes());
+                buf.extend_from_slice(&canonical_zero(p.lon).to_le_bytes());
+            }
+            IndexValue::Null => buf.push(Self::TAG_NULL),
+        }
+    }
+    
+    /// Order two values for range comparisons
+    ///
+    /// Numbers compare with numbers whatever their width, strings with
+    /// strings, and so on; values of unrelated kinds have no order.
+    pub(crate) fn compare(&self, other: &IndexValue) -> Option<std::cmp::Ordering> {
+        use IndexValue::*;
+        match (self, other) {
+            (String(a), String(b)) => Some(a.cmp(b)),
+            (Bool(a), Bool(b)) => Some(a.cmp(b)),
+            (Hash(a), Hash(b)) => Some(a.cmp(b)),
+            (Uuid(a), Uuid(b)) => Some(a.cmp(b)),
+            (Float64(v), _) | (_, Float64(v)) if v.is_nan() => None,
+            (a, b) => a.numeric_cmp(b),
+        }
+    }
+    
+    /// Total order on numbers, exact across widths
+    ///
+    /// Integers above 2^53 keep their precision against floats, `-0.0`
+    /// equals `0.0`, and NaN sorts above every other number (below, if
+    /// its sign is negative). `None` unless both values are numbers.
+    pub(crate) fn numeric_cmp(&self, other: &IndexValue) -> Option<std::cmp::Ordering> {
+        use IndexValue::*;
+        Some(match (self, other) {
+            (Float64(a), Float64(b)) => canonical_zero(*a).total_cmp(&canonical_zero(*b)),
+            (Float64(a), b) => int_float_cmp(b.as_i128()?, *a).reverse(),
+            (a, Float64(b)) => int_float_cmp(a.as_i128()?, *b),
+            (a, b) => a.as_i128()?.cmp(&b.as_i128()?),
+        })
+    }
+    
+    /// Whether this is a number (`Int64`, `Int128`, `Float64` or `Timestamp`)
+    pub(crate) fn is_numeric(&self) -> bool {
+        self.as_f64().is_some()
+    }
+    
+    fn as_i128(&self) -> Option<i128> {
+        match self {
+            IndexValue::Int64(v) | IndexValue::Timestamp(v) => Some(*v as i128),
+            IndexValue::Int128(v) => Some(*v),
+            _ => None,
+        }
+    }
+    
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            IndexValue::Float64(v) => Some(*v),
+            other => other.as_i128().map(|v| v as f64),
+        }
+    }
+    
+    /// Decode a value written by `encode`
+    pub(crate) fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let value = match reader.u8()? {
+            Self::TAG_STRING => IndexValue::String(reader.string()?),
+            Self::TAG_INT64 => IndexValue::Int64(reader.i64()?),
+            Self::TAG_INT128 => {
+                let bits = u128::from_be_bytes(reader.take(16)?.try_into().unwrap());
+                IndexValue::Int128((bits ^ (1 << 127)) as i128)
+            }
+            Self::TAG_FLOAT64 => IndexValue::Float64(reader.f64()?),
+            Self::TAG_BOOL => IndexValue::Bool(reader.u8()? != 0),
+            Self::TAG_HASH => IndexValue::Hash(reader.hash()?),
+            Self::TAG_TIMESTAMP => IndexValue::Timestamp(reader.i64()?),
+            Self::TAG_UUID => IndexValue::Uuid(reader.take(16)?.try_into().unwrap()),
+            Self::TAG_GEO_POINT => {
+                let lat = reader.f64()?;
+                let lon = reader.f64()?;
+                IndexValue::GeoPoint(GeoPoint::new(lat, lon))
+            }
+            Self::TAG_NULL => IndexValue::Null,
+            tag => {
+                return Err(reader.error(format!(
+                    "unknown index value tag {} at offset {}", tag, reader.offset() - 1
+                )))
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Plain rendering for labels and exports: strings unquoted, hashes in hex
+impl std::fmt::Display for IndexValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexValue::String(v) => f.write_str(v),
+            IndexValue::Int64(v) | IndexValue::Timestamp(v) => write!(f, "{}", v),
+            IndexValue::Int128(v) => write!(f, "{}", v),
+            IndexValue::Float64(v) => write!(f, "{}", v),
+            IndexValue::Bool(v) => write!(f, "{}", v),
+            IndexValue::Hash(h) => write!(f, "{}", h),
+            IndexValue::Uuid(b) => {
+                let hex = hex::encode(b);
+                write!(f, "{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+            }
+            IndexValue::GeoPoint(p) => write!(f, "{},{}", p.lat, p.lon),
+            IndexValue::Null => f.write_str("null"),
+        }
+    }
 }
 
 impl From<&str> for IndexValue {
@@ -51,6 +376,12 @@ impl From<i64> for IndexValue {
     }
 }
 
+impl From<i128> for IndexValue {
+    fn from(v: i128) -> Self {
+        IndexValue::Int128(v)
+    }
+}
+
 impl From<f64> for IndexValue {
     fn from(v: f64) -> Self {
         IndexValue::Float64(v)
@@ -69,65 +400,282 @@ impl From<Hash256> for IndexValue {
     }
 }
 
+impl From<GeoPoint> for IndexValue {
+    fn from(v: GeoPoint) -> Self {
+        IndexValue::GeoPoint(v)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for IndexValue {
+    fn from(v: uuid::Uuid) -> Self {
+        IndexValue::Uuid(v.into_bytes())
+    }
+}
+
 /// An envelope wrapping a zero-copy payload
 #[derive(Debug, Clone)]
 pub struct Envelope {
     /// Hash of the type schema
     pub type_hash: Hash256,
-    /// Human-readable type name (optional)
+    /// Human-readable type name (optional); not hashed, so the first
+    /// stored copy of some content keeps its name
     pub type_name: Option<String>,
+    /// MIME type of the payload, e.g. `application/flatbuffers; schema=BlogPost`
+    pub content_type: Option<String>,
     /// Outgoing relationships
     pub relationships: Vec<Relationship>,
     /// Index fields for queries
     pub index: HashMap<String, IndexValue>,
     /// Previous version (for version chain)
     pub previous: Option<Hash256>,
+    /// Additional predecessors merged into this version
+    pub merge_parents: Vec<Hash256>,
     /// Creation timestamp
     pub created_at: Option<i64>,
+    /// Identity envelope of the writer that created this version
+    pub created_by: Option<Hash256>,
+    /// Start of the business-time period this version describes (inclusive)
+    pub valid_from: Option<i64>,
+    /// End of the business-time period this version describes (exclusive)
+    pub valid_to: Option<i64>,
+    /// Application-defined metadata sections (signatures, encryption headers, ...)
+    pub extensions: HashMap<String, Vec<u8>>,
     /// The payload bytes
     pub payload: Vec<u8>,
 }
 
+/// Why an index value or edge property can't be stored or compared
+/// reliably, if it can't
+fn value_problem(value: &IndexValue) -> Option<String> {
+    match value {
+        IndexValue::String(v) if v.len() > MAX_STRING_VALUE_LEN => {
+            Some(format!("value longer than {} bytes", MAX_STRING_VALUE_LEN))
+        }
+        IndexValue::Float64(v) if v.is_nan() => Some("NaN breaks hash determinism".into()),
+        IndexValue::GeoPoint(p) if p.lat.is_nan() || p.lon.is_nan() => Some("NaN breaks hash determinism".into()),
+        _ => None,
+    }
+}
+
 impl Envelope {
+    /// `validate`, plus the rules `store` enforces on put: allowed
+    /// relationships and unique fields (see `IndexedStore::declare_unique`)
+    ///
+    /// Reports what a strict put into `store` would reject, without
+    /// putting anything.
+    pub fn validate_in(&self, store: &crate::index::IndexedStore) -> std::result::Result<(), ValidationError> {
+        ValidationError::check(store.violations(self))
+    }
+    
+    /// Check for metadata that can't be stored or hashed reliably,
+    /// reporting every problem rather than the first
+    ///
+    /// Only the envelope itself is checked; rules that depend on what's
+    /// already stored, like unique fields, need `validate_in`.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        let mut violations = Vec::new();
+        let mut violation = |field: String, message: String| violations.push(Violation { field, message });
+        
+        if let Some(name) = &self.type_name {
+            if name.len() > MAX_NAME_LEN {
+                violation("type_name".into(), format!("longer than {} bytes", MAX_NAME_LEN));
+            }
+        }
+        
+        if let Some(content_type) = &self.content_type {
+            if content_type.is_empty() {
+                violation("content_type".into(), "empty".into());
+            }
+            if content_type.len() > MAX_NAME_LEN {
+                violation("content_type".into(), format!("longer than {} bytes", MAX_NAME_LEN));
+            }
+        }
+        
+        for (i, rel) in self.relationships.iter().enumerate() {
+            if rel.rel_type.is_empty() {
+                violation(format!("relationship {}", i), "empty rel_type".into());
+            }
+            if rel.rel_type.len() > MAX_NAME_LEN {
+                violation(format!("relationship '{}'", rel.rel_type), format!("rel_type longer than {} bytes", MAX_NAME_LEN));
+            }
+            let mut keys: Vec<_> = rel.properties.keys().collect();
+            keys.sort();
+            for key in keys {
+                if let Some(message) = value_problem(&rel.properties[key]) {
+                    violation(format!("relationship {} property '{}'", i, key), message);
+                }
+            }
+        }
+        
+        if let (Some(from), Some(to)) = (self.valid_from, self.valid_to) {
+            if to <= from {
+                violation("valid_to".into(), format!("{} is not after valid_from {}", to, from));
+            }
+        }
+        
+        let mut keys: Vec<_> = self.index.keys().collect();
+        keys.sort();
+        for key in keys {
+            let field = || format!("index field '{}'", key);
+            if key.is_empty() {
+                violation(field(), "empty key".into());
+            }
+            if key.starts_with(RESERVED_KEY_PREFIX) {
+                violation(field(), format!("prefix '{}' is reserved", RESERVED_KEY_PREFIX));
+            }
+            if key.len() > MAX_NAME_LEN {
+                violation(field(), format!("key longer than {} bytes", MAX_NAME_LEN));
+            }
+            if let Some(message) = value_problem(&self.index[key]) {
+                violation(field(), message);
+            }
+        }
+        
+        ValidationError::check(violations)
+    }
+    
     /// Compute the content hash of this envelope
+    ///
+    /// This is the envelope's identity in a `Store`. `type_name` is
+    /// excluded since it's only a debugging aid.
     pub fn hash(&self) -> Hash256 {
-        // Hash: type_hash + sorted relationships + sorted index + payload
-        let mut parts: Vec<&[u8]> = Vec::new();
+        // Hash: type_hash + sorted relationships + sorted index + version + payload
+        let mut buf = Vec::new();
         
-        // Type hash
-        parts.push(self.type_hash.as_bytes());
+        // Type hash and payload content type
+        wire::put_hash(&mut buf, &self.type_hash);
+        wire::put_str(&mut buf, self.content_type.as_deref().unwrap_or(""));
         
-        // Relationships (sorted for determinism)
-        let mut rels: Vec<_> = self.relationships.iter().collect();
-        rels.sort_by(|a, b| {
-            (&a.rel_type, a.target.as_bytes())
-                .cmp(&(&b.rel_type, b.target.as_bytes()))
-        });
+        // Relationships (sorted by encoding for determinism)
+        let mut rels: Vec<Vec<u8>> = self.relationships.iter()
+            .map(|rel| {
+                let mut encoded = Vec::new();
+                rel.encode(&mut encoded);
+                encoded
+            })
+            .collect();
+        rels.sort();
+        wire::put_u32(&mut buf, rels.len() as u32);
         for rel in rels {
-            parts.push(rel.rel_type.as_bytes());
-            parts.push(rel.target.as_bytes());
+            buf.extend_from_slice(&rel);
         }
         
         // Index fields (sorted for determinism)
         let mut idx: Vec<_> = self.index.iter().collect();
         idx.sort_by_key(|(k, _)| *k);
+        wire::put_u32(&mut buf, idx.len() as u32);
         for (key, value) in idx {
-            parts.push(key.as_bytes());
-            match value {
-                IndexValue::String(s) => parts.push(s.as_bytes()),
-                IndexValue::Int64(v) => {
-                    // This is a hack; proper impl would use fixed encoding
-                    let bytes = v.to_le_bytes();
-                    // Can't push local; hash_parts handles this better
-                }
-                _ => {} // Simplified for now
-            }
+            wire::put_str(&mut buf, key);
+            value.encode(&mut buf);
+        }
+        
+        // Version chain and provenance
+        wire::put_opt_hash(&mut buf, self.previous.as_ref());
+        wire::put_u32(&mut buf, self.merge_parents.len() as u32);
+        for parent in &self.merge_parents {
+            wire::put_hash(&mut buf, parent);
+        }
+        wire::put_opt_i64(&mut buf, self.created_at);
+        wire::put_opt_hash(&mut buf, self.created_by.as_ref());
+        wire::put_opt_i64(&mut buf, self.valid_from);
+        wire::put_opt_i64(&mut buf, self.valid_to);
+        
+        // Extensions (sorted for determinism)
+        let mut exts: Vec<_> = self.extensions.iter().collect();
+        exts.sort_by_key(|(k, _)| *k);
+        wire::put_u32(&mut buf, exts.len() as u32);
+        for (key, value) in exts {
+            wire::put_str(&mut buf, key);
+            wire::put_bytes(&mut buf, value);
         }
         
         // Payload
-        parts.push(&self.payload);
+        wire::put_bytes(&mut buf, &self.payload);
         
-        Hash256::hash_parts(parts)
+        Hash256::hash(&buf)
+    }
+    
+    /// An index field extracted by `extract`, failing with
+    /// `Error::TypeMismatch` for other kinds; missing and null fields are `None`
+    fn typed<'a, T>(&'a self, field: &str, expected: &'static str, extract: impl FnOnce(&'a IndexValue) -> Option<T>) -> Result<Option<T>> {
+        match self.index.get(field) {
+            None | Some(IndexValue::Null) => Ok(None),
+            Some(value) => extract(value).map(Some).ok_or_else(|| Error::TypeMismatch {
+                field: field.to_string(),
+                expected,
+                actual: value.kind(),
+            }),
+        }
+    }
+    
+    /// A string index field
+    pub fn get_str(&self, field: &str) -> Result<Option<&str>> {
+        self.typed(field, "string", |v| match v { IndexValue::String(s) => Some(s.as_str()), _ => None })
+    }
+    
+    pub fn get_i64(&self, field: &str) -> Result<Option<i64>> {
+        self.typed(field, "int64", |v| match v { IndexValue::Int64(n) => Some(*n), _ => None })
+    }
+    
+    pub fn get_i128(&self, field: &str) -> Result<Option<i128>> {
+        self.typed(field, "int128", |v| match v { IndexValue::Int128(n) => Some(*n), _ => None })
+    }
+    
+    pub fn get_f64(&self, field: &str) -> Result<Option<f64>> {
+        self.typed(field, "float64", |v| match v { IndexValue::Float64(n) => Some(*n), _ => None })
+    }
+    
+    pub fn get_bool(&self, field: &str) -> Result<Option<bool>> {
+        self.typed(field, "bool", |v| match v { IndexValue::Bool(b) => Some(*b), _ => None })
+    }
+    
+    pub fn get_hash(&self, field: &str) -> Result<Option<Hash256>> {
+        self.typed(field, "hash", |v| match v { IndexValue::Hash(h) => Some(*h), _ => None })
+    }
+    
+    pub fn get_timestamp(&self, field: &str) -> Result<Option<i64>> {
+        self.typed(field, "timestamp", |v| match v { IndexValue::Timestamp(t) => Some(*t), _ => None })
+    }
+    
+    pub fn get_geo_point(&self, field: &str) -> Result<Option<GeoPoint>> {
+        self.typed(field, "geo_point", |v| match v { IndexValue::GeoPoint(p) => Some(*p), _ => None })
+    }
+    
+    /// All predecessors: `previous` first, then merge parents
+    pub fn parents(&self) -> impl Iterator<Item = &Hash256> {
+        self.previous.iter().chain(&self.merge_parents)
+    }
+    
+    /// Relationships of a type in position order; unpositioned edges come last
+    pub fn relationships_ordered(&self, rel_type: &str) -> Vec<&Relationship> {
+        let mut rels: Vec<_> = self.relationships.iter()
+            .filter(|r| r.rel_type == rel_type)
+            .collect();
+        rels.sort_by_key(|r| r.position.unwrap_or(u32::MAX));
+        rels
+    }
+    
+    /// Start the next version of this envelope
+    ///
+    /// The builder carries over every field except `created_at` and
+    /// `created_by`, with `previous` set to this envelope's hash.
+    pub fn derive(&self) -> EnvelopeBuilder {
+        EnvelopeBuilder {
+            type_hash: self.type_hash,
+            type_name: self.type_name.clone(),
+            content_type: self.content_type.clone(),
+            relationships: self.relationships.clone(),
+            index: self.index.clone(),
+            previous: Some(self.hash()),
+            merge_parents: Vec::new(),
+            created_at: None,
+            created_by: None,
+            valid_from: self.valid_from,
+            valid_to: self.valid_to,
+            extensions: self.extensions.clone(),
+            payload: self.payload.clone(),
+        }
     }
     
     /// Create a builder for constructing envelopes
@@ -135,10 +683,16 @@ impl Envelope {
         EnvelopeBuilder {
             type_hash,
             type_name: None,
+            content_type: None,
             relationships: Vec::new(),
             index: HashMap::new(),
             previous: None,
+            merge_parents: Vec::new(),
             created_at: None,
+            created_by: None,
+            valid_from: None,
+            valid_to: None,
+            extensions: HashMap::new(),
             payload,
         }
     }
@@ -149,10 +703,16 @@ impl Envelope {
 pub struct EnvelopeBuilder {
     type_hash: Hash256,
     type_name: Option<String>,
+    content_type: Option<String>,
     relationships: Vec<Relationship>,
     index: HashMap<String, IndexValue>,
     previous: Option<Hash256>,
+    merge_parents: Vec<Hash256>,
     created_at: Option<i64>,
+    created_by: Option<Hash256>,
+    valid_from: Option<i64>,
+    valid_to: Option<i64>,
+    extensions: HashMap<String, Vec<u8>>,
     payload: Vec<u8>,
 }
 
@@ -163,39 +723,142 @@ impl EnvelopeBuilder {
         self
     }
     
+    /// Set the payload's MIME type
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
+    }
+    
     /// Add a relationship
     pub fn relationship(mut self, rel_type: impl Into<String>, target: Hash256) -> Self {
         self.relationships.push(Relationship::new(rel_type, target));
         self
     }
     
+    /// Add several relationships, e.g. `(rel_type, target)` pairs
+    pub fn relationships<R: Into<Relationship>>(mut self, rels: impl IntoIterator<Item = R>) -> Self {
+        self.relationships.extend(rels.into_iter().map(Into::into));
+        self
+    }
+    
+    /// Add relationships of one type, positioned in iteration order
+    pub fn ordered_relationships(mut self, rel_type: &str, targets: impl IntoIterator<Item = Hash256>) -> Self {
+        for (i, target) in targets.into_iter().enumerate() {
+            self.relationships.push(Relationship::new(rel_type, target).with_position(i as u32));
+        }
+        self
+    }
+    
     /// Add an index field
     pub fn index(mut self, key: impl Into<String>, value: impl Into<IndexValue>) -> Self {
         self.index.insert(key.into(), value.into());
         self
     }
     
+    /// Add several index fields from key/value pairs (including maps)
+    pub fn indexes<K, V>(mut self, fields: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<IndexValue>,
+    {
+        self.index.extend(fields.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+    
+    /// Remove an index field
+    pub fn remove_index(mut self, key: &str) -> Self {
+        self.index.remove(key);
+        self
+    }
+    
+    /// Remove all relationships of a type
+    pub fn remove_relationships(mut self, rel_type: &str) -> Self {
+        self.relationships.retain(|r| r.rel_type != rel_type);
+        self
+    }
+    
+    /// Attach an extension section
+    pub fn extension(mut self, key: impl Into<String>, data: Vec<u8>) -> Self {
+        self.extensions.insert(key.into(), data);
+        self
+    }
+    
+    /// Replace the payload
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+    
     /// Set previous version
     pub fn previous(mut self, hash: Hash256) -> Self {
         self.previous = Some(hash);
         self
     }
     
+    /// Add a merged predecessor besides `previous`
+    pub fn merge_parent(mut self, hash: Hash256) -> Self {
+        self.merge_parents.push(hash);
+        self
+    }
+    
     /// Set creation timestamp
     pub fn created_at(mut self, timestamp: i64) -> Self {
         self.created_at = Some(timestamp);
         self
     }
     
+    /// Set the identity envelope of the writer
+    pub fn created_by(mut self, identity: Hash256) -> Self {
+        self.created_by = Some(identity);
+        self
+    }
+    
+    /// Set when the described facts start to hold (business time)
+    pub fn valid_from(mut self, timestamp: i64) -> Self {
+        self.valid_from = Some(timestamp);
+        self
+    }
+    
+    /// Set when the described facts stop holding (business time, exclusive)
+    pub fn valid_to(mut self, timestamp: i64) -> Self {
+        self.valid_to = Some(timestamp);
+        self
+    }
+    
+    /// Set creation timestamp to the current system time
+    pub fn created_at_now(self) -> Self {
+        self.created_at_from(&SystemClock)
+    }
+    
+    /// Set creation timestamp from a clock
+    pub fn created_at_from<C: Clock + ?Sized>(self, clock: &C) -> Self {
+        self.created_at(clock.now())
+    }
+    
+    /// Build the envelope, rejecting metadata that can't be stored or hashed
+    /// reliably (see `Envelope::validate`; store rules such as unique
+    /// fields are checked by `Envelope::validate_in`)
+    pub fn try_build(self) -> std::result::Result<Envelope, ValidationError> {
+        let envelope = self.build();
+        envelope.validate()?;
+        Ok(envelope)
+    }
+    
     /// Build the envelope
     pub fn build(self) -> Envelope {
         Envelope {
             type_hash: self.type_hash,
             type_name: self.type_name,
+            content_type: self.content_type,
             relationships: self.relationships,
             index: self.index,
             previous: self.previous,
+            merge_parents: self.merge_parents,
             created_at: self.created_at,
+            created_by: self.created_by,
+            valid_from: self.valid_from,
+            valid_to: self.valid_to,
+            extensions: self.extensions,
             payload: self.payload,
         }
     }
@@ -220,6 +883,143 @@ mod tests {
         assert_eq!(env.index.len(), 2);
     }
     
+    #[test]
+    fn test_bulk_builder_methods() {
+        let type_hash = Hash256::hash(b"TestType");
+        let tags = [Hash256::hash(b"rust"), Hash256::hash(b"serialization")];
+        
+        let mut fields = HashMap::new();
+        fields.insert("title".to_string(), IndexValue::from("Zero-Copy Dreams"));
+        fields.insert("word_count".to_string(), IndexValue::from(1500i64));
+        
+        let env = Envelope::builder(type_hash, vec![])
+            .relationships(tags.iter().map(|t| ("tag", *t)))
+            .indexes(fields)
+            .indexes([("lang", "en")])
+            .build();
+        
+        assert_eq!(env.relationships.len(), 2);
+        assert!(env.relationships.iter().all(|r| r.rel_type == "tag"));
+        assert_eq!(env.index.len(), 3);
+    }
+    
+    #[test]
+    fn test_created_at_from_clock() {
+        let type_hash = Hash256::hash(b"TestType");
+        let clock = crate::clock::FixedClock(1708523400);
+        
+        let env = Envelope::builder(type_hash, vec![])
+            .created_at_from(&clock)
+            .build();
+        assert_eq!(env.created_at, Some(1708523400));
+        
+        let env = Envelope::builder(type_hash, vec![]).created_at_now().build();
+        assert!(env.created_at.unwrap() > 1708523400);
+    }
+    
+    #[test]
+    fn test_derive_next_version() {
+        let type_hash = Hash256::hash(b"TestType");
+        let author = Hash256::hash(b"author");
+        
+        let v1 = Envelope::builder(type_hash, b"v1".to_vec())
+            .type_name("TestType")
+            .relationship("author", author)
+            .index("title", "Draft")
+            .index("word_count", 100i64)
+            .created_at(1708523400)
+            .build();
+        
+        let v2 = v1.derive()
+            .index("word_count", 150i64)
+            .payload(b"v2".to_vec())
+            .build();
+        
+        assert_eq!(v2.previous, Some(v1.hash()));
+        assert_eq!(v2.type_name, v1.type_name);
+        assert_eq!(v2.relationships.len(), 1);
+        assert!(matches!(v2.index.get("title"), Some(IndexValue::String(s)) if s == "Draft"));
+        assert!(matches!(v2.index.get("word_count"), Some(IndexValue::Int64(150))));
+        assert_eq!(v2.created_at, None);
+        assert_ne!(v2.hash(), v1.hash());
+    }
+    
+    #[test]
+    fn test_merge_parents() {
+        let type_hash = Hash256::hash(b"Doc");
+        let base = Envelope::builder(type_hash, b"base".to_vec()).build();
+        let a = base.derive().payload(b"a".to_vec()).build();
+        let b = base.derive().payload(b"b".to_vec()).build();
+        
+        let merged = a.derive()
+            .merge_parent(b.hash())
+            .payload(b"ab".to_vec())
+            .build();
+        let parents: Vec<_> = merged.parents().copied().collect();
+        assert_eq!(parents, vec![a.hash(), b.hash()]);
+        
+        let linear = a.derive().payload(b"ab".to_vec()).build();
+        assert_ne!(merged.hash(), linear.hash());
+        assert!(merged.derive().build().merge_parents.is_empty());
+    }
+    
+    #[test]
+    fn test_try_build_validation() {
+        let type_hash = Hash256::hash(b"TestType");
+        let target = Hash256::hash(b"target");
+        
+        let ok = Envelope::builder(type_hash, vec![])
+            .relationship("author", target)
+            .index("score", 1.5)
+            .try_build();
+        assert!(ok.is_ok());
+        
+        let cases = [
+            (Envelope::builder(type_hash, vec![]).relationship("", target), "relationship 0"),
+            (Envelope::builder(type_hash, vec![]).index("envelope/seq", 1i64), "envelope/seq"),
+            (Envelope::builder(type_hash, vec![]).index("body", "x".repeat(MAX_STRING_VALUE_LEN + 1)), "body"),
+            (Envelope::builder(type_hash, vec![]).index("score", f64::NAN), "score"),
+            (
+                Envelope::builder(type_hash, vec![]).relationships([Relationship::new("cites", target).with_property("weight", f64::NAN)]),
+                "relationship 0 property 'weight'",
+            ),
+        ];
+        for (builder, field) in cases {
+            let err = builder.try_build().unwrap_err();
+            assert!(err.violations.len() == 1 && err.violations[0].field.contains(field), "{}", err);
+        }
+        
+        // Every problem is reported at once
+        let err = Envelope::builder(type_hash, vec![])
+            .relationship("", target)
+            .index("a", f64::NAN)
+            .index("k".repeat(MAX_NAME_LEN + 1), 1i64)
+            .try_build()
+            .unwrap_err();
+        let fields: Vec<_> = err.violations.iter().map(|v| &v.field[..]).collect();
+        assert_eq!(fields, ["relationship 0", "index field 'a'", &format!("index field '{}'", "k".repeat(MAX_NAME_LEN + 1))]);
+        assert!(matches!(Error::from(err), Error::Validation(_)));
+    }
+    
+    #[test]
+    fn test_typed_accessors() {
+        let envelope = Envelope::builder(Hash256::hash(b"Post"), vec![])
+            .index("title", "Hello")
+            .index("count", 3i64)
+            .index("draft", true)
+            .index("cleared", IndexValue::Null)
+            .build();
+        assert_eq!(envelope.get_str("title").unwrap(), Some("Hello"));
+        assert_eq!(envelope.get_i64("count").unwrap(), Some(3));
+        assert_eq!(envelope.get_bool("draft").unwrap(), Some(true));
+        assert_eq!(envelope.get_str("missing").unwrap(), None);
+        assert_eq!(envelope.get_f64("cleared").unwrap(), None);
+        match envelope.get_str("count") {
+            Err(Error::TypeMismatch { field, expected: "string", actual: "int64" }) => assert_eq!(field, "count"),
+            other => panic!("expected TypeMismatch, got {:?}", other),
+        }
+    }
+    
     #[test]
     fn test_envelope_hash_deterministic() {
         let type_hash = Hash256::hash(b"TestType");
@@ -238,4 +1038,171 @@ mod tests {
         
         assert_eq!(env1.hash(), env2.hash());
     }
+    
+    #[test]
+    fn test_int128_encoding_preserves_order() {
+        let values = [i128::MIN, -1_000_000_000_000_000_000_000, -1, 0, 1, i128::MAX];
+        let encoded: Vec<Vec<u8>> = values.iter()
+            .map(|v| {
+                let mut buf = Vec::new();
+                IndexValue::Int128(*v).encode(&mut buf);
+                buf
+            })
+            .collect();
+        
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        
+        for (value, bytes) in values.iter().zip(&encoded) {
+            let decoded = IndexValue::decode(&mut Reader::new(bytes)).unwrap();
+            assert!(matches!(decoded, IndexValue::Int128(v) if v == *value));
+        }
+    }
+    
+    #[test]
+    fn test_geo_point_roundtrip() {
+        let point = GeoPoint::new(48.8584, 2.2945);
+        let mut buf = Vec::new();
+        IndexValue::from(point).encode(&mut buf);
+        
+        assert_eq!(buf.len(), 17);
+        let decoded = IndexValue::decode(&mut Reader::new(&buf)).unwrap();
+        assert!(matches!(decoded, IndexValue::GeoPoint(p) if p == point));
+    }
+    
+    #[test]
+    fn test_negative_zero_hashes_as_zero() {
+        let type_hash = Hash256::hash(b"TestType");
+        let hash = |v: IndexValue| Envelope::builder(type_hash, vec![]).index("at", v).build().hash();
+        
+        assert_eq!(hash(IndexValue::Float64(-0.0)), hash(IndexValue::Float64(0.0)));
+        assert_eq!(hash(GeoPoint::new(-0.0, 1.0).into()), hash(GeoPoint::new(0.0, 1.0).into()));
+        assert_ne!(hash(IndexValue::Float64(-1.0)), hash(IndexValue::Float64(1.0)));
+    }
+    
+    #[test]
+    fn test_created_by_is_hashed() {
+        let type_hash = Hash256::hash(b"TestType");
+        let alice = Envelope::builder(type_hash, vec![1])
+            .created_by(Hash256::hash(b"alice"))
+            .build();
+        let bob = Envelope::builder(type_hash, vec![1])
+            .created_by(Hash256::hash(b"bob"))
+            .build();
+        
+        assert_ne!(alice.hash(), bob.hash());
+        assert_eq!(alice.derive().build().created_by, None);
+    }
+    
+    #[test]
+    fn test_content_type_is_hashed() {
+        let type_hash = Hash256::hash(b"BlogPost");
+        let fb = Envelope::builder(type_hash, vec![1])
+            .content_type("application/flatbuffers; schema=BlogPost")
+            .build();
+        let plain = Envelope::builder(type_hash, vec![1]).build();
+        
+        assert_ne!(fb.hash(), plain.hash());
+        assert_eq!(fb.derive().build().content_type, fb.content_type);
+        assert!(Envelope::builder(type_hash, vec![]).content_type("").try_build().is_err());
+        let empty = Envelope::builder(type_hash, vec![]).content_type("").build();
+        assert!(matches!(crate::Store::new().put(&empty), Err(Error::InvalidEnvelope(_))));
+    }
+    
+    #[test]
+    fn test_validity_period() {
+        let type_hash = Hash256::hash(b"Price");
+        let env = Envelope::builder(type_hash, vec![])
+            .valid_from(100)
+            .valid_to(200)
+            .build();
+        assert_ne!(env.hash(), Envelope::builder(type_hash, vec![]).build().hash());
+        assert_eq!(env.derive().build().valid_to, Some(200));
+        
+        let err = Envelope::builder(type_hash, vec![])
+            .valid_from(200)
+            .valid_to(100)
+            .try_build()
+            .unwrap_err();
+        assert!(err.to_string().contains("valid_to"));
+    }
+    
+    #[test]
+    fn test_extensions_are_hashed() {
+        let type_hash = Hash256::hash(b"TestType");
+        let plain = Envelope::builder(type_hash, vec![1]).build();
+        let signed = Envelope::builder(type_hash, vec![1])
+            .extension("sig", vec![0xAB; 64])
+            .build();
+        
+        assert_ne!(plain.hash(), signed.hash());
+    }
+    
+    #[test]
+    fn test_relationship_properties_are_hashed() {
+        let type_hash = Hash256::hash(b"TestType");
+        let target = Hash256::hash(b"target");
+        
+        let editor = Envelope::builder(type_hash, vec![])
+            .relationships([Relationship::new("contributor", target).with_property("role", "editor")])
+            .build();
+        let reviewer = Envelope::builder(type_hash, vec![])
+            .relationships([Relationship::new("contributor", target).with_property("role", "reviewer")])
+            .build();
+        
+        assert_ne!(editor.hash(), reviewer.hash());
+    }
+    
+    #[test]
+    fn test_ordered_relationships() {
+        let type_hash = Hash256::hash(b"Book");
+        let chapters: Vec<_> = (0..3u8).map(|i| Hash256::hash(&[i])).collect();
+        
+        let book = Envelope::builder(type_hash, vec![])
+            .relationship("author", Hash256::hash(b"author"))
+            .relationships([
+                Relationship::new("chapter", chapters[2]).with_position(2),
+                Relationship::new("chapter", chapters[0]).with_position(0),
+                Relationship::new("chapter", chapters[1]).with_position(1),
+            ])
+            .build();
+        
+        let ordered: Vec<_> = book.relationships_ordered("chapter").iter().map(|r| r.target).collect();
+        assert_eq!(ordered, chapters);
+        
+        // Position is part of identity
+        let reordered = Envelope::builder(type_hash, vec![])
+            .relationship("author", Hash256::hash(b"author"))
+            .ordered_relationships("chapter", [chapters[1], chapters[0], chapters[2]])
+            .build();
+        assert_ne!(book.hash(), reordered.hash());
+    }
+    
+    #[test]
+    fn test_weak_relationship() {
+        let type_hash = Hash256::hash(b"Article");
+        let target = Hash256::hash(b"other");
+        
+        let strong = Envelope::builder(type_hash, vec![])
+            .relationship("see_also", target)
+            .build();
+        let weak = Envelope::builder(type_hash, vec![])
+            .relationships([Relationship::weak("see_also", target)])
+            .build();
+        
+        assert!(strong.relationships[0].is_strong());
+        assert!(!weak.relationships[0].is_strong());
+        assert_ne!(strong.hash(), weak.hash());
+    }
+    
+    #[test]
+    fn test_null_differs_from_missing() {
+        let type_hash = Hash256::hash(b"TestType");
+        
+        let with_null = Envelope::builder(type_hash, vec![1])
+            .index("published_at", IndexValue::Null)
+            .build();
+        let without = Envelope::builder(type_hash, vec![1]).build();
+        
+        assert_ne!(with_null.hash(), without.hash());
+    }
 }
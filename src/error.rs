@@ -1,5 +1,6 @@
 //! Error types for envelope operations
 
+use crate::hash::Hash256;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,8 +11,37 @@ pub enum Error {
     #[error("Hash mismatch: expected {expected}, got {actual}")]
     HashMismatch { expected: String, actual: String },
     
-    #[error("Object not found: {0}")]
-    NotFound(String),
+    /// `operation` names what needed the object, e.g. `get` or `tag`
+    #[error("Object not found: {hash} (in {operation})")]
+    NotFound { hash: Hash256, operation: &'static str },
+    
+    #[error("Tag already exists: {0}")]
+    TagExists(String),
+    
+    #[error("Ref {name} changed: expected {expected}, found {actual}")]
+    RefConflict { name: String, expected: String, actual: String },
+    
+    #[error("Invalid envelope: {0}")]
+    Validation(#[from] ValidationError),
+    
+    #[error("Field {field} is {actual}, not {expected}")]
+    TypeMismatch { field: String, expected: &'static str, actual: &'static str },
+    
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    
+    /// An argument out of range for the operation, e.g. keeping zero versions
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    
+    #[error("Store locked: {0}")]
+    StoreLocked(String),
+    
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
     
     #[error("Storage error: {0}")]
     Storage(String),
@@ -22,3 +52,128 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
+
+/// One problem found validating an envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// What it's about, e.g. `relationship 0` or `index field 'title'`
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Every problem found with an envelope, from `try_build`, `validate_in`
+/// or a strict put
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{}", violations.iter().map(Violation::to_string).collect::<Vec<_>>().join("; "))]
+pub struct ValidationError {
+    /// In the order of the envelope's fields
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationError {
+    /// `Ok` if there are no violations
+    pub(crate) fn check(violations: Vec<Violation>) -> std::result::Result<(), Self> {
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(Self { violations }),
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn not_found(hash: Hash256, operation: &'static str) -> Self {
+        Error::NotFound { hash, operation }
+    }
+    
+    /// Stable name and number of the variant, for FFI, HTTP and logs
+    ///
+    /// Neither changes between versions, and a retired number is never
+    /// reused. The hundreds group related errors: 1xx invalid input,
+    /// 2xx missing, 3xx conflicts, 4xx denied, 5xx corruption, 6xx I/O.
+    fn codes(&self) -> (&'static str, u16) {
+        match self {
+            Error::InvalidEnvelope(_) => ("invalid_envelope", 100),
+            Error::InvalidQuery(_) => ("invalid_query", 101),
+            Error::Validation(_) => ("validation", 102),
+            Error::TypeMismatch { .. } => ("type_mismatch", 103),
+            Error::InvalidArgument(_) => ("invalid_argument", 104),
+            Error::NotFound { .. } => ("not_found", 200),
+            Error::TagExists(_) => ("tag_exists", 300),
+            Error::RefConflict { .. } => ("ref_conflict", 301),
+            Error::StoreLocked(_) => ("store_locked", 302),
+            Error::Unauthorized(_) => ("unauthorized", 400),
+            Error::QuotaExceeded(_) => ("quota_exceeded", 401),
+            Error::HashMismatch { .. } => ("hash_mismatch", 500),
+            Error::Serialization(_) => ("serialization", 501),
+            Error::Storage(_) => ("storage", 502),
+            Error::Io(_) => ("io", 600),
+        }
+    }
+    
+    /// Stable snake_case code, e.g. `not_found` (see `numeric_code`)
+    pub fn code(&self) -> &'static str {
+        self.codes().0
+    }
+    
+    /// Stable number, e.g. 200 for `not_found`
+    pub fn numeric_code(&self) -> u16 {
+        self.codes().1
+    }
+    
+    /// A missing object
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound { .. })
+    }
+    
+    /// Stored data that doesn't decode or doesn't match its hash
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, Error::HashMismatch { .. } | Error::Serialization(_) | Error::Storage(_))
+    }
+    
+    /// A ref or tag changed by someone else, or a store open elsewhere;
+    /// retrying after re-reading may succeed
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Error::RefConflict { .. } | Error::TagExists(_) | Error::StoreLocked(_))
+    }
+    
+    /// Refused by a capability or quota
+    pub fn is_denied(&self) -> bool {
+        matches!(self, Error::Unauthorized(_) | Error::QuotaExceeded(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use crate::store::Store;
+    
+    #[test]
+    fn test_error_classification() {
+        let mut store = Store::new();
+        let missing = Hash256::hash(b"missing");
+        let err = store.get(&missing).unwrap_err();
+        assert!(err.is_not_found() && !err.is_corruption());
+        assert!(matches!(err, Error::NotFound { hash, operation: "get" } if hash == missing));
+        assert!(matches!(store.tag("v1", missing), Err(Error::NotFound { operation: "tag", .. })));
+        
+        let hash = store.put(&Envelope::builder(Hash256::hash(b"Note"), vec![]).build()).unwrap();
+        let err = store.compare_and_swap_ref("main", Some(hash), hash).unwrap_err();
+        assert!(err.is_conflict() && !err.is_not_found());
+        assert!(Error::Serialization("truncated".into()).is_corruption());
+    }
+    
+    #[test]
+    fn test_error_codes() {
+        let err = Store::new().get(&Hash256::hash(b"missing")).unwrap_err();
+        assert_eq!((err.code(), err.numeric_code()), ("not_found", 200));
+        assert_eq!(Error::Io(std::io::ErrorKind::Other.into()).code(), "io");
+        assert_eq!(Error::QuotaExceeded(String::new()).numeric_code() / 100, 4);
+    }
+}
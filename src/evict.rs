@@ -0,0 +1,385 @@
+//! Byte budgets with automatic eviction
+//!
+//! For stores used as caches: once the store is over its budget,
+//! `evict` removes objects, least recently used (or oldest) first, until
+//! usage is back under the low-water mark, so eviction runs in
+//! occasional batches rather than on every put. Puts never evict
+//! themselves, so they stay cheap; call `evict` after writing, or let
+//! `evict_in_background` do it from a thread. Pinned objects, refs,
+//! tags and everything reachable from them (see `Store::closure`) are
+//! never evicted. Other objects may lose relationship targets to
+//! eviction, as is usual for a cache.
+//!
+//! A budget measures serialized bytes held by default. `on_disk`
+//! measures the object log instead, which also holds removed objects
+//! until it's compacted, so eviction then compacts it (see
+//! `Store::compact`).
+
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::store::{Store, RECORD_HEADER_LEN};
+use crate::trace::span;
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Which objects to evict first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently put or retrieved
+    #[default]
+    Lru,
+    /// Least recently put
+    Fifo,
+}
+
+/// Serialized bytes a store may hold before evicting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    max_bytes: usize,
+    low_water: usize,
+    policy: EvictionPolicy,
+    on_disk: bool,
+}
+
+impl Budget {
+    /// Evict when over `max_bytes`, down to 90% of it, by LRU
+    pub fn new(max_bytes: usize) -> Self {
+        Self { max_bytes, low_water: max_bytes / 10 * 9, policy: EvictionPolicy::Lru, on_disk: false }
+    }
+    
+    /// Usage to evict down to (at most `max_bytes`)
+    pub fn evict_to(mut self, bytes: usize) -> Self {
+        self.low_water = bytes.min(self.max_bytes);
+        self
+    }
+    
+    pub fn policy(mut self, policy: EvictionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    
+    /// Measure the object log's size rather than the bytes held, for
+    /// persistent stores (an in-memory store never goes over)
+    pub fn on_disk(mut self) -> Self {
+        self.on_disk = true;
+        self
+    }
+}
+
+/// Put and access order of each object, kept while a budget is set
+#[derive(Debug, Default)]
+struct Ticks {
+    clock: u64,
+    /// Hash -> (tick it was put, tick it was last put or retrieved)
+    objects: HashMap<Hash256, (u64, u64)>,
+}
+
+/// A store's budget, pins and access tracking
+#[derive(Debug, Default)]
+pub(crate) struct Eviction {
+    budget: Option<Budget>,
+    pins: HashSet<Hash256>,
+    /// Locked so retrieval through `&Store` can record accesses
+    ticks: Mutex<Ticks>,
+}
+
+impl Eviction {
+    fn ticks(&self) -> std::sync::MutexGuard<'_, Ticks> {
+        self.ticks.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// Record a put of a new object
+    pub(crate) fn inserted(&self, hash: Hash256) {
+        if self.budget.is_some() {
+            let mut ticks = self.ticks();
+            ticks.clock += 1;
+            let now = ticks.clock;
+            ticks.objects.insert(hash, (now, now));
+        }
+    }
+    
+    /// Record a retrieval
+    pub(crate) fn touch(&self, hash: &Hash256) {
+        if self.budget.is_some() {
+            let mut ticks = self.ticks();
+            ticks.clock += 1;
+            let now = ticks.clock;
+            if let Some((_, accessed)) = ticks.objects.get_mut(hash) {
+                *accessed = now;
+            }
+        }
+    }
+    
+    pub(crate) fn forget(&self, hash: &Hash256) {
+        self.ticks().objects.remove(hash);
+    }
+}
+
+impl Store {
+    /// Keep the store within `budget` when `evict` runs
+    ///
+    /// Objects already stored are ranked in the order they were put, so
+    /// a reopened store evicts its oldest objects first.
+    pub fn set_budget(&mut self, budget: Budget) {
+        let mut ticks = Ticks::default();
+        for change in self.changes_since(0) {
+            if self.contains(&change.hash) {
+                ticks.clock += 1;
+                ticks.objects.insert(change.hash, (ticks.clock, ticks.clock));
+            }
+        }
+        let eviction = self.eviction_mut();
+        eviction.budget = Some(budget);
+        *eviction.ticks.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = ticks;
+    }
+    
+    /// Stop evicting
+    pub fn clear_budget(&mut self) {
+        let eviction = self.eviction_mut();
+        eviction.budget = None;
+        *eviction.ticks.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = Ticks::default();
+    }
+    
+    pub fn budget(&self) -> Option<Budget> {
+        self.eviction().budget
+    }
+    
+    /// Protect an object and its closure from eviction
+    ///
+    /// Pins last while the store is open; point a ref at objects that must
+    /// survive reopening too.
+    pub fn pin(&mut self, hash: Hash256) {
+        self.eviction_mut().pins.insert(hash);
+    }
+    
+    /// Remove a pin, returning whether there was one
+    pub fn unpin(&mut self, hash: &Hash256) -> bool {
+        self.eviction_mut().pins.remove(hash)
+    }
+    
+    pub fn is_pinned(&self, hash: &Hash256) -> bool {
+        self.eviction().pins.contains(hash)
+    }
+    
+    /// Whether the store holds more than its budget allows
+    pub fn over_budget(&self) -> bool {
+        self.budget().is_some_and(|budget| self.budget_usage(&budget) > budget.max_bytes)
+    }
+    
+    /// Usage as `budget` measures it
+    fn budget_usage(&self, budget: &Budget) -> usize {
+        match budget.on_disk {
+            true => self.log_size() as usize,
+            false => self.usage().bytes,
+        }
+    }
+    
+    /// Objects to evict to get under the low-water mark, in eviction order;
+    /// empty unless over budget
+    pub(crate) fn eviction_candidates(&self) -> Result<Vec<Hash256>> {
+        let eviction = self.eviction();
+        let Some(budget) = eviction.budget else {
+            return Ok(Vec::new());
+        };
+        if !self.over_budget() {
+            return Ok(Vec::new());
+        }
+        let pins: Vec<_> = eviction.pins.iter().copied().collect();
+        let protected = self.live_set(&pins)?;
+        let mut ranked: Vec<_> = eviction.ticks().objects.iter()
+            .filter(|(hash, _)| !protected.contains(*hash))
+            .map(|(hash, (put, accessed))| match budget.policy {
+                EvictionPolicy::Lru => (*accessed, *hash),
+                EvictionPolicy::Fifo => (*put, *hash),
+            })
+            .collect();
+        ranked.sort();
+        
+        // On disk, what the log will hold once compacted
+        let overhead = if budget.on_disk { RECORD_HEADER_LEN } else { 0 };
+        let mut bytes = self.usage().bytes + self.len() * overhead;
+        let mut candidates = Vec::new();
+        for (_, hash) in ranked {
+            if bytes <= budget.low_water {
+                break;
+            }
+            bytes -= self.stored_size(&hash).unwrap_or(0) + overhead;
+            candidates.push(hash);
+        }
+        Ok(candidates)
+    }
+    
+    /// Evict down to the low-water mark if over budget; returns the
+    /// evicted hashes
+    pub fn evict(&mut self) -> Result<Vec<Hash256>> {
+        let candidates = self.eviction_candidates()?;
+        if candidates.is_empty() && !self.over_budget() {
+            return Ok(candidates);
+        }
+        let _span = span!("Store::evict", objects = candidates.len());
+        for hash in &candidates {
+            self.remove(hash)?;
+        }
+        self.compact_for_budget()?;
+        Ok(candidates)
+    }
+    
+    /// Compact the log if the budget measures it
+    pub(crate) fn compact_for_budget(&mut self) -> Result<()> {
+        if self.budget().is_some_and(|budget| budget.on_disk) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+impl IndexedStore {
+    /// Keep the store within `budget` when `evict` runs (see
+    /// `Store::set_budget`)
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.store_mut().set_budget(budget);
+    }
+    
+    /// Protect an object and its closure from eviction
+    pub fn pin(&mut self, hash: Hash256) {
+        self.store_mut().pin(hash);
+    }
+    
+    /// Remove a pin, returning whether there was one
+    pub fn unpin(&mut self, hash: &Hash256) -> bool {
+        self.store_mut().unpin(hash)
+    }
+    
+    /// Evict down to the low-water mark if over budget, removing evicted
+    /// objects from every index; returns their hashes
+    pub fn evict(&mut self) -> Result<Vec<Hash256>> {
+        let candidates = self.store().eviction_candidates()?;
+        if candidates.is_empty() && !self.store().over_budget() {
+            return Ok(candidates);
+        }
+        let _span = span!("IndexedStore::evict", objects = candidates.len());
+        for hash in &candidates {
+            self.remove_object(hash)?;
+        }
+        self.store_mut().compact_for_budget()?;
+        #[cfg(feature = "metrics")]
+        self.metrics().record_evictions(candidates.len());
+        Ok(candidates)
+    }
+    
+    /// Run `evict` from a new thread every `interval`
+    ///
+    /// The thread holds only a weak reference, and ends once every other
+    /// handle to the store is dropped, or when eviction fails. Needs a
+    /// target with threads (not wasm32-unknown-unknown).
+    pub fn evict_in_background(store: &Arc<Mutex<Self>>, interval: Duration) -> JoinHandle<Result<()>> {
+        let store = Arc::downgrade(store);
+        std::thread::spawn(move || loop {
+            crate::clock::sleep(interval);
+            let Some(store) = store.upgrade() else {
+                return Ok(());
+            };
+            let mut store = store.lock().map_err(|_| Error::Storage("store lock poisoned".into()))?;
+            if store.store().over_budget() {
+                store.evict()?;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    
+    #[test]
+    fn test_lru_eviction() {
+        let mut store = IndexedStore::new();
+        let blob = Hash256::hash(b"Blob");
+        let put = |store: &mut IndexedStore, i: u8| store.put(&Envelope::builder(blob, vec![i; 100]).build()).unwrap();
+        let first = put(&mut store, 0);
+        let size = store.store().stored_size(&first).unwrap();
+        store.set_budget(Budget::new(size * 4).evict_to(size * 4));
+        let hashes: Vec<_> = std::iter::once(first).chain((1..4).map(|i| put(&mut store, i))).collect();
+        store.pin(hashes[1]);
+        store.set_ref("keep", hashes[2]).unwrap();
+        store.get(&hashes[0]).unwrap();
+        
+        // Over budget: only 0, 3 and the latest are evictable, and 3 was used least recently
+        let latest = put(&mut store, 4);
+        assert!(store.store().over_budget() && store.contains(&hashes[3]));
+        assert_eq!(store.evict().unwrap(), vec![hashes[3]]);
+        assert!(store.query_by_type(&blob).len() == 4 && store.contains(&latest));
+        let latest = put(&mut store, 5);
+        store.evict().unwrap();
+        assert!(!store.contains(&hashes[0]) && store.contains(&latest));
+        assert!(store.contains(&hashes[1]) && store.contains(&hashes[2]));
+    }
+    
+    #[test]
+    fn test_stats_are_not_accesses() {
+        let mut store = Store::new();
+        store.set_budget(Budget::new(1 << 20));
+        let blob = Hash256::hash(b"Blob");
+        for i in 0..3 {
+            store.put(&Envelope::builder(blob, vec![i; 10]).build()).unwrap();
+        }
+        let ticks = store.eviction().ticks().objects.clone();
+        assert_eq!(store.stats_by_type().unwrap()[&blob].count, 3);
+        assert_eq!(store.eviction().ticks().objects, ticks);
+    }
+    
+    #[test]
+    fn test_eviction_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = Hash256::hash(b"Blob");
+        let hashes: Vec<_> = {
+            let mut store = Store::open(dir.path()).unwrap();
+            (0..4u8).map(|i| store.put(&Envelope::builder(blob, vec![i; 100]).build()).unwrap()).collect()
+        };
+        
+        let mut store = Store::open(dir.path()).unwrap();
+        let size = store.stored_size(&hashes[0]).unwrap();
+        store.set_budget(Budget::new(size * 3).policy(EvictionPolicy::Fifo).evict_to(size * 2));
+        assert_eq!(store.evict().unwrap(), vec![hashes[0], hashes[1]]);
+        drop(store);
+        assert_eq!(Store::open(dir.path()).unwrap().len(), 2);
+    }
+    
+    #[test]
+    fn test_disk_budget_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = Hash256::hash(b"Blob");
+        let mut store = IndexedStore::open(dir.path()).unwrap();
+        let hashes: Vec<_> = (0..8u8).map(|i| store.put(&Envelope::builder(blob, vec![i; 100]).build()).unwrap()).collect();
+        // Removal records count against a disk budget until compaction
+        let record = store.store().log_size() / 8;
+        store.delete(&hashes[0], false).unwrap();
+        store.set_budget(Budget::new(record as usize * 7).evict_to(record as usize * 5).on_disk());
+        assert!(store.store().over_budget());
+        
+        let store = Arc::new(Mutex::new(store));
+        let evicting = IndexedStore::evict_in_background(&store, Duration::from_millis(1));
+        while store.lock().unwrap().store().over_budget() {
+            std::thread::yield_now();
+        }
+        {
+            let mut store = store.lock().unwrap();
+            assert_eq!(store.len(), 5);
+            assert_eq!(store.store().log_size(), record * 5);
+            assert!(!store.contains(&hashes[1]) && store.contains(&hashes[7]));
+            store.put(&Envelope::builder(blob, vec![9; 100]).build()).unwrap();
+        }
+        drop(store);
+        evicting.join().unwrap().unwrap();
+        
+        // The compacted log replays to the same objects
+        let store = Store::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 6);
+        assert_eq!(store.changes_since(0).len(), 6);
+    }
+}
@@ -0,0 +1,370 @@
+//! Rendering envelope graphs for external tools
+
+use crate::envelope::{Envelope, IndexValue};
+use crate::hash::Hash256;
+use crate::store::Store;
+use crate::Result;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+/// Settings for `Store::to_dot`
+#[derive(Debug, Clone, Default)]
+pub struct DotOptions {
+    fields: Vec<String>,
+    versions: bool,
+}
+
+impl DotOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Show this index field in node labels; may be repeated
+    pub fn field(mut self, name: impl Into<String>) -> Self {
+        self.fields.push(name.into());
+        self
+    }
+    
+    /// Also follow and draw version-chain links (`previous`, merge parents)
+    pub fn versions(mut self, versions: bool) -> Self {
+        self.versions = versions;
+        self
+    }
+}
+
+/// Nodes reachable from `roots` over relationships (and parents, with
+/// `versions`), in breadth-first order; `None` for objects not stored
+fn reachable(store: &Store, roots: &[Hash256], versions: bool) -> Result<Vec<(Hash256, Option<Envelope>)>> {
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<Hash256> = roots.iter().copied().collect();
+    let mut nodes = Vec::new();
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        if !store.contains(&hash) {
+            nodes.push((hash, None));
+            continue;
+        }
+        let envelope = store.get(&hash)?;
+        queue.extend(envelope.relationships.iter().map(|r| r.target));
+        if versions {
+            queue.extend(envelope.parents().copied());
+        }
+        nodes.push((hash, Some(envelope)));
+    }
+    Ok(nodes)
+}
+
+/// Escape text for a double-quoted DOT string
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Escape text for XML content and attribute values
+///
+/// Characters XML 1.0 can't carry at all (control characters other than
+/// tab and line breaks, U+FFFE and U+FFFF) become U+FFFD.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 || c == '\u{fffe}' || c == '\u{ffff}' => out.push(char::REPLACEMENT_CHARACTER),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// GraphML `attr.type` for a value
+fn graphml_type(value: &IndexValue) -> &'static str {
+    match value {
+        IndexValue::Int64(_) | IndexValue::Timestamp(_) => "long",
+        IndexValue::Float64(_) => "double",
+        IndexValue::Bool(_) => "boolean",
+        _ => "string",
+    }
+}
+
+/// Quote and escape a JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// JSON-LD node identifier for an object hash
+fn json_ld_id(hash: &Hash256) -> String {
+    format!("{{\"@id\": \"urn:sha256:{}\"}}", hash)
+}
+
+/// JSON-LD rendering of an index value
+fn json_ld_value(value: &IndexValue) -> String {
+    match value {
+        IndexValue::Int64(v) | IndexValue::Timestamp(v) => v.to_string(),
+        IndexValue::Float64(v) if v.is_finite() => v.to_string(),
+        IndexValue::Bool(v) => v.to_string(),
+        IndexValue::Null => "null".to_string(),
+        IndexValue::Hash(h) => json_ld_id(h),
+        other => json_string(&other.to_string()),
+    }
+}
+
+impl Store {
+    /// Render the graph reachable from `roots` as a JSON-LD document
+    ///
+    /// Each envelope becomes a node with `@id` `urn:sha256:<hash>`, its
+    /// type name (or type hash) as `@type`, index fields as `field:<key>`
+    /// properties and relationships as `rel:<type>` links to other nodes,
+    /// so neither can collide with each other, `parent` or JSON-LD
+    /// keywords. `parent` links every version the envelope supersedes,
+    /// `previous` first. Terms resolve against the `urn:envelope:` vocabulary.
+    pub fn to_json_ld(&self, roots: &[Hash256]) -> Result<String> {
+        let nodes = reachable(self, roots, false)?;
+        let mut json = String::from(concat!(
+            "{\n  \"@context\": {\"@vocab\": \"urn:envelope:\", ",
+            "\"field\": \"urn:envelope:field:\", \"rel\": \"urn:envelope:rel:\"},\n  \"@graph\": [",
+        ));
+        let mut first = true;
+        for (hash, envelope) in &nodes {
+            let Some(envelope) = envelope else { continue };
+            json.push_str(if first { "\n    {" } else { ",\n    {" });
+            first = false;
+            write!(json, "\"@id\": \"urn:sha256:{}\"", hash).unwrap();
+            let type_term = match &envelope.type_name {
+                Some(name) => json_string(name),
+                None => format!("\"urn:sha256:{}\"", envelope.type_hash),
+            };
+            write!(json, ", \"@type\": {}", type_term).unwrap();
+            
+            // Index fields and relationships, grouped under sorted keys
+            let mut properties: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for (key, value) in &envelope.index {
+                properties.entry(format!("field:{}", key)).or_default().push(json_ld_value(value));
+            }
+            for rel in &envelope.relationships {
+                properties.entry(format!("rel:{}", rel.rel_type)).or_default().push(json_ld_id(&rel.target));
+            }
+            for parent in envelope.parents() {
+                properties.entry("parent".to_string()).or_default().push(json_ld_id(parent));
+            }
+            for (key, values) in properties {
+                let value = match values.as_slice() {
+                    [single] => single.clone(),
+                    many => format!("[{}]", many.join(", ")),
+                };
+                write!(json, ", {}: {}", json_string(&key), value).unwrap();
+            }
+            json.push('}');
+        }
+        json.push_str("\n  ]\n}\n");
+        Ok(json)
+    }
+    
+    /// Render the graph reachable from `roots` as GraphML
+    ///
+    /// Every index field becomes a node attribute, typed when all its
+    /// values agree (`long`, `double`, `boolean`) and `string` otherwise.
+    /// Edges carry their relationship type as `rel_type`.
+    pub fn to_graphml(&self, roots: &[Hash256]) -> Result<String> {
+        let nodes = reachable(self, roots, false)?;
+        
+        // field name -> attribute type, agreed across all nodes
+        let mut fields: BTreeMap<&str, &str> = BTreeMap::new();
+        for envelope in nodes.iter().filter_map(|(_, e)| e.as_ref()) {
+            for (key, value) in &envelope.index {
+                let ty = graphml_type(value);
+                fields.entry(key)
+                    .and_modify(|t| if *t != ty { *t = "string" })
+                    .or_insert(ty);
+            }
+        }
+        let ids: BTreeMap<&str, String> = fields.keys()
+            .enumerate()
+            .map(|(i, key)| (*key, format!("f{}", i)))
+            .collect();
+        
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        xml.push_str("  <key id=\"type_name\" for=\"node\" attr.name=\"type_name\" attr.type=\"string\"/>\n");
+        xml.push_str("  <key id=\"type_hash\" for=\"node\" attr.name=\"type_hash\" attr.type=\"string\"/>\n");
+        xml.push_str("  <key id=\"rel_type\" for=\"edge\" attr.name=\"rel_type\" attr.type=\"string\"/>\n");
+        for (key, ty) in &fields {
+            writeln!(xml, "  <key id=\"{}\" for=\"node\" attr.name=\"{}\" attr.type=\"{}\"/>", ids[key], xml_escape(key), ty).unwrap();
+        }
+        xml.push_str("  <graph edgedefault=\"directed\">\n");
+        for (hash, envelope) in &nodes {
+            let Some(envelope) = envelope else {
+                writeln!(xml, "    <node id=\"{}\"/>", hash).unwrap();
+                continue;
+            };
+            writeln!(xml, "    <node id=\"{}\">", hash).unwrap();
+            if let Some(name) = &envelope.type_name {
+                writeln!(xml, "      <data key=\"type_name\">{}</data>", xml_escape(name)).unwrap();
+            }
+            writeln!(xml, "      <data key=\"type_hash\">{}</data>", envelope.type_hash).unwrap();
+            let mut index: Vec<_> = envelope.index.iter().collect();
+            index.sort_by_key(|(k, _)| *k);
+            for (key, value) in index {
+                writeln!(xml, "      <data key=\"{}\">{}</data>", ids[key.as_str()], xml_escape(&value.to_string())).unwrap();
+            }
+            xml.push_str("    </node>\n");
+        }
+        for (hash, envelope) in &nodes {
+            let Some(envelope) = envelope else { continue };
+            for rel in &envelope.relationships {
+                writeln!(xml, "    <edge source=\"{}\" target=\"{}\">", hash, rel.target).unwrap();
+                writeln!(xml, "      <data key=\"rel_type\">{}</data>", xml_escape(&rel.rel_type)).unwrap();
+                xml.push_str("    </edge>\n");
+            }
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        Ok(xml)
+    }
+    
+    /// Render the graph reachable from `roots` as Graphviz DOT
+    ///
+    /// Nodes are labeled with their type name, short hash and the
+    /// selected index fields; weak edges are dashed, objects missing
+    /// from the store are drawn as dashed boxes.
+    pub fn to_dot(&self, roots: &[Hash256], options: &DotOptions) -> Result<String> {
+        let nodes = reachable(self, roots, options.versions)?;
+        let mut dot = String::from("digraph envelopes {\n    node [shape=box];\n");
+        for (hash, envelope) in &nodes {
+            let Some(envelope) = envelope else {
+                writeln!(dot, "    \"{}\" [label=\"{}\", style=dashed];", hash, hash.short()).unwrap();
+                continue;
+            };
+            let mut label = String::new();
+            if let Some(name) = &envelope.type_name {
+                label.push_str(name);
+                label.push('\n');
+            }
+            label.push_str(&hash.short());
+            for field in &options.fields {
+                if let Some(value) = envelope.index.get(field) {
+                    write!(label, "\n{} = {}", field, value).unwrap();
+                }
+            }
+            writeln!(dot, "    \"{}\" [label=\"{}\"];", hash, dot_escape(&label)).unwrap();
+        }
+        for (hash, envelope) in &nodes {
+            let Some(envelope) = envelope else { continue };
+            for rel in &envelope.relationships {
+                let style = if rel.is_strong() { "" } else { ", style=dashed" };
+                writeln!(dot, "    \"{}\" -> \"{}\" [label=\"{}\"{}];", hash, rel.target, dot_escape(&rel.rel_type), style).unwrap();
+            }
+            if options.versions {
+                for parent in envelope.parents() {
+                    writeln!(dot, "    \"{}\" -> \"{}\" [label=\"previous\", style=dotted];", hash, parent).unwrap();
+                }
+            }
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Relationship;
+    
+    #[test]
+    fn test_to_dot() {
+        let mut store = Store::new();
+        let author = store.put(&Envelope::builder(Hash256::hash(b"Author"), vec![])
+            .type_name("Author")
+            .build()).unwrap();
+        let missing = Hash256::hash(b"not stored");
+        let post = store.put(&Envelope::builder(Hash256::hash(b"Post"), vec![])
+            .type_name("Post")
+            .index("title", "Say \"hi\"")
+            .relationship("author", author)
+            .relationships([Relationship::weak("see_also", missing)])
+            .build()).unwrap();
+        
+        let dot = store.to_dot(&[post], &DotOptions::new().field("title")).unwrap();
+        assert!(dot.starts_with("digraph envelopes {"));
+        assert!(dot.contains(&format!("\"{}\" [label=\"Post\\n{}\\ntitle = Say \\\"hi\\\"\"];", post, post.short())));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"author\"];", post, author)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"see_also\", style=dashed];", post, missing)));
+        assert!(dot.contains(&format!("\"{}\" [label=\"{}\", style=dashed];", missing, missing.short())));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+    
+    #[test]
+    fn test_to_graphml() {
+        let mut store = Store::new();
+        let tag = store.put(&Envelope::builder(Hash256::hash(b"Tag"), vec![])
+            .index("name", "R&D\u{1}")
+            .index("count", 3i64)
+            .build()).unwrap();
+        let post = store.put(&Envelope::builder(Hash256::hash(b"Post"), vec![])
+            .type_name("Post")
+            .index("count", "many")
+            .relationship("tag", tag)
+            .build()).unwrap();
+        
+        let xml = store.to_graphml(&[post]).unwrap();
+        assert!(xml.contains("<key id=\"f0\" for=\"node\" attr.name=\"count\" attr.type=\"string\"/>"));
+        assert!(xml.contains("<key id=\"f1\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>"));
+        assert!(xml.contains("<data key=\"f1\">R&amp;D\u{fffd}</data>"));
+        assert!(xml.contains(&format!("<edge source=\"{}\" target=\"{}\">", post, tag)));
+        assert!(xml.contains("<data key=\"rel_type\">tag</data>"));
+        assert_eq!(xml.matches("<node id=").count(), 2);
+        assert!(xml.ends_with("</graphml>\n"));
+    }
+    
+    #[test]
+    fn test_to_json_ld() {
+        let mut store = Store::new();
+        let a = store.put(&Envelope::builder(Hash256::hash(b"Tag"), vec![]).index("name", "a").build()).unwrap();
+        let b = store.put(&Envelope::builder(Hash256::hash(b"Tag"), vec![]).index("name", "b").build()).unwrap();
+        let post = store.put(&Envelope::builder(Hash256::hash(b"Post"), vec![])
+            .type_name("BlogPost")
+            .index("title", "Line\nbreak \"quoted\"")
+            .index("words", 1500i64)
+            .index("tag", "colliding")
+            .index("parent", "colliding")
+            .relationship("tag", a)
+            .relationship("tag", b)
+            .build()).unwrap();
+        let edit = store.get(&post).unwrap().derive().index("words", 1600i64).build();
+        let edit = store.put(&edit).unwrap();
+        let merge = store.put(&store.get(&post).unwrap().derive().merge_parent(edit).build()).unwrap();
+        
+        let json = store.to_json_ld(&[post]).unwrap();
+        assert!(json.contains("\"field\": \"urn:envelope:field:\", \"rel\": \"urn:envelope:rel:\"}"));
+        assert!(json.contains(&format!("{{\"@id\": \"urn:sha256:{}\", \"@type\": \"BlogPost\"", post)));
+        assert!(json.contains(&format!("\"rel:tag\": [{{\"@id\": \"urn:sha256:{}\"}}, {{\"@id\": \"urn:sha256:{}\"}}]", a, b)));
+        assert!(json.contains("\"field:title\": \"Line\\nbreak \\\"quoted\\\"\""));
+        assert!(json.contains("\"field:words\": 1500"));
+        // Fields named like relationships or reserved keys stay separate
+        assert!(json.contains("\"field:tag\": \"colliding\""));
+        assert!(json.contains("\"field:parent\": \"colliding\""));
+        assert_eq!(json.matches("\"@type\"").count(), 3);
+        
+        // A merge links all of its parents
+        let json = store.to_json_ld(&[merge]).unwrap();
+        assert!(json.contains(&format!("\"parent\": [{}, {}]", json_ld_id(&post), json_ld_id(&edit))));
+    }
+}
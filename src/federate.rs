@@ -0,0 +1,194 @@
+//! One logical store over several backends
+//!
+//! A `FederatedStore` writes to a local primary `Store` and reads from
+//! it first, falling back to each remote `Backend` in the order added,
+//! for when part of the graph lives in a shared service. Anything that
+//! can look up envelopes by hash can be a backend.
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::shard::ShardedStore;
+use crate::store::Store;
+use crate::Result;
+
+/// A read-only source of envelopes by hash
+pub trait Backend {
+    /// Retrieve an object, failing with `Error::NotFound` if it's absent
+    fn get(&self, hash: &Hash256) -> Result<Envelope>;
+    
+    fn contains(&self, hash: &Hash256) -> bool;
+}
+
+impl Backend for Store {
+    fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        Store::get(self, hash)
+    }
+    
+    fn contains(&self, hash: &Hash256) -> bool {
+        Store::contains(self, hash)
+    }
+}
+
+impl Backend for IndexedStore {
+    fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        IndexedStore::get(self, hash)
+    }
+    
+    fn contains(&self, hash: &Hash256) -> bool {
+        IndexedStore::contains(self, hash)
+    }
+}
+
+impl Backend for ShardedStore {
+    fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        ShardedStore::get(self, hash)
+    }
+    
+    fn contains(&self, hash: &Hash256) -> bool {
+        ShardedStore::contains(self, hash)
+    }
+}
+
+/// Where `FederatedStore::locate` found an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Primary,
+    /// Index of the remote, in the order added
+    Remote(usize),
+}
+
+/// A primary store for writes plus remotes to read through to
+pub struct FederatedStore {
+    primary: Store,
+    remotes: Vec<Box<dyn Backend + Send + Sync>>,
+}
+
+impl std::fmt::Debug for FederatedStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FederatedStore")
+            .field("primary", &self.primary)
+            .field("remotes", &self.remotes.len())
+            .finish()
+    }
+}
+
+impl FederatedStore {
+    pub fn new(primary: Store) -> Self {
+        Self { primary, remotes: Vec::new() }
+    }
+    
+    /// Read through to `backend` after the primary and earlier remotes
+    pub fn remote(mut self, backend: impl Backend + Send + Sync + 'static) -> Self {
+        self.remotes.push(Box::new(backend));
+        self
+    }
+    
+    pub fn primary(&self) -> &Store {
+        &self.primary
+    }
+    
+    pub fn primary_mut(&mut self) -> &mut Store {
+        &mut self.primary
+    }
+    
+    /// Store an envelope in the primary
+    pub fn put(&mut self, envelope: &Envelope) -> Result<Hash256> {
+        self.primary.put(envelope)
+    }
+    
+    /// Retrieve an object from the first backend holding it
+    ///
+    /// A remote that fails for any reason but the object being absent
+    /// fails the read, rather than being skipped over. So does one that
+    /// returns an envelope with another hash (`Error::HashMismatch`), since
+    /// remotes needn't check what they serve.
+    pub fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        match self.primary.get(hash) {
+            Err(e) if e.is_not_found() => {}
+            result => return result,
+        }
+        for remote in &self.remotes {
+            match remote.get(hash) {
+                Err(e) if e.is_not_found() => continue,
+                Ok(envelope) if envelope.hash() != *hash => {
+                    return Err(Error::HashMismatch { expected: hash.to_hex(), actual: envelope.hash().to_hex() });
+                }
+                result => return result,
+            }
+        }
+        Err(Error::not_found(*hash, "get"))
+    }
+    
+    /// `get`, copying objects found remotely into the primary so later
+    /// reads stay local
+    pub fn fetch(&mut self, hash: &Hash256) -> Result<Envelope> {
+        let envelope = self.get(hash)?;
+        if !self.primary.contains(hash) {
+            self.primary.put(&envelope)?;
+        }
+        Ok(envelope)
+    }
+    
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.locate(hash).is_some()
+    }
+    
+    /// The first backend holding an object
+    pub fn locate(&self, hash: &Hash256) -> Option<Location> {
+        if self.primary.contains(hash) {
+            return Some(Location::Primary);
+        }
+        self.remotes.iter().position(|remote| remote.contains(hash)).map(Location::Remote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_federated_reads() {
+        let node = Hash256::hash(b"Node");
+        let mut shared = Store::new();
+        let remote_only = shared.put(&Envelope::builder(node, b"shared".to_vec()).build()).unwrap();
+        let mut indexed = IndexedStore::new();
+        let second = indexed.put(&Envelope::builder(node, b"second".to_vec()).build()).unwrap();
+        
+        let mut store = FederatedStore::new(Store::new()).remote(shared).remote(indexed);
+        let local = store.put(&Envelope::builder(node, b"local".to_vec()).build()).unwrap();
+        assert_eq!(store.locate(&local), Some(Location::Primary));
+        assert_eq!(store.locate(&second), Some(Location::Remote(1)));
+        assert_eq!(store.get(&remote_only).unwrap().payload, b"shared");
+        assert!(store.get(&Hash256::hash(b"nowhere")).unwrap_err().is_not_found());
+        
+        // Fetching caches remote objects in the primary
+        store.fetch(&remote_only).unwrap();
+        assert_eq!(store.locate(&remote_only), Some(Location::Primary));
+        assert_eq!(store.primary().len(), 2);
+    }
+    
+    /// Serves the same envelope whatever is asked for
+    struct Liar(Envelope);
+    
+    impl Backend for Liar {
+        fn get(&self, _: &Hash256) -> Result<Envelope> {
+            Ok(self.0.clone())
+        }
+        
+        fn contains(&self, _: &Hash256) -> bool {
+            true
+        }
+    }
+    
+    #[test]
+    fn test_remote_hash_checked() {
+        let forged = Envelope::builder(Hash256::hash(b"Node"), b"forged".to_vec()).build();
+        let mut store = FederatedStore::new(Store::new()).remote(Liar(forged.clone()));
+        assert_eq!(store.get(&forged.hash()).unwrap().payload, b"forged");
+        let wanted = Hash256::hash(b"wanted");
+        assert!(matches!(store.fetch(&wanted), Err(Error::HashMismatch { .. })));
+        assert!(store.primary().is_empty());
+    }
+}
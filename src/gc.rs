@@ -0,0 +1,150 @@
+//! Garbage collection planning
+//!
+//! An object is live if it's reachable (see `Store::closure`) from the
+//! given roots or from any ref or tag; everything else is garbage.
+//! `orphans` lists unreachable objects for auditing without that
+//! implicit protection.
+
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::store::{Store, Usage};
+use crate::trace::span;
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+
+/// What a collection would remove, from `Store::gc_plan`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcPlan {
+    /// Unreachable objects, sorted
+    pub hashes: Vec<Hash256>,
+    /// Their total serialized size
+    pub bytes: usize,
+    /// Objects and bytes per `type_hash`
+    pub by_type: HashMap<Hash256, Usage>,
+}
+
+impl Store {
+    /// Hashes of every stored object reachable from `roots`, refs and tags
+    pub(crate) fn live_set(&self, roots: &[Hash256]) -> Result<HashSet<Hash256>> {
+        let mut all_roots = roots.to_vec();
+        all_roots.extend(self.named());
+        Ok(self.closure(&all_roots)?.into_iter().collect())
+    }
+    
+    /// Targets of every ref and tag
+    fn named(&self) -> Vec<Hash256> {
+        self.refs().map(|(_, hash)| *hash)
+            .chain(self.tags().into_iter().map(|(_, hash)| hash))
+            .collect()
+    }
+    
+    /// Stored objects unreachable from `roots`, or from the refs and tags
+    /// if `None`, sorted
+    ///
+    /// Nothing is deleted: in datasets that shouldn't have garbage, an
+    /// orphan usually means a missing link worth investigating.
+    pub fn orphans(&self, roots: Option<&[Hash256]>) -> Result<Vec<Hash256>> {
+        let roots = roots.map_or_else(|| self.named(), <[Hash256]>::to_vec);
+        let reachable: HashSet<_> = self.closure(&roots)?.into_iter().collect();
+        let mut orphans: Vec<_> = self.hashes().filter(|hash| !reachable.contains(*hash)).copied().collect();
+        orphans.sort();
+        Ok(orphans)
+    }
+    
+    /// Report what collecting garbage would remove, without removing it
+    ///
+    /// Refs and tags always count as roots, so named objects are kept.
+    pub fn gc_plan(&self, roots: &[Hash256]) -> Result<GcPlan> {
+        let span = span!("Store::gc_plan", roots = roots.len(), objects = self.len(), garbage = Empty, bytes = Empty);
+        let live = self.live_set(roots)?;
+        let mut plan = GcPlan::default();
+        for hash in self.hashes().filter(|hash| !live.contains(*hash)) {
+            let (Some(type_hash), Some(size)) = (self.stored_type(hash), self.stored_size(hash)) else {
+                continue;
+            };
+            plan.hashes.push(*hash);
+            plan.bytes += size;
+            plan.by_type.entry(type_hash).or_default().add(size);
+        }
+        plan.hashes.sort();
+        span.record("garbage", plan.hashes.len());
+        span.record("bytes", plan.bytes);
+        Ok(plan)
+    }
+}
+
+impl IndexedStore {
+    /// Remove what `Store::gc_plan` reports from the store and every
+    /// index; returns the plan carried out
+    pub fn collect_garbage(&mut self, roots: &[Hash256]) -> Result<GcPlan> {
+        let span = span!("IndexedStore::collect_garbage", roots = roots.len(), collected = Empty, bytes = Empty);
+        let plan = self.store().gc_plan(roots)?;
+        span.record("collected", plan.hashes.len());
+        span.record("bytes", plan.bytes);
+        for hash in &plan.hashes {
+            self.remove_object(hash)?;
+        }
+        #[cfg(feature = "metrics")]
+        self.metrics().record_gc(plan.hashes.len(), plan.bytes);
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    
+    #[test]
+    fn test_gc_plan() {
+        let mut store = Store::new();
+        let node = Hash256::hash(b"Node");
+        let blob = Hash256::hash(b"Blob");
+        let child = store.put(&Envelope::builder(node, b"child".to_vec()).build()).unwrap();
+        let root = store.put(&Envelope::builder(node, b"root".to_vec()).relationship("child", child).build()).unwrap();
+        let tagged = store.put(&Envelope::builder(blob, b"tagged".to_vec()).build()).unwrap();
+        store.tag("v1", tagged).unwrap();
+        let lost = store.put(&Envelope::builder(node, b"lost".to_vec()).build()).unwrap();
+        let big = store.put(&Envelope::builder(blob, vec![0; 100]).build()).unwrap();
+        
+        let plan = store.gc_plan(&[root]).unwrap();
+        let mut garbage = vec![lost, big];
+        garbage.sort();
+        assert_eq!(plan.hashes, garbage);
+        assert_eq!(plan.bytes, store.stored_size(&lost).unwrap() + store.stored_size(&big).unwrap());
+        assert_eq!(plan.by_type[&blob].objects, 1);
+        assert_eq!(plan.by_type[&node].objects, 1);
+        // Nothing was removed
+        assert_eq!(store.len(), 5);
+        assert!(store.gc_plan(&[root, lost, big]).unwrap().hashes.is_empty());
+    }
+    
+    #[test]
+    fn test_collect_garbage() {
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let root = store.put(&Envelope::builder(node, b"root".to_vec()).build()).unwrap();
+        let lost = store.put(&Envelope::builder(node, b"lost".to_vec()).index("name", "lost").build()).unwrap();
+        
+        assert_eq!(store.collect_garbage(&[root]).unwrap().hashes, vec![lost]);
+        assert!(!store.contains(&lost));
+        assert!(store.query_by_field("name", "lost").is_empty());
+        assert!(store.collect_garbage(&[root]).unwrap().hashes.is_empty());
+    }
+    
+    #[test]
+    fn test_orphans() {
+        let mut store = Store::new();
+        let node = Hash256::hash(b"Node");
+        let child = store.put(&Envelope::builder(node, b"child".to_vec()).build()).unwrap();
+        let root = store.put(&Envelope::builder(node, b"root".to_vec()).relationship("child", child).build()).unwrap();
+        let lost = store.put(&Envelope::builder(node, b"lost".to_vec()).build()).unwrap();
+        store.set_ref("main", root).unwrap();
+        
+        assert_eq!(store.orphans(None).unwrap(), vec![lost]);
+        let mut unreachable = vec![root, lost];
+        unreachable.sort();
+        assert_eq!(store.orphans(Some(&[child])).unwrap(), unreachable);
+        assert_eq!(store.len(), 3);
+    }
+}
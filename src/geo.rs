@@ -0,0 +1,260 @@
+//! Spatial index over `GeoPoint` fields
+//!
+//! Points are keyed by a Z-order (Morton) code interleaving 32 bits of
+//! longitude and latitude, so nearby points share key prefixes. A box
+//! query covers itself with a bounded number of quadtree cells, scans
+//! their key ranges and checks each point exactly.
+
+use crate::envelope::GeoPoint;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use std::collections::{BTreeMap, HashMap};
+
+/// Mean Earth radius in meters
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Most key ranges a box query scans; coarser cells are checked exactly
+const MAX_CELLS: usize = 64;
+
+/// Deepest quadtree level: one cell per distinct code
+const MAX_LEVEL: u32 = 32;
+
+impl GeoPoint {
+    /// Great-circle distance in meters (haversine)
+    pub fn distance_to(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// Spread the bits of `v` to the even positions of a u64
+fn spread(v: u32) -> u64 {
+    let mut x = v as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Inverse of `spread`: gather the even bits of `x`
+fn compact(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    ((x | (x >> 16)) & 0x0000_0000_FFFF_FFFF) as u32
+}
+
+fn quantize(v: f64, min: f64, span: f64) -> u32 {
+    (((v - min) / span) * 4_294_967_296.0).clamp(0.0, u32::MAX as f64) as u32
+}
+
+/// Z-order code: longitude in the even bits, latitude in the odd bits
+fn morton(point: &GeoPoint) -> u64 {
+    spread(quantize(point.lon, -180.0, 360.0)) | (spread(quantize(point.lat, -90.0, 180.0)) << 1)
+}
+
+/// A latitude/longitude box that doesn't cross the antimeridian
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+}
+
+impl Rect {
+    fn contains(&self, p: &GeoPoint) -> bool {
+        (self.south..=self.north).contains(&p.lat) && (self.west..=self.east).contains(&p.lon)
+    }
+    
+    fn intersects(&self, other: &Rect) -> bool {
+        self.south <= other.north && other.south <= self.north && self.west <= other.east && other.west <= self.east
+    }
+}
+
+/// A quadtree cell: the codes sharing a `2 * level`-bit prefix
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    level: u32,
+    prefix: u64,
+}
+
+impl Cell {
+    fn bounds(&self) -> Rect {
+        let size = (1u64 << self.level) as f64;
+        let (x, y) = (compact(self.prefix) as f64, compact(self.prefix >> 1) as f64);
+        Rect {
+            south: -90.0 + y * 180.0 / size,
+            west: -180.0 + x * 360.0 / size,
+            north: -90.0 + (y + 1.0) * 180.0 / size,
+            east: -180.0 + (x + 1.0) * 360.0 / size,
+        }
+    }
+    
+    /// First and last code in the cell
+    fn range(&self) -> (u64, u64) {
+        if self.level == 0 {
+            return (0, u64::MAX);
+        }
+        let shift = 64 - 2 * self.level;
+        (self.prefix << shift, (self.prefix << shift) | ((1u64 << shift) - 1))
+    }
+    
+    fn children(self) -> impl Iterator<Item = Cell> {
+        (0..4).map(move |quadrant| Cell { level: self.level + 1, prefix: (self.prefix << 2) | quadrant })
+    }
+}
+
+/// Code ranges covering `rect` with at most about `MAX_CELLS` cells
+fn cover(rect: &Rect) -> Vec<(u64, u64)> {
+    let mut frontier = vec![Cell { level: 0, prefix: 0 }];
+    loop {
+        frontier.retain(|cell| cell.bounds().intersects(rect));
+        let level = frontier.first().map_or(MAX_LEVEL, |cell| cell.level);
+        if level == MAX_LEVEL || frontier.len() * 4 > MAX_CELLS {
+            return frontier.iter().map(Cell::range).collect();
+        }
+        frontier = frontier.into_iter().flat_map(Cell::children).collect();
+    }
+}
+
+/// Points of every `GeoPoint` field, keyed by Z-order code
+#[derive(Debug, Clone, Default)]
+pub struct GeoIndex {
+    fields: HashMap<String, BTreeMap<(u64, Hash256), GeoPoint>>,
+}
+
+impl crate::memory::HeapSize for GeoIndex {
+    fn heap_size(&self) -> usize {
+        self.fields.heap_size()
+    }
+}
+
+impl GeoIndex {
+    pub(crate) fn insert(&mut self, field: &str, point: GeoPoint, hash: Hash256) {
+        self.fields.entry(field.to_string()).or_default().insert((morton(&point), hash), point);
+    }
+    
+    pub(crate) fn remove(&mut self, field: &str, point: GeoPoint, hash: &Hash256) {
+        if let Some(points) = self.fields.get_mut(field) {
+            points.remove(&(morton(&point), *hash));
+        }
+    }
+    
+    pub(crate) fn remove_field(&mut self, field: &str) {
+        self.fields.remove(field);
+    }
+    
+    /// Envelopes whose `field` lies in the box from `south_west` to
+    /// `north_east`; a west edge east of the east edge crosses the
+    /// antimeridian
+    pub fn within(&self, field: &str, south_west: GeoPoint, north_east: GeoPoint) -> Vec<(Hash256, GeoPoint)> {
+        let (south, north) = (south_west.lat, north_east.lat);
+        let rects = if south_west.lon <= north_east.lon {
+            vec![Rect { south, west: south_west.lon, north, east: north_east.lon }]
+        } else {
+            vec![
+                Rect { south, west: south_west.lon, north, east: 180.0 },
+                Rect { south, west: -180.0, north, east: north_east.lon },
+            ]
+        };
+        let Some(points) = self.fields.get(field) else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        for rect in rects.iter().filter(|r| r.south <= r.north) {
+            for (lo, hi) in cover(rect) {
+                let range = (lo, Hash256::from_bytes([0; 32]))..=(hi, Hash256::from_bytes([0xff; 32]));
+                found.extend(points.range(range)
+                    .filter(|(_, point)| rect.contains(point))
+                    .map(|((_, hash), point)| (*hash, *point)));
+            }
+        }
+        found
+    }
+    
+    /// Envelopes whose `field` is within `radius` meters of `center`,
+    /// nearest first, with their distances
+    pub fn near(&self, field: &str, center: GeoPoint, radius: f64) -> Vec<(Hash256, f64)> {
+        // Bounding box of the circle, widened to all longitudes near a pole
+        let angle = radius / EARTH_RADIUS_M;
+        let dlat = angle.to_degrees();
+        let (south, north) = ((center.lat - dlat).max(-90.0), (center.lat + dlat).min(90.0));
+        let ratio = angle.sin() / center.lat.to_radians().cos();
+        let (west, east) = if north >= 90.0 || south <= -90.0 || angle >= std::f64::consts::FRAC_PI_2 || ratio >= 1.0 {
+            (-180.0, 180.0)
+        } else {
+            let dlon = ratio.asin().to_degrees();
+            let wrap = |lon: f64| if lon < -180.0 { lon + 360.0 } else if lon > 180.0 { lon - 360.0 } else { lon };
+            (wrap(center.lon - dlon), wrap(center.lon + dlon))
+        };
+        let mut found: Vec<_> = self.within(field, GeoPoint::new(south, west), GeoPoint::new(north, east))
+            .into_iter()
+            .map(|(hash, point)| (hash, center.distance_to(&point)))
+            .filter(|(_, distance)| *distance <= radius)
+            .collect();
+        found.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        found
+    }
+}
+
+impl IndexedStore {
+    /// Query envelopes whose point field is within `radius` meters of
+    /// `center`, nearest first
+    pub fn query_near(&self, field: &str, center: GeoPoint, radius: f64) -> Vec<Hash256> {
+        self.index().geo().near(field, center, radius).into_iter().map(|(hash, _)| hash).collect()
+    }
+    
+    /// Query envelopes whose point field lies in a bounding box; results
+    /// are sorted by hash
+    pub fn query_bbox(&self, field: &str, south_west: GeoPoint, north_east: GeoPoint) -> Vec<Hash256> {
+        let mut hashes: Vec<_> = self.index().geo().within(field, south_west, north_east)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        hashes.sort();
+        hashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    
+    #[test]
+    fn test_query_near_and_bbox() {
+        let mut store = IndexedStore::new();
+        let place = Hash256::hash(b"Place");
+        let mut put = |lat: f64, lon: f64| {
+            store.put(&Envelope::builder(place, vec![]).index("at", GeoPoint::new(lat, lon)).build()).unwrap()
+        };
+        let eiffel = put(48.8584, 2.2945);
+        let trocadero = put(48.8616, 2.2893);
+        let louvre = put(48.8606, 2.3376);
+        let suva = put(-18.1416, 178.4419);
+        let taveuni = put(-16.8, -179.9);
+        
+        let center = GeoPoint::new(48.8583, 2.2944);
+        assert_eq!(store.query_near("at", center, 1_000.0), vec![eiffel, trocadero]);
+        assert_eq!(store.query_near("at", center, 5_000.0), vec![eiffel, trocadero, louvre]);
+        assert_eq!(store.query_near("at", center, 20.0), vec![eiffel]);
+        assert!(store.query_near("missing", center, 5_000.0).is_empty());
+        
+        let mut paris = vec![eiffel, trocadero, louvre];
+        paris.sort();
+        assert_eq!(store.query_bbox("at", GeoPoint::new(48.8, 2.2), GeoPoint::new(48.9, 2.4)), paris);
+        
+        // Boxes and circles may cross the antimeridian
+        let mut fiji = vec![suva, taveuni];
+        fiji.sort();
+        assert_eq!(store.query_bbox("at", GeoPoint::new(-19.0, 178.0), GeoPoint::new(-16.0, -179.0)), fiji);
+        assert_eq!(store.query_near("at", GeoPoint::new(-17.0, 180.0), 50_000.0), vec![taveuni]);
+    }
+}
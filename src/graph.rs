@@ -0,0 +1,915 @@
+//! Traversal over the relationship graph
+
+use crate::codec::Codec;
+use crate::envelope::Envelope;
+use crate::hash::Hash256;
+use crate::index::{Index, IndexedStore};
+use crate::store::{Store, Usage};
+use crate::Result;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Which edges a traversal follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    /// Edges stored on the envelope (source -> target)
+    #[default]
+    Outgoing,
+    /// Edges pointing at the envelope, via the reverse index
+    Incoming,
+    /// Both of the above
+    Both,
+}
+
+/// What a visitor wants the traversal to do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Keep going, including past this envelope
+    Continue,
+    /// Don't expand this envelope's edges
+    Skip,
+    /// End the traversal
+    Stop,
+}
+
+/// Settings for `IndexedStore::traverse`
+#[derive(Debug, Clone, Default)]
+pub struct TraverseOptions {
+    direction: Direction,
+    rel_types: Option<HashSet<String>>,
+    max_depth: Option<usize>,
+}
+
+impl TraverseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Follow edges in this direction (default: outgoing)
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+    
+    /// Only follow edges of this type; may be repeated (default: all types)
+    pub fn rel_type(mut self, rel_type: impl Into<String>) -> Self {
+        self.rel_types.get_or_insert_with(HashSet::new).insert(rel_type.into());
+        self
+    }
+    
+    /// Don't expand envelopes more than `depth` edges from the start
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+    
+    fn follows(&self, rel_type: &str) -> bool {
+        self.rel_types.as_ref().is_none_or(|types| types.contains(rel_type))
+    }
+}
+
+/// Lazy graph walk from `IndexedStore::bfs` or `IndexedStore::dfs`
+///
+/// Yields `(depth, hash, envelope)` for each stored envelope reached,
+/// starting with the start envelope at depth 0.
+pub struct Walk<'a> {
+    store: &'a IndexedStore,
+    options: TraverseOptions,
+    depth_first: bool,
+    pending: VecDeque<(usize, Hash256)>,
+    seen: HashSet<Hash256>,
+}
+
+impl Walk<'_> {
+    /// Don't expand envelopes more than `depth` edges from the start
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.options = self.options.max_depth(depth);
+        self
+    }
+    
+    /// Follow edges in this direction (default: outgoing)
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.options = self.options.direction(direction);
+        self
+    }
+    
+    /// Only follow edges of this type; may be repeated
+    pub fn rel_type(mut self, rel_type: impl Into<String>) -> Self {
+        self.options = self.options.rel_type(rel_type);
+        self
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = Result<(usize, Hash256, Envelope)>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (depth, hash) = if self.depth_first {
+                self.pending.pop_back()?
+            } else {
+                self.pending.pop_front()?
+            };
+            // Depth-first marks on visit, so deeper paths don't shadow earlier ones
+            if self.depth_first && !self.seen.insert(hash) {
+                continue;
+            }
+            if !self.store.contains(&hash) {
+                continue;
+            }
+            let envelope = match self.store.get(&hash) {
+                Ok(envelope) => envelope,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.options.max_depth.is_none_or(|max| depth < max) {
+                let mut next = self.store.step(&hash, &self.options);
+                if self.depth_first {
+                    // Reversed so the smallest neighbour is popped first
+                    next.reverse();
+                    next.retain(|h| !self.seen.contains(h));
+                } else {
+                    next.retain(|h| self.seen.insert(*h));
+                }
+                self.pending.extend(next.into_iter().map(|h| (depth + 1, h)));
+            }
+            return Some(Ok((depth, hash, envelope)));
+        }
+    }
+}
+
+impl IndexedStore {
+    /// Breadth-first walk from `start`, following outgoing edges by default
+    pub fn bfs(&self, start: &Hash256) -> Walk<'_> {
+        self.walk(start, false)
+    }
+    
+    /// Depth-first walk from `start`, following outgoing edges by default
+    pub fn dfs(&self, start: &Hash256) -> Walk<'_> {
+        self.walk(start, true)
+    }
+    
+    fn walk(&self, start: &Hash256, depth_first: bool) -> Walk<'_> {
+        Walk {
+            store: self,
+            options: TraverseOptions::new(),
+            depth_first,
+            pending: VecDeque::from([(0, *start)]),
+            seen: if depth_first { HashSet::new() } else { HashSet::from([*start]) },
+        }
+    }
+    
+    /// Walk the relationship graph breadth-first from `start`
+    ///
+    /// The visitor sees each stored envelope once, with its distance from
+    /// `start` (which is visited at depth 0). Edges to objects that are
+    /// not in the store are ignored.
+    pub fn traverse<F>(&self, start: &Hash256, options: &TraverseOptions, mut visitor: F) -> Result<()>
+    where
+        F: FnMut(usize, &Hash256, &Envelope) -> Visit,
+    {
+        let mut seen = HashSet::from([*start]);
+        let mut queue = VecDeque::from([(0, *start)]);
+        while let Some((depth, hash)) = queue.pop_front() {
+            if !self.contains(&hash) {
+                continue;
+            }
+            let envelope = self.get(&hash)?;
+            match visitor(depth, &hash, &envelope) {
+                Visit::Stop => break,
+                Visit::Skip => continue,
+                Visit::Continue => {}
+            }
+            if options.max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            for next in self.step(&hash, options) {
+                if seen.insert(next) {
+                    queue.push_back((depth + 1, next));
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Shortest chain of outgoing edges from `from` to `to`, both included
+    ///
+    /// With `rel_filter`, only edges of that type are followed.
+    pub fn shortest_path(&self, from: &Hash256, to: &Hash256, rel_filter: Option<&str>) -> Option<Vec<Hash256>> {
+        let options = match rel_filter {
+            Some(rel_type) => TraverseOptions::new().rel_type(rel_type),
+            None => TraverseOptions::new(),
+        };
+        let mut came_from: HashMap<Hash256, Hash256> = HashMap::new();
+        let mut queue = VecDeque::from([*from]);
+        let mut seen = HashSet::from([*from]);
+        while let Some(hash) = queue.pop_front() {
+            if hash == *to {
+                let mut path = vec![hash];
+                while let Some(prev) = came_from.get(path.last().unwrap()) {
+                    path.push(*prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in self.step(&hash, &options) {
+                if seen.insert(next) {
+                    came_from.insert(next, hash);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+    
+    /// Stored targets of `hash`'s edges of `rel_type`, and sources of
+    /// edges of its inverse pointing at `hash`
+    pub fn outgoing(&self, hash: &Hash256, rel_type: &str) -> Result<Vec<(Hash256, Envelope)>> {
+        let options = TraverseOptions::new().rel_type(rel_type);
+        self.resolve(self.step(hash, &options))
+    }
+    
+    /// Stored sources of `rel_type` edges pointing at `hash`, and targets
+    /// of `hash`'s edges of its inverse
+    pub fn incoming(&self, hash: &Hash256, rel_type: &str) -> Result<Vec<(Hash256, Envelope)>> {
+        let options = TraverseOptions::new().direction(Direction::Incoming).rel_type(rel_type);
+        self.resolve(self.step(hash, &options))
+    }
+    
+    /// Stored envelopes one edge away from `hash`, in either direction
+    pub fn neighbors(&self, hash: &Hash256) -> Result<Vec<(Hash256, Envelope)>> {
+        let options = TraverseOptions::new().direction(Direction::Both);
+        self.resolve(self.step(hash, &options))
+    }
+    
+    /// An envelope together with the stored targets of its relationships,
+    /// by relationship type and in position order
+    ///
+    /// Targets are fetched in one `get_many`, so each is read once however
+    /// many edges point at it; missing targets are left out.
+    pub fn get_with_relationships(&self, hash: &Hash256) -> Result<WithRelationships> {
+        let envelope = self.get(hash)?;
+        let targets = self.get_many(envelope.relationships.iter().map(|r| &r.target))?;
+        let mut by_type: HashMap<_, Vec<_>> = HashMap::new();
+        let rel_types: HashSet<_> = envelope.relationships.iter().map(|r| r.rel_type.as_str()).collect();
+        for rel_type in rel_types {
+            for rel in envelope.relationships_ordered(rel_type) {
+                if let Some(target) = targets.get(&rel.target) {
+                    by_type.entry(rel_type.to_string()).or_default().push((rel.target, target.clone()));
+                }
+            }
+        }
+        Ok(WithRelationships { envelope, by_type })
+    }
+    
+    /// `root` and every stored envelope within `depth` outgoing hops,
+    /// following only `rel_filter` edges if given
+    ///
+    /// Each hop is one `get_many`, so a remote backend can answer the
+    /// whole fetch in `depth + 1` round trips, or one if it implements
+    /// this directly.
+    pub fn get_graph(&self, root: &Hash256, depth: usize, rel_filter: Option<&str>) -> Result<Subgraph> {
+        let mut envelopes = HashMap::from([(*root, self.get(root)?)]);
+        let mut edges = Vec::new();
+        let mut frontier = vec![*root];
+        for _ in 0..depth {
+            let mut next = Vec::new();
+            for hash in &frontier {
+                for rel in &envelopes[hash].relationships {
+                    if rel_filter.is_none_or(|filter| rel.rel_type == filter) {
+                        edges.push((*hash, rel.rel_type.clone(), rel.target));
+                        next.push(rel.target);
+                    }
+                }
+            }
+            let fetched = self.get_many(next.iter().filter(|hash| !envelopes.contains_key(*hash)))?;
+            frontier = fetched.keys().copied().collect();
+            envelopes.extend(fetched);
+            if frontier.is_empty() {
+                break;
+            }
+        }
+        // The last hop's nodes weren't expanded, but their edges among
+        // the fetched nodes belong to the subgraph
+        for hash in &frontier {
+            for rel in &envelopes[hash].relationships {
+                if rel_filter.is_none_or(|filter| rel.rel_type == filter) && envelopes.contains_key(&rel.target) {
+                    edges.push((*hash, rel.rel_type.clone(), rel.target));
+                }
+            }
+        }
+        edges.retain(|(_, _, target)| envelopes.contains_key(target));
+        edges.sort();
+        edges.dedup();
+        Ok(Subgraph { root: *root, envelopes, edges })
+    }
+    
+    fn resolve(&self, hashes: Vec<Hash256>) -> Result<Vec<(Hash256, Envelope)>> {
+        hashes.into_iter()
+            .filter(|h| self.contains(h))
+            .map(|h| Ok((h, self.get(&h)?)))
+            .collect()
+    }
+    
+    /// Neighbours of `hash` reachable by one edge under `options`, sorted
+    ///
+    /// An edge also counts as one of its type's declared inverse (see
+    /// `declare_inverse`) running the other way.
+    pub(crate) fn step(&self, hash: &Hash256, options: &TraverseOptions) -> Vec<Hash256> {
+        let index = self.index();
+        let mut next = index.step(hash, options);
+        if options.direction != Direction::Incoming {
+            for (rel_type, inverse) in self.inverses() {
+                if options.follows(inverse) {
+                    next.extend(index.by_relationship(rel_type, hash).copied());
+                }
+            }
+        }
+        if options.direction != Direction::Outgoing {
+            next.extend(index.outgoing(hash)
+                .filter(|(rel, _)| self.inverse_of(rel).is_some_and(|inverse| options.follows(inverse)))
+                .map(|(_, target)| *target));
+        }
+        next.sort();
+        next.dedup();
+        next
+    }
+    
+    /// Cycles among edges of `rel_type`, or of any type
+    ///
+    /// Content hashing rules out cycles between envelopes built from each
+    /// other's hashes, but edges can still point at hashes computed ahead
+    /// of time. Reports one cycle per back edge found by a depth-first
+    /// search, not every elementary cycle.
+    pub fn find_cycles(&self, rel_type: Option<&str>) -> Vec<Vec<Hash256>> {
+        self.index().cycles(rel_type)
+    }
+    
+    /// Strongly connected components of the relationship graph
+    pub fn components(&self) -> Vec<Vec<Hash256>> {
+        self.index().components()
+    }
+}
+
+impl Index {
+    /// Neighbours of `hash` reachable by one edge under `options`, sorted
+    pub(crate) fn step(&self, hash: &Hash256, options: &TraverseOptions) -> Vec<Hash256> {
+        let mut next = Vec::new();
+        if options.direction != Direction::Incoming {
+            next.extend(self.outgoing(hash)
+                .filter(|(rel, _)| options.follows(rel))
+                .map(|(_, target)| *target));
+        }
+        if options.direction != Direction::Outgoing {
+            match &options.rel_types {
+                Some(types) => {
+                    for rel_type in types {
+                        next.extend(self.by_relationship(rel_type, hash).copied());
+                    }
+                }
+                None => next.extend(self.references_to(hash).copied()),
+            }
+        }
+        next.sort();
+        next.dedup();
+        next
+    }
+    
+    /// Cycles along outgoing edges; see `IndexedStore::find_cycles`
+    pub fn cycles(&self, rel_type: Option<&str>) -> Vec<Vec<Hash256>> {
+        let options = match rel_type {
+            Some(rel_type) => TraverseOptions::new().rel_type(rel_type),
+            None => TraverseOptions::new(),
+        };
+        let mut roots: Vec<Hash256> = self.hashes().copied().collect();
+        roots.sort();
+        
+        // Finished nodes are in `done`; the current path is `stack`
+        let mut done: HashSet<Hash256> = HashSet::new();
+        let mut on_path: HashSet<Hash256> = HashSet::new();
+        let mut cycles = Vec::new();
+        for root in roots {
+            if done.contains(&root) {
+                continue;
+            }
+            let mut stack: Vec<(Hash256, Vec<Hash256>)> = vec![(root, self.step(&root, &options))];
+            on_path.insert(root);
+            while let Some((node, pending)) = stack.last_mut() {
+                let Some(next) = pending.pop() else {
+                    on_path.remove(node);
+                    done.insert(*node);
+                    stack.pop();
+                    continue;
+                };
+                if on_path.contains(&next) {
+                    let start = stack.iter().position(|(h, _)| *h == next).unwrap();
+                    cycles.push(stack[start..].iter().map(|(h, _)| *h).collect());
+                } else if !done.contains(&next) {
+                    on_path.insert(next);
+                    stack.push((next, self.step(&next, &options)));
+                }
+            }
+        }
+        cycles
+    }
+    
+    /// Strongly connected components over outgoing edges of any type
+    ///
+    /// Every indexed envelope, and every target it points at, lands in
+    /// exactly one component; members and components are sorted. Uses an
+    /// iterative Tarjan's algorithm.
+    pub fn components(&self) -> Vec<Vec<Hash256>> {
+        let mut roots: Vec<Hash256> = self.hashes().copied().collect();
+        roots.sort();
+        
+        let mut tarjan = Tarjan::default();
+        for root in roots {
+            if tarjan.order.contains_key(&root) {
+                continue;
+            }
+            tarjan.visit(self, root);
+            while let Some((node, pending)) = tarjan.work.last_mut() {
+                let node = *node;
+                if let Some(next) = pending.pop() {
+                    if !tarjan.order.contains_key(&next) {
+                        tarjan.visit(self, next);
+                    } else if tarjan.on_stack.contains(&next) {
+                        tarjan.lower(node, tarjan.order[&next]);
+                    }
+                    continue;
+                }
+                tarjan.work.pop();
+                if let Some((parent, _)) = tarjan.work.last() {
+                    tarjan.lower(*parent, tarjan.low[&node]);
+                }
+                if tarjan.low[&node] == tarjan.order[&node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = tarjan.stack.pop() {
+                        tarjan.on_stack.remove(&member);
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    tarjan.components.push(component);
+                }
+            }
+        }
+        let mut components = tarjan.components;
+        components.sort();
+        components
+    }
+}
+
+/// Which relationships `Store::hydrate` resolves, level by level
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    follow: Vec<(String, Plan)>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Resolve edges of `rel_type`, then apply `then` to their targets
+    pub fn follow(mut self, rel_type: impl Into<String>, then: Plan) -> Self {
+        self.follow.push((rel_type.into(), then));
+        self
+    }
+    
+    /// Resolve edges of `rel_type` repeatedly, up to `depth` levels deep
+    pub fn follow_depth(self, rel_type: impl Into<String>, depth: usize) -> Self {
+        let rel_type = rel_type.into();
+        let nested = (0..depth).fold(Plan::new(), |plan, _| Plan::new().follow(rel_type.clone(), plan));
+        match nested.follow.into_iter().next() {
+            Some((rel_type, then)) => self.follow(rel_type, then),
+            None => self,
+        }
+    }
+}
+
+/// Envelopes near a root, from `IndexedStore::get_graph`
+#[derive(Debug, Clone)]
+pub struct Subgraph {
+    pub root: Hash256,
+    pub envelopes: HashMap<Hash256, Envelope>,
+    /// (source, relationship type, target) between fetched envelopes, sorted
+    pub edges: Vec<(Hash256, String, Hash256)>,
+}
+
+/// An envelope and its relationships' targets, from
+/// `IndexedStore::get_with_relationships`
+#[derive(Debug, Clone)]
+pub struct WithRelationships {
+    pub envelope: Envelope,
+    /// Relationship type -> stored targets, in position order
+    pub by_type: HashMap<String, Vec<(Hash256, Envelope)>>,
+}
+
+impl WithRelationships {
+    /// Stored targets of one relationship type
+    pub fn targets(&self, rel_type: &str) -> &[(Hash256, Envelope)] {
+        self.by_type.get(rel_type).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// A resolved envelope and the related envelopes its plan asked for
+#[derive(Debug, Clone)]
+pub struct Hydrated {
+    pub hash: Hash256,
+    pub envelope: Envelope,
+    /// (relationship type, resolved target), in relationship order
+    pub children: Vec<(String, Hydrated)>,
+}
+
+impl Hydrated {
+    /// Resolved targets of one relationship type
+    pub fn related<'a>(&'a self, rel_type: &'a str) -> impl Iterator<Item = &'a Hydrated> {
+        self.children.iter()
+            .filter(move |(rel, _)| rel == rel_type)
+            .map(|(_, child)| child)
+    }
+    
+    /// The payload decoded as `T`
+    pub fn value<T: Codec>(&self) -> Result<T> {
+        self.envelope.value()
+    }
+}
+
+impl Store {
+    /// Every stored object reachable from `roots`, roots first
+    ///
+    /// Follows strong relationships and version parents; weak edges and
+    /// objects missing from the store are not followed.
+    pub fn closure(&self, roots: &[Hash256]) -> Result<Vec<Hash256>> {
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<Hash256> = roots.iter().copied().collect();
+        let mut closure = Vec::new();
+        while let Some(hash) = queue.pop_front() {
+            if !self.contains(&hash) || !seen.insert(hash) {
+                continue;
+            }
+            let envelope = self.get(&hash)?;
+            queue.extend(envelope.relationships.iter()
+                .filter(|r| r.is_strong())
+                .map(|r| r.target));
+            queue.extend(envelope.parents().copied());
+            closure.push(hash);
+        }
+        Ok(closure)
+    }
+    
+    /// Object count and serialized bytes of `root`'s closure, e.g. to
+    /// estimate the cost of syncing or exporting it
+    pub fn closure_size(&self, root: &Hash256) -> Result<Usage> {
+        let mut usage = Usage::default();
+        for hash in self.closure(&[*root])? {
+            usage.add(self.stored_size(&hash).unwrap_or(0));
+        }
+        Ok(usage)
+    }
+    
+    /// Resolve `root` and the relationships `plan` names into a tree
+    ///
+    /// Targets missing from the store are left out of the tree.
+    pub fn hydrate(&self, root: &Hash256, plan: &Plan) -> Result<Hydrated> {
+        let envelope = self.get(root)?;
+        let mut children = Vec::new();
+        for rel in &envelope.relationships {
+            for (rel_type, then) in &plan.follow {
+                if rel.rel_type == *rel_type && self.contains(&rel.target) {
+                    children.push((rel_type.clone(), self.hydrate(&rel.target, then)?));
+                }
+            }
+        }
+        Ok(Hydrated { hash: *root, envelope, children })
+    }
+    
+    /// Copy the closure of `roots` into a fresh in-memory store
+    pub fn extract_subgraph(&self, roots: &[Hash256]) -> Result<Store> {
+        let mut subgraph = Store::new();
+        for hash in self.closure(roots)? {
+            subgraph.put(&self.get(&hash)?)?;
+        }
+        Ok(subgraph)
+    }
+}
+
+/// Working state for `Index::components`
+#[derive(Default)]
+struct Tarjan {
+    order: HashMap<Hash256, usize>,
+    low: HashMap<Hash256, usize>,
+    stack: Vec<Hash256>,
+    on_stack: HashSet<Hash256>,
+    /// DFS frames: node and the neighbours it has yet to explore
+    work: Vec<(Hash256, Vec<Hash256>)>,
+    components: Vec<Vec<Hash256>>,
+}
+
+impl Tarjan {
+    fn visit(&mut self, index: &Index, node: Hash256) {
+        let n = self.order.len();
+        self.order.insert(node, n);
+        self.low.insert(node, n);
+        self.stack.push(node);
+        self.on_stack.insert(node);
+        self.work.push((node, index.step(&node, &TraverseOptions::new())));
+    }
+    
+    fn lower(&mut self, node: Hash256, to: usize) {
+        let low = self.low.get_mut(&node).unwrap();
+        *low = (*low).min(to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::tests::Person;
+    use crate::envelope::Relationship;
+    
+    #[test]
+    fn test_traverse() {
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let leaf = store.put(&Envelope::builder(node, b"leaf".to_vec()).build()).unwrap();
+        let mid = store.put(&Envelope::builder(node, b"mid".to_vec()).relationship("child", leaf).build()).unwrap();
+        let root = store.put(&Envelope::builder(node, b"root".to_vec())
+            .relationship("child", mid)
+            .relationship("owner", Hash256::hash(b"not stored"))
+            .build()).unwrap();
+        
+        let mut visited = Vec::new();
+        store.traverse(&root, &TraverseOptions::new(), |depth, hash, _| {
+            visited.push((depth, *hash));
+            Visit::Continue
+        }).unwrap();
+        assert_eq!(visited, vec![(0, root), (1, mid), (2, leaf)]);
+        
+        let mut visited = Vec::new();
+        let options = TraverseOptions::new().direction(Direction::Incoming).rel_type("child").max_depth(1);
+        store.traverse(&leaf, &options, |_, hash, _| {
+            visited.push(*hash);
+            Visit::Continue
+        }).unwrap();
+        assert_eq!(visited, vec![leaf, mid]);
+        
+        let mut count = 0;
+        store.traverse(&root, &TraverseOptions::new(), |_, _, _| {
+            count += 1;
+            Visit::Skip
+        }).unwrap();
+        assert_eq!(count, 1);
+    }
+    
+    #[test]
+    fn test_traverse_through_inverses() {
+        let mut store = IndexedStore::new();
+        store.declare_inverse("author", "wrote");
+        let alice = store.put(&Envelope::builder(Hash256::hash(b"Author"), b"alice".to_vec()).build()).unwrap();
+        let post = store.put(&Envelope::builder(Hash256::hash(b"Post"), b"post".to_vec())
+            .relationship("author", alice)
+            .build()).unwrap();
+        let reply = store.put(&Envelope::builder(Hash256::hash(b"Post"), b"reply".to_vec())
+            .relationship("reply_to", post)
+            .build()).unwrap();
+        
+        // Only `author` is stored, but `wrote` can be followed either way
+        assert_eq!(store.outgoing(&alice, "wrote").unwrap()[0].0, post);
+        assert_eq!(store.incoming(&post, "wrote").unwrap()[0].0, alice);
+        assert!(store.outgoing(&post, "wrote").unwrap().is_empty());
+        
+        let mut visited = Vec::new();
+        let options = TraverseOptions::new().rel_type("wrote").rel_type("reply_to").direction(Direction::Both);
+        store.traverse(&alice, &options, |depth, hash, _| {
+            visited.push((depth, *hash));
+            Visit::Continue
+        }).unwrap();
+        assert_eq!(visited, vec![(0, alice), (1, post), (2, reply)]);
+        
+        let walked: Vec<_> = store.bfs(&alice).rel_type("wrote").map(|r| r.unwrap().1).collect();
+        assert_eq!(walked, vec![alice, post]);
+        assert_eq!(store.shortest_path(&alice, &post, Some("wrote")), Some(vec![alice, post]));
+    }
+    
+    #[test]
+    fn test_neighbors() {
+        let mut store = IndexedStore::new();
+        let post_type = Hash256::hash(b"Post");
+        let alice = store.put(&Envelope::builder(Hash256::hash(b"Author"), b"alice".to_vec()).build()).unwrap();
+        let post = store.put(&Envelope::builder(post_type, b"post".to_vec())
+            .relationship("author", alice)
+            .build()).unwrap();
+        let reply = store.put(&Envelope::builder(post_type, b"reply".to_vec())
+            .relationship("reply_to", post)
+            .build()).unwrap();
+        
+        let authors = store.outgoing(&post, "author").unwrap();
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].1.payload, b"alice");
+        assert!(store.outgoing(&post, "reply_to").unwrap().is_empty());
+        assert_eq!(store.incoming(&post, "reply_to").unwrap()[0].0, reply);
+        
+        let mut around: Vec<_> = store.neighbors(&post).unwrap().into_iter().map(|(h, _)| h).collect();
+        around.sort();
+        let mut expected = vec![alice, reply];
+        expected.sort();
+        assert_eq!(around, expected);
+    }
+    
+    #[test]
+    fn test_bfs_dfs() {
+        // root -> a -> c, root -> b
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let c = store.put(&Envelope::builder(node, b"c".to_vec()).build()).unwrap();
+        let a = store.put(&Envelope::builder(node, b"a".to_vec()).relationship("to", c).build()).unwrap();
+        let b = store.put(&Envelope::builder(node, b"b".to_vec()).build()).unwrap();
+        let root = store.put(&Envelope::builder(node, b"root".to_vec())
+            .relationship("to", a)
+            .relationship("to", b)
+            .build()).unwrap();
+        let (first, second) = if a < b { (a, b) } else { (b, a) };
+        
+        let bfs: Vec<_> = store.bfs(&root).map(|r| r.unwrap()).map(|(d, h, _)| (d, h)).collect();
+        assert_eq!(bfs.len(), 4);
+        assert_eq!(bfs[..3], [(0, root), (1, first), (1, second)]);
+        assert_eq!(bfs[3], (2, c));
+        
+        let dfs: Vec<_> = store.dfs(&root).map(|r| r.unwrap().1).collect();
+        let expected = if first == a { vec![root, a, c, b] } else { vec![root, b, a, c] };
+        assert_eq!(dfs, expected);
+        
+        assert_eq!(store.bfs(&root).max_depth(1).count(), 3);
+        let payloads: Vec<_> = store.dfs(&root).filter_map(|r| r.ok()).map(|(_, _, e)| e.payload).take(2).collect();
+        assert_eq!(payloads.len(), 2);
+    }
+    
+    #[test]
+    fn test_shortest_path() {
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let author = store.put(&Envelope::builder(node, b"author".to_vec()).build()).unwrap();
+        let draft = store.put(&Envelope::builder(node, b"draft".to_vec()).relationship("author", author).build()).unwrap();
+        let doc = store.put(&Envelope::builder(node, b"doc".to_vec())
+            .relationship("based_on", draft)
+            .relationship("cites", author)
+            .build()).unwrap();
+        
+        assert_eq!(store.shortest_path(&doc, &author, None), Some(vec![doc, author]));
+        assert_eq!(store.shortest_path(&doc, &author, Some("based_on")), None);
+        assert_eq!(store.shortest_path(&draft, &author, Some("author")), Some(vec![draft, author]));
+        assert_eq!(store.shortest_path(&doc, &doc, None), Some(vec![doc]));
+        assert_eq!(store.shortest_path(&author, &doc, None), None);
+    }
+    
+    #[test]
+    fn test_find_cycles() {
+        // Edges between precomputed hashes: a -> b -> c -> a, c -> c, d -> a
+        let (a, b, c, d) = (Hash256::hash(b"a"), Hash256::hash(b"b"), Hash256::hash(b"c"), Hash256::hash(b"d"));
+        let node = Hash256::hash(b"Node");
+        let mut index = Index::new();
+        index.add(a, &Envelope::builder(node, vec![]).relationship("dep", b).build());
+        index.add(b, &Envelope::builder(node, vec![]).relationship("dep", c).build());
+        index.add(c, &Envelope::builder(node, vec![]).relationship("dep", a).relationship("self", c).build());
+        index.add(d, &Envelope::builder(node, vec![]).relationship("dep", a).build());
+        
+        let mut cycles = index.cycles(Some("dep"));
+        assert_eq!(cycles.len(), 1);
+        let cycle = cycles.pop().unwrap();
+        assert_eq!(cycle.len(), 3);
+        assert!(cycle.contains(&a) && cycle.contains(&b) && cycle.contains(&c));
+        
+        assert_eq!(index.cycles(None).len(), 2);
+        assert!(index.cycles(Some("self")).contains(&vec![c]));
+        assert!(IndexedStore::new().find_cycles(None).is_empty());
+    }
+    
+    #[test]
+    fn test_components() {
+        // {a, b} reference each other, c -> a, d stands alone (and dangling e)
+        let (a, b, c, d, e) = (Hash256::hash(b"a"), Hash256::hash(b"b"), Hash256::hash(b"c"), Hash256::hash(b"d"), Hash256::hash(b"e"));
+        let node = Hash256::hash(b"Node");
+        let mut index = Index::new();
+        index.add(a, &Envelope::builder(node, vec![]).relationship("knows", b).build());
+        index.add(b, &Envelope::builder(node, vec![]).relationship("knows", a).build());
+        index.add(c, &Envelope::builder(node, vec![]).relationship("knows", a).build());
+        index.add(d, &Envelope::builder(node, vec![]).relationship("knows", e).build());
+        
+        let components = index.components();
+        let mut pair = vec![a, b];
+        pair.sort();
+        assert!(components.contains(&pair));
+        assert!(components.contains(&vec![c]));
+        assert!(components.contains(&vec![d]));
+        assert_eq!(components.iter().map(Vec::len).sum::<usize>(), 5);
+    }
+    
+    #[test]
+    fn test_closure() {
+        let mut store = Store::new();
+        let node = Hash256::hash(b"Node");
+        let author = store.put(&Envelope::builder(node, b"author".to_vec()).build()).unwrap();
+        let related = store.put(&Envelope::builder(node, b"related".to_vec()).build()).unwrap();
+        let v1 = Envelope::builder(node, b"v1".to_vec()).relationship("author", author).build();
+        let h1 = store.put(&v1).unwrap();
+        let v2 = v1.derive()
+            .payload(b"v2".to_vec())
+            .relationships([Relationship::weak("see_also", related)])
+            .build();
+        let h2 = store.put(&v2).unwrap();
+        
+        assert_eq!(store.closure(&[h2]).unwrap(), vec![h2, author, h1]);
+        let size = store.closure_size(&h2).unwrap();
+        assert_eq!(size.objects, 3);
+        assert_eq!(size.bytes, [h2, author, h1].iter().map(|h| store.stored_size(h).unwrap()).sum::<usize>());
+        
+        let subgraph = store.extract_subgraph(&[h2]).unwrap();
+        assert_eq!(subgraph.len(), 3);
+        assert!(!subgraph.contains(&related));
+        assert_eq!(subgraph.get(&h2).unwrap().payload, b"v2");
+    }
+    
+    #[test]
+    fn test_get_with_relationships() {
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let alice = store.put(&Envelope::builder(node, b"alice".to_vec()).build()).unwrap();
+        let bob = store.put(&Envelope::builder(node, b"bob".to_vec()).build()).unwrap();
+        let post = store.put(&Envelope::builder(node, b"post".to_vec())
+            .relationships([Relationship::new("author", bob).with_position(1), Relationship::new("author", alice).with_position(0)])
+            .relationship("reviewer", alice)
+            .relationship("missing", Hash256::hash(b"gone"))
+            .build()).unwrap();
+        
+        let fetched = store.get_with_relationships(&post).unwrap();
+        assert_eq!(fetched.envelope.payload, b"post");
+        let authors: Vec<_> = fetched.targets("author").iter().map(|(hash, e)| (*hash, e.payload.as_slice())).collect();
+        assert_eq!(authors, vec![(alice, &b"alice"[..]), (bob, &b"bob"[..])]);
+        assert_eq!(fetched.targets("reviewer")[0].0, alice);
+        assert!(fetched.targets("missing").is_empty());
+    }
+    
+    #[test]
+    fn test_get_graph() {
+        let mut store = IndexedStore::new();
+        let node = Hash256::hash(b"Node");
+        let leaf = store.put(&Envelope::builder(node, b"leaf".to_vec()).build()).unwrap();
+        let tag = store.put(&Envelope::builder(node, b"tag".to_vec()).build()).unwrap();
+        let mid = store.put(&Envelope::builder(node, b"mid".to_vec())
+            .relationship("child", leaf)
+            .relationship("see", tag)
+            .build()).unwrap();
+        let root = store.put(&Envelope::builder(node, b"root".to_vec())
+            .relationship("child", mid)
+            .relationship("tag", tag)
+            .relationship("child", Hash256::hash(b"missing"))
+            .build()).unwrap();
+        
+        let graph = store.get_graph(&root, 1, None).unwrap();
+        assert_eq!(graph.envelopes.len(), 3);
+        // Including the edge between the two nodes one hop out
+        assert_eq!(graph.edges.len(), 3);
+        assert!(graph.edges.contains(&(mid, "see".to_string(), tag)));
+        let graph = store.get_graph(&root, 5, Some("child")).unwrap();
+        let mut hashes: Vec<_> = graph.envelopes.keys().copied().collect();
+        hashes.sort();
+        let mut expected = vec![root, mid, leaf];
+        expected.sort();
+        assert_eq!(hashes, expected);
+        assert!(graph.edges.contains(&(mid, "child".to_string(), leaf)));
+        assert_eq!(store.get_graph(&root, 0, None).unwrap().envelopes.len(), 1);
+    }
+    
+    #[test]
+    fn test_hydrate() {
+        let mut store = Store::new();
+        let node = Hash256::hash(b"Node");
+        let avatar = store.put(&Envelope::builder(node, b"avatar".to_vec()).build()).unwrap();
+        let person = Person { name: "alice".into(), age: 30 };
+        let author = store.put(&Envelope::from_value(&person).relationship("avatar", avatar).build()).unwrap();
+        let root_tag = store.put(&Envelope::builder(node, b"lang".to_vec()).build()).unwrap();
+        let tag = store.put(&Envelope::builder(node, b"rust".to_vec()).relationship("tag", root_tag).build()).unwrap();
+        let post = store.put(&Envelope::builder(node, b"post".to_vec())
+            .relationship("author", author)
+            .relationship("tag", tag)
+            .build()).unwrap();
+        
+        let plan = Plan::new()
+            .follow("author", Plan::new())
+            .follow_depth("tag", 1);
+        let tree = store.hydrate(&post, &plan).unwrap();
+        assert_eq!(tree.children.len(), 2);
+        let alice = tree.related("author").next().unwrap();
+        assert_eq!(alice.value::<Person>().unwrap(), person);
+        assert!(tree.value::<Person>().is_err());
+        assert!(alice.children.is_empty());
+        let rust = tree.related("tag").next().unwrap();
+        assert!(rust.children.is_empty());
+        
+        let tree = store.hydrate(&post, &Plan::new().follow_depth("tag", 2)).unwrap();
+        let lang = tree.related("tag").next().unwrap().related("tag").next().unwrap();
+        assert_eq!(lang.hash, root_tag);
+    }
+}
@@ -4,7 +4,7 @@ use sha2::{Sha256, Digest};
 use std::fmt;
 
 /// A 256-bit content hash (SHA-256)
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Hash256([u8; 32]);
 
 impl Hash256 {
@@ -74,12 +74,6 @@ impl fmt::Debug for Hash256 {
     }
 }
 
-impl Default for Hash256 {
-    fn default() -> Self {
-        Self([0u8; 32])
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,317 @@
+//! Version-chain maintenance
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::store::Store;
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Outcome of `IndexedStore::squash_history`
+#[derive(Debug, Clone)]
+pub struct Squash {
+    /// New head of the shortened chain
+    pub head: Hash256,
+    /// Old hash -> rewritten hash, for every kept version
+    pub rewritten: Vec<(Hash256, Hash256)>,
+    /// Old versions deleted from the store (only when pruning)
+    pub removed: Vec<Hash256>,
+}
+
+/// The version that last changed an index field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlameEntry {
+    /// Version that introduced the field's current value
+    pub hash: Hash256,
+    /// That version's `created_at`
+    pub created_at: Option<i64>,
+    /// That version's `created_by`
+    pub created_by: Option<Hash256>,
+}
+
+impl IndexedStore {
+    /// The version that gave `field` its value at `head`
+    ///
+    /// Returns `None` if `head` has no such field.
+    pub fn blame(&self, head: &Hash256, field: &str) -> Result<Option<BlameEntry>> {
+        Ok(self.blame_fields(head, Some(field))?.remove(field))
+    }
+    
+    /// `blame` for every index field of `head`
+    pub fn blame_all(&self, head: &Hash256) -> Result<HashMap<String, BlameEntry>> {
+        self.blame_fields(head, None)
+    }
+    
+    fn blame_fields(&self, head: &Hash256, only: Option<&str>) -> Result<HashMap<String, BlameEntry>> {
+        let mut versions = self.history(head);
+        let Some(first) = versions.next() else {
+            return Ok(HashMap::new());
+        };
+        let (hash, current) = first?;
+        let entry = BlameEntry { hash, created_at: current.created_at, created_by: current.created_by };
+        let mut blamed: HashMap<String, BlameEntry> = current.index.keys()
+            .filter(|key| only.is_none_or(|field| field == key.as_str()))
+            .map(|key| (key.clone(), entry))
+            .collect();
+        
+        // Walk back while older versions still agree with `head`'s value
+        let mut pending: Vec<String> = blamed.keys().cloned().collect();
+        for version in versions {
+            if pending.is_empty() {
+                break;
+            }
+            let (hash, envelope) = version?;
+            let entry = BlameEntry { hash, created_at: envelope.created_at, created_by: envelope.created_by };
+            pending.retain(|key| {
+                let same = envelope.index.get(key) == current.index.get(key);
+                if same {
+                    blamed.insert(key.clone(), entry);
+                }
+                same
+            });
+        }
+        Ok(blamed)
+    }
+    
+    /// Rewrite the chain ending at `head` to its newest `keep` versions
+    ///
+    /// Kept versions are re-created with collapsed `previous` links (the
+    /// oldest kept version becomes the root), and refs pointing into the
+    /// chain move to the rewritten versions. With `prune`, the old
+    /// versions are deleted unless still needed: reachable from a ref or
+    /// tag, referenced by another envelope, or continued by a version
+    /// outside the chain (along with everything those reach).
+    pub fn squash_history(&mut self, head: &Hash256, keep: usize, prune: bool) -> Result<Squash> {
+        if keep == 0 {
+            return Err(Error::InvalidArgument("squash_history: keep must be at least 1".to_string()));
+        }
+        let chain: Vec<(Hash256, Envelope)> = self.history(head).collect::<Result<_>>()?;
+        if chain.len() <= keep {
+            return Ok(Squash { head: *head, rewritten: Vec::new(), removed: Vec::new() });
+        }
+        
+        // Re-create kept versions oldest first, so each can link to the last
+        let mut rewritten = Vec::with_capacity(keep);
+        let mut previous = None;
+        for (old_hash, envelope) in chain[..keep].iter().rev() {
+            let envelope = Envelope { previous, ..envelope.clone() };
+            let new_hash = self.put(&envelope)?;
+            rewritten.push((*old_hash, new_hash));
+            previous = Some(new_hash);
+        }
+        let new_head = previous.unwrap();
+        
+        let moved: HashMap<Hash256, Hash256> = rewritten.iter().copied().collect();
+        let refs: Vec<(String, Hash256)> = self.store().refs()
+            .filter_map(|(name, hash)| moved.get(hash).map(|new| (name.to_string(), *new)))
+            .collect();
+        for (name, new) in refs {
+            self.set_ref(name, new)?;
+        }
+        
+        let mut removed = Vec::new();
+        if prune {
+            let in_chain: HashSet<Hash256> = chain.iter().map(|(hash, _)| *hash).collect();
+            let mut roots: Vec<Hash256> = self.store().refs().map(|(_, hash)| *hash)
+                .chain(self.store().tags().into_iter().map(|(_, hash)| hash))
+                .collect();
+            roots.extend(in_chain.iter().filter(|hash| self.is_pinned(hash, &in_chain)));
+            let needed: HashSet<Hash256> = self.store().closure(&roots)?.into_iter().collect();
+            for (old_hash, _) in &chain {
+                if moved.values().any(|new| new == old_hash) || needed.contains(old_hash) {
+                    continue;
+                }
+                if self.remove_object(old_hash)?.is_some() {
+                    removed.push(*old_hash);
+                }
+            }
+        }
+        
+        Ok(Squash { head: new_head, rewritten, removed })
+    }
+    
+    /// Whether another envelope links to `hash`, or a version outside
+    /// `chain` continues it
+    fn is_pinned(&self, hash: &Hash256, chain: &HashSet<Hash256>) -> bool {
+        self.index().references_to(hash).next().is_some()
+            || self.index().successors(hash).any(|successor| !chain.contains(successor))
+    }
+}
+
+impl Store {
+    /// The version of `head`'s chain that was current at `timestamp`
+    ///
+    /// Returns the newest version created at or before `timestamp`;
+    /// versions without `created_at` are skipped.
+    pub fn version_as_of(&self, head: &Hash256, timestamp: i64) -> Result<Option<(Hash256, Envelope)>> {
+        for version in self.history(head) {
+            let (hash, envelope) = version?;
+            if envelope.created_at.is_some_and(|t| t <= timestamp) {
+                return Ok(Some((hash, envelope)));
+            }
+        }
+        Ok(None)
+    }
+    
+    /// Binary-search `head`'s chain for the version where `predicate` flips
+    ///
+    /// Assumes the predicate changes once along the chain: it returns the
+    /// oldest version that agrees with `head`. Returns `None` if the root
+    /// already agrees, i.e. nothing flipped.
+    pub fn history_bisect<F>(&self, head: &Hash256, mut predicate: F) -> Result<Option<(Hash256, Envelope)>>
+    where
+        F: FnMut(&Envelope) -> bool,
+    {
+        let mut chain: Vec<(Hash256, Envelope)> = self.history(head).collect::<Result<_>>()?;
+        chain.reverse();
+        let Some((_, newest)) = chain.last() else {
+            return Ok(None);
+        };
+        let target = predicate(newest);
+        if predicate(&chain[0].1) == target {
+            return Ok(None);
+        }
+        
+        // Invariant: chain[lo] disagrees with head, chain[hi] agrees
+        let (mut lo, mut hi) = (0, chain.len() - 1);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if predicate(&chain[mid].1) == target {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Ok(Some(chain.swap_remove(hi)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn chain(store: &mut IndexedStore, len: usize) -> Vec<Hash256> {
+        let doc_type = Hash256::hash(b"Doc");
+        let mut envelope = Envelope::builder(doc_type, b"v0".to_vec()).created_at(0).build();
+        let mut hashes = vec![store.put(&envelope).unwrap()];
+        for i in 1..len {
+            envelope = envelope.derive()
+                .payload(format!("v{}", i).into_bytes())
+                .created_at(i as i64)
+                .build();
+            hashes.push(store.put(&envelope).unwrap());
+        }
+        hashes
+    }
+    
+    #[test]
+    fn test_squash_history() {
+        let mut store = IndexedStore::new();
+        let hashes = chain(&mut store, 5);
+        let head = hashes[4];
+        store.set_ref("doc/latest", head).unwrap();
+        
+        let squash = store.squash_history(&head, 2, false).unwrap();
+        let versions: Vec<_> = store.history(&squash.head).map(|r| r.unwrap().1).collect();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].payload, b"v4");
+        assert_eq!(versions[1].payload, b"v3");
+        assert_eq!(versions[1].previous, None);
+        assert_eq!(store.get_ref("doc/latest"), Some(squash.head));
+        assert!(squash.removed.is_empty());
+        assert!(store.contains(&hashes[0]));
+    }
+    
+    #[test]
+    fn test_squash_history_prune() {
+        let mut store = IndexedStore::new();
+        let hashes = chain(&mut store, 4);
+        store.tag("v1", hashes[1]).unwrap();
+        
+        // v0 is referenced from elsewhere, v1 is tagged: both survive
+        let note = Envelope::builder(Hash256::hash(b"Note"), vec![])
+            .relationship("about", hashes[0])
+            .build();
+        store.put(&note).unwrap();
+        
+        let squash = store.squash_history(&hashes[3], 1, true).unwrap();
+        assert_eq!(squash.removed.len(), 2);
+        assert!(store.contains(&hashes[0]));
+        assert!(store.contains(&hashes[1]));
+        assert!(!store.contains(&hashes[2]));
+        assert!(!store.contains(&hashes[3]));
+        assert_eq!(store.history(&squash.head).count(), 1);
+        assert!(matches!(store.squash_history(&squash.head, 0, true), Err(Error::InvalidArgument(_))));
+    }
+    
+    #[test]
+    fn test_squash_history_keeps_forked_versions() {
+        let mut store = IndexedStore::new();
+        let hashes = chain(&mut store, 6);
+        // A branch continues v3, so v3 and everything before it stay
+        let v3 = store.get(&hashes[3]).unwrap();
+        store.put(&v3.derive().payload(b"branch".to_vec()).build()).unwrap();
+        
+        let squash = store.squash_history(&hashes[5], 1, true).unwrap();
+        assert_eq!(squash.removed, vec![hashes[5], hashes[4]]);
+        assert!(hashes[..4].iter().all(|hash| store.contains(hash)));
+    }
+    
+    #[test]
+    fn test_version_as_of() {
+        let mut store = IndexedStore::new();
+        let hashes = chain(&mut store, 3);
+        let head = hashes[2];
+        let store = store.store();
+        
+        assert_eq!(store.version_as_of(&head, 1).unwrap().map(|v| v.0), Some(hashes[1]));
+        assert_eq!(store.version_as_of(&head, 100).unwrap().map(|v| v.0), Some(head));
+        assert!(store.version_as_of(&head, -1).unwrap().is_none());
+    }
+    
+    #[test]
+    fn test_blame() {
+        let mut store = IndexedStore::new();
+        let alice = Hash256::hash(b"alice");
+        let bob = Hash256::hash(b"bob");
+        let v1 = Envelope::builder(Hash256::hash(b"Doc"), vec![])
+            .index("title", "Draft")
+            .index("status", "open")
+            .created_at(1)
+            .created_by(alice)
+            .build();
+        let h1 = store.put(&v1).unwrap();
+        let v2 = v1.derive().index("title", "Final").created_at(2).created_by(bob).build();
+        let h2 = store.put(&v2).unwrap();
+        let v3 = v2.derive().payload(b"body".to_vec()).created_at(3).build();
+        let h3 = store.put(&v3).unwrap();
+        
+        let title = store.blame(&h3, "title").unwrap().unwrap();
+        assert_eq!(title.hash, h2);
+        assert_eq!(title.created_by, Some(bob));
+        assert_eq!(store.blame(&h3, "status").unwrap().map(|b| b.hash), Some(h1));
+        assert!(store.blame(&h3, "missing").unwrap().is_none());
+        
+        let all = store.blame_all(&h3).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all["status"].created_at, Some(1));
+    }
+    
+    #[test]
+    fn test_history_bisect() {
+        let mut store = IndexedStore::new();
+        let hashes = chain(&mut store, 8);
+        let store = store.store();
+        
+        let mut calls = 0;
+        let found = store.history_bisect(&hashes[7], |e| {
+            calls += 1;
+            e.created_at.unwrap() >= 5
+        }).unwrap();
+        assert_eq!(found.map(|v| v.0), Some(hashes[5]));
+        assert!(calls < 8);
+        
+        assert!(store.history_bisect(&hashes[7], |_| true).unwrap().is_none());
+    }
+}
@@ -0,0 +1,133 @@
+//! Hooks run around `IndexedStore` puts and gets
+//!
+//! Hooks run in registration order. `before_put` and `before_get` hooks
+//! may rewrite the envelope or veto the operation by returning an error,
+//! which is passed to the caller unchanged.
+
+use crate::envelope::Envelope;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::Result;
+use std::sync::Arc;
+
+type BeforePut = Arc<dyn Fn(&mut Envelope) -> Result<()> + Send + Sync>;
+type AfterPut = Arc<dyn Fn(&Hash256, &Envelope) + Send + Sync>;
+type BeforeGet = Arc<dyn Fn(&Hash256, &mut Envelope) -> Result<()> + Send + Sync>;
+
+/// Registered hooks
+#[derive(Default)]
+pub(crate) struct Hooks {
+    before_put: Vec<BeforePut>,
+    after_put: Vec<AfterPut>,
+    before_get: Vec<BeforeGet>,
+}
+
+impl Hooks {
+    /// The envelope to store after every `before_put` hook, or the first veto
+    ///
+    /// `None` (store `envelope` as is) when no hooks are registered.
+    pub(crate) fn before_put(&self, envelope: &Envelope) -> Result<Option<Envelope>> {
+        if self.before_put.is_empty() {
+            return Ok(None);
+        }
+        let mut envelope = envelope.clone();
+        for hook in &self.before_put {
+            hook(&mut envelope)?;
+        }
+        Ok(Some(envelope))
+    }
+    
+    pub(crate) fn after_put(&self, hash: &Hash256, envelope: &Envelope) {
+        self.after_put.iter().for_each(|hook| hook(hash, envelope));
+    }
+    
+    pub(crate) fn before_get(&self, hash: &Hash256, envelope: &mut Envelope) -> Result<()> {
+        self.before_get.iter().try_for_each(|hook| hook(hash, envelope))
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("before_put", &self.before_put.len())
+            .field("after_put", &self.after_put.len())
+            .field("before_get", &self.before_get.len())
+            .finish()
+    }
+}
+
+impl IndexedStore {
+    /// Run `hook` on every envelope before it's stored
+    ///
+    /// The hook may modify the envelope (e.g. stamp `created_by`), which
+    /// changes the hash `put` returns, or return an error to reject it.
+    pub fn before_put<F>(&mut self, hook: F)
+    where
+        F: Fn(&mut Envelope) -> Result<()> + Send + Sync + 'static,
+    {
+        self.hooks_mut().before_put.push(Arc::new(hook));
+    }
+    
+    /// Run `hook` after every successful put, with the stored hash
+    pub fn after_put<F>(&mut self, hook: F)
+    where
+        F: Fn(&Hash256, &Envelope) + Send + Sync + 'static,
+    {
+        self.hooks_mut().after_put.push(Arc::new(hook));
+    }
+    
+    /// Run `hook` on every envelope `get` returns, before the caller sees it
+    ///
+    /// The hook may rewrite the returned copy (the stored object is
+    /// unaffected) or return an error to deny access.
+    pub fn before_get<F>(&mut self, hook: F)
+    where
+        F: Fn(&Hash256, &mut Envelope) -> Result<()> + Send + Sync + 'static,
+    {
+        self.hooks_mut().before_get.push(Arc::new(hook));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    #[test]
+    fn test_hooks() {
+        let mut store = IndexedStore::new();
+        let writer = Hash256::hash(b"writer");
+        store.before_put(move |envelope| {
+            envelope.created_by = Some(writer);
+            Ok(())
+        });
+        store.before_put(|envelope| match envelope.payload.is_empty() {
+            true => Err(Error::InvalidEnvelope("empty payload".into())),
+            false => Ok(()),
+        });
+        let puts = Arc::new(AtomicUsize::new(0));
+        let counter = puts.clone();
+        store.after_put(move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        store.before_get(|_, envelope| {
+            envelope.payload.make_ascii_uppercase();
+            Ok(())
+        });
+        
+        let note = Hash256::hash(b"Note");
+        let envelope = Envelope::builder(note, b"hello".to_vec()).build();
+        let hash = store.put(&envelope).unwrap();
+        assert_ne!(hash, envelope.hash());
+        let stored = store.get(&hash).unwrap();
+        assert_eq!(stored.created_by, Some(writer));
+        assert_eq!(stored.payload, b"HELLO");
+        assert_eq!(store.store().get(&hash).unwrap().payload, b"hello");
+        
+        // Vetoed puts store nothing and skip the after hooks
+        assert!(matches!(store.put(&Envelope::builder(note, vec![]).build()), Err(Error::InvalidEnvelope(_))));
+        assert_eq!(store.len(), 1);
+        assert_eq!(puts.load(Ordering::SeqCst), 1);
+    }
+}
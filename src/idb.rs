@@ -0,0 +1,182 @@
+//! Stores saved to IndexedDB, for browsers (`js` feature on
+//! `wasm32-unknown-unknown`)
+//!
+//! A browser has no filesystem for `Store::open`, so an `IdbStore` keeps
+//! the store in a database instead, with three object stores: `objects`
+//! (serialized envelopes by hex hash), and `refs` and `tags` (hex hashes
+//! by name). `load` reads it all into an in-memory `Store`, and `save`
+//! writes back the objects put or removed since (found through
+//! `Store::changes_since`) along with every ref and tag. A save is one
+//! transaction, so it lands whole or not at all.
+
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::store::Store;
+use crate::Result;
+use js_sys::{Array, Promise, Uint8Array};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{DomException, IdbDatabase, IdbFactory, IdbRequest, IdbRequestReadyState, IdbTransaction, IdbTransactionMode};
+
+const OBJECTS: &str = "objects";
+const REFS: &str = "refs";
+const TAGS: &str = "tags";
+/// Database version, bumped whenever the object stores change
+const VERSION: u32 = 1;
+
+/// A store kept in an IndexedDB database
+#[derive(Debug)]
+pub struct IdbStore {
+    db: IdbDatabase,
+    /// `Store::last_seq` of the loaded store as of the last load or save
+    saved_seq: u64,
+}
+
+impl IdbStore {
+    /// Open (or create) the database `name`
+    pub async fn open(name: &str) -> Result<Self> {
+        let factory = js_sys::Reflect::get(&js_sys::global(), &"indexedDB".into())
+            .ok()
+            .filter(|factory| !factory.is_undefined())
+            .ok_or_else(|| Error::Storage("IndexedDB is not available".to_string()))?
+            .unchecked_into::<IdbFactory>();
+        let opening = factory.open_with_u32(name, VERSION).map_err(js_error)?;
+        let upgrading = opening.clone();
+        let upgrade = Closure::once_into_js(move || {
+            // Only a new database needs upgrading, to version 1
+            if let Ok(db) = upgrading.result() {
+                let db = db.unchecked_into::<IdbDatabase>();
+                for name in [OBJECTS, REFS, TAGS] {
+                    let _ = db.create_object_store(name);
+                }
+            }
+        });
+        opening.set_onupgradeneeded(Some(upgrade.unchecked_ref()));
+        let db = wait(&opening).await?.unchecked_into();
+        Ok(Self { db, saved_seq: 0 })
+    }
+    
+    /// Read everything saved into a new in-memory store, checking each
+    /// object against its hash
+    ///
+    /// `save` then writes back what changes in that store.
+    pub async fn load(&mut self) -> Result<Store> {
+        let transaction = self.transaction(IdbTransactionMode::Readonly)?;
+        // Every read is made before waiting on any, so the transaction
+        // can't finish in between
+        let mut requests = Vec::new();
+        for name in [OBJECTS, REFS, TAGS] {
+            let objects = transaction.object_store(name).map_err(js_error)?;
+            requests.push(objects.get_all_keys().map_err(js_error)?);
+            requests.push(objects.get_all().map_err(js_error)?);
+        }
+        let mut results = Vec::new();
+        for request in &requests {
+            results.push(wait(request).await?.unchecked_into::<Array>());
+        }
+        
+        let mut store = Store::new();
+        for (key, bytes) in results[0].iter().zip(results[1].iter()) {
+            let envelope = store.deserialize(&Uint8Array::new(&bytes).to_vec())?;
+            let hash = store.put(&envelope)?;
+            let expected = key.as_string().unwrap_or_default();
+            if hash.to_hex() != expected {
+                return Err(Error::HashMismatch { expected, actual: hash.to_hex() });
+            }
+        }
+        store.restore_names(names(&results[2], &results[3])?, names(&results[4], &results[5])?);
+        self.saved_seq = store.last_seq();
+        Ok(store)
+    }
+    
+    /// Write back the objects put or removed since `load` returned
+    /// `store` (or since the last save), and all of its refs and tags
+    pub async fn save(&mut self, store: &Store) -> Result<()> {
+        let transaction = self.transaction(IdbTransactionMode::Readwrite)?;
+        let objects = transaction.object_store(OBJECTS).map_err(js_error)?;
+        let changed: HashSet<_> = store.changes_since(self.saved_seq).iter().map(|change| change.hash).collect();
+        for hash in changed {
+            let key = JsValue::from(hash.to_hex());
+            match store.stored_bytes(&hash) {
+                Some(bytes) => objects.put_with_key(&Uint8Array::from(bytes), &key),
+                None => objects.delete(&key),
+            }.map_err(js_error)?;
+        }
+        let refs: Vec<_> = store.refs().map(|(name, hash)| (name, *hash)).collect();
+        for (name, names) in [(REFS, refs), (TAGS, store.tags())] {
+            let saved = transaction.object_store(name).map_err(js_error)?;
+            saved.clear().map_err(js_error)?;
+            for (name, hash) in names {
+                saved.put_with_key(&hash.to_hex().into(), &name.into()).map_err(js_error)?;
+            }
+        }
+        commit(&transaction).await?;
+        self.saved_seq = store.last_seq();
+        Ok(())
+    }
+    
+    fn transaction(&self, mode: IdbTransactionMode) -> Result<IdbTransaction> {
+        let names = Array::of3(&OBJECTS.into(), &REFS.into(), &TAGS.into());
+        self.db.transaction_with_str_sequence_and_mode(&names, mode).map_err(js_error)
+    }
+}
+
+impl Drop for IdbStore {
+    fn drop(&mut self) {
+        self.db.close();
+    }
+}
+
+/// Hashes by name, from the keys and values of a names object store
+fn names(keys: &Array, values: &Array) -> Result<HashMap<String, Hash256>> {
+    keys.iter().zip(values.iter())
+        .map(|(name, hash)| {
+            let name = name.as_string().ok_or_else(|| Error::Storage("IndexedDB: name is not a string".to_string()))?;
+            let hash = hash.as_string().and_then(|hex| Hash256::from_hex(&hex).ok())
+                .ok_or_else(|| Error::Storage(format!("IndexedDB: '{}' doesn't hold a hash", name)))?;
+            Ok((name, hash))
+        })
+        .collect()
+}
+
+/// Wait for a request to finish, returning its result
+async fn wait(request: &IdbRequest) -> Result<JsValue> {
+    if request.ready_state() == IdbRequestReadyState::Pending {
+        let finished = Promise::new(&mut |resolve, reject| {
+            request.set_onsuccess(Some(&resolve));
+            request.set_onerror(Some(&reject));
+        });
+        // A failure is read from the request below
+        let _ = JsFuture::from(finished).await;
+    }
+    if let Ok(Some(error)) = request.error() {
+        return Err(dom_error(error));
+    }
+    request.result().map_err(js_error)
+}
+
+/// Wait for a transaction to commit
+async fn commit(transaction: &IdbTransaction) -> Result<()> {
+    let committed = Promise::new(&mut |resolve, reject| {
+        transaction.set_oncomplete(Some(&resolve));
+        transaction.set_onerror(Some(&reject));
+        transaction.set_onabort(Some(&reject));
+    });
+    match JsFuture::from(committed).await {
+        Ok(_) => Ok(()),
+        Err(_) => Err(transaction.error().map_or_else(|| Error::Storage("IndexedDB: transaction aborted".to_string()), dom_error)),
+    }
+}
+
+fn dom_error(error: DomException) -> Error {
+    Error::Storage(format!("IndexedDB: {}", error.message()))
+}
+
+fn js_error(error: JsValue) -> Error {
+    match error.dyn_into::<DomException>() {
+        Ok(error) => dom_error(error),
+        Err(error) => Error::Storage(format!("IndexedDB: {:?}", error)),
+    }
+}
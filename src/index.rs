@@ -3,9 +3,127 @@
 //! This is a naive in-memory implementation for exploration.
 //! Production would use proper B-trees, LSM trees, etc.
 
+use crate::audit::Auditor;
+use crate::build::IndexBuild;
 use crate::envelope::{Envelope, IndexValue};
+use crate::error::{ValidationError, Violation};
 use crate::hash::Hash256;
-use std::collections::{HashMap, HashSet};
+use crate::geo::GeoIndex;
+use crate::hook::Hooks;
+use crate::memory::{HeapSize, MemoryUsage};
+use crate::text::TextIndex;
+use crate::trace::{self, span};
+use crate::trigger::Triggers;
+use crate::view::Views;
+use crate::watch::{EventKind, Subscribers};
+use crate::wire::{self, Reader};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
+use std::mem::size_of;
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization form for `FieldOptions::normalize`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Canonical composition
+    Nfc,
+    /// Compatibility composition (folds ligatures, full-width forms, ...)
+    Nfkc,
+}
+
+/// How a field's string values are normalized, at index and query time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FieldOptions {
+    lowercase: bool,
+    trim: bool,
+    form: Option<Normalization>,
+}
+
+impl FieldOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Match regardless of case
+    pub fn lowercase(mut self) -> Self {
+        self.lowercase = true;
+        self
+    }
+    
+    /// Ignore leading and trailing whitespace
+    pub fn trim(mut self) -> Self {
+        self.trim = true;
+        self
+    }
+    
+    /// Apply a Unicode normalization form
+    pub fn normalize(mut self, form: Normalization) -> Self {
+        self.form = Some(form);
+        self
+    }
+    
+    /// Normalize a string; prefixes skip trimming, which would change their meaning
+    fn apply(&self, s: &str, prefix: bool) -> String {
+        let mut s = match self.form {
+            Some(Normalization::Nfc) => s.nfc().collect(),
+            Some(Normalization::Nfkc) => s.nfkc().collect(),
+            None => s.to_string(),
+        };
+        if self.trim && !prefix {
+            s = s.trim().to_string();
+        }
+        if self.lowercase {
+            s = s.to_lowercase();
+        }
+        s
+    }
+}
+
+/// Computes a derived index value from an envelope, or `None` to skip it
+pub(crate) type IndexExtractor = Arc<dyn Fn(&Envelope) -> Option<IndexValue> + Send + Sync>;
+
+/// Pulls (field, value) pairs out of the payloads of one type
+type PayloadExtractor = Arc<dyn Fn(&[u8]) -> Vec<(String, IndexValue)> + Send + Sync>;
+
+/// Derived indexes registered with `IndexedStore::register_index` and
+/// `register_payload_index`
+#[derive(Clone, Default)]
+struct Extractors {
+    fields: Vec<(String, IndexExtractor)>,
+    payloads: HashMap<Hash256, PayloadExtractor>,
+}
+
+impl Extractors {
+    fn contains(&self, field: &str) -> bool {
+        self.fields.iter().any(|(name, _)| name == field)
+    }
+    
+    fn register(&mut self, field: String, extractor: IndexExtractor) {
+        self.fields.retain(|(name, _)| *name != field);
+        self.fields.push((field, extractor));
+    }
+    
+    /// Digest of what is registered: derived field names and payload types
+    fn signature(&self) -> Hash256 {
+        let mut names: Vec<String> = self.fields.iter().map(|(name, _)| name.clone())
+            .chain(self.payloads.keys().map(|type_hash| format!("payload:{}", type_hash.to_hex())))
+            .collect();
+        names.sort();
+        Hash256::hash(names.join("\n").as_bytes())
+    }
+}
+
+impl std::fmt::Debug for Extractors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extractors")
+            .field("fields", &self.fields.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .field("payloads", &self.payloads.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
 
 /// A simple index supporting basic queries
 #[derive(Debug, Default)]
@@ -13,15 +131,72 @@ pub struct Index {
     /// type_hash -> set of envelope hashes
     by_type: HashMap<Hash256, HashSet<Hash256>>,
     
-    /// (field_name, string_value) -> set of envelope hashes
-    by_string_field: HashMap<(String, String), HashSet<Hash256>>,
+    /// type_name -> set of envelope hashes (envelopes that carry a name)
+    by_type_name: HashMap<String, HashSet<Hash256>>,
+    
+    /// (field_name, encoded value) -> set of envelope hashes
+    by_value: HashMap<(String, Vec<u8>), HashSet<Hash256>>,
+    
+    /// field_name -> set of envelope hashes carrying that field (any value, including Null)
+    by_field_name: HashMap<String, HashSet<Hash256>>,
+    
+    /// field_name -> ordered values -> envelope hashes
+    ordered: HashMap<String, BTreeMap<SortKey, HashSet<Hash256>>>,
+    
+    /// field_name -> envelope -> its ordered value
+    values: HashMap<String, HashMap<Hash256, SortKey>>,
+    
+    /// field_name -> normalization applied to its string values
+    field_options: HashMap<String, FieldOptions>,
+    
+    /// Full-text posting lists for fields that opted in
+    text: TextIndex,
+    
+    /// Z-ordered points of every GeoPoint field
+    geo: GeoIndex,
+    
+    /// Materialized views, updated as envelopes are added and removed
+    views: Views,
+    
+    /// Derived fields computed from each envelope
+    extractors: Extractors,
+    
+    /// Every indexed envelope, needed to answer "field is missing"
+    all: HashSet<Hash256>,
     
     /// relationship_type -> target_hash -> set of source envelope hashes
     /// This is the reverse index: "who references X?"
     by_relationship: HashMap<String, HashMap<Hash256, HashSet<Hash256>>>,
     
+    /// (relationship_type, property, encoded value) -> set of source envelope hashes
+    by_relationship_property: HashMap<(String, String, Vec<u8>), HashSet<Hash256>>,
+    
     /// target_hash -> set of source hashes (all relationship types)
     references_to: HashMap<Hash256, HashSet<Hash256>>,
+    
+    /// source_hash -> outgoing (relationship_type, target) edges
+    outgoing: HashMap<Hash256, Vec<(String, Hash256)>>,
+    
+    /// parent version -> versions that supersede it
+    superseded_by: HashMap<Hash256, HashSet<Hash256>>,
+    
+    /// previous version -> versions that claim it as `previous` (first parent)
+    by_previous: HashMap<Hash256, HashSet<Hash256>>,
+    
+    /// valid_from (i64::MIN if open) -> envelopes carrying validity metadata
+    by_valid_from: BTreeMap<i64, HashSet<Hash256>>,
+    
+    /// envelope -> valid_to, for envelopes whose validity period ends
+    valid_to: HashMap<Hash256, i64>,
+    
+    /// (created_at, envelope) in time order
+    by_created_at: BTreeSet<(i64, Hash256)>,
+    
+    /// type_hash -> (created_at, envelope) in time order
+    by_type_created_at: HashMap<Hash256, BTreeSet<(i64, Hash256)>>,
+    
+    /// envelope -> created_at
+    created_at: HashMap<Hash256, i64>,
 }
 
 impl Index {
@@ -29,25 +204,108 @@ impl Index {
         Self::default()
     }
     
+    /// Estimated heap bytes of the indexes, with view members as caches
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let indexes = self.by_type.heap_size()
+            + self.by_type_name.heap_size()
+            + self.by_value.heap_size()
+            + self.by_field_name.heap_size()
+            + self.ordered.heap_size()
+            + self.values.heap_size()
+            + self.field_options.capacity() * (size_of::<(String, FieldOptions)>() + 1)
+            + self.field_options.keys().map(HeapSize::heap_size).sum::<usize>()
+            + self.text.heap_size()
+            + self.geo.heap_size()
+            + self.all.heap_size()
+            + self.by_relationship.heap_size()
+            + self.by_relationship_property.heap_size()
+            + self.references_to.heap_size()
+            + self.outgoing.heap_size()
+            + self.superseded_by.heap_size()
+            + self.by_previous.heap_size()
+            + self.by_valid_from.heap_size()
+            + self.valid_to.heap_size()
+            + self.by_created_at.heap_size()
+            + self.by_type_created_at.heap_size()
+            + self.created_at.heap_size();
+        MemoryUsage { objects: 0, indexes, caches: self.views.heap_size() }
+    }
+    
+    /// An index with the same configuration but no entries
+    pub(crate) fn empty_like(&self) -> Self {
+        Self {
+            field_options: self.field_options.clone(),
+            text: self.text.empty_like(),
+            views: self.views.empty_like(),
+            extractors: self.extractors.clone(),
+            ..Self::default()
+        }
+    }
+    
     /// Index an envelope
     pub fn add(&mut self, hash: Hash256, envelope: &Envelope) {
+        self.all.insert(hash);
+        self.text.add(hash, envelope);
+        
         // Index by type
         self.by_type
             .entry(envelope.type_hash)
             .or_default()
             .insert(hash);
+        if let Some(name) = &envelope.type_name {
+            self.by_type_name
+                .entry(name.clone())
+                .or_default()
+                .insert(hash);
+        }
         
-        // Index string fields
-        for (key, value) in &envelope.index {
-            if let IndexValue::String(s) = value {
-                self.by_string_field
-                    .entry((key.clone(), s.clone()))
-                    .or_default()
-                    .insert(hash);
-            }
+        // Index field presence and values
+        for (key, value) in self.fields(envelope) {
+            self.add_field(hash, key.as_ref(), &value);
         }
         
-        // Index relationships (reverse index)
+        // Index version chain
+        for parent in envelope.parents() {
+            self.superseded_by
+                .entry(*parent)
+                .or_default()
+                .insert(hash);
+        }
+        if let Some(previous) = envelope.previous {
+            self.by_previous
+                .entry(previous)
+                .or_default()
+                .insert(hash);
+        }
+        
+        // Index business-time validity
+        if envelope.valid_from.is_some() || envelope.valid_to.is_some() {
+            self.by_valid_from
+                .entry(envelope.valid_from.unwrap_or(i64::MIN))
+                .or_default()
+                .insert(hash);
+        }
+        if let Some(valid_to) = envelope.valid_to {
+            self.valid_to.insert(hash, valid_to);
+        }
+        
+        // Index creation time
+        if let Some(created_at) = envelope.created_at {
+            self.by_created_at.insert((created_at, hash));
+            self.by_type_created_at
+                .entry(envelope.type_hash)
+                .or_default()
+                .insert((created_at, hash));
+            self.created_at.insert(hash, created_at);
+        }
+        
+        // Index relationships (forward and reverse)
+        if !envelope.relationships.is_empty() {
+            self.outgoing.insert(
+                hash,
+                envelope.relationships.iter().map(|r| (r.rel_type.clone(), r.target)).collect(),
+            );
+        }
         for rel in &envelope.relationships {
             self.by_relationship
                 .entry(rel.rel_type.clone())
@@ -60,26 +318,85 @@ impl Index {
                 .entry(rel.target)
                 .or_default()
                 .insert(hash);
+            
+            for (key, value) in &rel.properties {
+                self.by_relationship_property
+                    .entry(property_key(&rel.rel_type, key, value))
+                    .or_default()
+                    .insert(hash);
+            }
         }
+        
+        // Views test the envelope against the postings just updated
+        let mut views = std::mem::take(&mut self.views);
+        views.add(hash, self);
+        self.views = views;
     }
     
     /// Remove an envelope from the index
     pub fn remove(&mut self, hash: &Hash256, envelope: &Envelope) {
+        self.all.remove(hash);
+        self.text.remove(hash, envelope);
+        self.views.remove(hash);
+        
         // Remove from type index
         if let Some(set) = self.by_type.get_mut(&envelope.type_hash) {
             set.remove(hash);
         }
+        if let Some(set) = envelope.type_name.as_ref().and_then(|name| self.by_type_name.get_mut(name)) {
+            set.remove(hash);
+        }
         
-        // Remove from string field indexes
-        for (key, value) in &envelope.index {
-            if let IndexValue::String(s) = value {
-                if let Some(set) = self.by_string_field.get_mut(&(key.clone(), s.clone())) {
-                    set.remove(hash);
-                }
+        // Remove from presence and value indexes
+        for (key, value) in self.fields(envelope) {
+            let key = key.as_ref();
+            let value = self.normalized(key, &value).into_owned();
+            let value = &value;
+            if let IndexValue::GeoPoint(point) = value {
+                self.geo.remove(key, *point, hash);
+            }
+            if let Some(set) = self.by_field_name.get_mut(key) {
+                set.remove(hash);
+            }
+            if let Some(set) = self.by_value.get_mut(&value_key(key, value)) {
+                set.remove(hash);
+            }
+            if let Some(set) = self.ordered.get_mut(key).and_then(|m| m.get_mut(&SortKey::new(value.clone()))) {
+                set.remove(hash);
+            }
+            if let Some(values) = self.values.get_mut(key) {
+                values.remove(hash);
+            }
+        }
+        
+        // Remove from version chain index
+        for parent in envelope.parents() {
+            if let Some(set) = self.superseded_by.get_mut(parent) {
+                set.remove(hash);
+            }
+        }
+        if let Some(previous) = &envelope.previous {
+            if let Some(set) = self.by_previous.get_mut(previous) {
+                set.remove(hash);
+            }
+        }
+        
+        // Remove from validity index
+        if let Some(set) = self.by_valid_from.get_mut(&envelope.valid_from.unwrap_or(i64::MIN)) {
+            set.remove(hash);
+        }
+        self.valid_to.remove(hash);
+        
+        // Remove from creation time indexes
+        if let Some(created_at) = self.created_at.remove(hash) {
+            self.by_created_at.remove(&(created_at, *hash));
+            if let Some(set) = self.by_type_created_at.get_mut(&envelope.type_hash) {
+                set.remove(&(created_at, *hash));
             }
         }
         
         // Remove from relationship indexes
+        self.outgoing.remove(hash);
         for rel in &envelope.relationships {
             if let Some(type_map) = self.by_relationship.get_mut(&rel.rel_type) {
                 if let Some(set) = type_map.get_mut(&rel.target) {
@@ -89,7 +406,97 @@ impl Index {
             if let Some(set) = self.references_to.get_mut(&rel.target) {
                 set.remove(hash);
             }
+            for (key, value) in &rel.properties {
+                if let Some(set) = self.by_relationship_property.get_mut(&property_key(&rel.rel_type, key, value)) {
+                    set.remove(hash);
+                }
+            }
+        }
+        
+        // Envelopes linking here may have matched views through it
+        let mut views = std::mem::take(&mut self.views);
+        views.retest_referrers(hash, self);
+        self.views = views;
+    }
+    
+    /// An envelope's indexed fields: stored ones not shadowed by a
+    /// derived index, fields from its payload the envelope doesn't
+    /// already store, then the derived ones
+    fn fields<'e>(&self, envelope: &'e Envelope) -> Vec<(Cow<'e, str>, Cow<'e, IndexValue>)> {
+        let mut fields: Vec<_> = envelope.index.iter()
+            .filter(|(key, _)| !self.extractors.contains(key))
+            .map(|(key, value)| (Cow::Borrowed(key.as_str()), Cow::Borrowed(value)))
+            .collect();
+        if let Some(extractor) = self.extractors.payloads.get(&envelope.type_hash) {
+            let mut seen = HashSet::new();
+            for (name, value) in extractor(&envelope.payload) {
+                if !envelope.index.contains_key(&name) && !self.extractors.contains(&name) && seen.insert(name.clone()) {
+                    fields.push((Cow::Owned(name), Cow::Owned(value)));
+                }
+            }
+        }
+        for (name, extractor) in &self.extractors.fields {
+            if let Some(value) = extractor(envelope) {
+                fields.push((Cow::Owned(name.clone()), Cow::Owned(value)));
+            }
+        }
+        fields
+    }
+    
+    /// Post one field value for an envelope
+    fn add_field(&mut self, hash: Hash256, key: &str, value: &IndexValue) {
+        let value = self.normalized(key, value).into_owned();
+        self.by_field_name
+            .entry(key.to_string())
+            .or_default()
+            .insert(hash);
+        
+        self.by_value
+            .entry(value_key(key, &value))
+            .or_default()
+            .insert(hash);
+        
+        if let IndexValue::GeoPoint(point) = value {
+            self.geo.insert(key, point, hash);
+        }
+        self.values
+            .entry(key.to_string())
+            .or_default()
+            .insert(hash, SortKey::new(value.clone()));
+        self.ordered
+            .entry(key.to_string())
+            .or_default()
+            .entry(SortKey::new(value))
+            .or_default()
+            .insert(hash);
+    }
+    
+    /// Register a derived field with its postings already computed
+    ///
+    /// Postings the field had (from stored values it now shadows) are
+    /// replaced, and views are refilled since their members may test it.
+    pub(crate) fn install_extractor(
+        &mut self,
+        name: String,
+        extractor: IndexExtractor,
+        values: impl IntoIterator<Item = (Hash256, IndexValue)>,
+    ) {
+        self.by_field_name.remove(&name);
+        self.by_value.retain(|(field, _), _| *field != name);
+        self.ordered.remove(&name);
+        self.values.remove(&name);
+        self.geo.remove_field(&name);
+        for (hash, value) in values {
+            self.add_field(hash, &name, &value);
         }
+        self.extractors.register(name, extractor);
+        let mut views = std::mem::take(&mut self.views);
+        views.refill(self);
+        self.views = views;
+    }
+    
+    pub(crate) fn has_extractor(&self, name: &str) -> bool {
+        self.extractors.contains(name)
     }
     
     /// Find all envelopes of a given type
@@ -100,14 +507,241 @@ impl Index {
             .flat_map(|s| s.iter())
     }
     
-    /// Find envelopes where field == value
+    /// Find envelopes by human-readable type name
+    pub fn by_type_name(&self, name: &str) -> impl Iterator<Item = &Hash256> {
+        self.by_type_name
+            .get(name)
+            .into_iter()
+            .flat_map(|s| s.iter())
+    }
+    
+    /// Find envelopes where field == value (string fields)
     pub fn by_field(&self, field: &str, value: &str) -> impl Iterator<Item = &Hash256> {
-        self.by_string_field
-            .get(&(field.to_string(), value.to_string()))
+        self.by_value(field, &IndexValue::String(value.to_string()))
+    }
+    
+    /// Find envelopes where field == value, for any value type
+    pub fn by_value(&self, field: &str, value: &IndexValue) -> impl Iterator<Item = &Hash256> {
+        self.by_value
+            .get(&value_key(field, &self.normalized(field, value)))
+            .into_iter()
+            .flat_map(|s| s.iter())
+    }
+    
+    /// Find envelopes that carry a field, whatever its value (including Null)
+    pub fn has_field(&self, field: &str) -> impl Iterator<Item = &Hash256> {
+        self.by_field_name
+            .get(field)
             .into_iter()
             .flat_map(|s| s.iter())
     }
     
+    /// Find envelopes that do not carry a field at all
+    pub fn missing_field<'a>(&'a self, field: &str) -> impl Iterator<Item = &'a Hash256> {
+        let present = self.by_field_name.get(field);
+        self.all
+            .iter()
+            .filter(move |h| present.is_none_or(|s| !s.contains(*h)))
+    }
+    
+    /// Every indexed envelope
+    pub fn hashes(&self) -> impl Iterator<Item = &Hash256> {
+        self.all.iter()
+    }
+    
+    /// Number of indexed envelopes
+    pub fn len(&self) -> usize {
+        self.all.len()
+    }
+    
+    /// Whether nothing is indexed
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty()
+    }
+    
+    /// Whether an envelope is indexed
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.all.contains(hash)
+    }
+    
+    /// Posting set behind `by_type`, for membership tests
+    pub(crate) fn type_set(&self, type_hash: &Hash256) -> Option<&HashSet<Hash256>> {
+        self.by_type.get(type_hash)
+    }
+    
+    /// Posting set behind `by_type_name`
+    pub(crate) fn type_name_set(&self, name: &str) -> Option<&HashSet<Hash256>> {
+        self.by_type_name.get(name)
+    }
+    
+    /// Posting set behind `by_value`
+    pub(crate) fn value_set(&self, field: &str, value: &IndexValue) -> Option<&HashSet<Hash256>> {
+        self.by_value.get(&value_key(field, &self.normalized(field, value)))
+    }
+    
+    /// Find envelopes whose field lies in a range
+    ///
+    /// Bounds must be both numbers or both strings (compared bytewise);
+    /// use `Query::range` for other kinds.
+    pub fn by_range<'a>(&'a self, field: &str, lower: Bound<&IndexValue>, upper: Bound<&IndexValue>) -> impl Iterator<Item = &'a Hash256> + 'a {
+        self.ordered
+            .get(field)
+            .zip(self.range_keys(field, lower, upper))
+            .into_iter()
+            .flat_map(|(m, (numeric, lower, upper))| {
+                m.range((lower, upper)).filter(move |(key, _)| key.is_kind(numeric))
+            })
+            .flat_map(|(_, s)| s.iter())
+    }
+    
+    /// Whether an envelope's value of `field` is one `by_range` finds
+    pub(crate) fn in_range(&self, field: &str, hash: &Hash256, lower: Bound<&IndexValue>, upper: Bound<&IndexValue>) -> bool {
+        match (self.field_key(field, hash), self.range_keys(field, lower, upper)) {
+            (Some(key), Some((numeric, lower, upper))) => key.is_kind(numeric) && (lower, upper).contains(key),
+            _ => false,
+        }
+    }
+    
+    /// `by_range` bounds as ordered keys, with whether they are numeric,
+    /// or `None` if the range is empty
+    fn range_keys(&self, field: &str, lower: Bound<&IndexValue>, upper: Bound<&IndexValue>) -> Option<(bool, Bound<SortKey>, Bound<SortKey>)> {
+        // Numbers and strings share one map; stay within the bounds' kind
+        let numeric = match (lower, upper) {
+            (Bound::Included(v) | Bound::Excluded(v), _) | (_, Bound::Included(v) | Bound::Excluded(v)) => v.is_numeric(),
+            _ => true,
+        };
+        // Bound keys sit between stored keys, so every bound is inclusive
+        let key = |v: &IndexValue, edge| SortKey(self.normalized(field, v).into_owned(), edge);
+        let lower = match lower {
+            Bound::Included(v) => Bound::Included(key(v, Ordering::Less)),
+            Bound::Excluded(v) => Bound::Included(key(v, Ordering::Greater)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let upper = match upper {
+            Bound::Included(v) => Bound::Included(key(v, Ordering::Greater)),
+            Bound::Excluded(v) => Bound::Included(key(v, Ordering::Less)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let empty = matches!((&lower, &upper), (Bound::Included(a), Bound::Included(b)) if a > b);
+        (!empty).then_some((numeric, lower, upper))
+    }
+    
+    /// An envelope's value of `field`, as the index stores it
+    pub(crate) fn field_value(&self, field: &str, hash: &Hash256) -> Option<&IndexValue> {
+        self.field_key(field, hash).map(|key| &key.0)
+    }
+    
+    fn field_key(&self, field: &str, hash: &Hash256) -> Option<&SortKey> {
+        self.values.get(field)?.get(hash)
+    }
+    
+    /// Find envelopes whose string field starts with `prefix`
+    pub fn by_prefix<'a>(&'a self, field: &str, prefix: &'a str) -> impl Iterator<Item = &'a Hash256> + 'a {
+        let prefix = match self.field_options.get(field) {
+            Some(options) => options.apply(prefix, true),
+            None => prefix.to_string(),
+        };
+        let start = SortKey::new(IndexValue::String(prefix.clone()));
+        self.ordered
+            .get(field)
+            .into_iter()
+            .flat_map(move |m| m.range(start.clone()..))
+            .take_while(move |(key, _)| matches!(&key.0, IndexValue::String(s) if s.starts_with(&prefix)))
+            .flat_map(|(_, s)| s.iter())
+    }
+    
+    /// A field's values with their posting sets, lowest first (or highest,
+    /// if `descending`), starting at `from` when given
+    pub(crate) fn ordered_sets<'a>(&'a self, field: &str, descending: bool, from: Option<&IndexValue>) -> Box<dyn Iterator<Item = (&'a IndexValue, &'a HashSet<Hash256>)> + 'a> {
+        let Some(m) = self.ordered.get(field) else {
+            return Box::new(std::iter::empty());
+        };
+        let entries = |(key, set): (&'a SortKey, &'a HashSet<Hash256>)| (&key.0, set);
+        match (from.map(|v| SortKey::new(v.clone())), descending) {
+            (Some(from), true) => Box::new(m.range(..=from).rev().map(entries)),
+            (Some(from), false) => Box::new(m.range(from..).map(entries)),
+            (None, true) => Box::new(m.iter().rev().map(entries)),
+            (None, false) => Box::new(m.iter().map(entries)),
+        }
+    }
+    
+    /// Whether two values occupy the same position in the ordered index
+    pub(crate) fn same_position(a: &IndexValue, b: &IndexValue) -> bool {
+        SortKey::new(a.clone()) == SortKey::new(b.clone())
+    }
+    
+    /// Find envelopes whose string field is within `max_distance` edits of `value`
+    ///
+    /// Yields (distance, hash) pairs in no particular order; scans the
+    /// field's distinct strings.
+    pub fn by_fuzzy<'a>(&'a self, field: &str, value: &str, max_distance: usize) -> impl Iterator<Item = (usize, &'a Hash256)> + 'a {
+        let value = match self.field_options.get(field) {
+            Some(options) => options.apply(value, false),
+            None => value.to_string(),
+        };
+        self.ordered
+            .get(field)
+            .into_iter()
+            .flat_map(|m| m.iter())
+            .filter_map(move |(key, set)| match &key.0 {
+                IndexValue::String(s) => crate::text::edit_distance(s, &value, max_distance).map(|d| (d, set)),
+                _ => None,
+            })
+            .flat_map(|(d, set)| set.iter().map(move |hash| (d, hash)))
+    }
+    
+    /// The full-text index
+    pub fn text(&self) -> &TextIndex {
+        &self.text
+    }
+    
+    pub(crate) fn text_mut(&mut self) -> &mut TextIndex {
+        &mut self.text
+    }
+    
+    /// The spatial index
+    pub fn geo(&self) -> &GeoIndex {
+        &self.geo
+    }
+    
+    /// The materialized views
+    pub fn views(&self) -> &Views {
+        &self.views
+    }
+    
+    pub(crate) fn views_mut(&mut self) -> &mut Views {
+        &mut self.views
+    }
+    
+    /// Normalization configured for a field
+    pub fn field_options(&self, field: &str) -> FieldOptions {
+        self.field_options.get(field).copied().unwrap_or_default()
+    }
+    
+    /// A value as this index stores it for `field`
+    pub(crate) fn normalized<'v>(&self, field: &str, value: &'v IndexValue) -> Cow<'v, IndexValue> {
+        match (self.field_options.get(field), value) {
+            (Some(options), IndexValue::String(s)) => Cow::Owned(IndexValue::String(options.apply(s, false))),
+            _ => Cow::Borrowed(value),
+        }
+    }
+    
+    /// Every distinct value of a field with its posting set (a full scan)
+    pub(crate) fn field_values<'a>(&'a self, field: &'a str) -> impl Iterator<Item = (IndexValue, &'a HashSet<Hash256>)> + 'a {
+        self.by_value
+            .iter()
+            .filter(move |((name, _), _)| name == field)
+            .filter_map(|((_, encoded), set)| {
+                let value = IndexValue::decode(&mut crate::wire::Reader::new(encoded)).ok()?;
+                Some((value, set))
+            })
+    }
+    
+    /// Posting set behind `has_field`
+    pub(crate) fn field_set(&self, field: &str) -> Option<&HashSet<Hash256>> {
+        self.by_field_name.get(field)
+    }
+    
     /// Find envelopes that reference a target (reverse lookup)
     pub fn references_to(&self, target: &Hash256) -> impl Iterator<Item = &Hash256> {
         self.references_to
@@ -124,65 +758,1080 @@ impl Index {
             .into_iter()
             .flat_map(|s| s.iter())
     }
-}
-
-/// A store with integrated indexing
-#[derive(Debug, Default)]
-pub struct IndexedStore {
-    store: crate::store::Store,
-    index: Index,
-}
-
-impl IndexedStore {
-    pub fn new() -> Self {
-        Self::default()
+    
+    /// Versions that list `hash` as a parent
+    pub fn successors(&self, hash: &Hash256) -> impl Iterator<Item = &Hash256> {
+        self.superseded_by
+            .get(hash)
+            .into_iter()
+            .flat_map(|s| s.iter())
     }
     
-    /// Store an envelope and update indexes
-    pub fn put(&mut self, envelope: &Envelope) -> crate::Result<Hash256> {
-        let hash = self.store.put(envelope)?;
-        self.index.add(hash, envelope);
-        Ok(hash)
+    /// Versions whose `previous` is `hash`
+    pub fn children(&self, hash: &Hash256) -> impl Iterator<Item = &Hash256> {
+        self.by_previous
+            .get(hash)
+            .into_iter()
+            .flat_map(|s| s.iter())
     }
     
-    /// Retrieve an envelope by hash
-    pub fn get(&self, hash: &Hash256) -> crate::Result<Envelope> {
-        self.store.get(hash)
+    /// Versions claimed as `previous` by more than one envelope
+    pub fn forks(&self) -> impl Iterator<Item = (&Hash256, &HashSet<Hash256>)> {
+        self.by_previous.iter().filter(|(_, children)| children.len() > 1)
     }
     
-    /// Check if an object exists
-    pub fn contains(&self, hash: &Hash256) -> bool {
-        self.store.contains(hash)
+    /// Whether no indexed version supersedes `hash`
+    pub fn is_head(&self, hash: &Hash256) -> bool {
+        self.superseded_by.get(hash).is_none_or(|s| s.is_empty())
     }
     
-    /// Query by type
-    pub fn query_by_type(&self, type_hash: &Hash256) -> Vec<Hash256> {
-        self.index.by_type(type_hash).copied().collect()
+    /// Envelopes whose validity period contains `timestamp`
+    ///
+    /// Envelopes without `valid_from`/`valid_to` carry no business time
+    /// and never match.
+    pub fn valid_at(&self, timestamp: i64) -> impl Iterator<Item = &Hash256> {
+        self.by_valid_from
+            .range(..=timestamp)
+            .flat_map(|(_, s)| s.iter())
+            .filter(move |h| self.valid_to.get(*h).is_none_or(|to| timestamp < *to))
     }
     
-    /// Query by field value
-    pub fn query_by_field(&self, field: &str, value: &str) -> Vec<Hash256> {
-        self.index.by_field(field, value).copied().collect()
+    /// When an envelope was created, if it records it
+    pub fn created_at(&self, hash: &Hash256) -> Option<i64> {
+        self.created_at.get(hash).copied()
     }
     
-    /// Query reverse references
-    pub fn query_references_to(&self, target: &Hash256) -> Vec<Hash256> {
-        self.index.references_to(target).copied().collect()
+    /// Envelopes created in `[start, end)`, oldest first
+    pub fn created_between(&self, start: i64, end: i64) -> impl Iterator<Item = &Hash256> {
+        let first = Hash256::from_bytes([0; 32]);
+        let range = (start < end).then(|| self.by_created_at.range((start, first)..(end, first)));
+        range.into_iter().flatten().map(|(_, h)| h)
     }
     
-    /// Number of objects
-    pub fn len(&self) -> usize {
-        self.store.len()
+    /// Envelopes of a type that record `created_at`, newest first
+    pub fn most_recent(&self, type_hash: &Hash256) -> impl Iterator<Item = &Hash256> {
+        self.by_type_created_at
+            .get(type_hash)
+            .into_iter()
+            .flat_map(|s| s.iter().rev().map(|(_, h)| h))
     }
     
-    pub fn is_empty(&self) -> bool {
-        self.store.is_empty()
+    /// Outgoing (relationship_type, target) edges of an envelope
+    pub fn outgoing(&self, source: &Hash256) -> impl Iterator<Item = (&str, &Hash256)> {
+        self.outgoing
+            .get(source)
+            .into_iter()
+            .flat_map(|edges| edges.iter().map(|(rel, target)| (rel.as_str(), target)))
     }
-}
-
-#[cfg(test)]
+    
+    /// Number of `rel_type` edges, an upper bound on their sources
+    pub(crate) fn relationship_count(&self, rel_type: &str) -> usize {
+        self.by_relationship.get(rel_type).map_or(0, |targets| targets.values().map(HashSet::len).sum())
+    }
+    
+    /// Find envelopes with a relationship of a type whose property equals a value
+    pub fn by_relationship_property(&self, rel_type: &str, key: &str, value: &IndexValue) -> impl Iterator<Item = &Hash256> {
+        self.by_relationship_property
+            .get(&property_key(rel_type, key, value))
+            .into_iter()
+            .flat_map(|s| s.iter())
+    }
+}
+
+/// Index file in a store directory, holding `IndexedStore::snapshot_index`
+const INDEX_FILE: &str = "index";
+
+/// Bytes of `IndexedStore::snapshot_index` before the `Index::snapshot`
+const INDEX_HEADER_LEN: usize = 68;
+
+/// Format tag and version of `Index::snapshot`
+const SNAPSHOT_MAGIC: &[u8; 8] = b"envidx01";
+
+fn put_set(buf: &mut Vec<u8>, set: &HashSet<Hash256>) {
+    let mut hashes: Vec<_> = set.iter().collect();
+    hashes.sort();
+    wire::put_u32(buf, hashes.len() as u32);
+    hashes.into_iter().for_each(|hash| wire::put_hash(buf, hash));
+}
+
+fn read_set(reader: &mut Reader<'_>) -> crate::Result<HashSet<Hash256>> {
+    (0..reader.u32()?).map(|_| reader.hash()).collect()
+}
+
+/// Map entries sorted by key, so encodings are deterministic
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+impl Index {
+    /// Append the primary structures; secondary ones (ordered values,
+    /// field presence, reverse edges, time order, points) are derived on
+    /// decode
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        put_set(buf, &self.all);
+        wire::put_u32(buf, self.by_type.len() as u32);
+        for (type_hash, set) in sorted(&self.by_type) {
+            wire::put_hash(buf, type_hash);
+            put_set(buf, set);
+        }
+        wire::put_u32(buf, self.by_type_name.len() as u32);
+        for (name, set) in sorted(&self.by_type_name) {
+            wire::put_str(buf, name);
+            put_set(buf, set);
+        }
+        wire::put_u32(buf, self.by_value.len() as u32);
+        for ((field, value), set) in sorted(&self.by_value) {
+            wire::put_str(buf, field);
+            wire::put_bytes(buf, value);
+            put_set(buf, set);
+        }
+        wire::put_u32(buf, self.field_options.len() as u32);
+        for (field, options) in sorted(&self.field_options) {
+            wire::put_str(buf, field);
+            buf.push(u8::from(options.lowercase) | u8::from(options.trim) << 1);
+            buf.push(match options.form {
+                None => 0,
+                Some(Normalization::Nfc) => 1,
+                Some(Normalization::Nfkc) => 2,
+            });
+        }
+        self.text.encode(buf);
+        wire::put_u32(buf, self.outgoing.len() as u32);
+        for (source, edges) in sorted(&self.outgoing) {
+            wire::put_hash(buf, source);
+            wire::put_u32(buf, edges.len() as u32);
+            for (rel_type, target) in edges {
+                wire::put_str(buf, rel_type);
+                wire::put_hash(buf, target);
+            }
+        }
+        wire::put_u32(buf, self.by_relationship_property.len() as u32);
+        for ((rel_type, key, value), set) in sorted(&self.by_relationship_property) {
+            wire::put_str(buf, rel_type);
+            wire::put_str(buf, key);
+            wire::put_bytes(buf, value);
+            put_set(buf, set);
+        }
+        for map in [&self.superseded_by, &self.by_previous] {
+            wire::put_u32(buf, map.len() as u32);
+            for (hash, set) in sorted(map) {
+                wire::put_hash(buf, hash);
+                put_set(buf, set);
+            }
+        }
+        wire::put_u32(buf, self.by_valid_from.len() as u32);
+        for (from, set) in &self.by_valid_from {
+            wire::put_i64(buf, *from);
+            put_set(buf, set);
+        }
+        for map in [&self.valid_to, &self.created_at] {
+            wire::put_u32(buf, map.len() as u32);
+            for (hash, time) in sorted(map) {
+                wire::put_hash(buf, hash);
+                wire::put_i64(buf, *time);
+            }
+        }
+        self.views.encode(buf);
+    }
+    
+    /// Serialize the index, e.g. to checkpoint it
+    ///
+    /// `[magic: 8] [checksum: 32] [index]`, where the checksum is the
+    /// SHA-256 of the encoded index so torn writes are caught on restore.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.encode(&mut body);
+        let mut buf = SNAPSHOT_MAGIC.to_vec();
+        wire::put_hash(&mut buf, &Hash256::hash(&body));
+        buf.extend_from_slice(&body);
+        buf
+    }
+    
+    /// Restore an index from `snapshot` bytes
+    pub fn restore(bytes: &[u8]) -> crate::Result<Self> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(8)? != SNAPSHOT_MAGIC {
+            return Err(crate::Error::Serialization("not an index snapshot".to_string()));
+        }
+        let checksum = reader.hash()?;
+        let body = &bytes[40..];
+        if Hash256::hash(body) != checksum {
+            return Err(crate::Error::Serialization("index snapshot checksum mismatch".to_string()));
+        }
+        Self::decode(&mut Reader::new(body))
+    }
+    
+    /// Decode an index written by `encode`
+    pub(crate) fn decode(reader: &mut Reader<'_>) -> crate::Result<Self> {
+        let mut index = Index { all: read_set(reader)?, ..Index::default() };
+        for _ in 0..reader.u32()? {
+            let type_hash = reader.hash()?;
+            index.by_type.insert(type_hash, read_set(reader)?);
+        }
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            index.by_type_name.insert(name, read_set(reader)?);
+        }
+        for _ in 0..reader.u32()? {
+            let field = reader.string()?;
+            let encoded = reader.bytes()?.to_vec();
+            let set = read_set(reader)?;
+            let value = IndexValue::decode(&mut Reader::new(&encoded))?;
+            if let IndexValue::GeoPoint(point) = value {
+                set.iter().for_each(|hash| index.geo.insert(&field, point, *hash));
+            }
+            index.by_field_name.entry(field.clone()).or_default().extend(&set);
+            let values = index.values.entry(field.clone()).or_default();
+            set.iter().for_each(|hash| {
+                values.insert(*hash, SortKey::new(value.clone()));
+            });
+            // Values with distinct encodings may share an ordered key
+            index.ordered.entry(field.clone()).or_default().entry(SortKey::new(value)).or_default().extend(&set);
+            index.by_value.insert((field, encoded), set);
+        }
+        for _ in 0..reader.u32()? {
+            let field = reader.string()?;
+            let flags = reader.u8()?;
+            let form = match reader.u8()? {
+                0 => None,
+                1 => Some(Normalization::Nfc),
+                _ => Some(Normalization::Nfkc),
+            };
+            index.field_options.insert(field, FieldOptions { lowercase: flags & 1 != 0, trim: flags & 2 != 0, form });
+        }
+        index.text = TextIndex::decode(reader)?;
+        for _ in 0..reader.u32()? {
+            let source = reader.hash()?;
+            let mut edges = Vec::new();
+            for _ in 0..reader.u32()? {
+                let (rel_type, target) = (reader.string()?, reader.hash()?);
+                index.by_relationship
+                    .entry(rel_type.clone())
+                    .or_default()
+                    .entry(target)
+                    .or_default()
+                    .insert(source);
+                index.references_to.entry(target).or_default().insert(source);
+                edges.push((rel_type, target));
+            }
+            index.outgoing.insert(source, edges);
+        }
+        for _ in 0..reader.u32()? {
+            let key = (reader.string()?, reader.string()?, reader.bytes()?.to_vec());
+            index.by_relationship_property.insert(key, read_set(reader)?);
+        }
+        for map in [&mut index.superseded_by, &mut index.by_previous] {
+            for _ in 0..reader.u32()? {
+                let hash = reader.hash()?;
+                map.insert(hash, read_set(reader)?);
+            }
+        }
+        for _ in 0..reader.u32()? {
+            let from = reader.i64()?;
+            index.by_valid_from.insert(from, read_set(reader)?);
+        }
+        for map in [&mut index.valid_to, &mut index.created_at] {
+            for _ in 0..reader.u32()? {
+                let hash = reader.hash()?;
+                map.insert(hash, reader.i64()?);
+            }
+        }
+        for (type_hash, set) in &index.by_type {
+            for hash in set {
+                if let Some(created_at) = index.created_at.get(hash) {
+                    index.by_created_at.insert((*created_at, *hash));
+                    index.by_type_created_at.entry(*type_hash).or_default().insert((*created_at, *hash));
+                }
+            }
+        }
+        index.views = Views::decode(reader, &index)?;
+        if !reader.is_empty() {
+            return Err(crate::Error::Serialization("trailing bytes after index".to_string()));
+        }
+        Ok(index)
+    }
+}
+
+/// Ordering wrapper for the range index
+///
+/// A total order: numbers sort first by exact value (see
+/// `IndexValue::numeric_cmp`), strings follow in byte order, and any
+/// other kind sorts last by its encoding. Equal numbers of different
+/// kinds, like `Int64(5)` and `Float64(5.0)`, are distinct adjacent keys.
+/// The second field is `Equal` for stored keys; range bounds use `Less`
+/// or `Greater` to sort before or after every key equal to their value.
+#[derive(Debug, Clone)]
+pub(crate) struct SortKey(IndexValue, Ordering);
+
+impl SortKey {
+    fn new(value: IndexValue) -> Self {
+        SortKey(value, Ordering::Equal)
+    }
+    
+    /// A number (or string, unless `numeric`)
+    fn is_kind(&self, numeric: bool) -> bool {
+        if numeric { self.0.is_numeric() } else { matches!(self.0, IndexValue::String(_)) }
+    }
+}
+
+impl HeapSize for SortKey {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size()
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let class = |v: &IndexValue| match v {
+            v if v.is_numeric() => 0,
+            IndexValue::String(_) => 1,
+            _ => 2,
+        };
+        let encoded = |v: &IndexValue| value_key("", v).1;
+        let (a, b) = (&self.0, &other.0);
+        class(a).cmp(&class(b))
+            .then_with(|| match (a, b) {
+                (IndexValue::String(a), IndexValue::String(b)) => a.cmp(b),
+                _ => a.numeric_cmp(b).unwrap_or_else(|| encoded(a).cmp(&encoded(b))),
+            })
+            .then(self.1.cmp(&other.1))
+            // Equal numbers of different kinds go by encoding, tag first
+            .then_with(|| encoded(a).cmp(&encoded(b)))
+    }
+}
+
+/// Equality key for a field value: values only match within the same type
+fn value_key(field: &str, value: &IndexValue) -> (String, Vec<u8>) {
+    let mut encoded = Vec::new();
+    value.encode(&mut encoded);
+    (field.to_string(), encoded)
+}
+
+/// Key for a relationship property value
+fn property_key(rel_type: &str, key: &str, value: &IndexValue) -> (String, String, Vec<u8>) {
+    let mut encoded = Vec::new();
+    value.encode(&mut encoded);
+    (rel_type.to_string(), key.to_string(), encoded)
+}
+
+/// Outcome of `IndexedStore::delete`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deletion {
+    /// Whether the object was removed
+    pub removed: bool,
+    /// Stored envelopes that reference the object, sorted
+    pub referenced_by: Vec<Hash256>,
+}
+
+/// A store with integrated indexing
+#[derive(Debug, Default)]
+pub struct IndexedStore {
+    store: crate::store::Store,
+    index: Index,
+    /// relationship_type -> declared inverse type (stored in both directions)
+    inverses: HashMap<String, String>,
+    /// type_hash -> legal outgoing relationship types (unrestricted if absent)
+    allowed_relationships: HashMap<Hash256, HashSet<String>>,
+    /// Fields whose values may be held by one current version only
+    unique_fields: HashSet<String>,
+    /// Whether puts run `Envelope::validate` and report every violation
+    strict: bool,
+    subscribers: Subscribers,
+    hooks: Hooks,
+    triggers: Triggers,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::Metrics,
+    auditor: Option<Auditor>,
+    building: Option<IndexBuild>,
+    /// Objects put through each namespace, loaded from the store directory
+    namespaces: HashMap<String, HashSet<Hash256>>,
+}
+
+impl IndexedStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Open a directory-backed store with its saved indexes
+    ///
+    /// Indexes written by `save_index` are loaded as-is when the store
+    /// still holds exactly the objects they describe. Otherwise (missing
+    /// or stale) they're rebuilt, keeping any saved configuration such as
+    /// field options, text fields and views. An index file that can't be
+    /// read fails the open rather than losing that configuration; delete
+    /// it to rebuild without.
+    pub fn open(dir: impl AsRef<std::path::Path>) -> crate::Result<Self> {
+        Self::with_store(crate::store::Store::open(dir.as_ref())?, dir.as_ref())
+    }
+    
+    /// Open a directory-backed store for reading (see `Store::open_read_only`)
+    pub fn open_read_only(dir: impl AsRef<std::path::Path>) -> crate::Result<Self> {
+        Self::with_store(crate::store::Store::open_read_only(dir.as_ref())?, dir.as_ref())
+    }
+    
+    /// `open`, or `open_read_only` if other readers have the store open
+    pub fn open_or_read_only(dir: impl AsRef<std::path::Path>) -> crate::Result<Self> {
+        Self::with_store(crate::store::Store::open_or_read_only(dir.as_ref())?, dir.as_ref())
+    }
+    
+    /// Open a snapshot of a store another process is writing (see
+    /// `Store::open_snapshot`)
+    pub fn open_snapshot(dir: impl AsRef<std::path::Path>) -> crate::Result<Self> {
+        Self::with_store(crate::store::Store::open_snapshot(dir.as_ref())?, dir.as_ref())
+    }
+    
+    /// Catch a snapshot up with the writer, indexing new objects; returns
+    /// whether anything changed
+    ///
+    /// Removals by the writer mean a full reindex, since the removed
+    /// envelopes can no longer be read to unindex them, and so does a
+    /// compaction, which may have dropped removals from the changelog.
+    pub fn refresh(&mut self) -> crate::Result<bool> {
+        let seq = self.store.last_seq();
+        if !self.store.refresh()? {
+            return Ok(false);
+        }
+        let changes = self.store.changes_since(seq).to_vec();
+        if seq < self.store.compacted_seq() || changes.iter().any(|change| change.op == EventKind::Delete) {
+            self.reindex(|_| {})?;
+            return Ok(true);
+        }
+        for change in changes {
+            let envelope = self.store.get(&change.hash)?;
+            self.index_add(change.hash, &envelope);
+        }
+        Ok(true)
+    }
+    
+    fn with_store(store: crate::store::Store, dir: &std::path::Path) -> crate::Result<Self> {
+        let mut indexed = Self { store, ..Self::default() };
+        indexed.load_namespaces(dir)?;
+        match std::fs::read(dir.join(INDEX_FILE)) {
+            // Rebuilds the entries itself if they're stale
+            Ok(bytes) => {
+                indexed.restore_index(&bytes)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => indexed.reindex(|_| {})?,
+            Err(e) => return Err(e.into()),
+        }
+        Ok(indexed)
+    }
+    
+    /// Snapshot the indexes along with a fingerprint of the stored objects
+    ///
+    /// `[object count: 4] [hash digest: 32] [extractors: 32] [Index::snapshot]`,
+    /// where `extractors` digests the names of the registered derived
+    /// indexes, whose entries the snapshot includes.
+    pub fn snapshot_index(&self) -> Vec<u8> {
+        let (count, digest) = self.store.fingerprint();
+        let mut buf = Vec::new();
+        wire::put_u32(&mut buf, count as u32);
+        wire::put_hash(&mut buf, &digest);
+        wire::put_hash(&mut buf, &self.index.extractors.signature());
+        buf.extend_from_slice(&self.index.snapshot());
+        buf
+    }
+    
+    /// Replace the indexes with a `snapshot_index` checkpoint
+    ///
+    /// Returns whether the snapshot matched the stored objects and the
+    /// registered derived indexes. If either changed since, its entries
+    /// are rebuilt from the store and only its configuration is kept:
+    /// derived entries from extractors that are no longer (or not yet)
+    /// registered would otherwise linger or be missing.
+    pub fn restore_index(&mut self, bytes: &[u8]) -> crate::Result<bool> {
+        let mut reader = Reader::new(bytes);
+        let fingerprint = (reader.u32()? as usize, reader.hash()?);
+        let extractors = reader.hash()?;
+        let mut index = Index::restore(&bytes[INDEX_HEADER_LEN..])?;
+        // Extractors are code, not data: keep the ones registered here
+        index.extractors = std::mem::take(&mut self.index.extractors);
+        self.index = index;
+        if fingerprint == self.store.fingerprint() && extractors == self.index.extractors.signature() {
+            return Ok(true);
+        }
+        self.reindex(|_| {})?;
+        Ok(false)
+    }
+    
+    /// Write the indexes next to a directory-backed store, so `open`
+    /// can skip the rebuild; does nothing for in-memory stores
+    ///
+    /// Saved indexes go stale as soon as objects change, so call this
+    /// before shutting down.
+    pub fn save_index(&self) -> crate::Result<()> {
+        let Some(dir) = self.store.dir() else {
+            return Ok(());
+        };
+        self.store.check_writable()?;
+        crate::store::replace_file(&dir.join(INDEX_FILE), &self.snapshot_index())
+    }
+    
+    /// Normalize a field's string values for indexing and lookups
+    ///
+    /// Existing envelopes are reindexed with the new options.
+    pub fn set_field_options(&mut self, field: impl Into<String>, options: FieldOptions) -> crate::Result<()> {
+        let field = field.into();
+        self.reindex(|index| {
+            index.field_options.insert(field, options);
+        })
+    }
+    
+    /// Maintain a derived field computed from each envelope
+    ///
+    /// `register_index("word_count_bucket", |e| ...)` indexes the returned
+    /// value under that name on every put and removal, queryable like a
+    /// stored field (which it shadows if both exist). Existing envelopes
+    /// are reindexed. Extractors aren't saved with the index, so register
+    /// them again after `open`.
+    pub fn register_index<F>(&mut self, name: impl Into<String>, extractor: F) -> crate::Result<()>
+    where
+        F: Fn(&Envelope) -> Option<IndexValue> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.reindex(|index| index.extractors.register(name, Arc::new(extractor)))
+    }
+    
+    /// Index values extracted from the payload of every envelope of a type
+    ///
+    /// The extractor decodes the payload and returns (field, value) pairs,
+    /// which are indexed as if passed to `index()`; fields the envelope
+    /// stores itself take precedence, and only the first pair per field
+    /// counts. Replaces any extractor for the type and reindexes existing
+    /// envelopes. Like `register_index`, register again after `open`.
+    pub fn register_payload_index<F>(&mut self, type_hash: Hash256, extractor: F) -> crate::Result<()>
+    where
+        F: Fn(&[u8]) -> Vec<(String, IndexValue)> + Send + Sync + 'static,
+    {
+        self.reindex(|index| {
+            index.extractors.payloads.insert(type_hash, Arc::new(extractor));
+        })
+    }
+    
+    /// `register_payload_index` for a `Codec` type: the extractor gets
+    /// the decoded value, and payloads that fail to decode index nothing
+    pub fn register_codec_index<T, F>(&mut self, extractor: F) -> crate::Result<()>
+    where
+        T: crate::codec::Codec,
+        F: Fn(&T) -> Vec<(String, IndexValue)> + Send + Sync + 'static,
+    {
+        self.register_payload_index(T::type_hash(), move |payload| match T::decode(payload) {
+            Ok(value) => extractor(&value),
+            Err(_) => Vec::new(),
+        })
+    }
+    
+    /// Rebuild the indexes from the store after changing their configuration
+    pub(crate) fn reindex(&mut self, configure: impl FnOnce(&mut Index)) -> crate::Result<()> {
+        let _span = span!("IndexedStore::reindex", objects = self.store.len());
+        self.finish_build()?;
+        let mut index = self.index.empty_like();
+        configure(&mut index);
+        for (hash, envelope) in self.store.iter() {
+            index.add(hash, &envelope.decode()?);
+        }
+        self.index = index;
+        trace::event!(info, objects = self.store.len(), "reindexed");
+        Ok(())
+    }
+    
+    /// Store an envelope and update indexes
+    ///
+    /// `before_put` hooks see the envelope first, so the returned hash is
+    /// that of the envelope as they left it.
+    pub fn put(&mut self, envelope: &Envelope) -> crate::Result<Hash256> {
+        let span = span!("IndexedStore::put", type_hash = %envelope.type_hash, hash = Empty, new = Empty);
+        #[cfg(feature = "metrics")]
+        let started = crate::clock::Stopwatch::start();
+        let hooked = self.hooks.before_put(envelope)?;
+        let envelope = hooked.as_ref().unwrap_or(envelope);
+        self.check(envelope)?;
+        let is_new = !self.store.contains(&envelope.hash());
+        let hash = self.store.put(envelope)?;
+        if is_new {
+            self.index_add(hash, envelope);
+        }
+        self.notify(EventKind::Put, hash, envelope);
+        if is_new {
+            self.triggers.fire(&self.index, &hash, envelope);
+            self.audit("put", hash, None)?;
+        }
+        self.hooks.after_put(&hash, envelope);
+        span.record_display("hash", hash);
+        span.record("new", is_new);
+        #[cfg(feature = "metrics")]
+        self.metrics.record_put(started, is_new.then(|| self.store.stored_size(&hash)).flatten());
+        Ok(hash)
+    }
+    
+    /// Retrieve an envelope by hash, as rewritten by any `before_get` hooks
+    pub fn get(&self, hash: &Hash256) -> crate::Result<Envelope> {
+        let span = span!("IndexedStore::get", hash = %hash, found = Empty);
+        #[cfg(feature = "metrics")]
+        let started = crate::clock::Stopwatch::start();
+        let found = self.store.get(hash);
+        span.record("found", found.is_ok());
+        if found.is_err() {
+            trace::event!(trace, "not found");
+        }
+        #[cfg(feature = "metrics")]
+        self.metrics.record_get(started, found.is_ok());
+        let mut envelope = found?;
+        self.hooks.before_get(hash, &mut envelope)?;
+        Ok(envelope)
+    }
+    
+    /// An envelope without its payload (see `Store::get_metadata`)
+    ///
+    /// `before_get` hooks don't run, since they may need the payload.
+    pub fn get_metadata(&self, hash: &Hash256) -> crate::Result<Envelope> {
+        self.store.get_metadata(hash)
+    }
+    
+    /// Retrieve every stored envelope among `hashes`, each once; missing
+    /// ones are left out
+    pub fn get_many<'a>(&self, hashes: impl IntoIterator<Item = &'a Hash256>) -> crate::Result<HashMap<Hash256, Envelope>> {
+        let mut found = HashMap::new();
+        for hash in hashes {
+            if !found.contains_key(hash) && self.store.contains(hash) {
+                found.insert(*hash, self.get(hash)?);
+            }
+        }
+        Ok(found)
+    }
+    
+    /// Check if an object exists
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.store.contains(hash)
+    }
+    
+    /// Store mutations after sequence number `seq` (see `Store::changes_since`)
+    pub fn changes_since(&self, seq: u64) -> &[crate::store::Change] {
+        self.store.changes_since(seq)
+    }
+    
+    /// The underlying store
+    pub fn store(&self) -> &crate::store::Store {
+        &self.store
+    }
+    
+    /// The indexes over the store
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+    
+    /// Estimated heap bytes of the store and its indexes
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.store.memory_usage() + self.index.memory_usage()
+    }
+    
+    /// Counters and latencies recorded by this store
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &crate::metrics::Metrics {
+        &self.metrics
+    }
+    
+    pub(crate) fn index_mut(&mut self) -> &mut Index {
+        &mut self.index
+    }
+    
+    /// Index a stored envelope, in any build in progress too
+    pub(crate) fn index_add(&mut self, hash: Hash256, envelope: &Envelope) {
+        self.index.add(hash, envelope);
+        if let Some(build) = &mut self.building {
+            build.add(hash, envelope);
+        }
+    }
+    
+    pub(crate) fn building(&self) -> Option<&IndexBuild> {
+        self.building.as_ref()
+    }
+    
+    pub(crate) fn building_mut(&mut self) -> &mut Option<IndexBuild> {
+        &mut self.building
+    }
+    
+    pub(crate) fn store_mut(&mut self) -> &mut crate::store::Store {
+        &mut self.store
+    }
+    
+    pub(crate) fn auditor(&self) -> Option<&Auditor> {
+        self.auditor.as_ref()
+    }
+    
+    pub(crate) fn auditor_mut(&mut self) -> &mut Option<Auditor> {
+        &mut self.auditor
+    }
+    
+    pub(crate) fn namespace_writes(&self, namespace: &str) -> Option<&HashSet<Hash256>> {
+        self.namespaces.get(namespace)
+    }
+    
+    pub(crate) fn namespace_writes_mut(&mut self, namespace: &str) -> &mut HashSet<Hash256> {
+        self.namespaces.entry(namespace.to_string()).or_default()
+    }
+    
+    pub(crate) fn subscribers_mut(&mut self) -> &mut Subscribers {
+        &mut self.subscribers
+    }
+    
+    pub(crate) fn hooks_mut(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+    
+    pub(crate) fn triggers_mut(&mut self) -> &mut Triggers {
+        &mut self.triggers
+    }
+    
+    /// Remove an object from the store and its indexes
+    pub(crate) fn remove_object(&mut self, hash: &Hash256) -> crate::Result<Option<Envelope>> {
+        if !self.store.contains(hash) {
+            return Ok(None);
+        }
+        let envelope = self.store.get(hash)?;
+        self.store.remove(hash)?;
+        self.index.remove(hash, &envelope);
+        if let Some(build) = &mut self.building {
+            build.remove(hash);
+        }
+        self.notify(EventKind::Delete, *hash, &envelope);
+        self.audit("delete", *hash, None)?;
+        #[cfg(feature = "metrics")]
+        self.metrics.record_removal();
+        Ok(Some(envelope))
+    }
+    
+    /// Delete an object from the store and every index
+    ///
+    /// Envelopes that still point at it, by relationship or as a parent
+    /// version, are reported in `referenced_by`. Unless `force` is set
+    /// such an object is kept; forced deletes leave those links dangling.
+    pub fn delete(&mut self, hash: &Hash256, force: bool) -> crate::Result<Deletion> {
+        let mut referenced_by: Vec<_> = self.index.references_to(hash)
+            .chain(self.index.successors(hash))
+            .filter(|h| *h != hash)
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        referenced_by.sort();
+        let removed = if referenced_by.is_empty() || force {
+            self.remove_object(hash)?.is_some()
+        } else {
+            trace::event!(debug, hash = %hash, referenced_by = referenced_by.len(), "kept");
+            false
+        };
+        Ok(Deletion { removed, referenced_by })
+    }
+    
+    /// Limit what the whole store may hold (see `Store::set_quota`)
+    pub fn set_quota(&mut self, quota: crate::store::Quota) {
+        self.store.set_quota(quota);
+    }
+    
+    /// Limit what objects of one type may hold
+    pub fn set_type_quota(&mut self, type_hash: Hash256, quota: crate::store::Quota) {
+        self.store.set_type_quota(type_hash, quota);
+    }
+    
+    /// Point a named ref at a stored object
+    pub fn set_ref(&mut self, name: impl Into<String>, hash: Hash256) -> crate::Result<()> {
+        let name = name.into();
+        self.store.set_ref(name.clone(), hash)?;
+        self.audit("set_ref", hash, Some(&name))
+    }
+    
+    /// Resolve a named ref
+    pub fn get_ref(&self, name: &str) -> Option<Hash256> {
+        self.store.get_ref(name)
+    }
+    
+    /// Move a ref only if it still points at `expected`
+    pub fn compare_and_swap_ref(&mut self, name: &str, expected: Option<Hash256>, new: Hash256) -> crate::Result<()> {
+        self.store.compare_and_swap_ref(name, expected, new)?;
+        self.audit("set_ref", new, Some(name))
+    }
+    
+    /// Tag a stored object permanently
+    pub fn tag(&mut self, name: impl Into<String>, hash: Hash256) -> crate::Result<()> {
+        let name = name.into();
+        let is_new = self.store.get_tag(&name).is_none();
+        self.store.tag(name.clone(), hash)?;
+        if is_new {
+            self.audit("tag", hash, Some(&name))?;
+        }
+        Ok(())
+    }
+    
+    /// Resolve a tag
+    pub fn get_tag(&self, name: &str) -> Option<Hash256> {
+        self.store.get_tag(name)
+    }
+    
+    /// Walk a version chain, newest to oldest
+    pub fn history(&self, hash: &Hash256) -> crate::store::History<'_> {
+        self.store.history(hash)
+    }
+    
+    /// Query by type
+    pub fn query_by_type(&self, type_hash: &Hash256) -> Vec<Hash256> {
+        self.index.by_type(type_hash).copied().collect()
+    }
+    
+    /// Query by field value
+    pub fn query_by_field(&self, field: &str, value: &str) -> Vec<Hash256> {
+        self.index.by_field(field, value).copied().collect()
+    }
+    
+    /// Query by field value of any type
+    pub fn query_by_value(&self, field: &str, value: &IndexValue) -> Vec<Hash256> {
+        self.index.by_value(field, value).copied().collect()
+    }
+    
+    /// Query envelopes whose field lies in a range, e.g. `1708000000i64..`
+    pub fn query_range<V: Into<IndexValue> + Clone>(&self, field: &str, range: impl RangeBounds<V>) -> Vec<Hash256> {
+        self.query(&crate::query::Query::range(field, range))
+    }
+    
+    /// Query envelopes whose string field starts with `prefix`
+    pub fn query_prefix(&self, field: &str, prefix: &str) -> Vec<Hash256> {
+        self.index.by_prefix(field, prefix).copied().collect()
+    }
+    
+    /// Query envelopes whose string field is within `max_distance` edits of `value`
+    ///
+    /// Closest matches come first, then by hash: `query_fuzzy("name", "Alcie", 1)`
+    /// finds "Alice" as well as an exact "Alcie".
+    pub fn query_fuzzy(&self, field: &str, value: &str, max_distance: usize) -> Vec<Hash256> {
+        let mut matches: Vec<_> = self.index.by_fuzzy(field, value, max_distance).collect();
+        matches.sort();
+        matches.into_iter().map(|(_, hash)| *hash).collect()
+    }
+    
+    /// Query envelopes that carry a field
+    pub fn query_has_field(&self, field: &str) -> Vec<Hash256> {
+        self.index.has_field(field).copied().collect()
+    }
+    
+    /// Query envelopes that lack a field
+    pub fn query_missing_field(&self, field: &str) -> Vec<Hash256> {
+        self.index.missing_field(field).copied().collect()
+    }
+    
+    /// Query envelopes valid at a business time
+    pub fn query_valid_at(&self, timestamp: i64) -> Vec<Hash256> {
+        self.index.valid_at(timestamp).copied().collect()
+    }
+    
+    /// Query envelopes created in `[start, end)`, oldest first
+    pub fn query_created_between(&self, start: i64, end: i64) -> Vec<Hash256> {
+        self.index.created_between(start, end).copied().collect()
+    }
+    
+    /// The `n` most recently created envelopes of a type, newest first
+    pub fn most_recent(&self, type_hash: &Hash256, n: usize) -> Vec<Hash256> {
+        self.index.most_recent(type_hash).take(n).copied().collect()
+    }
+    
+    /// Restrict the outgoing relationship types allowed on envelopes of a type
+    pub fn allow_relationships<S: Into<String>>(&mut self, type_hash: Hash256, rel_types: impl IntoIterator<Item = S>) {
+        self.allowed_relationships
+            .entry(type_hash)
+            .or_default()
+            .extend(rel_types.into_iter().map(Into::into));
+    }
+    
+    /// Allow a field's value on only one current version at a time
+    ///
+    /// A put fails if an envelope without successors already holds the
+    /// value, unless it's a parent of the new version.
+    pub fn declare_unique(&mut self, field: impl Into<String>) {
+        self.unique_fields.insert(field.into());
+    }
+    
+    /// Validate every put with `Envelope::validate` as well, failing
+    /// with all violations found (`Error::Validation`)
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    
+    /// Reject envelopes breaking relationship rules or unique fields,
+    /// listing every violation in strict mode
+    fn check(&self, envelope: &Envelope) -> crate::Result<()> {
+        if self.strict {
+            return Ok(ValidationError::check(self.violations(envelope))?);
+        }
+        if let Some(violation) = self.relationship_violations(envelope).first() {
+            return Err(crate::Error::InvalidEnvelope(violation.to_string()));
+        }
+        Ok(ValidationError::check(self.unique_violations(envelope))?)
+    }
+    
+    /// Everything a strict put would reject `envelope` for (see
+    /// `Envelope::validate_in`)
+    pub(crate) fn violations(&self, envelope: &Envelope) -> Vec<Violation> {
+        let mut violations = envelope.validate().err().map(|err| err.violations).unwrap_or_default();
+        violations.extend(self.relationship_violations(envelope));
+        violations.extend(self.unique_violations(envelope));
+        violations
+    }
+    
+    fn relationship_violations(&self, envelope: &Envelope) -> Vec<Violation> {
+        let Some(allowed) = self.allowed_relationships.get(&envelope.type_hash) else {
+            return Vec::new();
+        };
+        envelope.relationships.iter()
+            .filter(|r| !allowed.contains(&r.rel_type))
+            .map(|rel| Violation {
+                field: format!("relationship '{}'", rel.rel_type),
+                message: format!(
+                    "not allowed on type {}",
+                    envelope.type_name.clone().unwrap_or_else(|| envelope.type_hash.short()),
+                ),
+            })
+            .collect()
+    }
+    
+    fn unique_violations(&self, envelope: &Envelope) -> Vec<Violation> {
+        let hash = envelope.hash();
+        let mut fields: Vec<_> = self.unique_fields.iter().filter(|f| envelope.index.contains_key(*f)).collect();
+        fields.sort();
+        fields.into_iter()
+            .filter_map(|field| {
+                let holder = self.index.by_value(field, &envelope.index[field]).find(|holder| {
+                    **holder != hash
+                        && !envelope.parents().any(|parent| parent == *holder)
+                        && self.index.successors(holder).next().is_none()
+                })?;
+                Some(Violation {
+                    field: format!("index field '{}'", field),
+                    message: format!("{} is already held by {}", envelope.index[field], holder.short()),
+                })
+            })
+            .collect()
+    }
+    
+    /// Declare two relationship types as inverses (e.g., `author` ⇄ `wrote`)
+    ///
+    /// Only the forward edge needs to be stored; `query_related`,
+    /// `outgoing`, `incoming` and traversals answer either direction.
+    pub fn declare_inverse(&mut self, rel_type: impl Into<String>, inverse: impl Into<String>) {
+        let (rel_type, inverse) = (rel_type.into(), inverse.into());
+        self.inverses.insert(rel_type.clone(), inverse.clone());
+        self.inverses.insert(inverse, rel_type);
+    }
+    
+    /// The declared inverse of a relationship type
+    pub fn inverse_of(&self, rel_type: &str) -> Option<&str> {
+        self.inverses.get(rel_type).map(String::as_str)
+    }
+    
+    /// Declared inverse pairs, each in both orders
+    pub(crate) fn inverses(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.inverses.iter().map(|(rel_type, inverse)| (rel_type.as_str(), inverse.as_str()))
+    }
+    
+    /// Envelopes related to `hash` by `rel_type`: stored edges of that type,
+    /// plus incoming edges of its declared inverse
+    pub fn query_related(&self, hash: &Hash256, rel_type: &str) -> Vec<Hash256> {
+        let mut related: HashSet<Hash256> = self.index.outgoing(hash)
+            .filter(|(rel, _)| *rel == rel_type)
+            .map(|(_, target)| *target)
+            .collect();
+        if let Some(inverse) = self.inverse_of(rel_type) {
+            related.extend(self.index.by_relationship(inverse, hash).copied());
+        }
+        related.into_iter().collect()
+    }
+    
+    /// Newest version superseding `hash`, or `hash` itself if it's a head
+    ///
+    /// When the chain forks, follows the successor with the latest
+    /// `created_at` (ties broken by hash).
+    pub fn latest(&self, hash: &Hash256) -> Hash256 {
+        let mut current = *hash;
+        let mut seen = HashSet::new();
+        while seen.insert(current) {
+            let next = self.index.successors(&current)
+                .max_by_key(|h| (self.index.created_at(h), **h));
+            match next {
+                Some(next) => current = *next,
+                None => break,
+            }
+        }
+        current
+    }
+    
+    /// Versions that directly continue `hash` along their first parent
+    /// (claim it as `previous`), sorted; `Index::successors` also counts
+    /// merge parents
+    pub fn first_parent_successors(&self, hash: &Hash256) -> Vec<Hash256> {
+        let mut successors: Vec<_> = self.index.children(hash).copied().collect();
+        successors.sort();
+        successors
+    }
+    
+    /// Every fork: a version with two or more competing successors, sorted
+    pub fn forks(&self) -> Vec<(Hash256, Vec<Hash256>)> {
+        let mut forks: Vec<_> = self.index.forks()
+            .map(|(base, children)| {
+                let mut children: Vec<_> = children.iter().copied().collect();
+                children.sort();
+                (*base, children)
+            })
+            .collect();
+        forks.sort();
+        forks
+    }
+    
+    /// Current versions of a type: those not superseded by any stored version
+    pub fn heads_of_type(&self, type_hash: &Hash256) -> Vec<Hash256> {
+        self.index.by_type(type_hash)
+            .filter(|h| self.index.is_head(h))
+            .copied()
+            .collect()
+    }
+    
+    /// Query reverse references
+    pub fn query_references_to(&self, target: &Hash256) -> Vec<Hash256> {
+        self.index.references_to(target).copied().collect()
+    }
+    
+    /// Query envelopes by a property of one of their relationships
+    pub fn query_by_relationship_property(&self, rel_type: &str, key: &str, value: &IndexValue) -> Vec<Hash256> {
+        self.index.by_relationship_property(rel_type, key, value).copied().collect()
+    }
+    
+    /// Number of objects
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query::Query;
     
     #[test]
     fn test_indexed_store() {
@@ -228,4 +1877,593 @@ mod tests {
         assert!(referencing.contains(&post1_hash));
         assert!(referencing.contains(&post2_hash));
     }
+    
+    #[test]
+    fn test_field_presence_queries() {
+        let mut store = IndexedStore::new();
+        let post_type = Hash256::hash(b"Post");
+        
+        let published = Envelope::builder(post_type, b"Post 1".to_vec())
+            .index("published_at", IndexValue::Timestamp(1708523400))
+            .build();
+        let published_hash = store.put(&published).unwrap();
+        
+        let retracted = Envelope::builder(post_type, b"Post 2".to_vec())
+            .index("published_at", IndexValue::Null)
+            .build();
+        let retracted_hash = store.put(&retracted).unwrap();
+        
+        let draft = Envelope::builder(post_type, b"Post 3".to_vec()).build();
+        let draft_hash = store.put(&draft).unwrap();
+        
+        let present = store.query_has_field("published_at");
+        assert_eq!(present.len(), 2);
+        assert!(present.contains(&published_hash));
+        assert!(present.contains(&retracted_hash));
+        
+        assert_eq!(store.query_missing_field("published_at"), vec![draft_hash]);
+        assert_eq!(store.query_missing_field("nonexistent").len(), 3);
+    }
+    
+    #[test]
+    fn test_query_by_uuid() {
+        let mut store = IndexedStore::new();
+        let order_type = Hash256::hash(b"Order");
+        let id = [7u8; 16];
+        
+        let order = Envelope::builder(order_type, b"order".to_vec())
+            .index("order_id", IndexValue::Uuid(id))
+            .build();
+        let order_hash = store.put(&order).unwrap();
+        
+        let other = Envelope::builder(order_type, b"other".to_vec())
+            .index("order_id", IndexValue::Uuid([8u8; 16]))
+            .build();
+        store.put(&other).unwrap();
+        
+        assert_eq!(store.query_by_value("order_id", &IndexValue::Uuid(id)), vec![order_hash]);
+    }
+    
+    #[test]
+    fn test_query_by_relationship_property() {
+        use crate::envelope::Relationship;
+        
+        let mut store = IndexedStore::new();
+        let person_type = Hash256::hash(b"Person");
+        let team = Hash256::hash(b"team");
+        
+        let alice = Envelope::builder(person_type, b"Alice".to_vec())
+            .relationships([Relationship::new("member", team).with_property("role", "maintainer")])
+            .build();
+        let alice_hash = store.put(&alice).unwrap();
+        
+        let bob = Envelope::builder(person_type, b"Bob".to_vec())
+            .relationships([Relationship::new("member", team).with_property("role", "contributor")])
+            .build();
+        store.put(&bob).unwrap();
+        
+        let maintainers = store.query_by_relationship_property("member", "role", &"maintainer".into());
+        assert_eq!(maintainers, vec![alice_hash]);
+    }
+    
+    #[test]
+    fn test_inverse_relationships() {
+        let mut store = IndexedStore::new();
+        store.declare_inverse("author", "wrote");
+        
+        let author_type = Hash256::hash(b"Author");
+        let post_type = Hash256::hash(b"Post");
+        
+        let alice = Envelope::builder(author_type, b"Alice".to_vec()).build();
+        let alice_hash = store.put(&alice).unwrap();
+        
+        let post = Envelope::builder(post_type, b"Post 1".to_vec())
+            .relationship("author", alice_hash)
+            .build();
+        let post_hash = store.put(&post).unwrap();
+        
+        // Edge stored from the other side
+        let essay = Envelope::builder(post_type, b"Essay".to_vec()).build();
+        let essay_hash = store.put(&essay).unwrap();
+        let bob = Envelope::builder(author_type, b"Bob".to_vec())
+            .relationship("wrote", essay_hash)
+            .build();
+        let bob_hash = store.put(&bob).unwrap();
+        
+        assert_eq!(store.query_related(&post_hash, "author"), vec![alice_hash]);
+        assert_eq!(store.query_related(&alice_hash, "wrote"), vec![post_hash]);
+        assert_eq!(store.query_related(&bob_hash, "wrote"), vec![essay_hash]);
+        assert_eq!(store.query_related(&essay_hash, "author"), vec![bob_hash]);
+    }
+    
+    #[test]
+    fn test_allowed_relationships() {
+        let mut store = IndexedStore::new();
+        let post_type = Hash256::hash(b"Post");
+        let author = Hash256::hash(b"author");
+        store.allow_relationships(post_type, ["author", "tag"]);
+        
+        let ok = Envelope::builder(post_type, b"ok".to_vec())
+            .relationship("author", author)
+            .build();
+        assert!(store.put(&ok).is_ok());
+        
+        let typo = Envelope::builder(post_type, b"typo".to_vec())
+            .type_name("Post")
+            .relationship("auther", author)
+            .build();
+        match store.put(&typo) {
+            Err(crate::Error::InvalidEnvelope(msg)) => assert!(msg.contains("auther")),
+            other => panic!("expected InvalidEnvelope, got {:?}", other),
+        }
+        assert!(!store.contains(&typo.hash()));
+        
+        // Other types are unrestricted
+        let note = Envelope::builder(Hash256::hash(b"Note"), vec![])
+            .relationship("anything", author)
+            .build();
+        assert!(store.put(&note).is_ok());
+    }
+    
+    #[test]
+    fn test_strict_put_and_unique_fields() {
+        let mut store = IndexedStore::new();
+        let user = Hash256::hash(b"User");
+        store.allow_relationships(user, ["manager"]);
+        store.declare_unique("email");
+        let alice = Envelope::builder(user, b"alice".to_vec()).index("email", "a@example.com").build();
+        store.put(&alice).unwrap();
+        // New versions keep their own value
+        let alice_v2 = alice.derive().payload(b"alice 2".to_vec()).build();
+        store.put(&alice_v2).unwrap();
+        
+        store.set_strict(true);
+        let bad = Envelope::builder(user, vec![])
+            .relationship("", user)
+            .index("email", "a@example.com")
+            .index("score", f64::NAN)
+            .build();
+        match store.put(&bad) {
+            Err(crate::Error::Validation(err)) => {
+                let fields: Vec<_> = err.violations.iter().map(|v| v.field.as_str()).collect();
+                assert_eq!(fields, ["relationship 0", "index field 'score'", "relationship ''", "index field 'email'"]);
+            }
+            other => panic!("expected Validation, got {:?}", other),
+        }
+        assert!(!store.contains(&bad.hash()));
+        
+        // Checked up front against the same rules, without a put
+        let bob = Envelope::builder(user, b"bob".to_vec()).index("email", "a@example.com").build();
+        assert!(bob.validate().is_ok());
+        let err = bob.validate_in(&store).unwrap_err();
+        assert_eq!(err.violations.len(), 1);
+        assert_eq!(err.violations[0].field, "index field 'email'");
+        assert_eq!(bad.validate_in(&store).unwrap_err().violations.len(), 4);
+        let alice_v3 = alice_v2.derive().payload(b"alice 3".to_vec()).build();
+        assert!(alice_v3.validate_in(&store).is_ok());
+        assert!(!store.contains(&bob.hash()));
+    }
+    
+    #[test]
+    fn test_latest_and_heads() {
+        let mut store = IndexedStore::new();
+        let post_type = Hash256::hash(b"Post");
+        
+        let v1 = Envelope::builder(post_type, b"v1".to_vec()).created_at(100).build();
+        let h1 = store.put(&v1).unwrap();
+        let v2 = v1.derive().payload(b"v2".to_vec()).created_at(200).build();
+        store.put(&v2).unwrap();
+        let v3 = v2.derive().payload(b"v3".to_vec()).created_at(300).build();
+        let h3 = store.put(&v3).unwrap();
+        
+        let other = Envelope::builder(post_type, b"other".to_vec()).build();
+        let other_hash = store.put(&other).unwrap();
+        
+        assert_eq!(store.latest(&h1), h3);
+        assert_eq!(store.latest(&h3), h3);
+        
+        let mut heads = store.heads_of_type(&post_type);
+        heads.sort();
+        let mut expected = vec![h3, other_hash];
+        expected.sort();
+        assert_eq!(heads, expected);
+        
+        // On a fork, the most recent branch wins
+        let fork = v2.derive().payload(b"fork".to_vec()).created_at(400).build();
+        let fork_hash = store.put(&fork).unwrap();
+        assert_eq!(store.latest(&h1), fork_hash);
+    }
+    
+    #[test]
+    fn test_fork_detection() {
+        let mut store = IndexedStore::new();
+        let doc_type = Hash256::hash(b"Doc");
+        
+        let base = Envelope::builder(doc_type, b"base".to_vec()).build();
+        let base_hash = store.put(&base).unwrap();
+        let a = base.derive().payload(b"a".to_vec()).build();
+        let a_hash = store.put(&a).unwrap();
+        assert!(store.forks().is_empty());
+        
+        let b = base.derive().payload(b"b".to_vec()).build();
+        let b_hash = store.put(&b).unwrap();
+        let mut both = vec![a_hash, b_hash];
+        both.sort();
+        assert_eq!(store.first_parent_successors(&base_hash), both);
+        assert_eq!(store.forks(), vec![(base_hash, both)]);
+        
+        // A merge version doesn't count as another fork of b
+        let merge = a.derive().merge_parent(b_hash).build();
+        store.put(&merge).unwrap();
+        assert_eq!(store.forks().len(), 1);
+    }
+    
+    #[test]
+    fn test_query_valid_at() {
+        let mut store = IndexedStore::new();
+        let price_type = Hash256::hash(b"Price");
+        let q1 = store.put(&Envelope::builder(price_type, b"10".to_vec()).valid_from(100).valid_to(200).build()).unwrap();
+        let q2 = store.put(&Envelope::builder(price_type, b"12".to_vec()).valid_from(200).build()).unwrap();
+        store.put(&Envelope::builder(price_type, b"untimed".to_vec()).build()).unwrap();
+        
+        assert!(store.query_valid_at(99).is_empty());
+        assert_eq!(store.query_valid_at(150), vec![q1]);
+        assert_eq!(store.query_valid_at(200), vec![q2]);
+        assert_eq!(store.query_valid_at(i64::MAX), vec![q2]);
+    }
+    
+    #[test]
+    fn test_created_at_index() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let page = Hash256::hash(b"Page");
+        let mut put = |type_hash: Hash256, created_at: i64| {
+            store.put(&Envelope::builder(type_hash, created_at.to_be_bytes().to_vec()).created_at(created_at).build()).unwrap()
+        };
+        let p100 = put(post, 100);
+        let p200 = put(post, 200);
+        let g250 = put(page, 250);
+        let p300 = put(post, 300);
+        store.put(&Envelope::builder(post, b"undated".to_vec()).build()).unwrap();
+        
+        assert_eq!(store.query_created_between(100, 300), vec![p100, p200, g250]);
+        assert!(store.query_created_between(300, 100).is_empty());
+        assert_eq!(store.most_recent(&post, 2), vec![p300, p200]);
+        assert_eq!(store.most_recent(&page, 5), vec![g250]);
+        assert_eq!(store.query(&Query::type_is(post).and(Query::created_between(150, 400))), {
+            let mut v = vec![p200, p300];
+            v.sort();
+            v
+        });
+    }
+    
+    #[test]
+    fn test_query_range() {
+        let mut store = IndexedStore::new();
+        let reading = Hash256::hash(b"Reading");
+        let mut put = |value: IndexValue| {
+            store.put(&Envelope::builder(reading, vec![]).index("at", value.clone()).build()).unwrap()
+        };
+        let t100 = put(IndexValue::Timestamp(100));
+        let i150 = put(150i64.into());
+        let f199 = put(199.5.into());
+        let big = put(IndexValue::Int128(1 << 100));
+        put("200".into());
+        
+        let sorted = |mut v: Vec<Hash256>| {
+            v.sort();
+            v
+        };
+        assert_eq!(store.query_range("at", 100i64..200), sorted(vec![t100, i150, f199]));
+        assert_eq!(store.query_range("at", 150.0..=199.5), sorted(vec![i150, f199]));
+        assert_eq!(store.query_range("at", 200i64..), vec![big]);
+        assert!(store.query_range("at", (Bound::Included(300i64), Bound::Excluded(100))).is_empty());
+        assert!(store.query_range("at", 150i64..150).is_empty());
+        assert!(store.query_range("missing", 0i64..).is_empty());
+    }
+    
+    #[test]
+    fn test_sort_key_total_order() {
+        use IndexValue::*;
+        let key = SortKey::new;
+        let exact = 1i64 << 53;
+        assert!(key(Int64(5)) != key(Timestamp(5)) && key(Int64(5)) != key(Float64(5.0)));
+        assert_eq!(key(Float64(-0.0)), key(Float64(0.0)));
+        assert!(key(Int64(exact + 1)) > key(Float64(exact as f64)));
+        assert!(key(Int128(i128::MAX)) < key(Float64(f64::INFINITY)));
+        assert!(key(Float64(f64::NAN)) > key(Float64(f64::INFINITY)));
+        
+        let mut store = IndexedStore::new();
+        let number = Hash256::hash(b"Number");
+        let mut put = |value: IndexValue| {
+            store.put(&Envelope::builder(number, vec![]).index("n", value.clone()).build()).unwrap()
+        };
+        let fives = [put(Int64(5)), put(Timestamp(5)), put(Float64(5.0))];
+        let above = put(Int64(exact + 1));
+        put(Float64(exact as f64));
+        
+        let mut sorted = fives.to_vec();
+        sorted.sort();
+        assert_eq!(store.query_range("n", 5i64..=5), sorted);
+        assert_eq!(store.query_range("n", 5.0..6.0), sorted);
+        assert_eq!(store.query_range("n", (Bound::Excluded(exact), Bound::Unbounded)), vec![above]);
+        assert!(store.query_range("n", (Bound::Excluded(5i64), Bound::Included(5))).is_empty());
+        assert!(store.query_range("n", (Bound::Excluded(5i64), Bound::Excluded(5))).is_empty());
+    }
+    
+    #[test]
+    fn test_query_prefix() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let mut put = |title: &str| store.put(&Envelope::builder(post, title.into()).index("title", title).build()).unwrap();
+        let dreams = put("Zero-Copy Dreams");
+        let zero = put("Zero-");
+        put("Zero");
+        put("Zebra");
+        let long = put("Zero-Copy Dreams, Part 2 of a much longer series");
+        
+        let sorted = |mut v: Vec<Hash256>| {
+            v.sort();
+            v
+        };
+        assert_eq!(sorted(store.query_prefix("title", "Zero-")), sorted(vec![dreams, zero, long]));
+        assert_eq!(store.query_prefix("title", "Zero-Copy Dreams,"), vec![long]);
+        assert!(store.query_prefix("title", "Zz").is_empty());
+        assert_eq!(store.query_range("title", "Zero-".."Zero-C"), vec![zero]);
+        assert_eq!(store.query(&Query::parse("title >= Zero- title < Zf").unwrap()).len(), 3);
+    }
+    
+    #[test]
+    fn test_query_fuzzy() {
+        let mut store = IndexedStore::new();
+        let user = Hash256::hash(b"User");
+        let mut put = |name: &str| store.put(&Envelope::builder(user, name.into()).index("name", name).build()).unwrap();
+        let alice = put("Alice");
+        let alcie = put("Alcie");
+        let alicia = put("Alicia");
+        put("Bob");
+        
+        // A transposition counts as one edit
+        assert_eq!(store.query_fuzzy("name", "Alcie", 0), vec![alcie]);
+        assert_eq!(store.query_fuzzy("name", "Alcie", 1), vec![alcie, alice]);
+        assert_eq!(store.query_fuzzy("name", "Alixia", 1), vec![alicia]);
+        assert_eq!(store.query_fuzzy("name", "Alice", 1), vec![alice, alcie]);
+        assert!(store.query_fuzzy("missing", "Alice", 3).is_empty());
+        
+        store.set_field_options("name", FieldOptions::new().lowercase()).unwrap();
+        assert_eq!(store.query_fuzzy("name", "ALICE", 0), vec![alice]);
+    }
+    
+    #[test]
+    fn test_normalized_fields() {
+        let mut store = IndexedStore::new();
+        let user = Hash256::hash(b"User");
+        let alice = store.put(&Envelope::builder(user, vec![1]).index("name", "  Alice ").build()).unwrap();
+        assert!(store.query_by_field("name", "alice").is_empty());
+        
+        store.set_field_options("name", FieldOptions::new().lowercase().trim()).unwrap();
+        assert_eq!(store.query_by_field("name", "alice"), vec![alice]);
+        assert_eq!(store.query_by_field("name", "ALICE "), vec![alice]);
+        assert_eq!(store.query_prefix("name", "AL"), vec![alice]);
+        
+        // "ﬁ" ligature folds to "fi" under NFKC
+        store.set_field_options("title", FieldOptions::new().normalize(Normalization::Nfkc)).unwrap();
+        let file = store.put(&Envelope::builder(user, vec![2]).index("title", "\u{FB01}le").build()).unwrap();
+        assert_eq!(store.query_by_field("title", "file"), vec![file]);
+        assert_eq!(store.index().field_options("other"), FieldOptions::default());
+    }
+    
+    #[test]
+    fn test_register_index() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let mut put = |words: i64| store.put(&Envelope::builder(post, words.to_le_bytes().to_vec()).index("word_count", words).build()).unwrap();
+        let short = put(300);
+        let long = put(4000);
+        
+        store.register_index("word_count_bucket", |envelope| match envelope.index.get("word_count") {
+            Some(IndexValue::Int64(n)) if *n >= 1000 => Some("long".into()),
+            Some(IndexValue::Int64(_)) => Some("short".into()),
+            _ => None,
+        }).unwrap();
+        assert_eq!(store.query_by_field("word_count_bucket", "long"), vec![long]);
+        assert_eq!(store.query_by_field("word_count_bucket", "short"), vec![short]);
+        
+        let longer = store.put(&Envelope::builder(post, vec![]).index("word_count", 2500i64).build()).unwrap();
+        let mut expected = vec![long, longer];
+        expected.sort();
+        assert_eq!(store.query(&Query::parse("word_count_bucket:long").unwrap()), expected);
+        
+        store.remove_object(&long).unwrap();
+        assert_eq!(store.query_by_field("word_count_bucket", "long"), vec![longer]);
+        assert_eq!(store.query_missing_field("word_count_bucket").len(), 0);
+    }
+    
+    #[test]
+    fn test_register_payload_index() {
+        let mut store = IndexedStore::new();
+        let reading = Hash256::hash(b"Reading");
+        // Payload: "<sensor>:<celsius>"
+        store.register_payload_index(reading, |payload| {
+            let text = String::from_utf8_lossy(payload);
+            let Some((sensor, celsius)) = text.split_once(':') else {
+                return Vec::new();
+            };
+            let mut fields = vec![("sensor".to_string(), IndexValue::from(sensor))];
+            if let Ok(celsius) = celsius.parse::<i64>() {
+                fields.push(("celsius".to_string(), celsius.into()));
+            }
+            fields
+        }).unwrap();
+        
+        let cold = store.put(&Envelope::builder(reading, b"roof:-4".to_vec()).build()).unwrap();
+        let warm = store.put(&Envelope::builder(reading, b"roof:21".to_vec()).build()).unwrap();
+        let labelled = store.put(&Envelope::builder(reading, b"cellar:12".to_vec()).index("sensor", "basement").build()).unwrap();
+        store.put(&Envelope::builder(Hash256::hash(b"Note"), b"roof:30".to_vec()).build()).unwrap();
+        
+        let sorted = |mut v: Vec<Hash256>| {
+            v.sort();
+            v
+        };
+        assert_eq!(sorted(store.query_by_field("sensor", "roof")), sorted(vec![cold, warm]));
+        assert_eq!(store.query_range("celsius", 10i64..), sorted(vec![warm, labelled]));
+        // Stored fields win over extracted ones
+        assert_eq!(store.query_by_field("sensor", "basement"), vec![labelled]);
+        assert!(store.query_by_field("sensor", "cellar").is_empty());
+    }
+    
+    #[test]
+    fn test_register_codec_index() {
+        use crate::codec::tests::Person;
+        let mut store = IndexedStore::new();
+        store.register_codec_index(|person: &Person| vec![("age".to_string(), person.age.into())]).unwrap();
+        
+        let alice = store.put(&Envelope::from_value(&Person { name: "alice".into(), age: 30 }).build()).unwrap();
+        store.put(&Envelope::from_value(&Person { name: "bob".into(), age: 12 }).build()).unwrap();
+        store.put(&Envelope::builder(Hash256::hash(b"schema:Person"), b"garbled".to_vec()).build()).unwrap();
+        assert_eq!(store.query_range("age", 18i64..), vec![alice]);
+    }
+    
+    #[test]
+    fn test_delete() {
+        let mut store = IndexedStore::new();
+        let author_type = Hash256::hash(b"Author");
+        let post_type = Hash256::hash(b"Post");
+        let author = store.put(&Envelope::builder(author_type, b"alice".to_vec()).index("name", "alice").build()).unwrap();
+        let v1 = store.put(&Envelope::builder(post_type, b"v1".to_vec()).relationship("author", author).build()).unwrap();
+        let v2 = store.put(&store.get(&v1).unwrap().derive().payload(b"v2".to_vec()).build()).unwrap();
+        
+        // Both versions still point at the author
+        let mut versions = vec![v1, v2];
+        versions.sort();
+        assert_eq!(store.delete(&author, false).unwrap(), Deletion { removed: false, referenced_by: versions });
+        assert!(store.contains(&author));
+        assert_eq!(store.delete(&v1, false).unwrap().referenced_by, vec![v2]);
+        
+        let deleted = store.delete(&v2, false).unwrap();
+        assert_eq!(deleted, Deletion { removed: true, referenced_by: vec![] });
+        assert!(!store.contains(&v2));
+        assert!(store.index().is_head(&v1));
+        
+        assert!(store.delete(&author, true).unwrap().removed);
+        assert!(store.query_by_field("name", "alice").is_empty());
+        assert!(store.query_by_type(&author_type).is_empty());
+        assert_eq!(store.delete(&author, true).unwrap(), Deletion { removed: false, referenced_by: vec![v1] });
+    }
+    
+    #[test]
+    fn test_persistent_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = Hash256::hash(b"User");
+        let (alice, bob) = {
+            let mut store = IndexedStore::open(dir.path()).unwrap();
+            store.set_field_options("name", FieldOptions::new().lowercase()).unwrap();
+            store.enable_text_index("bio").unwrap();
+            store.create_view("users", Query::type_is(user));
+            let alice = store.put(&Envelope::builder(user, vec![1])
+                .index("name", "Alice")
+                .index("bio", "Writes about zero-copy parsing")
+                .index("home", crate::envelope::GeoPoint::new(48.85, 2.35))
+                .created_at(100)
+                .build()).unwrap();
+            let bob = store.put(&Envelope::builder(user, vec![2])
+                .index("name", "Bob")
+                .relationship("follows", alice)
+                .previous(alice)
+                .build()).unwrap();
+            store.save_index().unwrap();
+            (alice, bob)
+        };
+        
+        let check = |store: &IndexedStore| {
+            assert_eq!(store.query_by_field("name", "ALICE"), vec![alice]);
+            assert_eq!(store.query_text("bio", "parsing"), vec![alice]);
+            assert_eq!(store.query_related(&bob, "follows"), vec![alice]);
+            assert_eq!(store.query_references_to(&alice), vec![bob]);
+            assert_eq!(store.query_created_between(0, 200), vec![alice]);
+            assert_eq!(store.query_near("home", crate::envelope::GeoPoint::new(48.85, 2.35), 10.0), vec![alice]);
+            assert_eq!(store.latest(&alice), bob);
+            assert_eq!(store.view("users").map(|v| v.len()), Some(2));
+        };
+        let reopened = IndexedStore::open(dir.path()).unwrap();
+        check(&reopened);
+        drop(reopened);
+        
+        // Objects added after the save make it stale: rebuilt, config kept
+        let carol = {
+            let mut store = IndexedStore::open(dir.path()).unwrap();
+            store.put(&Envelope::builder(user, vec![3]).index("name", "Carol").build()).unwrap()
+        };
+        let store = IndexedStore::open(dir.path()).unwrap();
+        assert_eq!(store.query_by_field("name", "CAROL"), vec![carol]);
+        assert_eq!(store.view("users").map(|v| v.len()), Some(3));
+        drop(store);
+        
+        // A corrupt index file fails the open instead of dropping the
+        // configuration it holds
+        std::fs::write(dir.path().join(INDEX_FILE), b"garbage").unwrap();
+        assert!(IndexedStore::open(dir.path()).unwrap_err().is_corruption());
+        std::fs::remove_file(dir.path().join(INDEX_FILE)).unwrap();
+        let store = IndexedStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.query_by_field("name", "Carol"), vec![carol]);
+    }
+    
+    #[test]
+    fn test_index_snapshot_restore() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        store.set_field_options("title", FieldOptions::new().trim()).unwrap();
+        let first = store.put(&Envelope::builder(post, vec![1]).index("title", " Hello ").index("words", 120i64).build()).unwrap();
+        let snapshot = store.snapshot_index();
+        
+        let index = Index::restore(&snapshot[INDEX_HEADER_LEN..]).unwrap();
+        assert_eq!(index.snapshot(), store.index().snapshot());
+        assert_eq!(index.by_field("title", "Hello").collect::<Vec<_>>(), vec![&first]);
+        assert!(index.in_range("words", &first, Bound::Included(&100i64.into()), Bound::Unbounded));
+        
+        let mut corrupt = snapshot.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(matches!(store.restore_index(&corrupt), Err(crate::Error::Serialization(_))));
+        assert!(store.restore_index(&snapshot[..20]).is_err());
+        
+        // A snapshot older than the store keeps its configuration only
+        let second = store.put(&Envelope::builder(post, vec![2]).index("title", "World ").build()).unwrap();
+        assert!(!store.restore_index(&snapshot).unwrap());
+        assert_eq!(store.query_by_field("title", "World"), vec![second]);
+        assert!(store.restore_index(&store.snapshot_index()).unwrap());
+        assert_eq!(store.query_range("words", 100i64..), vec![first]);
+        
+        // Derived entries are rebuilt when the extractors differ
+        store.register_index("long", |e| e.index.contains_key("words").then(|| "yes".into())).unwrap();
+        let derived = store.snapshot_index();
+        let mut reopened = IndexedStore::new();
+        reopened.put(&store.get(&first).unwrap()).unwrap();
+        reopened.put(&store.get(&second).unwrap()).unwrap();
+        assert!(!reopened.restore_index(&derived).unwrap());
+        assert!(reopened.query_by_field("long", "yes").is_empty());
+        reopened.register_index("long", |e| e.index.contains_key("words").then(|| "yes".into())).unwrap();
+        assert!(reopened.restore_index(&derived).unwrap());
+        assert_eq!(reopened.query_by_field("long", "yes"), vec![first]);
+    }
+    
+    #[test]
+    fn test_snapshot_numbers_roundtrip() {
+        let mut store = IndexedStore::new();
+        let number = Hash256::hash(b"Number");
+        let values = [IndexValue::Int64(5), IndexValue::Timestamp(5), IndexValue::Float64(5.0), IndexValue::Float64(-0.0), IndexValue::Float64(0.0)];
+        for (i, value) in values.iter().enumerate() {
+            store.put(&Envelope::builder(number, vec![i as u8]).index("n", value.clone()).build()).unwrap();
+        }
+        let index = Index::restore(&store.snapshot_index()[INDEX_HEADER_LEN..]).unwrap();
+        assert_eq!(index.snapshot(), store.index().snapshot());
+        for (lower, upper) in [(5.0, 5.0), (-0.0, 0.0), (0.0, 5.0)] {
+            let mut restored: Vec<_> = index.by_range("n", Bound::Included(&lower.into()), Bound::Included(&upper.into())).collect();
+            let mut original: Vec<_> = store.index().by_range("n", Bound::Included(&lower.into()), Bound::Included(&upper.into())).collect();
+            restored.sort();
+            original.sort();
+            assert_eq!(restored, original);
+        }
+        assert_eq!(index.by_range("n", Bound::Included(&0i64.into()), Bound::Included(&0i64.into())).count(), 2);
+    }
 }
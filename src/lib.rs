@@ -7,17 +7,74 @@
 //! - References between objects (graph structures)
 //! - Index fields for queryability
 //! - Version chains for immutable updates
+//!
+//! The crate builds for `wasm32-unknown-unknown`, where in-memory stores,
+//! indexes, queries and sync work as elsewhere (enable `js` for
+//! timestamps; see `clock`). Directory-backed stores need a filesystem
+//! and background drivers need threads, so neither works there: a
+//! browser app keeps its graph in a `Store` saved to IndexedDB instead
+//! (`idb`, also behind `js`), and pulls from a server (see `sync`).
 
+pub mod archive;
+pub mod audit;
+pub mod auth;
 pub mod hash;
+pub mod bloom;
+pub mod build;
+pub mod evict;
 pub mod envelope;
 pub mod store;
+pub mod shard;
+pub mod sync;
 pub mod index;
 pub mod error;
+pub mod clock;
+pub mod codec;
+pub mod diff;
+pub mod memory;
+pub mod merge;
+pub mod namespace;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod history;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+pub mod idb;
+pub mod hook;
+pub mod graph;
+pub mod path;
+pub mod export;
+pub mod federate;
+pub mod gc;
+pub mod geo;
+pub mod query;
+pub mod text;
+pub mod trigger;
+pub mod view;
+pub mod watch;
+mod mmap;
+mod trace;
+mod wire;
 
-pub use crate::envelope::{Envelope, EnvelopeBuilder};
+pub use crate::envelope::{Envelope, EnvelopeBuilder, GeoPoint, IndexValue, Relationship, Strength};
 pub use crate::hash::Hash256;
-pub use crate::store::Store;
-pub use crate::index::IndexedStore;
-pub use crate::error::Error;
+pub use crate::store::{Change, DedupStats, EnvelopeRef, Quota, ScanPage, Store, TypeStats, Usage};
+pub use crate::index::{Deletion, FieldOptions, IndexedStore, Normalization};
+pub use crate::error::{Error, ValidationError, Violation};
+pub use crate::diff::{EnvelopeDiff, RelationshipDiff};
+pub use crate::merge::{merge3, Merge, MergeConflict};
+pub use crate::query::{field_eq, parse_literal, Cursor, Explain, Order, Page, Query};
+pub use crate::graph::{Direction, Hydrated, Plan, TraverseOptions, Subgraph, Visit, Walk, WithRelationships};
+pub use crate::watch::{Event, EventKind};
+pub use crate::memory::MemoryUsage;
+pub use crate::gc::GcPlan;
+pub use crate::evict::{Budget, EvictionPolicy};
+pub use crate::federate::{Backend, FederatedStore, Location};
+pub use crate::shard::ShardedStore;
+pub use crate::sync::{Pull, SyncOptions};
+pub use crate::audit::AuditEntry;
+pub use crate::auth::{Capability, Operation, Scoped};
+pub use crate::namespace::Namespace;
+pub use crate::clock::{Clock, FixedClock, SystemClock};
+pub use crate::codec::Codec;
 
 pub type Result<T> = std::result::Result<T, Error>;
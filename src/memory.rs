@@ -0,0 +1,142 @@
+//! Heap usage estimates for memory budgets
+//!
+//! Estimates count the bytes owned by collections and strings from their
+//! capacities and entry sizes; allocator overhead and B-tree node slack
+//! aren't included, so treat them as lower bounds.
+
+use crate::envelope::{GeoPoint, IndexValue};
+use crate::hash::Hash256;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::mem::size_of;
+use std::ops::Add;
+
+/// Estimated heap bytes, by what they hold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Serialized objects
+    pub objects: usize,
+    /// Lookup structures: indexes, refs, tags and the changelog
+    pub indexes: usize,
+    /// Data kept to speed things up that could be recomputed: the bloom
+    /// filter and materialized view members
+    pub caches: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.objects + self.indexes + self.caches
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+    
+    fn add(self, other: Self) -> Self {
+        Self {
+            objects: self.objects + other.objects,
+            indexes: self.indexes + other.indexes,
+            caches: self.caches + other.caches,
+        }
+    }
+}
+
+/// Heap bytes owned by a value, not counting its own inline size
+pub(crate) trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! inline_only {
+    ($($ty:ty),*) => {
+        $(impl HeapSize for $ty {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+}
+
+inline_only!(u8, u32, u64, usize, i64, bool, Hash256, GeoPoint);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for IndexValue {
+    fn heap_size(&self) -> usize {
+        match self {
+            IndexValue::String(s) => s.heap_size(),
+            _ => 0,
+        }
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize, C: HeapSize> HeapSize for (A, B, C) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size() + self.2.heap_size()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize, S> HeapSize for HashMap<K, V, S> {
+    fn heap_size(&self) -> usize {
+        // One control byte per bucket
+        self.capacity() * (size_of::<(K, V)>() + 1)
+            + self.iter().map(|(k, v)| k.heap_size() + v.heap_size()).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize, S> HeapSize for HashSet<T, S> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * (size_of::<T>() + 1) + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for BTreeMap<K, V> {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<(K, V)>()
+            + self.iter().map(|(k, v)| k.heap_size() + v.heap_size()).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for BTreeSet<T> {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use crate::index::IndexedStore;
+    
+    #[test]
+    fn test_memory_usage() {
+        let mut store = IndexedStore::new();
+        let empty = store.memory_usage();
+        let post = Hash256::hash(b"Post");
+        for i in 0..100i64 {
+            store.put(&Envelope::builder(post, vec![0; 1000]).index("n", i).index("title", "a title").build()).unwrap();
+        }
+        let usage = store.memory_usage();
+        assert!(usage.objects >= 100 * 1000);
+        assert!(usage.indexes > empty.indexes + 100 * 32);
+        assert!(usage.caches > 0);
+        assert_eq!(usage, store.store().memory_usage() + store.index().memory_usage());
+        assert_eq!(usage.total(), usage.objects + usage.indexes + usage.caches);
+        assert_eq!(store.index().memory_usage().objects, 0);
+    }
+}
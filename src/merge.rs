@@ -0,0 +1,324 @@
+//! Three-way merging of divergent envelope versions
+
+use crate::envelope::{Envelope, EnvelopeBuilder, IndexValue, Relationship};
+use crate::hash::Hash256;
+use crate::store::Store;
+use crate::Result;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// A change made differently on both sides of a merge
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeConflict {
+    TypeHash { ours: Hash256, theirs: Hash256 },
+    TypeName { ours: Option<String>, theirs: Option<String> },
+    Payload,
+    ContentType,
+    Validity { ours: (Option<i64>, Option<i64>), theirs: (Option<i64>, Option<i64>) },
+    Index { key: String, ours: Option<IndexValue>, theirs: Option<IndexValue> },
+    Relationship { rel_type: String, base: Hash256, ours: Hash256, theirs: Hash256 },
+    /// The same edge given different properties (or strength) on each side
+    RelationshipProperties {
+        rel_type: String,
+        target: Hash256,
+        ours: HashMap<String, IndexValue>,
+        theirs: HashMap<String, IndexValue>,
+    },
+    Extension { key: String },
+}
+
+/// Result of `merge3`
+///
+/// Conflicting fields take our (`a`) side in the builder, so callers can
+/// resolve conflicts by overriding them before building.
+#[derive(Debug)]
+pub struct Merge {
+    pub builder: EnvelopeBuilder,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl Merge {
+    /// Whether every change merged automatically
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merge two versions `a` and `b` that diverged from `base`
+///
+/// The merged builder has `a` as `previous` and `b` as a merge parent.
+pub fn merge3(base: &Envelope, a: &Envelope, b: &Envelope) -> Merge {
+    let mut conflicts = Vec::new();
+    
+    let type_hash = pick(base.type_hash, a.type_hash, b.type_hash).unwrap_or_else(|| {
+        conflicts.push(MergeConflict::TypeHash { ours: a.type_hash, theirs: b.type_hash });
+        a.type_hash
+    });
+    let payload = pick(&base.payload, &a.payload, &b.payload).unwrap_or_else(|| {
+        conflicts.push(MergeConflict::Payload);
+        &a.payload
+    });
+    
+    let mut builder = Envelope::builder(type_hash, payload.clone());
+    let type_name = pick(&base.type_name, &a.type_name, &b.type_name).unwrap_or_else(|| {
+        conflicts.push(MergeConflict::TypeName { ours: a.type_name.clone(), theirs: b.type_name.clone() });
+        &a.type_name
+    });
+    if let Some(name) = type_name {
+        builder = builder.type_name(name.clone());
+    }
+    let content_type = pick(&base.content_type, &a.content_type, &b.content_type).unwrap_or_else(|| {
+        conflicts.push(MergeConflict::ContentType);
+        &a.content_type
+    });
+    if let Some(content_type) = content_type {
+        builder = builder.content_type(content_type.clone());
+    }
+    
+    // Validity period, merged as a unit
+    let (ours, theirs) = ((a.valid_from, a.valid_to), (b.valid_from, b.valid_to));
+    let (valid_from, valid_to) = pick((base.valid_from, base.valid_to), ours, theirs).unwrap_or_else(|| {
+        conflicts.push(MergeConflict::Validity { ours, theirs });
+        ours
+    });
+    if let Some(from) = valid_from {
+        builder = builder.valid_from(from);
+    }
+    if let Some(to) = valid_to {
+        builder = builder.valid_to(to);
+    }
+    
+    // Index fields
+    let keys: BTreeSet<&String> = base.index.keys().chain(a.index.keys()).chain(b.index.keys()).collect();
+    for key in keys {
+        let (ours, theirs) = (a.index.get(key), b.index.get(key));
+        let merged = pick(base.index.get(key), ours, theirs).unwrap_or_else(|| {
+            conflicts.push(MergeConflict::Index {
+                key: key.clone(),
+                ours: ours.cloned(),
+                theirs: theirs.cloned(),
+            });
+            ours
+        });
+        if let Some(value) = merged {
+            builder = builder.index(key.clone(), value.clone());
+        }
+    }
+    
+    // Extensions
+    let keys: BTreeSet<&String> = base.extensions.keys().chain(a.extensions.keys()).chain(b.extensions.keys()).collect();
+    for key in keys {
+        let (ours, theirs) = (a.extensions.get(key), b.extensions.get(key));
+        let merged = pick(base.extensions.get(key), ours, theirs).unwrap_or_else(|| {
+            conflicts.push(MergeConflict::Extension { key: key.clone() });
+            ours
+        });
+        if let Some(data) = merged {
+            builder = builder.extension(key.clone(), data.clone());
+        }
+    }
+    
+    // Relationships: keep edges both sides have or one side added; drop
+    // edges either side removed. Retargeting one base edge two ways, or
+    // changing its properties two ways, conflicts.
+    let mut dropped: Vec<Relationship> = Vec::new();
+    let ours_diff = base.diff_relationships(a);
+    let theirs_diff = base.diff_relationships(b);
+    for ours in &ours_diff.retargeted {
+        let clash = theirs_diff.retargeted.iter()
+            .find(|t| t.rel_type == ours.rel_type && t.from == ours.from && t.to != ours.to);
+        if let Some(theirs) = clash {
+            conflicts.push(MergeConflict::Relationship {
+                rel_type: ours.rel_type.clone(),
+                base: ours.from,
+                ours: ours.to,
+                theirs: theirs.to,
+            });
+            dropped.extend(b.relationships.iter()
+                .filter(|r| r.rel_type == theirs.rel_type && r.target == theirs.to)
+                .cloned());
+        }
+    }
+    for ours in &ours_diff.properties_changed {
+        let clash = theirs_diff.properties_changed.iter().find(|t| {
+            t.rel_type == ours.rel_type && t.target == ours.target && t.position == ours.position
+                && (&t.properties, t.strength) != (&ours.properties, ours.strength)
+        });
+        if let Some(theirs) = clash {
+            conflicts.push(MergeConflict::RelationshipProperties {
+                rel_type: ours.rel_type.clone(),
+                target: ours.target,
+                ours: ours.properties.clone(),
+                theirs: theirs.properties.clone(),
+            });
+            dropped.extend(b.relationships.iter()
+                .filter(|r| r.rel_type == theirs.rel_type && r.target == theirs.target && r.position == theirs.position)
+                .filter(|r| !base.relationships.contains(r))
+                .cloned());
+        }
+    }
+    let mut merged: Vec<&Relationship> = Vec::new();
+    for rel in a.relationships.iter().chain(&b.relationships) {
+        if merged.contains(&rel) || dropped.contains(rel) {
+            continue;
+        }
+        let in_base = base.relationships.contains(rel);
+        let in_a = a.relationships.contains(rel);
+        let in_b = b.relationships.contains(rel);
+        if (in_a && in_b) || !in_base {
+            merged.push(rel);
+        }
+    }
+    builder = builder.relationships(merged.into_iter().cloned());
+    
+    Merge {
+        builder: builder.previous(a.hash()).merge_parent(b.hash()),
+        conflicts,
+    }
+}
+
+/// Three-way pick: the side that changed wins; `None` if both changed differently
+fn pick<T: PartialEq + Copy>(base: T, ours: T, theirs: T) -> Option<T> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+impl Store {
+    /// Nearest version on `b`'s first-parent chain that is also on `a`'s
+    pub fn common_ancestor(&self, a: &Hash256, b: &Hash256) -> Result<Option<Hash256>> {
+        let mut ours = HashSet::new();
+        for version in self.history(a) {
+            ours.insert(version?.0);
+        }
+        for version in self.history(b) {
+            let (hash, _) = version?;
+            if ours.contains(&hash) {
+                return Ok(Some(hash));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_merge3_clean() {
+        let mut store = Store::new();
+        let post_type = Hash256::hash(b"Post");
+        let rust = Hash256::hash(b"rust");
+        let wasm = Hash256::hash(b"wasm");
+        
+        let base = Envelope::builder(post_type, b"body".to_vec())
+            .index("title", "Draft")
+            .index("status", "draft")
+            .relationship("tag", rust)
+            .build();
+        let a = base.derive().index("title", "Zero-Copy Dreams").build();
+        let b = base.derive()
+            .index("status", "published")
+            .relationship("tag", wasm)
+            .build();
+        store.put(&base).unwrap();
+        let a_hash = store.put(&a).unwrap();
+        let b_hash = store.put(&b).unwrap();
+        
+        assert_eq!(store.common_ancestor(&a_hash, &b_hash).unwrap(), Some(base.hash()));
+        
+        let merge = merge3(&base, &a, &b);
+        assert!(merge.is_clean());
+        let merged = merge.builder.build();
+        assert_eq!(merged.index.get("title"), Some(&"Zero-Copy Dreams".into()));
+        assert_eq!(merged.index.get("status"), Some(&"published".into()));
+        assert_eq!(merged.relationships.len(), 2);
+        assert_eq!(merged.parents().copied().collect::<Vec<_>>(), vec![a_hash, b_hash]);
+    }
+    
+    #[test]
+    fn test_merge3_conflicts() {
+        let post_type = Hash256::hash(b"Post");
+        let alice = Hash256::hash(b"alice");
+        
+        let base = Envelope::builder(post_type, b"body".to_vec())
+            .index("title", "Draft")
+            .relationship("author", alice)
+            .build();
+        let a = base.derive()
+            .index("title", "Ours")
+            .remove_relationships("author")
+            .relationship("author", Hash256::hash(b"bob"))
+            .build();
+        let b = base.derive()
+            .index("title", "Theirs")
+            .remove_relationships("author")
+            .relationship("author", Hash256::hash(b"carol"))
+            .payload(b"edited".to_vec())
+            .build();
+        
+        let merge = merge3(&base, &a, &b);
+        assert_eq!(merge.conflicts.len(), 2);
+        assert!(merge.conflicts.contains(&MergeConflict::Index {
+            key: "title".into(),
+            ours: Some("Ours".into()),
+            theirs: Some("Theirs".into()),
+        }));
+        assert!(matches!(merge.conflicts[1], MergeConflict::Relationship { .. }));
+        
+        // Conflicts default to our side; non-conflicting changes still apply
+        let merged = merge.builder.build();
+        assert_eq!(merged.relationships_ordered("author").len(), 1);
+        assert_eq!(merged.payload, b"edited");
+    }
+    
+    #[test]
+    fn test_merge3_type_name_conflict() {
+        let base = Envelope::builder(Hash256::hash(b"Post"), vec![]).type_name("Post").build();
+        let a = base.derive().type_name("Article").build();
+        let b = base.derive().type_name("Entry").build();
+        
+        let merge = merge3(&base, &a, &b);
+        assert_eq!(merge.conflicts, vec![MergeConflict::TypeName {
+            ours: Some("Article".into()),
+            theirs: Some("Entry".into()),
+        }]);
+        assert_eq!(merge.builder.build().type_name.as_deref(), Some("Article"));
+        
+        // One side renaming is clean
+        assert!(merge3(&base, &a, &base).is_clean());
+    }
+    
+    #[test]
+    fn test_merge3_relationship_properties_conflict() {
+        let person = Hash256::hash(b"Person");
+        let team = Hash256::hash(b"team");
+        let member = |role: &str| Relationship::new("member", team).with_property("role", role);
+        
+        let base = Envelope::builder(person, vec![]).relationships([member("contributor")]).build();
+        let a = base.derive().remove_relationships("member").relationships([member("maintainer")]).build();
+        let b = base.derive().remove_relationships("member").relationships([member("owner")]).build();
+        
+        let merge = merge3(&base, &a, &b);
+        assert_eq!(merge.conflicts, vec![MergeConflict::RelationshipProperties {
+            rel_type: "member".into(),
+            target: team,
+            ours: HashMap::from([("role".to_string(), "maintainer".into())]),
+            theirs: HashMap::from([("role".to_string(), "owner".into())]),
+        }]);
+        // Our edge is kept, not both
+        assert_eq!(merge.builder.build().relationships, vec![member("maintainer")]);
+        
+        // The same change on both sides, or on one, merges cleanly
+        let same = merge3(&base, &a, &a.derive().build());
+        assert!(same.is_clean());
+        assert_eq!(same.builder.build().relationships, vec![member("maintainer")]);
+        let one = merge3(&base, &base, &b);
+        assert!(one.is_clean());
+        assert_eq!(one.builder.build().relationships, vec![member("owner")]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Operation counters and latency histograms (`metrics` feature)
+//!
+//! `IndexedStore` reports its puts, gets, queries, pulls, evictions and
+//! garbage collections through the `metrics` facade, to whichever
+//! recorder the application installed (e.g. `metrics-exporter-prometheus`
+//! for a scrape endpoint), under names prefixed `envelope_`. `describe`
+//! registers their help text and units. Each store also keeps its own
+//! totals in a `Metrics`, for reading in-process without a recorder.
+
+use crate::clock::Stopwatch;
+use ::metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters, with their help text
+const COUNTERS: [(&str, &str); 10] = [
+    ("envelope_objects_stored_total", "New objects written"),
+    ("envelope_bytes_written_total", "Serialized bytes of new objects"),
+    ("envelope_objects_removed_total", "Objects removed"),
+    ("envelope_get_hits_total", "Gets that found the object"),
+    ("envelope_get_misses_total", "Gets for missing objects"),
+    ("envelope_objects_evicted_total", "Objects evicted to stay within budget"),
+    ("envelope_gc_collected_total", "Objects removed by garbage collection"),
+    ("envelope_gc_collected_bytes_total", "Serialized bytes removed by garbage collection"),
+    ("envelope_sync_objects_total", "Objects received by pulls"),
+    ("envelope_sync_bytes_total", "Serialized bytes received by pulls"),
+];
+
+/// Latency histograms in seconds, with their help text
+const HISTOGRAMS: [(&str, &str); 4] = [
+    ("envelope_put_seconds", "Put latency"),
+    ("envelope_get_seconds", "Get latency"),
+    ("envelope_query_seconds", "Query latency"),
+    ("envelope_sync_seconds", "Pull latency"),
+];
+
+/// Register help text and units for every metric with the installed
+/// recorder; call it once after installing one
+pub fn describe() {
+    for (name, help) in COUNTERS {
+        let unit = if name.contains("bytes") { Unit::Bytes } else { Unit::Count };
+        describe_counter!(name, unit, help);
+    }
+    for (name, help) in HISTOGRAMS {
+        describe_histogram!(name, Unit::Seconds, help);
+    }
+}
+
+/// Store operation totals
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// New objects written (re-puts of stored objects aren't counted)
+    pub objects_stored: AtomicU64,
+    /// Serialized bytes of the new objects
+    pub bytes_written: AtomicU64,
+    /// Objects removed by deletes and history pruning
+    pub objects_removed: AtomicU64,
+    /// Gets that found the object
+    pub get_hits: AtomicU64,
+    /// Gets for objects the store doesn't have
+    pub get_misses: AtomicU64,
+    /// Objects evicted to stay within a budget (see `IndexedStore::set_budget`)
+    pub objects_evicted: AtomicU64,
+    /// Objects removed by `IndexedStore::collect_garbage`
+    pub gc_collected: AtomicU64,
+    /// Serialized bytes of those objects
+    pub gc_collected_bytes: AtomicU64,
+    /// Objects received by `IndexedStore::pull_from`
+    pub sync_objects: AtomicU64,
+    /// Serialized bytes of those objects
+    pub sync_bytes: AtomicU64,
+}
+
+/// Add to one of the store's totals and the facade counter of that name
+fn add(total: &AtomicU64, name: &'static str, n: u64) {
+    total.fetch_add(n, Ordering::Relaxed);
+    counter!(name).increment(n);
+}
+
+impl Metrics {
+    pub(crate) fn record_put(&self, started: Stopwatch, written: Option<usize>) {
+        histogram!("envelope_put_seconds").record(started.elapsed());
+        if let Some(bytes) = written {
+            add(&self.objects_stored, "envelope_objects_stored_total", 1);
+            add(&self.bytes_written, "envelope_bytes_written_total", bytes as u64);
+        }
+    }
+    
+    pub(crate) fn record_get(&self, started: Stopwatch, hit: bool) {
+        histogram!("envelope_get_seconds").record(started.elapsed());
+        match hit {
+            true => add(&self.get_hits, "envelope_get_hits_total", 1),
+            false => add(&self.get_misses, "envelope_get_misses_total", 1),
+        }
+    }
+    
+    pub(crate) fn record_query(&self, started: Stopwatch) {
+        histogram!("envelope_query_seconds").record(started.elapsed());
+    }
+    
+    pub(crate) fn record_removal(&self) {
+        add(&self.objects_removed, "envelope_objects_removed_total", 1);
+    }
+    
+    pub(crate) fn record_evictions(&self, objects: usize) {
+        add(&self.objects_evicted, "envelope_objects_evicted_total", objects as u64);
+    }
+    
+    pub(crate) fn record_gc(&self, objects: usize, bytes: usize) {
+        add(&self.gc_collected, "envelope_gc_collected_total", objects as u64);
+        add(&self.gc_collected_bytes, "envelope_gc_collected_bytes_total", bytes as u64);
+    }
+    
+    pub(crate) fn record_sync(&self, started: Stopwatch, objects: usize, bytes: u64) {
+        histogram!("envelope_sync_seconds").record(started.elapsed());
+        add(&self.sync_objects, "envelope_sync_objects_total", objects as u64);
+        add(&self.sync_bytes, "envelope_sync_bytes_total", bytes);
+    }
+    
+    /// Fraction of gets that found their object (0 before any get)
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.get_hits.load(Ordering::Relaxed) as f64;
+        let total = hits + self.get_misses.load(Ordering::Relaxed) as f64;
+        if total == 0.0 { 0.0 } else { hits / total }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use crate::hash::Hash256;
+    use crate::index::IndexedStore;
+    use crate::query::Query;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+    use std::collections::HashMap;
+    
+    /// Recorded metrics by name: counter values, or histogram sample counts
+    fn recorded(snapshotter: &Snapshotter) -> HashMap<String, (u64, Option<Unit>)> {
+        snapshotter.snapshot().into_vec().into_iter()
+            .map(|(key, unit, _, value)| {
+                let value = match value {
+                    DebugValue::Counter(n) => n,
+                    DebugValue::Histogram(samples) => samples.len() as u64,
+                    DebugValue::Gauge(_) => unreachable!("no gauges are reported"),
+                };
+                (key.key().name().to_string(), (value, unit))
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn test_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut store = IndexedStore::new();
+        let note = Hash256::hash(b"Note");
+        ::metrics::with_local_recorder(&recorder, || {
+            describe();
+            let envelope = Envelope::builder(note, b"hello".to_vec()).build();
+            let hash = store.put(&envelope).unwrap();
+            store.put(&envelope).unwrap();
+            store.get(&hash).unwrap();
+            assert!(store.get(&Hash256::hash(b"missing")).is_err());
+            store.query(&Query::type_is(note));
+            store.delete(&hash, false).unwrap();
+        });
+        
+        let metrics = store.metrics();
+        assert_eq!(metrics.objects_stored.load(Ordering::Relaxed), 1);
+        assert!(metrics.bytes_written.load(Ordering::Relaxed) > 5);
+        assert_eq!(metrics.objects_removed.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.hit_rate(), 0.5);
+        
+        let recorded = recorded(&snapshotter);
+        assert_eq!(recorded["envelope_objects_stored_total"], (1, Some(Unit::Count)));
+        assert_eq!(recorded["envelope_bytes_written_total"].1, Some(Unit::Bytes));
+        assert_eq!(recorded["envelope_get_hits_total"].0, 1);
+        assert_eq!(recorded["envelope_get_misses_total"].0, 1);
+        assert_eq!(recorded["envelope_put_seconds"], (2, Some(Unit::Seconds)));
+        assert_eq!(recorded["envelope_query_seconds"].0, 1);
+    }
+    
+    #[test]
+    fn test_sync_and_gc_metrics() {
+        let note = Hash256::hash(b"Note");
+        let mut source = crate::store::Store::new();
+        let leaf = source.put(&Envelope::builder(note, b"leaf".to_vec()).build()).unwrap();
+        let root = source.put(&Envelope::builder(note, b"root".to_vec()).relationship("child", leaf).build()).unwrap();
+        
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut store = IndexedStore::new();
+        let size = ::metrics::with_local_recorder(&recorder, || {
+            assert_eq!(store.pull_from(&source, &[root], Default::default()).unwrap(), 2);
+            let garbage = store.put(&Envelope::builder(note, b"garbage".to_vec()).build()).unwrap();
+            let size = store.store().stored_size(&garbage).unwrap();
+            assert_eq!(store.collect_garbage(&[root]).unwrap().hashes, vec![garbage]);
+            size
+        });
+        
+        let metrics = store.metrics();
+        assert_eq!(metrics.sync_objects.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.gc_collected.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.gc_collected_bytes.load(Ordering::Relaxed), size as u64);
+        
+        let recorded = recorded(&snapshotter);
+        assert_eq!(recorded["envelope_sync_objects_total"].0, 2);
+        assert_eq!(recorded["envelope_sync_seconds"].0, 1);
+        assert_eq!(recorded["envelope_gc_collected_total"].0, 1);
+        assert_eq!(recorded["envelope_gc_collected_bytes_total"].0, size as u64);
+    }
+}
@@ -0,0 +1,119 @@
+//! Object bytes, owned or mapped from the object log
+//!
+//! With the `mmap` feature on Unix, snapshot readers (`Store::open_snapshot`)
+//! map the object log read-only rather than copying it onto the heap, so
+//! many processes reading one live store share its pages through the OS
+//! page cache. Each `refresh` maps the log as the manifest then describes
+//! it; objects keep the mapping they were read from alive. The writer
+//! only appends to the log, and `compact` writes a new log file rather
+//! than rewriting it, so mapped bytes never change underneath a reader.
+
+use crate::memory::HeapSize;
+use std::ops::Deref;
+
+/// The serialized bytes of one stored object
+#[derive(Clone)]
+pub(crate) enum Blob {
+    Owned(Vec<u8>),
+    #[cfg(all(feature = "mmap", unix))]
+    Mapped {
+        map: std::sync::Arc<Mmap>,
+        start: usize,
+        len: usize,
+    },
+}
+
+impl std::fmt::Debug for Blob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Blob({} bytes)", self.len())
+    }
+}
+
+impl Deref for Blob {
+    type Target = [u8];
+    
+    fn deref(&self) -> &[u8] {
+        match self {
+            Blob::Owned(bytes) => bytes,
+            #[cfg(all(feature = "mmap", unix))]
+            Blob::Mapped { map, start, len } => &map[*start..*start + *len],
+        }
+    }
+}
+
+impl From<Vec<u8>> for Blob {
+    fn from(bytes: Vec<u8>) -> Self {
+        Blob::Owned(bytes)
+    }
+}
+
+impl HeapSize for Blob {
+    /// Mapped bytes live in the page cache, not the heap
+    fn heap_size(&self) -> usize {
+        match self {
+            Blob::Owned(bytes) => bytes.heap_size(),
+            #[cfg(all(feature = "mmap", unix))]
+            Blob::Mapped { .. } => 0,
+        }
+    }
+}
+
+/// A read-only mapping of the start of a file
+#[cfg(all(feature = "mmap", unix))]
+pub(crate) struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this value until dropped
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Send for Mmap {}
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Sync for Mmap {}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Mmap {
+    /// Map the first `len` bytes of `file`, which must be at least that
+    /// long and not shrink while mapped
+    pub(crate) fn map(file: &std::fs::File, len: usize) -> std::io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        if len == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "cannot map 0 bytes"));
+        }
+        // SAFETY: a fresh private read-only mapping; failure is checked
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+    
+    /// `bytes`, which must lie within this mapping, as a blob keeping it alive
+    pub(crate) fn blob(self: &std::sync::Arc<Self>, bytes: &[u8]) -> Blob {
+        let start = bytes.as_ptr() as usize - self.ptr as usize;
+        debug_assert!(start + bytes.len() <= self.len);
+        Blob::Mapped { map: std::sync::Arc::clone(self), start, len: bytes.len() }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Deref for Mmap {
+    type Target = [u8];
+    
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` readable bytes until dropped
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly what `map` mapped
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
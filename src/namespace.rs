@@ -0,0 +1,311 @@
+//! Logical namespaces within one store
+//!
+//! `IndexedStore::namespace` returns a handle whose puts, reads, queries
+//! and refs see only that namespace's objects: those reachable (see
+//! `Store::closure`) from its refs, plus those put through it. Objects
+//! are still stored once, so identical content put in two namespaces is
+//! shared, and namespace refs are ordinary refs under `ns/<name>/`,
+//! making them GC roots like any other. A put can't link (strongly, or
+//! as a parent) to objects outside its namespace, so it can't pull them
+//! in. Directory-backed stores record what was put through each
+//! namespace in a `namespaces` file, so membership survives reopening;
+//! only refs protect objects from GC, though.
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::query::Query;
+use crate::wire::{self, Reader};
+use crate::Result;
+use std::cell::OnceCell;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Prefix of the refs belonging to namespaces
+pub const NAMESPACE_PREFIX: &str = "ns/";
+
+/// File in the store directory recording objects put through namespaces
+pub const NAMESPACES_FILE: &str = "namespaces";
+
+impl IndexedStore {
+    /// A handle scoped to the namespace `name`
+    ///
+    /// Fails with `Error::InvalidArgument` if `name` is empty or contains
+    /// `/`, since its refs would then overlap another namespace's.
+    pub fn namespace(&mut self, name: impl Into<String>) -> Result<Namespace<'_>> {
+        let name = name.into();
+        check_name(&name)?;
+        Ok(Namespace { refs_prefix: refs_prefix(&name), name, store: self, members: OnceCell::new() })
+    }
+    
+    /// Every object in the namespace `name` (see `Namespace::members`)
+    pub(crate) fn namespace_members(&self, name: &str) -> Result<HashSet<Hash256>> {
+        check_name(name)?;
+        let prefix = refs_prefix(name);
+        let roots: Vec<_> = self.store().refs()
+            .filter(|(ref_name, _)| ref_name.starts_with(&prefix))
+            .map(|(_, hash)| *hash)
+            .collect();
+        let mut members: HashSet<_> = self.store().closure(&roots)?.into_iter().collect();
+        members.extend(self.namespace_writes(name).into_iter().flatten().filter(|hash| self.contains(hash)));
+        Ok(members)
+    }
+    
+    /// Read the namespace writes recorded in `dir`
+    ///
+    /// `([namespace] [hash: 32])*`, appended to on each put; a record cut
+    /// short by a crash ends the file.
+    pub(crate) fn load_namespaces(&mut self, dir: &Path) -> Result<()> {
+        let data = match fs::read(dir.join(NAMESPACES_FILE)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut reader = Reader::new(&data);
+        while !reader.is_empty() {
+            let Ok((name, hash)) = reader.string().and_then(|name| Ok((name, reader.hash()?))) else {
+                break;
+            };
+            self.namespace_writes_mut(&name).insert(hash);
+        }
+        Ok(())
+    }
+    
+    /// Record a namespace write in the store directory, if there is one
+    fn save_namespace_write(&self, name: &str, hash: &Hash256) -> Result<()> {
+        let Some(dir) = self.store().dir() else {
+            return Ok(());
+        };
+        let mut record = Vec::new();
+        wire::put_str(&mut record, name);
+        wire::put_hash(&mut record, hash);
+        let mut file = OpenOptions::new().create(true).append(true).open(dir.join(NAMESPACES_FILE))?;
+        file.write_all(&record)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::InvalidArgument(format!("namespace: {:?} must be non-empty and without '/'", name)));
+    }
+    Ok(())
+}
+
+fn refs_prefix(name: &str) -> String {
+    format!("{}{}/", NAMESPACE_PREFIX, name)
+}
+
+/// Store access limited to one namespace, from `IndexedStore::namespace`
+///
+/// Objects outside the namespace read as missing.
+#[derive(Debug)]
+pub struct Namespace<'a> {
+    store: &'a mut IndexedStore,
+    name: String,
+    refs_prefix: String,
+    /// Every object in the namespace, worked out on first use; puts
+    /// through the handle add to it and ref changes reset it, and nothing
+    /// else can change the store while the handle borrows it
+    members: OnceCell<HashSet<Hash256>>,
+}
+
+impl Namespace<'_> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    
+    /// Targets of this namespace's refs
+    pub fn roots(&self) -> Vec<Hash256> {
+        self.refs().into_iter().map(|(_, hash)| hash).collect()
+    }
+    
+    /// Every object in the namespace
+    pub fn members(&self) -> Result<HashSet<Hash256>> {
+        self.member_set().cloned()
+    }
+    
+    fn member_set(&self) -> Result<&HashSet<Hash256>> {
+        if let Some(members) = self.members.get() {
+            return Ok(members);
+        }
+        let members = self.store.namespace_members(&self.name)?;
+        Ok(self.members.get_or_init(|| members))
+    }
+    
+    fn written(&self) -> impl Iterator<Item = Hash256> + '_ {
+        self.store.namespace_writes(&self.name).into_iter().flatten().copied()
+    }
+    
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        let written = self.store.namespace_writes(&self.name).is_some_and(|set| set.contains(hash));
+        self.store.contains(hash) && (written || self.member_set().is_ok_and(|members| members.contains(hash)))
+    }
+    
+    /// Store an envelope in the namespace
+    ///
+    /// Every strong relationship target and parent of the envelope must
+    /// already be in the namespace, or the put fails with
+    /// `Error::Unauthorized`.
+    pub fn put(&mut self, envelope: &Envelope) -> Result<Hash256> {
+        let mut links = envelope.relationships.iter()
+            .filter(|r| r.is_strong())
+            .map(|r| &r.target)
+            .chain(envelope.parents())
+            .peekable();
+        if links.peek().is_some() {
+            let members = self.member_set()?;
+            if let Some(outside) = links.find(|target| !members.contains(*target)) {
+                return Err(Error::Unauthorized(format!("{} is outside namespace {}", outside.short(), self.name)));
+            }
+        }
+        let hash = self.store.put(envelope)?;
+        if self.store.namespace_writes_mut(&self.name).insert(hash) {
+            self.store.save_namespace_write(&self.name, &hash)?;
+        }
+        if let Some(members) = self.members.get_mut() {
+            members.insert(hash);
+        }
+        Ok(hash)
+    }
+    
+    /// Retrieve an object in the namespace
+    pub fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        if !self.contains(hash) {
+            return Err(Error::not_found(*hash, "get"));
+        }
+        self.store.get(hash)
+    }
+    
+    /// Run a query, keeping only results in the namespace
+    pub fn query(&self, query: &Query) -> Result<Vec<Hash256>> {
+        let members = self.member_set()?;
+        Ok(self.store.query(query).into_iter().filter(|hash| members.contains(hash)).collect())
+    }
+    
+    /// Point a namespace ref at an object in the namespace
+    pub fn set_ref(&mut self, name: &str, hash: Hash256) -> Result<()> {
+        if !self.contains(&hash) {
+            return Err(Error::not_found(hash, "set_ref"));
+        }
+        let name = format!("{}{}", self.refs_prefix, name);
+        self.store.set_ref(name, hash)?;
+        // The ref's old target may no longer be reachable
+        self.members = OnceCell::new();
+        Ok(())
+    }
+    
+    pub fn get_ref(&self, name: &str) -> Option<Hash256> {
+        self.store.get_ref(&format!("{}{}", self.refs_prefix, name))
+    }
+    
+    /// Remove a namespace ref, returning where it pointed
+    pub fn delete_ref(&mut self, name: &str) -> Result<Option<Hash256>> {
+        let name = format!("{}{}", self.refs_prefix, name);
+        let deleted = self.store.store_mut().delete_ref(&name)?;
+        self.members = OnceCell::new();
+        Ok(deleted)
+    }
+    
+    /// The namespace's refs, by name without the prefix, sorted
+    pub fn refs(&self) -> Vec<(String, Hash256)> {
+        let mut refs: Vec<_> = self.store.store().refs()
+            .filter_map(|(name, hash)| Some((name.strip_prefix(&self.refs_prefix)?.to_string(), *hash)))
+            .collect();
+        refs.sort();
+        refs
+    }
+    
+    /// Objects put through the namespace that its refs don't reach, sorted;
+    /// they stay members, but aren't GC roots
+    pub fn orphans(&self) -> Result<Vec<Hash256>> {
+        let reachable: HashSet<_> = self.store.store().closure(&self.roots())?.into_iter().collect();
+        let mut orphans: Vec<_> = self.written()
+            .filter(|hash| self.store.contains(hash) && !reachable.contains(hash))
+            .collect();
+        orphans.sort();
+        Ok(orphans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_namespaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = Hash256::hash(b"Doc");
+        let shared = Envelope::builder(doc, b"same content".to_vec()).build();
+        let (a_hash, kept, draft) = {
+            let mut store = IndexedStore::open(dir.path()).unwrap();
+            let mut a = store.namespace("a").unwrap();
+            let a_hash = a.put(&shared).unwrap();
+            let kept = a.put(&Envelope::builder(doc, b"kept".to_vec()).relationship("see", a_hash).build()).unwrap();
+            let draft = a.put(&Envelope::builder(doc, b"draft".to_vec()).build()).unwrap();
+            a.set_ref("main", kept).unwrap();
+            assert_eq!(a.orphans().unwrap(), vec![draft]);
+            
+            let mut b = store.namespace("b").unwrap();
+            assert!(b.get(&kept).unwrap_err().is_not_found());
+            assert!(b.set_ref("stolen", kept).is_err());
+            // Nor can it link to a's objects to pull them in
+            let link = Envelope::builder(doc, b"link".to_vec()).relationship("see", kept).build();
+            assert!(matches!(b.put(&link), Err(Error::Unauthorized(_))));
+            let newer = Envelope::builder(doc, b"newer".to_vec()).previous(kept).build();
+            assert!(matches!(b.put(&newer), Err(Error::Unauthorized(_))));
+            // Same content, one stored object
+            assert_eq!(b.put(&shared).unwrap(), a_hash);
+            assert_eq!(b.query(&Query::type_is(doc)).unwrap(), vec![a_hash]);
+            assert_eq!(store.len(), 3);
+            (a_hash, kept, draft)
+        };
+        
+        // After reopening, refs and recorded writes still define membership
+        let mut store = IndexedStore::open(dir.path()).unwrap();
+        let a = store.namespace("a").unwrap();
+        assert_eq!(a.refs(), vec![("main".to_string(), kept)]);
+        let mut expected = vec![a_hash, kept, draft];
+        expected.sort();
+        let mut found = a.query(&Query::type_is(doc)).unwrap();
+        found.sort();
+        assert_eq!(found, expected);
+        assert_eq!(a.orphans().unwrap(), vec![draft]);
+        assert_eq!(store.namespace("b").unwrap().members().unwrap(), HashSet::from([a_hash]));
+    }
+    
+    #[test]
+    fn test_namespace_names() {
+        let mut store = IndexedStore::new();
+        assert!(matches!(store.namespace(""), Err(Error::InvalidArgument(_))));
+        // "a/b" would read namespace a's refs named "b/..."
+        assert!(matches!(store.namespace("a/b"), Err(Error::InvalidArgument(_))));
+        assert!(matches!(store.namespace_members("a/b"), Err(Error::InvalidArgument(_))));
+        assert!(store.namespace("a-b").is_ok());
+    }
+    
+    #[test]
+    fn test_members_follow_ref_changes() {
+        let doc = Hash256::hash(b"Doc");
+        let mut store = IndexedStore::new();
+        // Members only through refs set outside the handle
+        let first = store.put(&Envelope::builder(doc, b"first".to_vec()).build()).unwrap();
+        let second = store.put(&Envelope::builder(doc, b"second".to_vec()).build()).unwrap();
+        store.set_ref("ns/a/main", first).unwrap();
+        store.set_ref("ns/a/other", second).unwrap();
+        
+        let mut a = store.namespace("a").unwrap();
+        assert_eq!(a.members().unwrap(), HashSet::from([first, second]));
+        a.delete_ref("other").unwrap();
+        assert!(!a.contains(&second));
+        let root = a.put(&Envelope::builder(doc, b"root".to_vec()).build()).unwrap();
+        assert!(a.contains(&root));
+        a.set_ref("main", root).unwrap();
+        assert!(!a.contains(&first));
+        assert_eq!(a.members().unwrap(), HashSet::from([root]));
+    }
+}
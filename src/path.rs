@@ -0,0 +1,96 @@
+//! Path expressions: chase relationships, then read index fields
+//!
+//! A path is a sequence of relationship types ending in an index field,
+//! separated by `/` or `->`: `author/name`, `tag->name`.
+
+use crate::envelope::IndexValue;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::query::Query;
+use crate::Result;
+
+/// Split a path into its relationship hops and final field
+pub(crate) fn split_path(path: &str) -> Result<(Vec<&str>, &str)> {
+    let mut segments: Vec<&str> = path.split("->").flat_map(|s| s.split('/')).collect();
+    let field = segments.pop().unwrap_or_default();
+    if field.is_empty() || segments.iter().any(|s| s.is_empty()) {
+        return Err(Error::InvalidQuery(format!("path '{}': empty segment", path)));
+    }
+    Ok((segments, field))
+}
+
+impl IndexedStore {
+    /// Values of the field at the end of `path`, starting from `hash`
+    ///
+    /// `select(post, "author/name")` returns the `name` of every stored
+    /// `author` target. Envelopes missing the field contribute nothing.
+    pub fn select(&self, hash: &Hash256, path: &str) -> Result<Vec<IndexValue>> {
+        let (hops, field) = split_path(path)?;
+        let mut current = vec![*hash];
+        for rel_type in hops {
+            let mut next = Vec::new();
+            for source in &current {
+                next.extend(self.index().outgoing(source)
+                    .filter(|(rel, _)| *rel == rel_type)
+                    .map(|(_, target)| *target));
+            }
+            current = next;
+        }
+        let mut values = Vec::new();
+        for hash in current.iter().filter(|h| self.contains(h)) {
+            if let Some(value) = self.get(hash)?.index.remove(field) {
+                values.push(value);
+            }
+        }
+        Ok(values)
+    }
+    
+    /// Run a query whose clauses may follow paths, such as
+    /// `type:BlogPost tag->name = rust`
+    ///
+    /// Shorthand for `query(&Query::parse(expr)?)`: see `Query::parse`
+    /// for the language. Path clauses are answered from the reverse and
+    /// field indexes without loading envelopes; results are sorted.
+    pub fn query_path(&self, expr: &str) -> Result<Vec<Hash256>> {
+        Ok(self.query(&Query::parse(expr)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    
+    #[test]
+    fn test_select_and_query_path() {
+        let mut store = IndexedStore::new();
+        let alice = store.put(&Envelope::builder(Hash256::hash(b"schema:Author"), vec![])
+            .type_name("Author")
+            .index("name", "Alice")
+            .build()).unwrap();
+        let rust = store.put(&Envelope::builder(Hash256::hash(b"schema:Tag"), vec![])
+            .type_name("Tag")
+            .index("name", "rust")
+            .build()).unwrap();
+        let post = store.put(&Envelope::builder(Hash256::hash(b"schema:BlogPost"), vec![])
+            .type_name("BlogPost")
+            .index("word_count", "1500")
+            .relationship("author", alice)
+            .relationship("tag", rust)
+            .build()).unwrap();
+        
+        assert_eq!(store.select(&post, "author/name").unwrap(), vec![IndexValue::from("Alice")]);
+        assert_eq!(store.select(&post, "word_count").unwrap(), vec![IndexValue::from("1500")]);
+        assert!(store.select(&post, "tag/missing").unwrap().is_empty());
+        
+        assert_eq!(store.query_path("type:BlogPost tag->name = rust").unwrap(), vec![post]);
+        assert_eq!(store.query_path("author->name = \"Alice\" word_count = 1500").unwrap(), vec![post]);
+        assert!(store.query_path("type:Tag tag->name = rust").unwrap().is_empty());
+        assert_eq!(store.query_path("type:Author").unwrap(), vec![alice]);
+        
+        assert!(matches!(store.query_path("tag->name rust"), Err(Error::InvalidQuery(_))));
+        assert!(matches!(store.query_path("author//name = x"), Err(Error::InvalidQuery(_))));
+        assert!(matches!(store.query_path(""), Err(Error::InvalidQuery(_))));
+    }
+}
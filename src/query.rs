@@ -0,0 +1,1216 @@
+//! Composable queries answered from the indexes
+//!
+//! A `Query` is a tree of predicates. Conjunctions stream the candidates
+//! of their most selective clause and test the rest by set membership;
+//! disjunctions stream each branch, skipping hashes an earlier branch
+//! already produced. No clause materializes its own result list.
+
+use crate::envelope::IndexValue;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::{Index, IndexedStore};
+use crate::path::split_path;
+use crate::wire::{self, Reader};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::ops::{Bound, RangeBounds};
+
+/// A predicate over indexed envelopes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    filter: Filter,
+    order: Option<(String, Order)>,
+    limit: Option<usize>,
+    after: Option<Cursor>,
+}
+
+/// Direction for `Query::order_by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Type(Hash256),
+    TypeName(String),
+    FieldEq(String, IndexValue),
+    Range { field: String, lower: Bound<IndexValue>, upper: Bound<IndexValue> },
+    HasField(String),
+    CreatedBetween(i64, i64),
+    /// Sources of a relationship whose target matches the inner filter
+    Related(String, Box<Filter>),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+/// Shorthand for `Query::field_eq`
+pub fn field_eq(field: impl Into<String>, value: impl Into<IndexValue>) -> Query {
+    Query::field_eq(field, value)
+}
+
+impl Query {
+    fn with(filter: Filter) -> Self {
+        Self { filter, order: None, limit: None, after: None }
+    }
+    
+    /// Envelopes of a type
+    pub fn type_is(type_hash: Hash256) -> Self {
+        Self::with(Filter::Type(type_hash))
+    }
+    
+    /// Envelopes with a human-readable type name
+    pub fn type_named(name: impl Into<String>) -> Self {
+        Self::with(Filter::TypeName(name.into()))
+    }
+    
+    /// Envelopes whose field equals a value (of the same type)
+    pub fn field_eq(field: impl Into<String>, value: impl Into<IndexValue>) -> Self {
+        Self::with(Filter::FieldEq(field.into(), value.into()))
+    }
+    
+    /// Envelopes whose field falls in a range
+    ///
+    /// Numbers compare across widths (`Int64`, `Int128`, `Float64`,
+    /// `Timestamp`); other kinds compare only with their own kind.
+    pub fn range<V: Into<IndexValue> + Clone>(field: impl Into<String>, range: impl RangeBounds<V>) -> Self {
+        let convert = |bound: Bound<&V>| match bound {
+            Bound::Included(v) => Bound::Included(v.clone().into()),
+            Bound::Excluded(v) => Bound::Excluded(v.clone().into()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Self::with(Filter::Range {
+            field: field.into(),
+            lower: convert(range.start_bound()),
+            upper: convert(range.end_bound()),
+        })
+    }
+    
+    /// Envelopes that carry a field, whatever its value
+    pub fn has_field(field: impl Into<String>) -> Self {
+        Self::with(Filter::HasField(field.into()))
+    }
+    
+    /// Envelopes whose `created_at` falls in `[start, end)`
+    pub fn created_between(start: i64, end: i64) -> Self {
+        Self::with(Filter::CreatedBetween(start, end))
+    }
+    
+    /// Envelopes with a `rel_type` relationship to one matching `target`
+    ///
+    /// `related("author", field_eq("name", "Alice"))` finds what Alice
+    /// wrote; the text form is `author->name = Alice`.
+    pub fn related(rel_type: impl Into<String>, target: Query) -> Self {
+        Self::with(Filter::Related(rel_type.into(), Box::new(target.filter)))
+    }
+    
+    /// Both this and `other`
+    pub fn and(self, other: Query) -> Self {
+        let filter = match (self.filter, other.filter) {
+            (Filter::And(mut a), Filter::And(b)) => {
+                a.extend(b);
+                Filter::And(a)
+            }
+            (Filter::And(mut a), b) => {
+                a.push(b);
+                Filter::And(a)
+            }
+            (a, b) => Filter::And(vec![a, b]),
+        };
+        Self { filter, ..self }
+    }
+    
+    /// This or `other`
+    pub fn or(self, other: Query) -> Self {
+        let filter = match (self.filter, other.filter) {
+            (Filter::Or(mut a), Filter::Or(b)) => {
+                a.extend(b);
+                Filter::Or(a)
+            }
+            (Filter::Or(mut a), b) => {
+                a.push(b);
+                Filter::Or(a)
+            }
+            (a, b) => Filter::Or(vec![a, b]),
+        };
+        Self { filter, ..self }
+    }
+    
+    /// Sort results by a field's value; ties are broken by hash, and
+    /// envelopes without the field follow, by hash
+    pub fn order_by(mut self, field: impl Into<String>, order: Order) -> Self {
+        self.order = Some((field.into(), order));
+        self
+    }
+    
+    /// Matching hashes, in no particular order
+    pub fn evaluate<'a>(&'a self, index: &'a Index) -> impl Iterator<Item = Hash256> + 'a {
+        self.filter.candidates(index)
+    }
+    
+    /// Return at most `n` results per page
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+    
+    /// Start after the position a previous page ended at
+    pub fn after(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+    
+    /// Matching hashes in the query's order, or by hash if it has none
+    pub fn results(&self, index: &Index) -> Vec<Hash256> {
+        self.page(index).hashes
+    }
+    
+    /// Whether an indexed envelope satisfies the filter
+    pub(crate) fn matches(&self, index: &Index, hash: &Hash256) -> bool {
+        self.filter.matches(index, hash)
+    }
+    
+    /// How many relationship hops the filter looks along (0 if it only
+    /// tests the envelope itself)
+    pub(crate) fn relationship_depth(&self) -> usize {
+        self.filter.relationship_depth()
+    }
+    
+    /// Describe the indexes, estimates and intersection order this query
+    /// would use, without running it
+    pub fn explain(&self, store: &IndexedStore) -> Explain {
+        let index = store.index();
+        let mut plan = self.filter.explain(index);
+        if let Some((field, order)) = &self.order {
+            let direction = match order {
+                Order::Asc => "ASC",
+                Order::Desc => "DESC",
+            };
+            plan = Explain {
+                clause: format!("ORDER BY {} {}", field, direction),
+                index: "ordered",
+                estimate: plan.estimate,
+                children: vec![plan],
+            };
+        }
+        if let Some(limit) = self.limit {
+            plan = Explain {
+                clause: format!("LIMIT {}", limit),
+                index: if self.after.is_some() { "cursor" } else { "first" },
+                estimate: plan.estimate.min(limit),
+                children: vec![plan],
+            };
+        }
+        plan
+    }
+    
+    /// One page of results, honouring `limit` and `after`
+    ///
+    /// Ordered queries walk the field's ordered index from the cursor and
+    /// test each entry against the filter, so no envelope is loaded to be
+    /// sorted.
+    pub fn page(&self, index: &Index) -> Page {
+        self.page_from(index, None)
+    }
+    
+    /// `page`, taking the filter's matches from `matched` if given
+    fn page_from(&self, index: &Index, matched: Option<&HashSet<Hash256>>) -> Page {
+        let matcher = matched.is_none().then(|| self.filter.matcher(index));
+        let matches = |h: &Hash256| match &matcher {
+            Some(matcher) => matcher.matches(index, h),
+            None => matched.is_some_and(|set| set.contains(h)),
+        };
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let after = self.after.as_ref();
+        let mut found: Vec<(Option<&IndexValue>, Hash256)> = Vec::new();
+        if let Some((field, order)) = &self.order {
+            // A cursor without a key already points past the ordered part
+            let from = match after {
+                Some(Cursor { key: None, .. }) => None,
+                _ => Some(after.and_then(|c| c.key.as_ref())),
+            };
+            'walk: for (value, set) in from.into_iter().flat_map(|from| index.ordered_sets(field, *order == Order::Desc, from)) {
+                let mut group: Vec<_> = set.iter().filter(|h| matches(h)).copied().collect();
+                group.sort();
+                if let Some(Cursor { key: Some(key), hash }) = after {
+                    if Index::same_position(key, value) {
+                        group.retain(|h| h > hash);
+                    }
+                }
+                for hash in group {
+                    found.push((Some(value), hash));
+                    if found.len() > limit {
+                        break 'walk;
+                    }
+                }
+            }
+        }
+        if found.len() <= limit {
+            let has_order_field = |h: &Hash256| match &self.order {
+                Some((field, _)) => index.field_set(field).is_some_and(|s| s.contains(h)),
+                None => false,
+            };
+            let candidates: Box<dyn Iterator<Item = Hash256>> = match matched {
+                Some(set) => Box::new(set.iter().copied()),
+                None => Box::new(self.evaluate(index)),
+            };
+            let mut rest: Vec<_> = candidates
+                .filter(|h| !has_order_field(h))
+                .filter(|h| match after {
+                    Some(Cursor { key: None, hash }) => h > hash,
+                    _ => true,
+                })
+                .collect();
+            rest.sort();
+            rest.truncate(limit.saturating_add(1) - found.len());
+            found.extend(rest.into_iter().map(|h| (None, h)));
+        }
+        let more = found.len() > limit;
+        found.truncate(limit);
+        let next = found.last()
+            .filter(|_| more)
+            .map(|(key, hash)| Cursor { key: key.cloned(), hash: *hash });
+        Page { hashes: found.into_iter().map(|(_, hash)| hash).collect(), next }
+    }
+}
+
+/// How the indexes will answer a query, from `Query::explain`
+///
+/// Children are listed in evaluation order: under `AND` the first child
+/// streams candidates and the rest are membership tests against it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explain {
+    /// The clause, e.g. `status = published` or `AND`
+    pub clause: String,
+    /// Index (or strategy) that answers it: `by_type`, `by_value`,
+    /// `ordered`, `scan`, `intersect`, ...
+    pub index: &'static str,
+    /// Upper bound on matches, from posting-set sizes
+    pub estimate: usize,
+    pub children: Vec<Explain>,
+}
+
+impl Explain {
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        writeln!(f, "{:indent$}{} [{}, ~{}]", "", self.clause, self.index, self.estimate, indent = depth * 2)?;
+        self.children.iter().try_for_each(|child| child.write(f, depth + 1))
+    }
+}
+
+impl fmt::Display for Explain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// Position after which the next page of results starts
+///
+/// Cursors round-trip through an opaque token (`to_string` / `parse`)
+/// holding the last result's sort value and hash, so paging stays
+/// deterministic while envelopes are added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    key: Option<IndexValue>,
+    hash: Hash256,
+}
+
+impl Cursor {
+    /// Parse a token produced by `to_string`
+    pub fn parse(token: &str) -> Result<Cursor> {
+        let invalid = || Error::InvalidQuery(format!("invalid cursor '{}'", token));
+        let bytes = hex::decode(token).map_err(|_| invalid())?;
+        let mut reader = Reader::new(&bytes);
+        let hash = reader.hash().map_err(|_| invalid())?;
+        let key = if reader.is_empty() {
+            None
+        } else {
+            Some(IndexValue::decode(&mut reader).map_err(|_| invalid())?)
+        };
+        if !reader.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Cursor { key, hash })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = Vec::new();
+        crate::wire::put_hash(&mut buf, &self.hash);
+        if let Some(key) = &self.key {
+            key.encode(&mut buf);
+        }
+        f.write_str(&hex::encode(buf))
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = Error;
+    
+    fn from_str(s: &str) -> Result<Cursor> {
+        Cursor::parse(s)
+    }
+}
+
+/// A page of query results
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub hashes: Vec<Hash256>,
+    /// Where the next page starts, or `None` on the last page
+    pub next: Option<Cursor>,
+}
+
+impl std::ops::Not for Query {
+    type Output = Query;
+    
+    fn not(self) -> Query {
+        let filter = match self.filter {
+            Filter::Not(inner) => *inner,
+            filter => Filter::Not(Box::new(filter)),
+        };
+        Query { filter, ..self }
+    }
+}
+
+impl Query {
+    /// Append a binary encoding of the query, for persisted views
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        self.filter.encode(buf);
+        match &self.order {
+            Some((field, order)) => {
+                buf.push(1);
+                wire::put_str(buf, field);
+                buf.push(u8::from(*order == Order::Desc));
+            }
+            None => buf.push(0),
+        }
+        wire::put_opt_i64(buf, self.limit.map(|n| n as i64));
+        wire::put_str(buf, &self.after.as_ref().map(Cursor::to_string).unwrap_or_default());
+    }
+    
+    /// Decode a query written by `encode`
+    pub(crate) fn decode(reader: &mut Reader<'_>) -> Result<Query> {
+        let filter = Filter::decode(reader)?;
+        let order = match reader.u8()? {
+            0 => None,
+            _ => {
+                let field = reader.string()?;
+                Some((field, if reader.u8()? == 0 { Order::Asc } else { Order::Desc }))
+            }
+        };
+        let limit = reader.opt_i64()?.map(|n| n as usize);
+        let after = match reader.string()? {
+            token if token.is_empty() => None,
+            token => Some(Cursor::parse(&token)?),
+        };
+        Ok(Query { filter, order, limit, after })
+    }
+}
+
+impl Filter {
+    const TAG_TYPE: u8 = 0;
+    const TAG_TYPE_NAME: u8 = 1;
+    const TAG_FIELD_EQ: u8 = 2;
+    const TAG_RANGE: u8 = 3;
+    const TAG_HAS_FIELD: u8 = 4;
+    const TAG_CREATED_BETWEEN: u8 = 5;
+    const TAG_AND: u8 = 6;
+    const TAG_OR: u8 = 7;
+    const TAG_NOT: u8 = 8;
+    const TAG_RELATED: u8 = 9;
+    
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let put_bound = |buf: &mut Vec<u8>, bound: &Bound<IndexValue>| match bound {
+            Bound::Unbounded => buf.push(0),
+            Bound::Included(v) => {
+                buf.push(1);
+                v.encode(buf);
+            }
+            Bound::Excluded(v) => {
+                buf.push(2);
+                v.encode(buf);
+            }
+        };
+        match self {
+            Filter::Type(t) => {
+                buf.push(Self::TAG_TYPE);
+                wire::put_hash(buf, t);
+            }
+            Filter::TypeName(name) => {
+                buf.push(Self::TAG_TYPE_NAME);
+                wire::put_str(buf, name);
+            }
+            Filter::FieldEq(field, value) => {
+                buf.push(Self::TAG_FIELD_EQ);
+                wire::put_str(buf, field);
+                value.encode(buf);
+            }
+            Filter::Range { field, lower, upper } => {
+                buf.push(Self::TAG_RANGE);
+                wire::put_str(buf, field);
+                put_bound(buf, lower);
+                put_bound(buf, upper);
+            }
+            Filter::HasField(field) => {
+                buf.push(Self::TAG_HAS_FIELD);
+                wire::put_str(buf, field);
+            }
+            Filter::CreatedBetween(start, end) => {
+                buf.push(Self::TAG_CREATED_BETWEEN);
+                wire::put_i64(buf, *start);
+                wire::put_i64(buf, *end);
+            }
+            Filter::And(children) | Filter::Or(children) => {
+                buf.push(if matches!(self, Filter::And(_)) { Self::TAG_AND } else { Self::TAG_OR });
+                wire::put_u32(buf, children.len() as u32);
+                children.iter().for_each(|child| child.encode(buf));
+            }
+            Filter::Not(inner) => {
+                buf.push(Self::TAG_NOT);
+                inner.encode(buf);
+            }
+            Filter::Related(rel_type, target) => {
+                buf.push(Self::TAG_RELATED);
+                wire::put_str(buf, rel_type);
+                target.encode(buf);
+            }
+        }
+    }
+    
+    fn decode(reader: &mut Reader<'_>) -> Result<Filter> {
+        let bound = |reader: &mut Reader<'_>| -> Result<Bound<IndexValue>> {
+            Ok(match reader.u8()? {
+                0 => Bound::Unbounded,
+                1 => Bound::Included(IndexValue::decode(reader)?),
+                _ => Bound::Excluded(IndexValue::decode(reader)?),
+            })
+        };
+        let children = |reader: &mut Reader<'_>| -> Result<Vec<Filter>> {
+            (0..reader.u32()?).map(|_| Filter::decode(reader)).collect()
+        };
+        Ok(match reader.u8()? {
+            Self::TAG_TYPE => Filter::Type(reader.hash()?),
+            Self::TAG_TYPE_NAME => Filter::TypeName(reader.string()?),
+            Self::TAG_FIELD_EQ => Filter::FieldEq(reader.string()?, IndexValue::decode(reader)?),
+            Self::TAG_RANGE => Filter::Range { field: reader.string()?, lower: bound(reader)?, upper: bound(reader)? },
+            Self::TAG_HAS_FIELD => Filter::HasField(reader.string()?),
+            Self::TAG_CREATED_BETWEEN => Filter::CreatedBetween(reader.i64()?, reader.i64()?),
+            Self::TAG_AND => Filter::And(children(reader)?),
+            Self::TAG_OR => Filter::Or(children(reader)?),
+            Self::TAG_NOT => Filter::Not(Box::new(Filter::decode(reader)?)),
+            Self::TAG_RELATED => Filter::Related(reader.string()?, Box::new(Filter::decode(reader)?)),
+            tag => return Err(Error::Serialization(format!("unknown query tag {}", tag))),
+        })
+    }
+    
+    fn relationship_depth(&self) -> usize {
+        match self {
+            Filter::Related(_, target) => 1 + target.relationship_depth(),
+            Filter::And(children) | Filter::Or(children) => {
+                children.iter().map(Filter::relationship_depth).max().unwrap_or(0)
+            }
+            Filter::Not(inner) => inner.relationship_depth(),
+            _ => 0,
+        }
+    }
+    
+    /// Upper bound on the number of matches, from posting-set sizes
+    fn estimate(&self, index: &Index) -> usize {
+        match self {
+            Filter::Type(t) => index.type_set(t).map_or(0, |s| s.len()),
+            Filter::TypeName(name) => index.type_name_set(name).map_or(0, |s| s.len()),
+            Filter::FieldEq(field, value) => index.value_set(field, value).map_or(0, |s| s.len()),
+            Filter::Range { field, .. } => index.field_set(field).map_or(0, |s| s.len()),
+            Filter::HasField(field) => index.field_set(field).map_or(0, |s| s.len()),
+            Filter::CreatedBetween(start, end) => index.created_between(*start, *end).count(),
+            Filter::Related(rel_type, _) => index.relationship_count(rel_type),
+            Filter::And(children) => children.iter().map(|c| c.estimate(index)).min().unwrap_or(0),
+            Filter::Or(children) => children.iter().map(|c| c.estimate(index)).sum(),
+            Filter::Not(_) => index.len(),
+        }
+    }
+    
+    fn explain(&self, index: &Index) -> Explain {
+        let leaf = |clause: String, index_name| Explain { clause, index: index_name, estimate: self.estimate(index), children: Vec::new() };
+        match self {
+            Filter::Type(t) => leaf(format!("type = {}", t), "by_type"),
+            Filter::TypeName(name) => leaf(format!("type_name = {}", name), "by_type_name"),
+            Filter::FieldEq(field, value) => leaf(format!("{} = {}", field, value), "by_value"),
+            Filter::Range { field, lower, upper } => {
+                let mut parts = Vec::new();
+                match lower {
+                    Bound::Included(v) => parts.push(format!("{} >= {}", field, v)),
+                    Bound::Excluded(v) => parts.push(format!("{} > {}", field, v)),
+                    Bound::Unbounded => {}
+                }
+                match upper {
+                    Bound::Included(v) => parts.push(format!("{} <= {}", field, v)),
+                    Bound::Excluded(v) => parts.push(format!("{} < {}", field, v)),
+                    Bound::Unbounded => {}
+                }
+                let clause = if parts.is_empty() { format!("{} in any range", field) } else { parts.join(", ") };
+                leaf(clause, if indexed_bounds(lower, upper) { "ordered" } else { "scan" })
+            }
+            Filter::HasField(field) => leaf(format!("has {}", field), "by_field_name"),
+            Filter::CreatedBetween(start, end) => {
+                leaf(format!("created_at >= {}, created_at < {}", start, end), "by_created_at")
+            }
+            Filter::And(children) => {
+                let mut plans: Vec<_> = children.iter().map(|c| c.explain(index)).collect();
+                if let Some(driver) = children.iter().enumerate().min_by_key(|(_, c)| c.estimate(index)).map(|(i, _)| i) {
+                    let first = plans.remove(driver);
+                    plans.insert(0, first);
+                }
+                Explain { clause: "AND".to_string(), index: "intersect", estimate: self.estimate(index), children: plans }
+            }
+            Filter::Or(children) => Explain {
+                clause: "OR".to_string(),
+                index: "union",
+                estimate: self.estimate(index),
+                children: children.iter().map(|c| c.explain(index)).collect(),
+            },
+            Filter::Not(inner) => Explain {
+                clause: "NOT".to_string(),
+                index: "complement",
+                estimate: self.estimate(index),
+                children: vec![inner.explain(index)],
+            },
+            Filter::Related(rel_type, target) => Explain {
+                clause: format!("{} ->", rel_type),
+                index: "by_relationship",
+                estimate: self.estimate(index),
+                children: vec![target.explain(index)],
+            },
+        }
+    }
+    
+    fn matches(&self, index: &Index, hash: &Hash256) -> bool {
+        match self {
+            Filter::Type(t) => index.type_set(t).is_some_and(|s| s.contains(hash)),
+            Filter::TypeName(name) => index.type_name_set(name).is_some_and(|s| s.contains(hash)),
+            Filter::FieldEq(field, value) => index.value_set(field, value).is_some_and(|s| s.contains(hash)),
+            Filter::Range { field, lower, upper } if indexed_bounds(lower, upper) => {
+                index.in_range(field, hash, lower.as_ref(), upper.as_ref())
+            }
+            Filter::Range { field, lower, upper } => index.field_value(field, hash)
+                .is_some_and(|value| in_range(value, lower, upper)),
+            Filter::HasField(field) => index.field_set(field).is_some_and(|s| s.contains(hash)),
+            Filter::CreatedBetween(start, end) => index.created_at(hash).is_some_and(|t| *start <= t && t < *end),
+            Filter::And(children) => children.iter().all(|c| c.matches(index, hash)),
+            Filter::Or(children) => children.iter().any(|c| c.matches(index, hash)),
+            Filter::Not(inner) => index.contains(hash) && !inner.matches(index, hash),
+            Filter::Related(rel_type, target) => index.outgoing(hash)
+                .any(|(rel, to)| rel == rel_type && target.matches(index, to)),
+        }
+    }
+    
+    fn candidates<'a>(&'a self, index: &'a Index) -> Box<dyn Iterator<Item = Hash256> + 'a> {
+        match self {
+            Filter::Type(t) => Box::new(index.type_set(t).into_iter().flatten().copied()),
+            Filter::TypeName(name) => Box::new(index.by_type_name(name).copied()),
+            Filter::FieldEq(field, value) => Box::new(index.value_set(field, value).into_iter().flatten().copied()),
+            Filter::Range { field, lower, upper } if indexed_bounds(lower, upper) => {
+                Box::new(index.by_range(field, lower.as_ref(), upper.as_ref()).copied())
+            }
+            Filter::Range { field, lower, upper } => Box::new(index.field_values(field)
+                .filter(move |(value, _)| in_range(value, lower, upper))
+                .flat_map(|(_, set)| set.iter().copied())),
+            Filter::HasField(field) => Box::new(index.field_set(field).into_iter().flatten().copied()),
+            Filter::CreatedBetween(start, end) => Box::new(index.created_between(*start, *end).copied()),
+            Filter::And(children) => {
+                let Some(driver) = children.iter().min_by_key(|c| c.estimate(index)) else {
+                    return Box::new(std::iter::empty());
+                };
+                let rest: Vec<_> = children.iter().filter(|c| !std::ptr::eq(*c, driver)).map(|c| c.matcher(index)).collect();
+                Box::new(driver.candidates(index).filter(move |h| rest.iter().all(|m| m.matches(index, h))))
+            }
+            Filter::Or(children) => {
+                let earlier: Rc<Vec<_>> = Rc::new(children.iter().map(|c| c.matcher(index)).collect());
+                Box::new(children.iter().enumerate().flat_map(move |(i, child)| {
+                    let earlier = Rc::clone(&earlier);
+                    child.candidates(index).filter(move |h| !earlier[..i].iter().any(|m| m.matches(index, h)))
+                }))
+            }
+            Filter::Not(inner) => {
+                let inner = inner.matcher(index);
+                Box::new(index.hashes().copied().filter(move |h| !inner.matches(index, h)))
+            }
+            Filter::Related(rel_type, target) => {
+                // Walk back from matching targets; a source linking to several is produced once
+                let mut seen = HashSet::new();
+                Box::new(target.candidates(index)
+                    .flat_map(move |to| index.by_relationship(rel_type, &to).copied())
+                    .filter(move |source| seen.insert(*source)))
+            }
+        }
+    }
+}
+
+/// A filter prepared for testing many hashes
+///
+/// Ranges are materialised into a set once, rather than walked for every
+/// hash; other leaves are already single lookups.
+enum Matcher<'a> {
+    Filter(&'a Filter),
+    Set(HashSet<Hash256>),
+    And(Vec<Matcher<'a>>),
+    Or(Vec<Matcher<'a>>),
+    Not(Box<Matcher<'a>>),
+    Related(&'a str, Box<Matcher<'a>>),
+}
+
+impl Filter {
+    fn matcher(&self, index: &Index) -> Matcher<'_> {
+        match self {
+            Filter::Range { .. } => Matcher::Set(self.candidates(index).collect()),
+            Filter::And(children) => Matcher::And(children.iter().map(|c| c.matcher(index)).collect()),
+            Filter::Or(children) => Matcher::Or(children.iter().map(|c| c.matcher(index)).collect()),
+            Filter::Not(inner) => Matcher::Not(Box::new(inner.matcher(index))),
+            Filter::Related(rel_type, target) => Matcher::Related(rel_type, Box::new(target.matcher(index))),
+            leaf => Matcher::Filter(leaf),
+        }
+    }
+}
+
+impl Matcher<'_> {
+    fn matches(&self, index: &Index, hash: &Hash256) -> bool {
+        match self {
+            Matcher::Filter(filter) => filter.matches(index, hash),
+            Matcher::Set(set) => set.contains(hash),
+            Matcher::And(children) => children.iter().all(|c| c.matches(index, hash)),
+            Matcher::Or(children) => children.iter().any(|c| c.matches(index, hash)),
+            Matcher::Not(inner) => index.contains(hash) && !inner.matches(index, hash),
+            Matcher::Related(rel_type, target) => index.outgoing(hash)
+                .any(|(rel, to)| rel == *rel_type && target.matches(index, to)),
+        }
+    }
+}
+
+/// Whether a range can be answered from the ordered index: both bounds
+/// numeric or both strings, and not fully unbounded
+fn indexed_bounds(lower: &Bound<IndexValue>, upper: &Bound<IndexValue>) -> bool {
+    let kind = |b: &Bound<IndexValue>| match b {
+        Bound::Included(v) | Bound::Excluded(v) if v.is_numeric() => Some(Some(true)),
+        Bound::Included(IndexValue::String(_)) | Bound::Excluded(IndexValue::String(_)) => Some(Some(false)),
+        Bound::Included(_) | Bound::Excluded(_) => None,
+        Bound::Unbounded => Some(None),
+    };
+    match (kind(lower), kind(upper)) {
+        (Some(Some(a)), Some(Some(b))) => a == b,
+        (Some(Some(_)), Some(None)) | (Some(None), Some(Some(_))) => true,
+        _ => false,
+    }
+}
+
+/// Whether `value` lies between the bounds (and is comparable with them)
+fn in_range(value: &IndexValue, lower: &Bound<IndexValue>, upper: &Bound<IndexValue>) -> bool {
+    use std::cmp::Ordering::*;
+    let above = match lower {
+        Bound::Included(b) => matches!(value.compare(b), Some(Greater | Equal)),
+        Bound::Excluded(b) => matches!(value.compare(b), Some(Greater)),
+        Bound::Unbounded => true,
+    };
+    let below = match upper {
+        Bound::Included(b) => matches!(value.compare(b), Some(Less | Equal)),
+        Bound::Excluded(b) => matches!(value.compare(b), Some(Less)),
+        Bound::Unbounded => true,
+    };
+    above && below
+}
+
+/// The value a query literal stands for: quoted text is a string,
+/// otherwise numbers, booleans and `null` are recognized, and anything
+/// else is a string, e.g. for typing index values given as text
+pub fn parse_literal(literal: &str) -> IndexValue {
+    literal_values(literal).swap_remove(0)
+}
+
+/// Values a literal may compare equal to
+///
+/// Bare numbers and booleans also match their string spelling, since
+/// index fields are often written as strings.
+pub(crate) fn literal_values(literal: &str) -> Vec<IndexValue> {
+    if let Some(quoted) = literal.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return vec![IndexValue::String(quoted.to_string())];
+    }
+    let mut values = Vec::new();
+    if let Ok(v) = literal.parse::<i64>() {
+        values.push(IndexValue::Int64(v));
+    } else if let Ok(v) = literal.parse::<f64>() {
+        values.push(IndexValue::Float64(v));
+    } else if let Ok(v) = literal.parse::<bool>() {
+        values.push(IndexValue::Bool(v));
+    } else if literal == "null" {
+        values.push(IndexValue::Null);
+    }
+    values.push(IndexValue::String(literal.to_string()));
+    values
+}
+
+/// Token of the textual query language
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+/// Split query text into tokens
+fn lex(input: &str) -> Result<Vec<Token>> {
+    const OPS: [&str; 7] = ["!=", "<=", ">=", ":", "=", "<", ">"];
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if let Some(r) = rest.strip_prefix('(') {
+            tokens.push(Token::Open);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(')') {
+            tokens.push(Token::Close);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('"') {
+            let end = r.find('"')
+                .ok_or_else(|| Error::InvalidQuery(format!("unterminated string in '{}'", input)))?;
+            tokens.push(Token::Quoted(r[..end].to_string()));
+            rest = &r[end + 1..];
+        } else {
+            let end = word_len(rest);
+            if end == 0 {
+                return Err(Error::InvalidQuery(format!("unexpected '{}'", &rest[..1])));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Length of the word `rest` starts with; `->` within a word is a path hop
+fn word_len(rest: &str) -> usize {
+    let mut end = 0;
+    while let Some(c) = rest[end..].chars().next() {
+        if rest[end..].starts_with("->") && end > 0 {
+            end += 2;
+        } else if c.is_whitespace() || "()\"!<>:=".contains(c) {
+            break;
+        } else {
+            end += c.len_utf8();
+        }
+    }
+    end
+}
+
+/// Recursive-descent parser: OR binds loosest, then AND (or adjacency), then NOT
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    
+    fn next(&mut self) -> Result<Token> {
+        let token = self.tokens.get(self.pos).cloned()
+            .ok_or_else(|| Error::InvalidQuery("unexpected end of query".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+    
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == keyword)
+    }
+    
+    fn or(&mut self) -> Result<Query> {
+        let mut query = self.and()?;
+        while self.at_keyword("OR") {
+            self.pos += 1;
+            query = query.or(self.and()?);
+        }
+        Ok(query)
+    }
+    
+    fn and(&mut self) -> Result<Query> {
+        let mut query = self.unary()?;
+        while self.peek().is_some_and(|t| *t != Token::Close) && !self.at_keyword("OR") {
+            if self.at_keyword("AND") {
+                self.pos += 1;
+            }
+            query = query.and(self.unary()?);
+        }
+        Ok(query)
+    }
+    
+    fn unary(&mut self) -> Result<Query> {
+        if self.at_keyword("NOT") {
+            self.pos += 1;
+            return Ok(!self.unary()?);
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let query = self.or()?;
+            return match self.next()? {
+                Token::Close => Ok(query),
+                other => Err(Error::InvalidQuery(format!("expected ')', found {:?}", other))),
+            };
+        }
+        self.clause()
+    }
+    
+    /// A predicate, on the envelope itself or at the end of a path
+    fn clause(&mut self) -> Result<Query> {
+        let path = match self.next()? {
+            Token::Word(w) => w,
+            other => return Err(Error::InvalidQuery(format!("expected a field name, found {:?}", other))),
+        };
+        let (hops, field) = split_path(&path)?;
+        let query = self.predicate(field.to_string())?;
+        Ok(hops.into_iter().rev().fold(query, |query, rel_type| Query::related(rel_type, query)))
+    }
+    
+    fn predicate(&mut self, field: String) -> Result<Query> {
+        let op = match self.next()? {
+            Token::Op(op) => op,
+            other => return Err(Error::InvalidQuery(format!("expected an operator after '{}', found {:?}", field, other))),
+        };
+        let values = match self.next()? {
+            Token::Quoted(s) => vec![IndexValue::String(s)],
+            Token::Word(w) => literal_values(&w),
+            other => return Err(Error::InvalidQuery(format!("expected a value after '{} {}', found {:?}", field, op, other))),
+        };
+        
+        if op == ":" && field == "type" {
+            let IndexValue::String(name) = values.last().unwrap() else { unreachable!() };
+            return Ok(match Hash256::from_hex(name) {
+                Ok(hash) => Query::type_is(hash),
+                Err(_) => Query::type_named(name.clone()),
+            });
+        }
+        if op == ":" && field == "has" {
+            let IndexValue::String(name) = values.last().unwrap() else { unreachable!() };
+            return Ok(Query::has_field(name.clone()));
+        }
+        let first = values[0].clone();
+        let equal = || {
+            values.iter().cloned()
+                .map(|v| Query::field_eq(field.clone(), v))
+                .reduce(Query::or)
+                .unwrap()
+        };
+        Ok(match op {
+            ":" | "=" => equal(),
+            "!=" => Query::has_field(field.clone()).and(!equal()),
+            "<" => Query::range(field, ..first),
+            "<=" => Query::range(field, ..=first),
+            ">" => Query::range(field, (Bound::Excluded(first), Bound::Unbounded)),
+            ">=" => Query::range(field, first..),
+            _ => unreachable!(),
+        })
+    }
+}
+
+impl Query {
+    /// Parse the textual query language
+    ///
+    /// `type:BlogPost AND title:"Zero-Copy Dreams" AND created_at > 1708000000`
+    ///
+    /// Clauses are `type:<name or hex hash>`, `has:<field>` and
+    /// `<field> <op> <value>` with `:`/`=`, `!=`, `<`, `<=`, `>`, `>=`.
+    /// Prefixing a clause with a path of relationship types (`tag->`
+    /// or `tag/`, see `IndexedStore::select`) applies it to the targets
+    /// instead: `tag->name = rust`. Combine clauses with `AND` (or plain
+    /// adjacency), `OR`, `NOT` and parentheses. Bare numbers and booleans
+    /// also match their string spelling for equality.
+    pub fn parse(input: &str) -> Result<Query> {
+        let mut parser = Parser { tokens: lex(input)?, pos: 0 };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(Error::InvalidQuery(format!("unexpected {:?}", token))),
+        }
+    }
+}
+
+impl std::str::FromStr for Query {
+    type Err = Error;
+    
+    fn from_str(s: &str) -> Result<Query> {
+        Query::parse(s)
+    }
+}
+
+impl IndexedStore {
+    /// Run a query against the indexes; results are sorted by hash unless
+    /// the query has an `order_by`
+    pub fn query(&self, query: &Query) -> Vec<Hash256> {
+        let span = crate::trace::span!("IndexedStore::query", results = Empty);
+        #[cfg(feature = "metrics")]
+        let started = crate::clock::Stopwatch::start();
+        let results = query.results(self.index());
+        span.record("results", results.len());
+        #[cfg(feature = "metrics")]
+        self.metrics().record_query(started);
+        results
+    }
+    
+    /// Run a query and return one page of results with the next cursor
+    pub fn query_page(&self, query: &Query) -> Page {
+        let span = crate::trace::span!("IndexedStore::query_page", results = Empty);
+        #[cfg(feature = "metrics")]
+        let started = crate::clock::Stopwatch::start();
+        let page = query.page(self.index());
+        span.record("results", page.hashes.len());
+        #[cfg(feature = "metrics")]
+        self.metrics().record_query(started);
+        page
+    }
+    
+    /// Run several queries at once, returning each one's results in input
+    /// order
+    ///
+    /// Queries with the same filter share one evaluation of it, whatever
+    /// their order, limit or cursor, and identical queries run once; for
+    /// dashboards issuing many small queries over a few filters.
+    pub fn query_many(&self, queries: &[Query]) -> Vec<Vec<Hash256>> {
+        let _span = crate::trace::span!("IndexedStore::query_many", queries = queries.len());
+        #[cfg(feature = "metrics")]
+        let started = crate::clock::Stopwatch::start();
+        let index = self.index();
+        let mut filters: HashMap<Vec<u8>, HashSet<Hash256>> = HashMap::new();
+        let mut done: HashMap<Vec<u8>, Vec<Hash256>> = HashMap::new();
+        let mut buf = Vec::new();
+        let results = queries.iter()
+            .map(|query| {
+                query.encode(&mut buf);
+                let query_key = std::mem::take(&mut buf);
+                if let Some(results) = done.get(&query_key) {
+                    return results.clone();
+                }
+                query.filter.encode(&mut buf);
+                let matched = filters.entry(std::mem::take(&mut buf)).or_insert_with(|| query.evaluate(index).collect());
+                let results = query.page_from(index, Some(matched)).hashes;
+                done.insert(query_key, results.clone());
+                results
+            })
+            .collect();
+        #[cfg(feature = "metrics")]
+        self.metrics().record_query(started);
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    
+    #[test]
+    fn test_query_combinators() {
+        let mut store = IndexedStore::new();
+        let post_type = Hash256::hash(b"Post");
+        let page_type = Hash256::hash(b"Page");
+        let published = store.put(&Envelope::builder(post_type, b"1".to_vec()).index("status", "published").build()).unwrap();
+        let draft = store.put(&Envelope::builder(post_type, b"2".to_vec()).index("status", "draft").build()).unwrap();
+        let pinned = store.put(&Envelope::builder(post_type, b"3".to_vec())
+            .index("status", "draft")
+            .index("pinned", "true")
+            .build()).unwrap();
+        let page = store.put(&Envelope::builder(page_type, b"4".to_vec()).index("status", "published").build()).unwrap();
+        
+        let sorted = |mut v: Vec<Hash256>| {
+            v.sort();
+            v
+        };
+        let q = Query::type_is(post_type).and(field_eq("status", "published"));
+        assert_eq!(store.query(&q), vec![published]);
+        
+        let q = q.or(field_eq("pinned", "true"));
+        assert_eq!(store.query(&q), sorted(vec![published, pinned]));
+        
+        let q = Query::type_is(post_type).and(!field_eq("status", "published"));
+        assert_eq!(store.query(&q), sorted(vec![draft, pinned]));
+        
+        let q = field_eq("status", "published").or(Query::type_is(page_type));
+        assert_eq!(store.query(&q), sorted(vec![published, page]));
+        assert!(store.query(&Query::has_field("missing")).is_empty());
+    }
+    
+    #[test]
+    fn test_parse_query() {
+        let mut store = IndexedStore::new();
+        let post_type = Hash256::hash(b"schema:BlogPost");
+        let dreams = store.put(&Envelope::builder(post_type, b"1".to_vec())
+            .type_name("BlogPost")
+            .index("title", "Zero-Copy Dreams")
+            .index("created_at", 1708523400i64)
+            .build()).unwrap();
+        let old = store.put(&Envelope::builder(post_type, b"2".to_vec())
+            .type_name("BlogPost")
+            .index("title", "Zero-Copy Dreams")
+            .index("created_at", 1600000000i64)
+            .build()).unwrap();
+        let tag = store.put(&Envelope::builder(Hash256::hash(b"schema:Tag"), vec![])
+            .type_name("Tag")
+            .index("words", "1500")
+            .build()).unwrap();
+        
+        let run = |q: &str| store.query(&Query::parse(q).unwrap());
+        assert_eq!(run("type:BlogPost AND title:\"Zero-Copy Dreams\" AND created_at > 1708000000"), vec![dreams]);
+        assert_eq!(run("type:BlogPost created_at <= 1708000000"), vec![old]);
+        assert_eq!(run(&format!("type:{}", post_type.to_hex())).len(), 2);
+        assert_eq!(run("words = 1500"), vec![tag]);
+        assert_eq!(run("NOT type:BlogPost"), vec![tag]);
+        assert_eq!(run("(type:Tag OR created_at >= 1708523400) AND has:title"), vec![dreams]);
+        assert_eq!(run("words != 1500"), Vec::<Hash256>::new());
+        
+        for bad in ["", "type:", "title \"x\"", "(type:Tag", "type:Tag )", "title:\"open"] {
+            assert!(matches!(Query::parse(bad), Err(Error::InvalidQuery(_))), "{bad}");
+        }
+    }
+    
+    #[test]
+    fn test_related_query() {
+        let mut store = IndexedStore::new();
+        let author = Hash256::hash(b"Author");
+        let alice = store.put(&Envelope::builder(author, b"a".to_vec()).index("name", "Alice").build()).unwrap();
+        let bob = store.put(&Envelope::builder(author, b"b".to_vec()).index("name", "Bob").build()).unwrap();
+        let post = Hash256::hash(b"Post");
+        let first = store.put(&Envelope::builder(post, b"1".to_vec()).relationship("author", alice).build()).unwrap();
+        store.put(&Envelope::builder(post, b"2".to_vec()).relationship("author", bob).build()).unwrap();
+        let cites = store.put(&Envelope::builder(post, b"3".to_vec()).relationship("cites", first).build()).unwrap();
+        
+        let by_alice = Query::related("author", Query::field_eq("name", "Alice"));
+        assert_eq!(store.query(&by_alice), vec![first]);
+        assert_eq!(Query::parse("author->name = Alice").unwrap(), by_alice);
+        assert_eq!(Query::parse("author/name = Alice").unwrap(), by_alice);
+        assert_eq!(store.query(&Query::parse("cites->author->name:Alice").unwrap()), vec![cites]);
+        assert_eq!(store.query(&Query::type_is(post).and(Query::parse("NOT author->name = Alice").unwrap())).len(), 2);
+        
+        let mut buf = Vec::new();
+        by_alice.encode(&mut buf);
+        assert_eq!(Query::decode(&mut Reader::new(&buf)).unwrap(), by_alice);
+        assert!(matches!(Query::parse("author-> = Alice"), Err(Error::InvalidQuery(_))));
+    }
+    
+    #[test]
+    fn test_order_by() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let mut put = |words: Option<i64>, status: &str| {
+            let mut builder = Envelope::builder(post, status.into()).index("status", status);
+            if let Some(words) = words {
+                builder = builder.index("words", words);
+            }
+            store.put(&builder.build()).unwrap()
+        };
+        let short = put(Some(300), "published");
+        let long = put(Some(2000), "published");
+        let medium = put(Some(900), "published");
+        let untitled = put(None, "published");
+        put(Some(100), "draft");
+        
+        let published = field_eq("status", "published");
+        assert_eq!(store.query(&published.clone().order_by("words", Order::Asc)), vec![short, medium, long, untitled]);
+        assert_eq!(store.query(&published.order_by("words", Order::Desc)), vec![long, medium, short, untitled]);
+        let q = Query::range("words", 500i64..).order_by("words", Order::Desc);
+        assert_eq!(store.query(&q), vec![long, medium]);
+    }
+    
+    #[test]
+    fn test_pagination() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let put = |store: &mut IndexedStore, rank: Option<i64>, tag: &[u8]| {
+            let mut builder = Envelope::builder(post, tag.to_vec());
+            if let Some(rank) = rank {
+                builder = builder.index("rank", rank);
+            }
+            store.put(&builder.build()).unwrap()
+        };
+        let r1 = put(&mut store, Some(1), b"a");
+        let r2 = put(&mut store, Some(2), b"b");
+        let r3 = put(&mut store, Some(3), b"c");
+        let unranked = put(&mut store, None, b"d");
+        
+        let q = Query::type_is(post).order_by("rank", Order::Asc).limit(2);
+        let first = store.query_page(&q);
+        assert_eq!(first.hashes, vec![r1, r2]);
+        let token = first.next.unwrap().to_string();
+        
+        // An envelope inserted before the cursor doesn't shift later pages
+        put(&mut store, Some(0), b"e");
+        let second = store.query_page(&q.clone().after(token.parse().unwrap()));
+        assert_eq!(second.hashes, vec![r3, unranked]);
+        assert!(second.next.is_none());
+        
+        let by_hash = Query::type_is(post).limit(4);
+        let all = store.query(&Query::type_is(post));
+        let page = store.query_page(&by_hash);
+        assert_eq!(page.hashes, all[..4]);
+        let rest = store.query_page(&by_hash.after(page.next.unwrap()));
+        assert_eq!(rest.hashes, all[4..]);
+        
+        assert!(matches!(Cursor::parse("zz"), Err(Error::InvalidQuery(_))));
+        assert!(matches!(Cursor::parse("00"), Err(Error::InvalidQuery(_))));
+    }
+    
+    #[test]
+    fn test_explain() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        for i in 0..5i64 {
+            let status = if i == 0 { "draft" } else { "published" };
+            store.put(&Envelope::builder(post, vec![i as u8])
+                .type_name("BlogPost")
+                .index("status", status)
+                .index("words", i * 100)
+                .build()).unwrap();
+        }
+        
+        let q = Query::parse("type:BlogPost status:draft words >= 100").unwrap().order_by("words", Order::Desc).limit(10);
+        let plan = q.explain(&store);
+        assert_eq!(plan.clause, "LIMIT 10");
+        let order = &plan.children[0];
+        assert_eq!(order.clause, "ORDER BY words DESC");
+        let and = &order.children[0];
+        assert_eq!((and.index, and.estimate), ("intersect", 1));
+        // The most selective clause drives the intersection
+        let clauses: Vec<_> = and.children.iter().map(|c| (c.clause.as_str(), c.index, c.estimate)).collect();
+        assert_eq!(clauses, vec![
+            ("status = draft", "by_value", 1),
+            ("type_name = BlogPost", "by_type_name", 5),
+            ("words >= 100", "ordered", 5),
+        ]);
+        assert!(plan.to_string().starts_with("LIMIT 10 [first, ~1]\n  ORDER BY words DESC [ordered, ~1]\n    AND [intersect, ~1]\n"));
+    }
+    
+    #[test]
+    fn test_query_many() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        for i in 0..6i64 {
+            store.put(&Envelope::builder(post, vec![i as u8]).index("n", i).index("even", i % 2 == 0).build()).unwrap();
+        }
+        let queries = vec![
+            Query::field_eq("even", true),
+            Query::field_eq("even", true).order_by("n", Order::Desc).limit(2),
+            Query::type_is(post).and(Query::field_eq("n", 3i64)),
+            Query::field_eq("even", true),
+            Query::field_eq("missing", 1i64),
+        ];
+        let batched = store.query_many(&queries);
+        let single: Vec<_> = queries.iter().map(|q| store.query(q)).collect();
+        assert_eq!(batched, single);
+        assert_eq!(batched.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2, 1, 3, 0]);
+    }
+}
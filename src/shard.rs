@@ -0,0 +1,179 @@
+//! Stores partitioned by hash prefix
+//!
+//! A `ShardedStore` routes each object to one of several `Store`s by the
+//! leading bytes of its hash, each in its own subdirectory with its own
+//! log and lock, so no single directory or writer holds everything.
+//! Batch puts and gets run one thread per shard, so they need a target
+//! with threads (not wasm32-unknown-unknown). Refs and tags live in
+//! the first shard. Each shard is an ordinary store, so whole-graph
+//! operations (gc, closures, pulls) on a shard see only its objects.
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::store::Store;
+use crate::Result;
+use std::fs;
+use std::path::Path;
+
+/// File in the root directory recording the shard count
+pub const SHARDS_FILE: &str = "shards";
+
+/// Objects spread over a fixed number of stores by hash prefix
+#[derive(Debug)]
+pub struct ShardedStore {
+    shards: Vec<Store>,
+}
+
+impl ShardedStore {
+    /// In-memory shards (at least one)
+    pub fn new(shards: usize) -> Self {
+        Self { shards: (0..shards.max(1)).map(|_| Store::new()).collect() }
+    }
+    
+    /// Open (or create) `shards` stores under `dir`, one subdirectory each
+    ///
+    /// The count is fixed when the directory is created: reopening with a
+    /// different one would route hashes to the wrong shards, so it fails.
+    pub fn open(dir: impl AsRef<Path>, shards: usize) -> Result<Self> {
+        let dir = dir.as_ref();
+        let shards = shards.max(1);
+        fs::create_dir_all(dir)?;
+        let count_path = dir.join(SHARDS_FILE);
+        match fs::read_to_string(&count_path) {
+            Ok(existing) if existing.trim() != shards.to_string() => {
+                return Err(Error::Storage(format!("store has {} shards, not {}", existing.trim(), shards)));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => fs::write(&count_path, shards.to_string())?,
+            Err(e) => return Err(e.into()),
+        }
+        let shards = (0..shards)
+            .map(|i| Store::open(dir.join(format!("{:03}", i))))
+            .collect::<Result<_>>()?;
+        Ok(Self { shards })
+    }
+    
+    /// Index of the shard holding `hash`
+    pub fn shard_of(&self, hash: &Hash256) -> usize {
+        let bytes = hash.as_bytes();
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize % self.shards.len()
+    }
+    
+    pub fn shards(&self) -> &[Store] {
+        &self.shards
+    }
+    
+    pub fn put(&mut self, envelope: &Envelope) -> Result<Hash256> {
+        let shard = self.shard_of(&envelope.hash());
+        self.shards[shard].put(envelope)
+    }
+    
+    pub fn get(&self, hash: &Hash256) -> Result<Envelope> {
+        self.shards[self.shard_of(hash)].get(hash)
+    }
+    
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.shards[self.shard_of(hash)].contains(hash)
+    }
+    
+    pub fn remove(&mut self, hash: &Hash256) -> Result<bool> {
+        let shard = self.shard_of(hash);
+        self.shards[shard].remove(hash)
+    }
+    
+    /// Objects across all shards
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(Store::len).sum()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(Store::is_empty)
+    }
+    
+    /// Store a batch, writing to each shard on its own thread; returns
+    /// hashes in input order
+    pub fn put_many(&mut self, envelopes: &[Envelope]) -> Result<Vec<Hash256>> {
+        let mut groups = vec![Vec::new(); self.shards.len()];
+        for envelope in envelopes {
+            groups[self.shard_of(&envelope.hash())].push(envelope);
+        }
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = self.shards.iter_mut().zip(groups)
+                .filter(|(_, group)| !group.is_empty())
+                .map(|(shard, group)| scope.spawn(move || group.into_iter().try_for_each(|e| shard.put(e).map(|_| ()))))
+                .collect();
+            workers.into_iter().try_for_each(|worker| worker.join().expect("shard writer panicked"))
+        })?;
+        Ok(envelopes.iter().map(Envelope::hash).collect())
+    }
+    
+    /// Retrieve a batch, reading each shard on its own thread; results
+    /// are in input order
+    pub fn get_many(&self, hashes: &[Hash256]) -> Result<Vec<Envelope>> {
+        let mut groups = vec![Vec::new(); self.shards.len()];
+        for (i, hash) in hashes.iter().enumerate() {
+            groups[self.shard_of(hash)].push((i, hash));
+        }
+        let mut found: Vec<Option<Envelope>> = vec![None; hashes.len()];
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = self.shards.iter().zip(groups)
+                .filter(|(_, group)| !group.is_empty())
+                .map(|(shard, group)| scope.spawn(move || {
+                    group.into_iter().map(|(i, hash)| Ok((i, shard.get(hash)?))).collect::<Result<Vec<_>>>()
+                }))
+                .collect();
+            for worker in workers {
+                for (i, envelope) in worker.join().expect("shard reader panicked")? {
+                    found[i] = Some(envelope);
+                }
+            }
+            Ok::<_, Error>(())
+        })?;
+        Ok(found.into_iter().flatten().collect())
+    }
+    
+    pub fn set_ref(&mut self, name: impl Into<String>, hash: Hash256) -> Result<()> {
+        self.shards[0].set_ref(name, hash)
+    }
+    
+    pub fn get_ref(&self, name: &str) -> Option<Hash256> {
+        self.shards[0].get_ref(name)
+    }
+    
+    pub fn tag(&mut self, name: impl Into<String>, hash: Hash256) -> Result<()> {
+        self.shards[0].tag(name, hash)
+    }
+    
+    pub fn get_tag(&self, name: &str) -> Option<Hash256> {
+        self.shards[0].get_tag(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_sharded_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = Hash256::hash(b"Blob");
+        let envelopes: Vec<_> = (0..32u8).map(|i| Envelope::builder(blob, vec![i]).build()).collect();
+        let hashes = {
+            let mut store = ShardedStore::open(dir.path(), 4).unwrap();
+            let hashes = store.put_many(&envelopes).unwrap();
+            store.set_ref("latest", hashes[0]).unwrap();
+            hashes
+        };
+        
+        let store = ShardedStore::open(dir.path(), 4).unwrap();
+        assert_eq!(store.len(), 32);
+        assert!(store.shards().iter().all(|shard| !shard.is_empty() && shard.len() < 32));
+        assert!(hashes.iter().all(|h| store.shards()[store.shard_of(h)].contains(h)));
+        let fetched = store.get_many(&hashes).unwrap();
+        assert!(fetched.iter().zip(&hashes).all(|(e, h)| e.hash() == *h));
+        assert_eq!(store.get_ref("latest"), Some(hashes[0]));
+        drop(store);
+        assert!(matches!(ShardedStore::open(dir.path(), 8), Err(Error::Storage(_))));
+    }
+}
@@ -1,19 +1,204 @@
 //! Content-addressed storage for envelopes
 
-use crate::envelope::Envelope;
+use crate::bloom::BloomFilter;
+use crate::envelope::{Envelope, IndexValue, Relationship};
 use crate::hash::Hash256;
 use crate::error::Error;
+use crate::evict::Eviction;
+use crate::memory::{HeapSize, MemoryUsage};
+use crate::mmap::Blob;
+use crate::trace::{self, span};
+use crate::watch::EventKind;
+use crate::wire::{self, Reader};
 use crate::Result;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
 
-/// A simple in-memory content-addressed store
-/// 
-/// For exploration only. Production would use mmap'd files.
+/// Append-only log of `[op: 1] [hash: 32] [len: 4] [envelope bytes]` records;
+/// each compaction writes a new one, `objects.1`, `objects.2` and so on
+/// (see `log_path`)
+const OBJECTS_FILE: &str = "objects";
+
+const OP_PUT: u8 = 0;
+const OP_DELETE: u8 = 1;
+/// Named refs, rewritten atomically on every change
+const REFS_FILE: &str = "refs";
+/// Tags, rewritten atomically whenever one is added
+const TAGS_FILE: &str = "tags";
+/// `[object log length: 8] [refs] [tags] [generation: 8] [base seq: 8]
+/// [compacted seq: 8]`, rewritten atomically on every flush so lock-free
+/// snapshot readers see a consistent state; manifests from before
+/// compaction was tracked end after the tags
+const MANIFEST_FILE: &str = "manifest";
+/// Object log records written between flushes, unless configured otherwise
+const FLUSH_INTERVAL: usize = 64;
+/// `[op: 1] [hash: 32] [len: 4]`
+pub(crate) const RECORD_HEADER_LEN: usize = 37;
+/// Empty file holding the advisory lock: exclusive for a writer, shared
+/// for readers
+const LOCK_FILE: &str = "lock";
+/// Target false positive rate of the object filter
+const BLOOM_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// One mutation in a store's changelog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    /// Position in the changelog, starting at 1
+    pub seq: u64,
+    pub op: EventKind,
+    pub hash: Hash256,
+}
+
+/// Aggregate statistics for one type, from `Store::stats_by_type`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeStats {
+    /// Name from any stored envelope of the type that sets one
+    pub type_name: Option<String>,
+    pub count: usize,
+    pub payload_bytes: usize,
+    /// Version-chain depth -> number of objects; originals have depth 1
+    pub depths: BTreeMap<usize, usize>,
+}
+
+impl TypeStats {
+    /// Mean payload size (0 for no objects)
+    pub fn average_payload_bytes(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.payload_bytes as f64 / self.count as f64 }
+    }
+}
+
+/// Limits on what a store (or one type in it) may hold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    max_objects: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+impl Quota {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Limit the number of objects
+    pub fn max_objects(mut self, n: usize) -> Self {
+        self.max_objects = Some(n);
+        self
+    }
+    
+    /// Limit the total serialized size of the objects
+    pub fn max_bytes(mut self, n: usize) -> Self {
+        self.max_bytes = Some(n);
+        self
+    }
+    
+    /// Describe the first limit `usage` exceeds, if any
+    fn violation(&self, usage: Usage) -> Option<String> {
+        match (self.max_objects, self.max_bytes) {
+            (Some(max), _) if usage.objects > max => Some(format!("{} objects, limit {}", usage.objects, max)),
+            (_, Some(max)) if usage.bytes > max => Some(format!("{} bytes, limit {}", usage.bytes, max)),
+            _ => None,
+        }
+    }
+}
+
+/// Objects held and their total serialized size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub objects: usize,
+    pub bytes: usize,
+}
+
+impl Usage {
+    pub(crate) fn add(&mut self, bytes: usize) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+    
+    fn sub(&mut self, bytes: usize) {
+        self.objects = self.objects.saturating_sub(1);
+        self.bytes = self.bytes.saturating_sub(bytes);
+    }
+}
+
+/// How often puts found their object already stored, since the store
+/// was created or opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    pub puts: u64,
+    /// Puts of an object that was already stored
+    pub duplicates: u64,
+    /// Serialized bytes those puts didn't have to write
+    pub bytes_saved: u64,
+}
+
+impl DedupStats {
+    /// Fraction of puts that were duplicates (0 before any put)
+    pub fn hit_rate(&self) -> f64 {
+        if self.puts == 0 { 0.0 } else { self.duplicates as f64 / self.puts as f64 }
+    }
+}
+
+/// One batch of a resumable scan, from `Store::scan_from`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanPage {
+    /// In hash order
+    pub hashes: Vec<Hash256>,
+    /// Pass as `after` to continue, or `None` once the scan is done
+    pub next: Option<Hash256>,
+}
+
+/// A content-addressed store of envelopes, with named refs and tags
+///
+/// `Store::new` keeps everything in memory. `Store::open` backs the
+/// store with a directory: objects are appended to a log and reloaded on
+/// open, checked against their hashes, and refs and tags are kept in
+/// files alongside it. Writes become durable (synced to disk) and
+/// visible to snapshot readers on `flush`, which runs every
+/// `set_flush_interval` records, on every ref or tag change and when the
+/// store is dropped. A record cut short by a crash is dropped on the
+/// next open.
 #[derive(Debug, Default)]
 pub struct Store {
     /// Hash -> serialized envelope
-    objects: HashMap<Hash256, Vec<u8>>,
+    objects: HashMap<Hash256, Blob>,
+    /// The keys of `objects` in hash order, for `scan_from`
+    sorted: BTreeSet<Hash256>,
+    /// Named mutable pointers to objects
+    refs: HashMap<String, Hash256>,
+    /// Named immutable pointers to objects
+    tags: HashMap<String, Hash256>,
+    /// Backing directory and open object log, if persistent
+    dir: Option<PathBuf>,
+    log: Option<File>,
+    /// Held open for its lock, which is released on drop
+    lock: Option<File>,
+    read_only: bool,
+    /// Bytes of the object log written (by a writer) or read (by a snapshot)
+    log_len: u64,
+    /// Records written since the last flush, and how many trigger one
+    unflushed: usize,
+    flush_interval: usize,
+    /// Every hash ever put (removals leave stale bits until it's resized)
+    bloom: BloomFilter,
+    /// Every put and removal, in order; replayed from the log on open
+    changes: Vec<Change>,
+    /// Sequence number before the first of `changes`, and `last_seq` as
+    /// of the latest compaction
+    base_seq: u64,
+    compacted_seq: u64,
+    /// Compactions of the object log, so snapshots notice it was rewritten
+    generation: u64,
+    /// Limits checked on put, for the whole store and per type
+    quota: Quota,
+    type_quotas: HashMap<Hash256, Quota>,
+    usage: Usage,
+    type_usage: HashMap<Hash256, Usage>,
+    dedup: DedupStats,
+    /// Byte budget, pins and access order for eviction
+    eviction: Eviction,
 }
 
 impl Store {
@@ -22,25 +207,532 @@ impl Store {
         Self::default()
     }
     
-    /// Store an envelope, returning its hash
+    /// Open (or create) a store backed by a directory, as its only writer
+    ///
+    /// Fails with `Error::StoreLocked` while another `Store`, in this or
+    /// any other process, has the directory open.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        Self::open_locked(dir, true)
+    }
+    
+    /// Open an existing store for reading, alongside other readers
+    ///
+    /// Writes fail with `Error::StoreLocked`. Fails the same way while a
+    /// writer has the directory open.
+    pub fn open_read_only(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_locked(dir.as_ref(), false)
+    }
+    
+    /// `open`, or `open_read_only` if other readers have the store open
+    pub fn open_or_read_only(dir: impl AsRef<Path>) -> Result<Self> {
+        match Self::open(dir.as_ref()) {
+            Err(Error::StoreLocked(_)) => Self::open_read_only(dir),
+            opened => opened,
+        }
+    }
+    
+    /// Open a consistent snapshot of a store that a writer may be
+    /// changing, without taking the lock
+    ///
+    /// Any number of processes can hold snapshots alongside the writer,
+    /// e.g. to run analytics against a live store. The snapshot shows the
+    /// state as of the writer's last completed change, and `refresh`
+    /// moves it forward. Writes fail with `Error::StoreLocked`. With the
+    /// `mmap` feature the log is mapped rather than read (see `mmap`).
+    pub fn open_snapshot(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let _span = span!("Store::open_snapshot", dir = %dir.display());
+        let mut store = Store::new();
+        store.read_only = true;
+        store.dir = Some(dir.to_path_buf());
+        store.refresh()?;
+        Ok(store)
+    }
+    
+    /// Catch a snapshot up with the writer, returning whether anything
+    /// changed; does nothing for other stores
+    ///
+    /// Only the part of the object log written since the last refresh is
+    /// read, unless the writer has compacted it since, in which case the
+    /// whole log is read again (see `compact`).
+    pub fn refresh(&mut self) -> Result<bool> {
+        let (Some(dir), None, true) = (&self.dir, &self.lock, self.read_only) else {
+            return Ok(false);
+        };
+        let dir = dir.clone();
+        let (manifest, mut log) = loop {
+            let Some(manifest) = read_manifest(&dir)? else {
+                // No writer has opened the store yet
+                return Ok(false);
+            };
+            if let Some(log) = open_log(&dir, &manifest)? {
+                break (manifest, log);
+            }
+            // Compacted since the manifest was read: read the new one
+        };
+        let compacted = manifest.generation != self.generation;
+        if compacted {
+            self.objects.clear();
+            self.sorted.clear();
+            self.changes.clear();
+            self.usage = Usage::default();
+            self.type_usage.clear();
+            self.log_len = 0;
+            self.generation = manifest.generation;
+            self.base_seq = manifest.base_seq;
+            self.compacted_seq = manifest.compacted_seq;
+        }
+        let log_len = manifest.log_len;
+        if log_len < self.log_len {
+            return Err(Error::Storage(format!("object log in {} shrank", dir.display())));
+        }
+        let grew = log_len > self.log_len;
+        let changed = compacted || grew || manifest.refs != self.refs || manifest.tags != self.tags;
+        
+        if grew {
+            if self.replay_log(&mut log, log_len)? < (log_len - self.log_len) as usize {
+                return Err(Error::Storage(format!("object log in {} ends mid-record", dir.display())));
+            }
+            self.log_len = log_len;
+        }
+        if grew || compacted {
+            self.rebuild_bloom();
+        }
+        self.refs = manifest.refs;
+        self.tags = manifest.tags;
+        Ok(changed)
+    }
+    
+    fn open_locked(dir: &Path, write: bool) -> Result<Self> {
+        let span = span!("Store::open", dir = %dir.display(), write, objects = Empty, bytes = Empty);
+        let mut store = Store::new();
+        store.lock = Some(lock(dir, write)?);
+        store.read_only = !write;
+        if let Some(manifest) = read_manifest(dir)? {
+            store.generation = manifest.generation;
+            store.base_seq = manifest.base_seq;
+            store.compacted_seq = manifest.compacted_seq;
+        }
+        if write && store.generation > 0 {
+            // Left behind if a crash cut the last compaction short
+            remove_if_exists(&log_path(dir, store.generation - 1))?;
+        }
+        
+        let log_path = log_path(dir, store.generation);
+        if log_path.exists() {
+            let data = fs::read(&log_path)?;
+            let complete = store.replay(&data, &|bytes| Blob::from(bytes.to_vec()))?;
+            if complete < data.len() && write {
+                trace::event!(warn, path = %log_path.display(), bytes = data.len() - complete, "dropping torn record at the end of the object log");
+                OpenOptions::new().write(true).open(&log_path)?.set_len(complete as u64)?;
+            }
+            store.log_len = complete as u64;
+        }
+        
+        store.rebuild_bloom();
+        store.refs = load_names(&dir.join(REFS_FILE))?;
+        store.tags = load_names(&dir.join(TAGS_FILE))?;
+        
+        store.dir = Some(dir.to_path_buf());
+        if write {
+            store.log = Some(OpenOptions::new().create(true).append(true).open(&log_path)?);
+            store.flush_interval = FLUSH_INTERVAL;
+            store.save_manifest()?;
+        }
+        trace::event!(info, dir = %dir.display(), objects = store.len(), "opened");
+        span.record("objects", store.len());
+        span.record("bytes", store.log_len);
+        Ok(store)
+    }
+    
+    /// Replay the log from where this store left off up to `log_len`,
+    /// mapping it if the `mmap` feature is on; returns the length of the
+    /// complete records read
+    fn replay_log(&mut self, log: &mut File, log_len: u64) -> Result<usize> {
+        #[cfg(all(feature = "mmap", unix))]
+        {
+            let map = std::sync::Arc::new(crate::mmap::Mmap::map(log, log_len as usize)?);
+            let data = &map[self.log_len as usize..];
+            self.replay(data, &|bytes| map.blob(bytes))
+        }
+        #[cfg(not(all(feature = "mmap", unix)))]
+        {
+            use std::io::{Read, Seek, SeekFrom};
+            log.seek(SeekFrom::Start(self.log_len))?;
+            let mut data = vec![0; (log_len - self.log_len) as usize];
+            log.read_exact(&mut data)?;
+            self.replay(&data, &|bytes| Blob::from(bytes.to_vec()))
+        }
+    }
+    
+    /// Apply object log records, returning the length of the complete ones;
+    /// `blob` keeps the bytes of each object put
+    ///
+    /// A record cut short at the end (by a crash mid-write) stops the
+    /// replay; anything else malformed, or an envelope that doesn't match
+    /// its hash, is an error.
+    fn replay(&mut self, data: &[u8], blob: &dyn Fn(&[u8]) -> Blob) -> Result<usize> {
+        let mut reader = Reader::new(data);
+        while !reader.is_empty() {
+            let rest = &data[reader.offset()..];
+            let torn = rest.len() < RECORD_HEADER_LEN
+                || rest.len() - RECORD_HEADER_LEN < u32::from_le_bytes(rest[33..37].try_into().unwrap()) as usize;
+            if torn {
+                return Ok(reader.offset());
+            }
+            let (op, hash, bytes) = reader.item("object log record at offset", reader.offset(), |reader| {
+                let record = (reader.u8()?, reader.hash()?, reader.bytes()?);
+                match record.0 {
+                    OP_PUT if record.2.len() < 32 => {
+                        Err(reader.error(format!("{} byte envelope is shorter than its type hash", record.2.len())))
+                    }
+                    OP_PUT | OP_DELETE => Ok(record),
+                    op => Err(reader.error(format!("unknown op {}", op))),
+                }
+            })?;
+            if op == OP_PUT {
+                let actual = self.deserialize(bytes)?.hash();
+                if actual != hash {
+                    return Err(Error::HashMismatch { expected: hash.to_hex(), actual: actual.to_hex() });
+                }
+            }
+            let (removed, kind) = match op {
+                OP_PUT => {
+                    self.usage.add(bytes.len());
+                    self.type_usage.entry(type_of(bytes)).or_default().add(bytes.len());
+                    self.sorted.insert(hash);
+                    (self.objects.insert(hash, blob(bytes)), EventKind::Put)
+                }
+                OP_DELETE => {
+                    self.sorted.remove(&hash);
+                    (self.objects.remove(&hash), EventKind::Delete)
+                }
+                _ => unreachable!("op checked when read"),
+            };
+            if let Some(bytes) = removed {
+                self.usage.sub(bytes.len());
+                if let Some(usage) = self.type_usage.get_mut(&type_of(&bytes)) {
+                    usage.sub(bytes.len());
+                }
+            }
+            self.record(kind, hash);
+        }
+        Ok(reader.offset())
+    }
+    
+    /// Sync the object log and record its length, refs and tags for
+    /// snapshot readers
+    fn save_manifest(&self) -> Result<()> {
+        let (Some(dir), Some(log)) = (&self.dir, &self.log) else {
+            return Ok(());
+        };
+        log.sync_data()?;
+        let mut buf = Vec::new();
+        wire::put_i64(&mut buf, self.log_len as i64);
+        write_names(&mut buf, &self.refs);
+        write_names(&mut buf, &self.tags);
+        wire::put_i64(&mut buf, self.generation as i64);
+        wire::put_i64(&mut buf, self.base_seq as i64);
+        wire::put_i64(&mut buf, self.compacted_seq as i64);
+        replace_file(&dir.join(MANIFEST_FILE), &buf)
+    }
+    
+    /// Store an envelope, returning its hash (`Envelope::hash`)
+    ///
+    /// `type_name` isn't part of the hash, so the first put of some
+    /// content fixes its name: re-putting it under another name succeeds
+    /// as a duplicate and keeps the stored one.
+    ///
+    /// Going over a budget (see `set_budget`) doesn't evict until `evict`
+    /// runs.
     pub fn put(&mut self, envelope: &Envelope) -> Result<Hash256> {
+        self.check_writable()?;
+        let hash = envelope.hash();
+        let span = span!("Store::put", hash = %hash, bytes = Empty);
+        self.dedup.puts += 1;
+        if let Some(existing) = self.objects.get(&hash) {
+            self.dedup.duplicates += 1;
+            self.dedup.bytes_saved += existing.len() as u64;
+            self.eviction.touch(&hash);
+            return Ok(hash);
+        }
+        
         let bytes = self.serialize(envelope)?;
-        let hash = Hash256::hash(&bytes);
-        self.objects.insert(hash, bytes);
+        self.check_quotas(&envelope.type_hash, bytes.len())?;
+        self.append_log(OP_PUT, &hash, &bytes)?;
+        trace::event!(debug, hash = %hash, bytes = bytes.len(), "put");
+        span.record("bytes", bytes.len());
+        self.usage.add(bytes.len());
+        self.type_usage.entry(envelope.type_hash).or_default().add(bytes.len());
+        self.objects.insert(hash, bytes.into());
+        self.sorted.insert(hash);
+        self.record(EventKind::Put, hash);
+        self.eviction.inserted(hash);
+        if self.bloom.len() >= self.bloom.capacity() {
+            self.rebuild_bloom();
+        } else {
+            self.bloom.insert(&hash);
+        }
         Ok(hash)
     }
     
+    /// Resize the object filter to twice the current object count
+    fn rebuild_bloom(&mut self) {
+        let mut bloom = BloomFilter::new(self.objects.len() * 2, BLOOM_FALSE_POSITIVE_RATE);
+        self.objects.keys().for_each(|hash| bloom.insert(hash));
+        self.bloom = bloom;
+    }
+    
+    /// Filter over the stored hashes, e.g. to send to a sync peer
+    pub fn bloom(&self) -> &BloomFilter {
+        &self.bloom
+    }
+    
+    /// Remove an object, returning whether it was present
+    ///
+    /// Refs and tags pointing at it are left dangling; callers decide
+    /// whether that's acceptable.
+    pub fn remove(&mut self, hash: &Hash256) -> Result<bool> {
+        self.check_writable()?;
+        if !self.objects.contains_key(hash) {
+            return Ok(false);
+        }
+        self.append_log(OP_DELETE, hash, &[])?;
+        trace::event!(debug, hash = %hash, "removed");
+        self.sorted.remove(hash);
+        if let Some(bytes) = self.objects.remove(hash) {
+            self.usage.sub(bytes.len());
+            if let Some(usage) = self.type_usage.get_mut(&type_of(&bytes)) {
+                usage.sub(bytes.len());
+            }
+        }
+        self.record(EventKind::Delete, *hash);
+        self.eviction.forget(hash);
+        Ok(true)
+    }
+    
+    /// Whether the store was opened read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        match (&self.dir, self.read_only) {
+            (Some(dir), true) => Err(Error::StoreLocked(format!("{} is open read-only", dir.display()))),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Limit what the whole store may hold; objects already stored stay
+    pub fn set_quota(&mut self, quota: Quota) {
+        self.quota = quota;
+    }
+    
+    /// Limit what objects of one type may hold, on top of the store quota
+    pub fn set_type_quota(&mut self, type_hash: Hash256, quota: Quota) {
+        self.type_quotas.insert(type_hash, quota);
+    }
+    
+    /// Put attempts that hit existing objects
+    ///
+    /// Payloads aren't chunked, so only whole-object duplicates count.
+    pub fn dedup_stats(&self) -> DedupStats {
+        self.dedup
+    }
+    
+    /// Objects and bytes currently stored
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+    
+    /// Objects and bytes currently stored for one type
+    pub fn type_usage(&self, type_hash: &Hash256) -> Usage {
+        self.type_usage.get(type_hash).copied().unwrap_or_default()
+    }
+    
+    /// Fail with `Error::QuotaExceeded` if storing `bytes` more of a type
+    /// would break a quota
+    fn check_quotas(&self, type_hash: &Hash256, bytes: usize) -> Result<()> {
+        let mut usage = self.usage;
+        usage.add(bytes);
+        if let Some(violation) = self.quota.violation(usage) {
+            return Err(Error::QuotaExceeded(format!("store would hold {}", violation)));
+        }
+        let Some(quota) = self.type_quotas.get(type_hash) else {
+            return Ok(());
+        };
+        let mut usage = self.type_usage(type_hash);
+        usage.add(bytes);
+        match quota.violation(usage) {
+            Some(violation) => Err(Error::QuotaExceeded(format!("type {} would hold {}", type_hash.short(), violation))),
+            None => Ok(()),
+        }
+    }
+    
+    fn record(&mut self, op: EventKind, hash: Hash256) {
+        let seq = self.last_seq() + 1;
+        self.changes.push(Change { seq, op, hash });
+    }
+    
+    /// Changes after sequence number `seq`, oldest first
+    ///
+    /// Consumers remember the last `seq` they processed and resume from
+    /// it; `changes_since(0)` replays everything kept. Persistent stores
+    /// keep the numbering across reopens and compactions, but compaction
+    /// drops history: a consumer that last saw a `seq` before
+    /// `compacted_seq` may have missed removals, and should start over.
+    pub fn changes_since(&self, seq: u64) -> &[Change] {
+        let start = (seq.saturating_sub(self.base_seq) as usize).min(self.changes.len());
+        &self.changes[start..]
+    }
+    
+    /// Sequence number of the latest change (0 if none)
+    pub fn last_seq(&self) -> u64 {
+        self.base_seq + self.changes.len() as u64
+    }
+    
+    /// `last_seq` as of the latest `compact` (0 if never compacted)
+    pub fn compacted_seq(&self) -> u64 {
+        self.compacted_seq
+    }
+    
+    fn append_log(&mut self, op: u8, hash: &Hash256, bytes: &[u8]) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            let mut record = Vec::with_capacity(bytes.len() + RECORD_HEADER_LEN);
+            write_record(&mut record, op, hash, bytes);
+            log.write_all(&record)?;
+            self.log_len += record.len() as u64;
+            self.unflushed += 1;
+            if self.unflushed >= self.flush_interval {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Make every write so far durable and visible to snapshot readers
+    pub fn flush(&mut self) -> Result<()> {
+        self.save_manifest()?;
+        self.unflushed = 0;
+        Ok(())
+    }
+    
+    /// Rewrite the object log with only the objects still stored,
+    /// dropping removal records and what they removed; returns the bytes
+    /// reclaimed
+    ///
+    /// Objects keep their put order. The changelog keeps only their puts,
+    /// numbered to end at the same `last_seq`, so sequence numbers never
+    /// go backwards; `compacted_seq` records where history was dropped.
+    /// Snapshots read the new log from the start on their next `refresh`.
+    pub fn compact(&mut self) -> Result<u64> {
+        self.check_writable()?;
+        let Some(dir) = self.dir.clone() else {
+            return Ok(0);
+        };
+        let span = span!("Store::compact", objects = self.len(), reclaimed = Empty);
+        // Latest put of each object still stored, in log order
+        let mut seen = HashSet::new();
+        let mut order: Vec<Hash256> = self.changes.iter().rev()
+            .filter(|change| change.op == EventKind::Put && self.objects.contains_key(&change.hash))
+            .filter(|change| seen.insert(change.hash))
+            .map(|change| change.hash)
+            .collect();
+        order.reverse();
+        
+        let mut data = Vec::with_capacity(self.usage.bytes + order.len() * RECORD_HEADER_LEN);
+        for hash in &order {
+            write_record(&mut data, OP_PUT, hash, &self.objects[hash]);
+        }
+        // A new file, so snapshots reading the old one never see it change
+        let old_log = log_path(&dir, self.generation);
+        let log_path = log_path(&dir, self.generation + 1);
+        replace_file(&log_path, &data)?;
+        self.log = Some(OpenOptions::new().append(true).open(&log_path)?);
+        let reclaimed = self.log_len.saturating_sub(data.len() as u64);
+        self.log_len = data.len() as u64;
+        self.unflushed = 0;
+        let last_seq = self.last_seq();
+        self.changes.clear();
+        self.base_seq = last_seq - order.len() as u64;
+        self.compacted_seq = last_seq;
+        self.generation += 1;
+        for hash in order {
+            self.record(EventKind::Put, hash);
+        }
+        self.save_manifest()?;
+        remove_if_exists(&old_log)?;
+        trace::event!(info, path = %log_path.display(), reclaimed, "compacted");
+        span.record("reclaimed", reclaimed);
+        Ok(reclaimed)
+    }
+    
+    /// Bytes of the object log, including records of removed objects
+    /// until `compact`; 0 for in-memory stores
+    pub fn log_size(&self) -> u64 {
+        self.log_len
+    }
+    
+    /// Flush after every `records` puts and removals (at least 1, which
+    /// makes each one durable before it returns)
+    pub fn set_flush_interval(&mut self, records: usize) {
+        self.flush_interval = records.max(1);
+    }
+    
     /// Retrieve an envelope by hash
     pub fn get(&self, hash: &Hash256) -> Result<Envelope> {
-        let bytes = self.objects
-            .get(hash)
-            .ok_or_else(|| Error::NotFound(hash.to_hex()))?;
+        let span = span!("Store::get", hash = %hash, bytes = Empty);
+        let bytes = self.lookup(hash, "get")?;
+        span.record("bytes", bytes.len());
         self.deserialize(bytes)
     }
     
+    /// The serialized envelope, recorded as an access
+    fn lookup(&self, hash: &Hash256, operation: &'static str) -> Result<&[u8]> {
+        let bytes = Some(hash)
+            .filter(|hash| self.bloom.may_contain(hash))
+            .and_then(|hash| self.objects.get(hash))
+            .ok_or_else(|| Error::not_found(*hash, operation))?;
+        self.eviction.touch(hash);
+        Ok(bytes)
+    }
+    
+    /// Everything about an envelope but its payload, which is left empty
+    ///
+    /// The payload comes last in the encoding, so a backend that reads
+    /// from disk can stop before it.
+    pub fn get_metadata(&self, hash: &Hash256) -> Result<Envelope> {
+        EnvelopeRef { bytes: self.lookup(hash, "get_metadata")? }.header()
+    }
+    
     /// Check if an object exists
     pub fn contains(&self, hash: &Hash256) -> bool {
-        self.objects.contains_key(hash)
+        self.bloom.may_contain(hash) && self.objects.contains_key(hash)
+    }
+    
+    pub(crate) fn eviction(&self) -> &Eviction {
+        &self.eviction
+    }
+    
+    pub(crate) fn eviction_mut(&mut self) -> &mut Eviction {
+        &mut self.eviction
+    }
+    
+    /// Serialized size of a stored object
+    pub(crate) fn stored_size(&self, hash: &Hash256) -> Option<usize> {
+        self.objects.get(hash).map(|bytes| bytes.len())
+    }
+    
+    /// A stored object's encoding, without recording an access
+    pub(crate) fn stored_bytes(&self, hash: &Hash256) -> Option<&[u8]> {
+        self.objects.get(hash).map(|bytes| &**bytes)
+    }
+    
+    /// Type hash of a stored object, without deserializing it
+    pub(crate) fn stored_type(&self, hash: &Hash256) -> Option<Hash256> {
+        self.objects.get(hash).map(|bytes| type_of(bytes))
     }
     
     /// Number of objects in the store
@@ -53,18 +745,258 @@ impl Store {
         self.objects.is_empty()
     }
     
+    /// The backing directory, if persistent
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+    
+    /// Object count and an order-independent digest (XOR) of the stored
+    /// hashes, to tell whether data derived from the store is stale
+    pub(crate) fn fingerprint(&self) -> (usize, Hash256) {
+        let mut digest = [0u8; 32];
+        for hash in self.objects.keys() {
+            digest.iter_mut().zip(hash.as_bytes()).for_each(|(d, b)| *d ^= b);
+        }
+        (self.objects.len(), Hash256::from_bytes(digest))
+    }
+    
     /// List all hashes in the store
     pub fn hashes(&self) -> impl Iterator<Item = &Hash256> {
         self.objects.keys()
     }
     
+    /// Every stored envelope, in no particular order, decoded only as far
+    /// as each `EnvelopeRef` is asked
+    ///
+    /// Scans don't count as accesses for LRU eviction.
+    pub fn iter(&self) -> impl Iterator<Item = (Hash256, EnvelopeRef<'_>)> {
+        self.objects.iter().map(|(hash, bytes)| (*hash, EnvelopeRef { bytes }))
+    }
+    
+    /// Hashes of envelopes whose header (see `EnvelopeRef::header`)
+    /// satisfies `predicate`, sorted
+    ///
+    /// Decodes every object but never copies payloads: a fallback for
+    /// conditions the indexes can't answer.
+    pub fn scan(&self, mut predicate: impl FnMut(&Envelope) -> bool) -> Result<Vec<Hash256>> {
+        let mut matches = Vec::new();
+        for (hash, envelope) in self.iter() {
+            if predicate(&envelope.header()?) {
+                matches.push(hash);
+            }
+        }
+        matches.sort();
+        Ok(matches)
+    }
+    
+    /// Up to `limit` hashes following `after`, in hash order
+    ///
+    /// The cursor is just the last hash returned, so a job can checkpoint
+    /// it anywhere and resume in another process. Objects put behind the
+    /// cursor are missed by the scan in progress; pair it with
+    /// `changes_since` for those.
+    pub fn scan_from(&self, after: Option<&Hash256>, limit: usize) -> ScanPage {
+        let following = match after {
+            Some(after) => self.sorted.range((Bound::Excluded(*after), Bound::Unbounded)),
+            None => self.sorted.range(..),
+        };
+        let mut following = following.copied();
+        let hashes: Vec<_> = following.by_ref().take(limit).collect();
+        let more = following.next().is_some();
+        let next = hashes.last().copied().filter(|_| more);
+        ScanPage { hashes, next }
+    }
+    
+    /// Check every object decodes and hashes to its key, and every ref and
+    /// tag points at a stored object; returns the problems found, by hash
+    pub fn verify(&self) -> Vec<(Hash256, Error)> {
+        let mut problems: Vec<_> = self.objects.iter()
+            .filter_map(|(hash, bytes)| {
+                let actual = match self.deserialize(bytes) {
+                    Ok(envelope) => envelope.hash(),
+                    Err(e) => return Some((*hash, e)),
+                };
+                (actual != *hash).then(|| (*hash, Error::HashMismatch { expected: hash.to_hex(), actual: actual.to_hex() }))
+            })
+            .collect();
+        let named = self.refs.values().copied().chain(self.tags.values().copied());
+        problems.extend(named.filter(|hash| !self.contains(hash)).map(|hash| (hash, Error::not_found(hash, "verify"))));
+        problems.sort_by_key(|(hash, _)| *hash);
+        problems
+    }
+    
+    /// Estimated heap bytes of the objects and their hash order, refs,
+    /// tags, changelog and bloom filter
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            objects: self.objects.heap_size(),
+            indexes: self.sorted.heap_size()
+                + self.refs.heap_size()
+                + self.tags.heap_size()
+                + self.changes.capacity() * std::mem::size_of::<Change>(),
+            caches: self.bloom.heap_size(),
+        }
+    }
+    
+    /// Object counts, payload sizes and version depths per `type_hash`
+    ///
+    /// Depth counts the versions reachable through `previous` links that
+    /// are still stored, so pruned history shortens chains. Only headers
+    /// are decoded, and reading them doesn't count as an access for
+    /// eviction.
+    pub fn stats_by_type(&self) -> Result<HashMap<Hash256, TypeStats>> {
+        // Hash -> (header, payload size)
+        let mut envelopes = HashMap::with_capacity(self.objects.len());
+        for (hash, bytes) in &self.objects {
+            let (header, payload) = decode_header(bytes)?;
+            envelopes.insert(*hash, (header, payload.len()));
+        }
+        
+        let mut depths: HashMap<Hash256, usize> = HashMap::new();
+        let mut stats: HashMap<Hash256, TypeStats> = HashMap::new();
+        for (hash, (envelope, payload_bytes)) in &envelopes {
+            // Walk back to a known depth or the oldest stored version
+            let mut chain = vec![*hash];
+            let mut base = 0;
+            while let Some(previous) = envelopes[chain.last().unwrap()].0.previous {
+                if let Some(depth) = depths.get(&previous) {
+                    base = *depth;
+                    break;
+                }
+                if !envelopes.contains_key(&previous) {
+                    break;
+                }
+                chain.push(previous);
+            }
+            for (i, version) in chain.iter().rev().enumerate() {
+                depths.insert(*version, base + i + 1);
+            }
+            
+            let entry = stats.entry(envelope.type_hash).or_default();
+            if entry.type_name.is_none() {
+                entry.type_name = envelope.type_name.clone();
+            }
+            entry.count += 1;
+            entry.payload_bytes += payload_bytes;
+            *entry.depths.entry(depths[hash]).or_default() += 1;
+        }
+        Ok(stats)
+    }
+    
+    /// Point a named ref at a stored object
+    pub fn set_ref(&mut self, name: impl Into<String>, hash: Hash256) -> Result<()> {
+        self.check_writable()?;
+        if !self.contains(&hash) {
+            return Err(Error::not_found(hash, "set_ref"));
+        }
+        self.refs.insert(name.into(), hash);
+        self.save_refs()
+    }
+    
+    /// Resolve a named ref
+    pub fn get_ref(&self, name: &str) -> Option<Hash256> {
+        self.refs.get(name).copied()
+    }
+    
+    /// Remove a named ref, returning where it pointed
+    pub fn delete_ref(&mut self, name: &str) -> Result<Option<Hash256>> {
+        self.check_writable()?;
+        let old = self.refs.remove(name);
+        if old.is_some() {
+            self.save_refs()?;
+        }
+        Ok(old)
+    }
+    
+    /// Move a ref to `new` only if it currently points at `expected`
+    /// (`None` meaning the ref must not exist yet)
+    pub fn compare_and_swap_ref(&mut self, name: &str, expected: Option<Hash256>, new: Hash256) -> Result<()> {
+        let actual = self.get_ref(name);
+        if actual != expected {
+            let describe = |h: Option<Hash256>| h.map_or_else(|| "(none)".to_string(), |h| h.to_hex());
+            return Err(Error::RefConflict {
+                name: name.to_string(),
+                expected: describe(expected),
+                actual: describe(actual),
+            });
+        }
+        self.set_ref(name, new)
+    }
+    
+    /// All named refs
+    pub fn refs(&self) -> impl Iterator<Item = (&str, &Hash256)> {
+        self.refs.iter().map(|(name, hash)| (name.as_str(), hash))
+    }
+    
+    /// Tag a stored object; tags never move once created
+    pub fn tag(&mut self, name: impl Into<String>, hash: Hash256) -> Result<()> {
+        self.check_writable()?;
+        let name = name.into();
+        if !self.contains(&hash) {
+            return Err(Error::not_found(hash, "tag"));
+        }
+        match self.tags.get(&name) {
+            Some(existing) if *existing == hash => return Ok(()),
+            Some(_) => return Err(Error::TagExists(name)),
+            None => {}
+        }
+        self.tags.insert(name, hash);
+        if let Some(dir) = &self.dir {
+            save_names(&dir.join(TAGS_FILE), &self.tags)?;
+        }
+        self.save_manifest()
+    }
+    
+    /// Resolve a tag
+    pub fn get_tag(&self, name: &str) -> Option<Hash256> {
+        self.tags.get(name).copied()
+    }
+    
+    /// All tags, sorted by name
+    pub fn tags(&self) -> Vec<(&str, Hash256)> {
+        let mut tags: Vec<_> = self.tags.iter().map(|(name, hash)| (name.as_str(), *hash)).collect();
+        tags.sort();
+        tags
+    }
+    
+    /// Replace the refs and tags with ones saved elsewhere (see `idb`),
+    /// dangling or not
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+    pub(crate) fn restore_names(&mut self, refs: HashMap<String, Hash256>, tags: HashMap<String, Hash256>) {
+        self.refs = refs;
+        self.tags = tags;
+    }
+    
+    fn save_refs(&self) -> Result<()> {
+        if let Some(dir) = &self.dir {
+            save_names(&dir.join(REFS_FILE), &self.refs)?;
+        }
+        self.save_manifest()
+    }
+    
+    /// Walk the version chain from `hash` back through `previous` links
+    ///
+    /// Merges are walked along their first parent, like `git log --first-parent`.
+    pub fn history(&self, hash: &Hash256) -> History<'_> {
+        History {
+            store: self,
+            next: Some(*hash),
+            seen: HashSet::new(),
+            remaining: None,
+        }
+    }
+    
     // Serialization - simple format for now, would use FlatBuffers in production
     fn serialize(&self, envelope: &Envelope) -> Result<Vec<u8>> {
         // Simple binary format:
         // [type_hash: 32] [type_name_len: 4] [type_name: N]
-        // [rel_count: 4] [rels...]
-        // [index_count: 4] [index...]
-        // [previous: 1 + 32?] [created_at: 1 + 8?]
+        // [content_type_len: 4] [content_type: N]
+        // [rel_count: 4] [rels: type + target + position + strength + properties...]
+        // [index_count: 4] [index: key + tagged value...]
+        // [previous: 1 + 32?] [merge_count: 4] [merge_parents: 32...]
+        // [created_at: 1 + 8?] [created_by: 1 + 32?]
+        // [valid_from: 1 + 8?] [valid_to: 1 + 8?]
+        // [ext_count: 4] [extensions...]
         // [payload_len: 4] [payload: N]
         
         let mut buf = Vec::new();
@@ -83,52 +1015,47 @@ impl Store {
             }
         }
         
+        // Content type (empty if unset, so an empty one would read back as unset)
+        if envelope.content_type.as_deref() == Some("") {
+            return Err(Error::InvalidEnvelope("content_type is empty".to_string()));
+        }
+        wire::put_str(&mut buf, envelope.content_type.as_deref().unwrap_or(""));
+        
         // Relationships
         buf.extend_from_slice(&(envelope.relationships.len() as u32).to_le_bytes());
         for rel in &envelope.relationships {
-            buf.extend_from_slice(&(rel.rel_type.len() as u32).to_le_bytes());
-            buf.extend_from_slice(rel.rel_type.as_bytes());
-            buf.extend_from_slice(rel.target.as_bytes());
+            rel.encode(&mut buf);
         }
         
-        // Index fields (simplified - strings only for now)
-        let string_index: Vec<_> = envelope.index.iter()
-            .filter_map(|(k, v)| {
-                match v {
-                    crate::envelope::IndexValue::String(s) => Some((k, s)),
-                    _ => None, // Skip non-string for now
-                }
-            })
-            .collect();
+        // Index fields (tagged values, sorted by key for determinism)
+        let mut index: Vec<_> = envelope.index.iter().collect();
+        index.sort_by_key(|(k, _)| *k);
         
-        buf.extend_from_slice(&(string_index.len() as u32).to_le_bytes());
-        for (key, value) in string_index {
+        buf.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        for (key, value) in index {
             buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
             buf.extend_from_slice(key.as_bytes());
-            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
-            buf.extend_from_slice(value.as_bytes());
+            value.encode(&mut buf);
         }
         
-        // Previous (optional)
-        match &envelope.previous {
-            Some(hash) => {
-                buf.push(1);
-                buf.extend_from_slice(hash.as_bytes());
-            }
-            None => {
-                buf.push(0);
-            }
+        // Previous (optional), merge parents, created at/by, validity (optional)
+        wire::put_opt_hash(&mut buf, envelope.previous.as_ref());
+        wire::put_u32(&mut buf, envelope.merge_parents.len() as u32);
+        for parent in &envelope.merge_parents {
+            wire::put_hash(&mut buf, parent);
         }
+        wire::put_opt_i64(&mut buf, envelope.created_at);
+        wire::put_opt_hash(&mut buf, envelope.created_by.as_ref());
+        wire::put_opt_i64(&mut buf, envelope.valid_from);
+        wire::put_opt_i64(&mut buf, envelope.valid_to);
         
-        // Created at (optional)
-        match envelope.created_at {
-            Some(ts) => {
-                buf.push(1);
-                buf.extend_from_slice(&ts.to_le_bytes());
-            }
-            None => {
-                buf.push(0);
-            }
+        // Extensions (sorted by key)
+        let mut extensions: Vec<_> = envelope.extensions.iter().collect();
+        extensions.sort_by_key(|(k, _)| *k);
+        wire::put_u32(&mut buf, extensions.len() as u32);
+        for (key, value) in extensions {
+            wire::put_str(&mut buf, key);
+            wire::put_bytes(&mut buf, value);
         }
         
         // Payload
@@ -138,97 +1065,316 @@ impl Store {
         Ok(buf)
     }
     
-    fn deserialize(&self, bytes: &[u8]) -> Result<Envelope> {
-        let mut cursor = 0;
-        
-        let read_u32 = |cursor: &mut usize| -> u32 {
-            let v = u32::from_le_bytes(bytes[*cursor..*cursor+4].try_into().unwrap());
-            *cursor += 4;
-            v
-        };
-        
-        let read_i64 = |cursor: &mut usize| -> i64 {
-            let v = i64::from_le_bytes(bytes[*cursor..*cursor+8].try_into().unwrap());
-            *cursor += 8;
-            v
-        };
-        
-        let read_hash = |cursor: &mut usize| -> Hash256 {
-            let mut arr = [0u8; 32];
-            arr.copy_from_slice(&bytes[*cursor..*cursor+32]);
-            *cursor += 32;
-            Hash256::from_bytes(arr)
-        };
-        
-        let read_string = |cursor: &mut usize| -> String {
-            let len = read_u32(cursor) as usize;
-            let s = String::from_utf8_lossy(&bytes[*cursor..*cursor+len]).to_string();
-            *cursor += len;
-            s
-        };
-        
-        // Type hash
-        let type_hash = read_hash(&mut cursor);
-        
-        // Type name
-        let type_name_len = read_u32(&mut cursor);
-        let type_name = if type_name_len > 0 {
-            let name = String::from_utf8_lossy(&bytes[cursor..cursor+(type_name_len as usize)]).to_string();
-            cursor += type_name_len as usize;
-            Some(name)
-        } else {
-            None
-        };
-        
-        // Relationships
-        let rel_count = read_u32(&mut cursor) as usize;
-        let mut relationships = Vec::with_capacity(rel_count);
-        for _ in 0..rel_count {
-            let rel_type = read_string(&mut cursor);
-            let target = read_hash(&mut cursor);
-            relationships.push(crate::envelope::Relationship::new(rel_type, target));
-        }
-        
-        // Index
-        let idx_count = read_u32(&mut cursor) as usize;
-        let mut index = HashMap::with_capacity(idx_count);
-        for _ in 0..idx_count {
-            let key = read_string(&mut cursor);
-            let value = read_string(&mut cursor);
-            index.insert(key, crate::envelope::IndexValue::String(value));
-        }
-        
-        // Previous
-        let has_previous = bytes[cursor] == 1;
-        cursor += 1;
-        let previous = if has_previous {
-            Some(read_hash(&mut cursor))
-        } else {
-            None
-        };
-        
-        // Created at
-        let has_created = bytes[cursor] == 1;
-        cursor += 1;
-        let created_at = if has_created {
-            Some(read_i64(&mut cursor))
-        } else {
-            None
-        };
-        
-        // Payload
-        let payload_len = read_u32(&mut cursor) as usize;
-        let payload = bytes[cursor..cursor+payload_len].to_vec();
-        
-        Ok(Envelope {
-            type_hash,
-            type_name,
-            relationships,
-            index,
-            previous,
-            created_at,
-            payload,
-        })
+    pub(crate) fn deserialize(&self, bytes: &[u8]) -> Result<Envelope> {
+        EnvelopeRef { bytes }.decode()
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        if self.unflushed > 0 {
+            if let Err(_e) = self.flush() {
+                trace::event!(error, error = %_e, "flushing the store on drop failed");
+            }
+        }
+    }
+}
+
+/// Decode everything but the payload, which is returned as a slice
+fn decode_header(bytes: &[u8]) -> Result<(Envelope, &[u8])> {
+    let mut reader = Reader::new(bytes);
+    
+    // Type hash
+    let type_hash = reader.section("type hash", Reader::hash)?;
+    
+    // Type name
+    let type_name = reader.section("type name", Reader::string)?;
+    let type_name = if type_name.is_empty() { None } else { Some(type_name) };
+    
+    // Content type
+    let content_type = reader.section("content type", Reader::string)?;
+    let content_type = if content_type.is_empty() { None } else { Some(content_type) };
+    
+    // Relationships (counts only bound the preallocation, since a
+    // corrupt one would otherwise ask for gigabytes)
+    let rel_count = reader.section("relationship count", Reader::u32)? as usize;
+    let mut relationships = Vec::with_capacity(rel_count.min(bytes.len()));
+    for i in 0..rel_count {
+        relationships.push(reader.item("relationship", i, Relationship::decode)?);
+    }
+    
+    // Index
+    let idx_count = reader.section("index field count", Reader::u32)? as usize;
+    let mut index = HashMap::with_capacity(idx_count.min(bytes.len()));
+    for i in 0..idx_count {
+        let (key, value) = reader.item("index field", i, |reader| {
+            Ok((reader.section("name", Reader::string)?, reader.section("value", IndexValue::decode)?))
+        })?;
+        index.insert(key, value);
+    }
+    
+    // Previous, merge parents, created at/by, validity
+    let previous = reader.section("previous", Reader::opt_hash)?;
+    let parent_count = reader.section("merge parent count", Reader::u32)? as usize;
+    let mut merge_parents = Vec::with_capacity(parent_count.min(bytes.len()));
+    for i in 0..parent_count {
+        merge_parents.push(reader.item("merge parent", i, Reader::hash)?);
+    }
+    let created_at = reader.section("created at", Reader::opt_i64)?;
+    let created_by = reader.section("created by", Reader::opt_hash)?;
+    let valid_from = reader.section("valid from", Reader::opt_i64)?;
+    let valid_to = reader.section("valid to", Reader::opt_i64)?;
+    
+    // Extensions
+    let ext_count = reader.section("extension count", Reader::u32)? as usize;
+    let mut extensions = HashMap::with_capacity(ext_count.min(bytes.len()));
+    for i in 0..ext_count {
+        let (key, value) = reader.item("extension", i, |reader| {
+            Ok((reader.section("name", Reader::string)?, reader.section("value", Reader::bytes)?.to_vec()))
+        })?;
+        extensions.insert(key, value);
+    }
+    
+    // Payload
+    let payload = reader.section("payload", Reader::bytes)?;
+    
+    let header = Envelope {
+        type_hash,
+        type_name,
+        content_type,
+        relationships,
+        index,
+        previous,
+        merge_parents,
+        created_at,
+        created_by,
+        valid_from,
+        valid_to,
+        extensions,
+        payload: Vec::new(),
+    };
+    Ok((header, payload))
+}
+
+/// Type hash of a serialized envelope, which leads its encoding
+fn type_of(bytes: &[u8]) -> Hash256 {
+    Hash256::from_bytes(bytes[..32].try_into().unwrap())
+}
+
+/// Take the store's lock without waiting
+fn lock(dir: &Path, exclusive: bool) -> Result<File> {
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.join(LOCK_FILE))?;
+    let locked = if exclusive { file.try_lock() } else { file.try_lock_shared() };
+    match locked {
+        Ok(()) => Ok(file),
+        Err(fs::TryLockError::WouldBlock) => Err(Error::StoreLocked(match exclusive {
+            true => format!("{} is already open", dir.display()),
+            false => format!("{} is open for writing", dir.display()),
+        })),
+        Err(fs::TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+/// Read a `[count: 4] [name + hash...]` file, if present
+fn load_names(path: &Path) -> Result<HashMap<String, Hash256>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let data = fs::read(path)?;
+    read_names(&mut Reader::new(&data))
+}
+
+/// The object log of a compaction generation
+fn log_path(dir: &Path, generation: u64) -> PathBuf {
+    match generation {
+        0 => dir.join(OBJECTS_FILE),
+        n => dir.join(format!("{}.{}", OBJECTS_FILE, n)),
+    }
+}
+
+/// Open the object log `manifest` describes, checking it's as long as
+/// the manifest says; `None` if the writer has compacted it away since
+fn open_log(dir: &Path, manifest: &Manifest) -> Result<Option<File>> {
+    let path = log_path(dir, manifest.generation);
+    let log = match File::open(&path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let compacted = read_manifest(dir)?.is_some_and(|latest| latest.generation != manifest.generation);
+            return if compacted { Ok(None) } else { Err(e.into()) };
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if log.metadata()?.len() < manifest.log_len {
+        return Err(Error::Storage(format!("{} is shorter than its manifest says", path.display())));
+    }
+    Ok(Some(log))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// A writer's last flushed state, from the manifest
+struct Manifest {
+    log_len: u64,
+    refs: HashMap<String, Hash256>,
+    tags: HashMap<String, Hash256>,
+    generation: u64,
+    base_seq: u64,
+    compacted_seq: u64,
+}
+
+/// Read the manifest in `dir`, if a writer has written one
+fn read_manifest(dir: &Path) -> Result<Option<Manifest>> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(&path)?;
+    let mut reader = Reader::new(&data);
+    let log_len = reader.i64()? as u64;
+    let refs = read_names(&mut reader)?;
+    let tags = read_names(&mut reader)?;
+    let mut manifest = Manifest { log_len, refs, tags, generation: 0, base_seq: 0, compacted_seq: 0 };
+    if !reader.is_empty() {
+        manifest.generation = reader.i64()? as u64;
+        manifest.base_seq = reader.i64()? as u64;
+        manifest.compacted_seq = reader.i64()? as u64;
+    }
+    Ok(Some(manifest))
+}
+
+/// `[count: 4] ([name] [hash: 32])*`
+fn read_names(reader: &mut Reader) -> Result<HashMap<String, Hash256>> {
+    let mut names = HashMap::new();
+    let count = reader.u32()? as usize;
+    for _ in 0..count {
+        let name = reader.string()?;
+        let hash = reader.hash()?;
+        names.insert(name, hash);
+    }
+    Ok(names)
+}
+
+fn write_names(buf: &mut Vec<u8>, names: &HashMap<String, Hash256>) {
+    let mut sorted: Vec<_> = names.iter().collect();
+    sorted.sort();
+    wire::put_u32(buf, sorted.len() as u32);
+    for (name, hash) in sorted {
+        wire::put_str(buf, name);
+        wire::put_hash(buf, hash);
+    }
+}
+
+/// Atomically replace a names file
+fn save_names(path: &Path, names: &HashMap<String, Hash256>) -> Result<()> {
+    let mut buf = Vec::new();
+    write_names(&mut buf, names);
+    replace_file(path, &buf)
+}
+
+/// Append an object log record: op, hash, then the length-prefixed bytes
+fn write_record(buf: &mut Vec<u8>, op: u8, hash: &Hash256, bytes: &[u8]) {
+    buf.push(op);
+    wire::put_hash(buf, hash);
+    wire::put_bytes(buf, bytes);
+}
+
+/// Write and sync a temp file, then rename over `path` and sync the
+/// directory so the rename survives a crash
+pub(crate) fn replace_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// A stored envelope borrowed from the store, from `Store::iter`
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> EnvelopeRef<'a> {
+    /// The type hash, read without decoding anything else
+    pub fn type_hash(&self) -> Hash256 {
+        type_of(self.bytes)
+    }
+    
+    /// Serialized size
+    pub fn stored_size(&self) -> usize {
+        self.bytes.len()
+    }
+    
+    /// Everything but the payload, which is left empty
+    pub fn header(&self) -> Result<Envelope> {
+        Ok(decode_header(self.bytes)?.0)
+    }
+    
+    /// The payload, borrowed rather than copied
+    pub fn payload(&self) -> Result<&'a [u8]> {
+        Ok(decode_header(self.bytes)?.1)
+    }
+    
+    /// Decode the whole envelope
+    pub fn decode(&self) -> Result<Envelope> {
+        let (mut envelope, payload) = decode_header(self.bytes)?;
+        envelope.payload = payload.to_vec();
+        Ok(envelope)
+    }
+}
+
+/// Iterator over a version chain, newest to oldest
+///
+/// Stops after an error (missing version or cycle) or the depth limit.
+#[derive(Debug)]
+pub struct History<'a> {
+    store: &'a Store,
+    next: Option<Hash256>,
+    seen: HashSet<Hash256>,
+    remaining: Option<usize>,
+}
+
+impl History<'_> {
+    /// Yield at most `depth` versions
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.remaining = Some(depth);
+        self
+    }
+}
+
+impl Iterator for History<'_> {
+    type Item = Result<(Hash256, Envelope)>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        let hash = self.next.take()?;
+        if let Some(remaining) = &mut self.remaining {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        if !self.seen.insert(hash) {
+            return Some(Err(Error::InvalidEnvelope(format!(
+                "version chain cycle at {}", hash
+            ))));
+        }
+        match self.store.get(&hash) {
+            Ok(envelope) => {
+                self.next = envelope.previous;
+                Some(Ok((hash, envelope)))
+            }
+            Err(e) => Some(Err(e)),
+        }
     }
 }
 
@@ -243,16 +1389,178 @@ mod tests {
         let type_hash = Hash256::hash(b"TestType");
         let envelope = Envelope::builder(type_hash, vec![1, 2, 3, 4])
             .type_name("TestType")
+            .content_type("application/octet-stream")
             .index("title", "Hello")
+            .created_by(Hash256::hash(b"alice"))
+            .valid_from(1700000000)
+            .valid_to(1800000000)
+            .extension("sig", vec![0xAB; 64])
+            .merge_parent(Hash256::hash(b"other branch"))
             .build();
         
         let hash = store.put(&envelope).unwrap();
         let retrieved = store.get(&hash).unwrap();
         
+        assert_eq!(hash, envelope.hash());
         assert_eq!(retrieved.type_name, envelope.type_name);
+        assert_eq!(retrieved.content_type, envelope.content_type);
+        assert_eq!(retrieved.created_by, envelope.created_by);
+        assert_eq!(retrieved.valid_from, envelope.valid_from);
+        assert_eq!(retrieved.valid_to, envelope.valid_to);
+        assert_eq!(retrieved.extensions, envelope.extensions);
+        assert_eq!(retrieved.merge_parents, envelope.merge_parents);
         assert_eq!(retrieved.payload, envelope.payload);
     }
     
+    #[test]
+    fn test_store_roundtrip_typed_index() {
+        let mut store = Store::new();
+        
+        let type_hash = Hash256::hash(b"TestType");
+        let envelope = Envelope::builder(type_hash, vec![])
+            .index("count", 42i64)
+            .index("published", true)
+            .index("published_at", IndexValue::Null)
+            .build();
+        
+        let hash = store.put(&envelope).unwrap();
+        let retrieved = store.get(&hash).unwrap();
+        
+        assert!(matches!(retrieved.index.get("count"), Some(IndexValue::Int64(42))));
+        assert!(matches!(retrieved.index.get("published"), Some(IndexValue::Bool(true))));
+        assert!(matches!(retrieved.index.get("published_at"), Some(IndexValue::Null)));
+    }
+    
+    #[test]
+    fn test_store_roundtrip_relationship_properties() {
+        let mut store = Store::new();
+        
+        let rel = Relationship::weak("member", Hash256::hash(b"team"))
+            .with_position(3)
+            .with_property("role", "maintainer")
+            .with_property("since", IndexValue::Timestamp(1708523400));
+        let envelope = Envelope::builder(Hash256::hash(b"Person"), vec![])
+            .relationships([rel.clone()])
+            .build();
+        
+        let hash = store.put(&envelope).unwrap();
+        assert_eq!(store.get(&hash).unwrap().relationships, vec![rel]);
+    }
+    
+    #[test]
+    fn test_decode_error_context() {
+        let store = Store::new();
+        let node = Hash256::hash(b"Node");
+        let child = Hash256::hash(b"child");
+        let bytes = store.serialize(&Envelope::builder(node, vec![]).relationship("child", child).build()).unwrap();
+        
+        // Cut inside the first relationship's target, which starts after the
+        // type hash, two empty strings, the count and the relationship type
+        let message = store.deserialize(&bytes[..60]).unwrap_err().to_string();
+        assert!(message.ends_with("relationship 0: target: expected 32 bytes at offset 53, 7 available"), "{}", message);
+        // One index field `k` with an unknown value tag, after the 47 byte
+        // relationship
+        let mut bad_tag = bytes[..91].to_vec();
+        bad_tag.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0, b'k', 99]);
+        let message = store.deserialize(&bad_tag).unwrap_err().to_string();
+        assert!(message.contains("index field 0: value: unknown index value tag 99 at offset 100"), "{}", message);
+    }
+    
+    #[test]
+    fn test_store_iter() {
+        let mut store = Store::new();
+        let post = Hash256::hash(b"Post");
+        let note = Hash256::hash(b"Note");
+        let a = store.put(&Envelope::builder(post, b"first".to_vec()).index("title", "A").build()).unwrap();
+        store.put(&Envelope::builder(note, b"second".to_vec()).build()).unwrap();
+        
+        let posts: Vec<_> = store.iter().filter(|(_, envelope)| envelope.type_hash() == post).collect();
+        assert_eq!(posts.len(), 1);
+        let (hash, envelope) = posts[0];
+        assert_eq!(hash, a);
+        let header = envelope.header().unwrap();
+        assert!(header.payload.is_empty());
+        assert_eq!(header.get_str("title").unwrap(), Some("A"));
+        assert_eq!(envelope.payload().unwrap(), b"first");
+        assert_eq!(envelope.decode().unwrap().hash(), a);
+        assert_eq!(store.iter().map(|(_, e)| e.stored_size()).sum::<usize>(), store.usage().bytes);
+    }
+    
+    #[test]
+    fn test_get_metadata() {
+        let mut store = Store::new();
+        let post = Hash256::hash(b"Post");
+        let envelope = Envelope::builder(post, vec![7; 10_000])
+            .index("title", "Big")
+            .relationship("author", Hash256::hash(b"alice"))
+            .created_at(100)
+            .build();
+        let hash = store.put(&envelope).unwrap();
+        let metadata = store.get_metadata(&hash).unwrap();
+        assert!(metadata.payload.is_empty());
+        assert_eq!(metadata.get_str("title").unwrap(), Some("Big"));
+        assert_eq!((metadata.relationships.len(), metadata.created_at), (1, Some(100)));
+        assert!(store.get_metadata(&Hash256::hash(b"missing")).unwrap_err().is_not_found());
+    }
+    
+    #[test]
+    fn test_scan() {
+        let mut store = Store::new();
+        let post = Hash256::hash(b"Post");
+        let mut expected: Vec<_> = [50i64, 300, 1200].iter()
+            .map(|words| store.put(&Envelope::builder(post, vec![0; 1000]).index("words", *words).created_at(*words).build()).unwrap())
+            .collect();
+        expected.remove(0);
+        expected.sort();
+        
+        let long = store.scan(|header| header.get_i64("words").ok().flatten().is_some_and(|w| w > 100)).unwrap();
+        assert_eq!(long, expected);
+        assert!(store.scan(|header| !header.payload.is_empty()).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_scan_from() {
+        let mut store = Store::new();
+        let blob = Hash256::hash(b"Blob");
+        let mut all: Vec<_> = (0..5u8).map(|i| store.put(&Envelope::builder(blob, vec![i]).build()).unwrap()).collect();
+        all.sort();
+        
+        let first = store.scan_from(None, 2);
+        assert_eq!(first.hashes, all[..2]);
+        // Resume from a checkpointed cursor
+        let cursor = Hash256::from_hex(&first.next.unwrap().to_string()).unwrap();
+        let second = store.scan_from(Some(&cursor), 2);
+        assert_eq!(second.hashes, all[2..4]);
+        let last = store.scan_from(second.next.as_ref(), 2);
+        assert_eq!((last.hashes, last.next), (all[4..].to_vec(), None));
+        assert_eq!(store.scan_from(None, 5).next, None);
+        
+        // A cursor whose object was removed still resumes after it
+        store.remove(&all[1]).unwrap();
+        assert_eq!(store.scan_from(Some(&all[1]), 2).hashes, all[2..4]);
+        assert_eq!(store.scan_from(None, 10).hashes.len(), 4);
+    }
+    
+    #[test]
+    fn test_verify() {
+        let mut store = Store::new();
+        let blob = Hash256::hash(b"Blob");
+        let good = store.put(&Envelope::builder(blob, b"good".to_vec()).build()).unwrap();
+        let bad = store.put(&Envelope::builder(blob, b"bad".to_vec()).build()).unwrap();
+        store.set_ref("main", good).unwrap();
+        assert!(store.verify().is_empty());
+        
+        // Swap in another object's bytes, and truncate the original
+        let other = store.objects[&good].clone();
+        store.objects.insert(bad, other);
+        let truncated = store.objects[&good][..10].to_vec();
+        store.objects.insert(good, truncated.into());
+        let problems = store.verify();
+        assert_eq!(problems.len(), 2);
+        assert!(problems.iter().any(|(h, e)| *h == bad && matches!(e, Error::HashMismatch { .. })));
+        assert!(problems.iter().any(|(h, e)| *h == good && e.is_corruption()));
+    }
+    
     #[test]
     fn test_store_deduplication() {
         let mut store = Store::new();
@@ -269,5 +1577,345 @@ mod tests {
         // Same content = same hash = deduplicated
         assert_eq!(hash1, hash2);
         assert_eq!(store.len(), 1);
+        
+        let stats = store.dedup_stats();
+        assert_eq!((stats.puts, stats.duplicates), (2, 1));
+        assert_eq!(stats.bytes_saved, store.stored_size(&hash1).unwrap() as u64);
+        assert_eq!(stats.hit_rate(), 0.5);
+        
+        // The name isn't part of the hash, so the first one put sticks
+        let renamed = Envelope::builder(type_hash, vec![1, 2, 3, 4]).type_name("Other").build();
+        assert_eq!(store.put(&renamed).unwrap(), hash1);
+        assert_eq!(store.get(&hash1).unwrap().type_name, None);
+        assert_eq!(store.dedup_stats().duplicates, 2);
+    }
+    
+    #[test]
+    fn test_history() {
+        let mut store = Store::new();
+        let type_hash = Hash256::hash(b"TestType");
+        
+        let v1 = Envelope::builder(type_hash, b"v1".to_vec()).build();
+        let h1 = store.put(&v1).unwrap();
+        let v2 = v1.derive().payload(b"v2".to_vec()).build();
+        let h2 = store.put(&v2).unwrap();
+        let v3 = v2.derive().payload(b"v3".to_vec()).build();
+        let h3 = store.put(&v3).unwrap();
+        
+        let chain: Vec<_> = store.history(&h3).map(|r| r.unwrap().0).collect();
+        assert_eq!(chain, vec![h3, h2, h1]);
+        
+        let recent: Vec<_> = store.history(&h3).max_depth(2).map(|r| r.unwrap().0).collect();
+        assert_eq!(recent, vec![h3, h2]);
+        
+        // A chain pointing at a missing version ends with an error
+        let orphan = Envelope::builder(type_hash, vec![]).previous(Hash256::hash(b"gone")).build();
+        let orphan_hash = store.put(&orphan).unwrap();
+        let results: Vec<_> = store.history(&orphan_hash).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+    }
+    
+    #[test]
+    fn test_refs_compare_and_swap() {
+        let mut store = Store::new();
+        let type_hash = Hash256::hash(b"Config");
+        let v1 = store.put(&Envelope::builder(type_hash, b"v1".to_vec()).build()).unwrap();
+        let v2 = store.put(&Envelope::builder(type_hash, b"v2".to_vec()).build()).unwrap();
+        
+        store.compare_and_swap_ref("config/prod", None, v1).unwrap();
+        assert_eq!(store.get_ref("config/prod"), Some(v1));
+        
+        // A writer working from a stale view loses
+        assert!(matches!(
+            store.compare_and_swap_ref("config/prod", None, v2),
+            Err(Error::RefConflict { .. })
+        ));
+        store.compare_and_swap_ref("config/prod", Some(v1), v2).unwrap();
+        assert_eq!(store.get_ref("config/prod"), Some(v2));
+        
+        assert!(store.set_ref("dangling", Hash256::hash(b"missing")).is_err());
+        assert_eq!(store.delete_ref("config/prod").unwrap(), Some(v2));
+        assert_eq!(store.get_ref("config/prod"), None);
+    }
+    
+    #[test]
+    fn test_persistent_store_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let type_hash = Hash256::hash(b"TestType");
+        let envelope = Envelope::builder(type_hash, b"persisted".to_vec())
+            .index("title", "Hello")
+            .build();
+        
+        let hash = {
+            let mut store = Store::open(dir.path()).unwrap();
+            let hash = store.put(&envelope).unwrap();
+            store.put(&envelope).unwrap();
+            store.set_ref("blog/latest", hash).unwrap();
+            store.tag("v1.0", hash).unwrap();
+            
+            let scratch = store.put(&Envelope::builder(type_hash, b"scratch".to_vec()).build()).unwrap();
+            assert!(store.remove(&scratch).unwrap());
+            hash
+        };
+        
+        let store = Store::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&hash).unwrap().payload, b"persisted");
+        assert_eq!(store.get_ref("blog/latest"), Some(hash));
+        assert_eq!(store.get_tag("v1.0"), Some(hash));
+    }
+    
+    #[test]
+    fn test_store_locking() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = Hash256::hash(b"Note");
+        let mut writer = Store::open(dir.path()).unwrap();
+        let hash = writer.put(&Envelope::builder(note, b"hi".to_vec()).build()).unwrap();
+        assert!(matches!(Store::open(dir.path()), Err(Error::StoreLocked(_))));
+        assert!(matches!(Store::open_or_read_only(dir.path()), Err(Error::StoreLocked(_))));
+        drop(writer);
+        
+        // Readers share the store but keep writers out
+        let reader = Store::open_read_only(dir.path()).unwrap();
+        let mut fallback = Store::open_or_read_only(dir.path()).unwrap();
+        assert!(fallback.is_read_only());
+        assert_eq!(fallback.get(&hash).unwrap().payload, b"hi");
+        assert!(matches!(fallback.put(&Envelope::builder(note, vec![]).build()), Err(Error::StoreLocked(_))));
+        assert!(matches!(fallback.set_ref("main", hash), Err(Error::StoreLocked(_))));
+        drop((reader, fallback));
+        assert!(!Store::open(dir.path()).unwrap().is_read_only());
+    }
+    
+    #[test]
+    fn test_snapshot_alongside_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = Hash256::hash(b"Note");
+        let mut writer = Store::open(dir.path()).unwrap();
+        let first = writer.put(&Envelope::builder(note, b"one".to_vec()).build()).unwrap();
+        writer.set_ref("main", first).unwrap();
+        
+        let mut snapshot = Store::open_snapshot(dir.path()).unwrap();
+        assert_eq!(snapshot.get_ref("main"), Some(first));
+        assert!(matches!(snapshot.remove(&first), Err(Error::StoreLocked(_))));
+        assert!(!snapshot.refresh().unwrap());
+        
+        let second = writer.put(&Envelope::builder(note, b"two".to_vec()).build()).unwrap();
+        writer.set_ref("main", second).unwrap();
+        writer.remove(&first).unwrap();
+        writer.flush().unwrap();
+        // A half-written record past the manifest's length isn't read
+        std::fs::OpenOptions::new().append(true).open(dir.path().join(OBJECTS_FILE)).unwrap()
+            .write_all(&[OP_PUT, 1, 2, 3]).unwrap();
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot.refresh().unwrap());
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.get(&second).unwrap().payload, b"two");
+        assert_eq!(snapshot.get_ref("main"), Some(second));
+        assert_eq!(snapshot.usage(), writer.usage());
+        // Mapped object bytes aren't on the heap
+        if cfg!(all(feature = "mmap", unix)) {
+            assert!(snapshot.objects.values().all(|bytes| bytes.heap_size() == 0));
+            assert!(writer.objects.values().all(|bytes| bytes.heap_size() > 0));
+        }
+    }
+    
+    #[test]
+    fn test_torn_tail_and_corruption_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = Hash256::hash(b"Note");
+        let log_path = dir.path().join(OBJECTS_FILE);
+        let hash = Store::open(dir.path()).unwrap().put(&Envelope::builder(note, b"kept".to_vec()).build()).unwrap();
+        let complete = std::fs::metadata(&log_path).unwrap().len();
+        
+        // A crash mid-record leaves a torn tail, which is dropped
+        std::fs::OpenOptions::new().append(true).open(&log_path).unwrap().write_all(&[OP_PUT, 1, 2, 3]).unwrap();
+        let store = Store::open(dir.path()).unwrap();
+        assert_eq!(store.get(&hash).unwrap().payload, b"kept");
+        drop(store);
+        assert_eq!(std::fs::metadata(&log_path).unwrap().len(), complete);
+        
+        // A complete record whose content doesn't match its hash is corruption
+        let data = std::fs::read(&log_path).unwrap();
+        let mut corrupt = data.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        std::fs::write(&log_path, corrupt).unwrap();
+        assert!(matches!(Store::open(dir.path()), Err(Error::HashMismatch { .. })));
+        
+        // As is a record with an unknown op, reported at its offset
+        let mut unknown = data.clone();
+        unknown.extend_from_slice(&data);
+        unknown[data.len()] = 7;
+        std::fs::write(&log_path, unknown).unwrap();
+        let message = Store::open(dir.path()).unwrap_err().to_string();
+        assert!(message.ends_with(&format!("object log record at offset {}: unknown op 7", data.len())), "{}", message);
+    }
+    
+    #[test]
+    fn test_stats_by_type() {
+        let mut store = Store::new();
+        let post = Hash256::hash(b"Post");
+        let v1 = store.put(&Envelope::builder(post, b"1234".to_vec()).type_name("Post").build()).unwrap();
+        let v2 = store.put(&Envelope::builder(post, b"12".to_vec()).previous(v1).build()).unwrap();
+        store.put(&Envelope::builder(post, vec![]).previous(v2).build()).unwrap();
+        store.put(&Envelope::builder(post, b"other".to_vec()).build()).unwrap();
+        let tag = Hash256::hash(b"Tag");
+        store.put(&Envelope::builder(tag, b"x".to_vec()).build()).unwrap();
+        
+        let stats = store.stats_by_type().unwrap();
+        assert_eq!(stats.len(), 2);
+        let posts = &stats[&post];
+        assert_eq!(posts.type_name.as_deref(), Some("Post"));
+        assert_eq!((posts.count, posts.payload_bytes), (4, 11));
+        assert_eq!(posts.average_payload_bytes(), 2.75);
+        assert_eq!(posts.depths, BTreeMap::from([(1, 2), (2, 1), (3, 1)]));
+        assert_eq!(stats[&tag].type_name, None);
+        assert_eq!(stats[&tag].depths, BTreeMap::from([(1, 1)]));
+        
+        // Removing the original shortens the chain
+        store.remove(&v1).unwrap();
+        assert_eq!(store.stats_by_type().unwrap()[&post].depths, BTreeMap::from([(1, 2), (2, 1)]));
+    }
+    
+    #[test]
+    fn test_quotas() {
+        let mut store = Store::new();
+        let post = Hash256::hash(b"Post");
+        let image = Hash256::hash(b"Image");
+        store.set_quota(Quota::new().max_objects(3));
+        store.set_type_quota(image, Quota::new().max_bytes(1000));
+        
+        let first = store.put(&Envelope::builder(post, b"a".to_vec()).build()).unwrap();
+        let err = store.put(&Envelope::builder(image, vec![0; 1000]).build()).unwrap_err();
+        assert!(matches!(err, Error::QuotaExceeded(_)));
+        store.put(&Envelope::builder(image, vec![0; 100]).build()).unwrap();
+        store.put(&Envelope::builder(post, b"b".to_vec()).build()).unwrap();
+        assert!(matches!(store.put(&Envelope::builder(post, b"c".to_vec()).build()), Err(Error::QuotaExceeded(_))));
+        // Re-putting a stored object needs no room
+        store.put(&Envelope::builder(post, b"a".to_vec()).build()).unwrap();
+        
+        assert_eq!(store.usage().objects, 3);
+        assert_eq!(store.usage().bytes, store.objects.values().map(|bytes| bytes.len()).sum::<usize>());
+        assert_eq!(store.type_usage(&post).objects, 2);
+        store.remove(&first).unwrap();
+        assert_eq!(store.type_usage(&post).objects, 1);
+        store.put(&Envelope::builder(post, b"c".to_vec()).build()).unwrap();
+    }
+    
+    #[test]
+    fn test_changes_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let type_hash = Hash256::hash(b"TestType");
+        let (first, second) = {
+            let mut store = Store::open(dir.path()).unwrap();
+            let first = store.put(&Envelope::builder(type_hash, b"one".to_vec()).build()).unwrap();
+            store.put(&Envelope::builder(type_hash, b"one".to_vec()).build()).unwrap();
+            let second = store.put(&Envelope::builder(type_hash, b"two".to_vec()).build()).unwrap();
+            store.remove(&first).unwrap();
+            (first, second)
+        };
+        
+        let mut store = Store::open(dir.path()).unwrap();
+        assert_eq!(store.last_seq(), 3);
+        assert_eq!(store.changes_since(1), &[
+            Change { seq: 2, op: EventKind::Put, hash: second },
+            Change { seq: 3, op: EventKind::Delete, hash: first },
+        ]);
+        let third = store.put(&Envelope::builder(type_hash, b"three".to_vec()).build()).unwrap();
+        assert_eq!(store.changes_since(3), &[Change { seq: 4, op: EventKind::Put, hash: third }]);
+        assert_eq!(store.changes_since(0).len(), 4);
+        assert!(store.changes_since(10).is_empty());
+    }
+    
+    #[test]
+    fn test_changes_across_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let type_hash = Hash256::hash(b"TestType");
+        let mut store = Store::open(dir.path()).unwrap();
+        let first = store.put(&Envelope::builder(type_hash, b"one".to_vec()).build()).unwrap();
+        let second = store.put(&Envelope::builder(type_hash, b"two".to_vec()).build()).unwrap();
+        store.remove(&first).unwrap();
+        assert!(store.compact().unwrap() > 0);
+        
+        // Numbering carries on from where it was, and survives a reopen
+        assert_eq!(store.last_seq(), 3);
+        assert_eq!(store.compacted_seq(), 3);
+        assert_eq!(store.changes_since(0), &[Change { seq: 3, op: EventKind::Put, hash: second }]);
+        let third = store.put(&Envelope::builder(type_hash, b"three".to_vec()).build()).unwrap();
+        drop(store);
+        let store = Store::open(dir.path()).unwrap();
+        assert_eq!(store.compacted_seq(), 3);
+        assert_eq!(store.changes_since(3), &[Change { seq: 4, op: EventKind::Put, hash: third }]);
+        assert_eq!(store.changes_since(0).len(), 2);
+    }
+    
+    #[test]
+    fn test_snapshot_refresh_across_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = Hash256::hash(b"Note");
+        let mut writer = Store::open(dir.path()).unwrap();
+        let hashes: Vec<_> = (0..3u8).map(|i| writer.put(&Envelope::builder(note, vec![i; 50]).build()).unwrap()).collect();
+        writer.flush().unwrap();
+        let mut snapshot = Store::open_snapshot(dir.path()).unwrap();
+        assert_eq!(snapshot.len(), 3);
+        
+        // The compacted log is shorter than what the snapshot has read
+        writer.remove(&hashes[0]).unwrap();
+        writer.remove(&hashes[1]).unwrap();
+        writer.compact().unwrap();
+        let fourth = writer.put(&Envelope::builder(note, vec![3; 50]).build()).unwrap();
+        writer.flush().unwrap();
+        assert!(snapshot.refresh().unwrap());
+        assert_eq!(snapshot.len(), 2);
+        assert!(!snapshot.contains(&hashes[0]) && snapshot.contains(&hashes[2]) && snapshot.contains(&fourth));
+        assert_eq!(snapshot.usage(), writer.usage());
+        assert_eq!(snapshot.last_seq(), writer.last_seq());
+        assert_eq!(snapshot.changes_since(5), writer.changes_since(5));
+        assert!(!snapshot.refresh().unwrap());
+    }
+    
+    #[test]
+    fn test_refresh_racing_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = Hash256::hash(b"Note");
+        let mut writer = Store::open(dir.path()).unwrap();
+        let hashes: Vec<_> = (0..4u8).map(|i| writer.put(&Envelope::builder(note, vec![i; 50]).build()).unwrap()).collect();
+        writer.flush().unwrap();
+        let mut snapshot = Store::open_snapshot(dir.path()).unwrap();
+        writer.remove(&hashes[0]).unwrap();
+        writer.flush().unwrap();
+        
+        // The writer compacts between a reader's manifest read and its
+        // open of the log that manifest names
+        let manifest = read_manifest(dir.path()).unwrap().unwrap();
+        writer.compact().unwrap();
+        assert!(open_log(dir.path(), &manifest).unwrap().is_none());
+        assert!(!log_path(dir.path(), 0).exists());
+        let current = read_manifest(dir.path()).unwrap().unwrap();
+        assert_eq!(current.generation, 1);
+        assert!(open_log(dir.path(), &current).unwrap().is_some());
+        
+        assert!(snapshot.refresh().unwrap());
+        assert_eq!(snapshot.len(), 3);
+        assert!(!snapshot.contains(&hashes[0]));
+        assert_eq!(snapshot.usage(), writer.usage());
+        drop(writer);
+        assert_eq!(Store::open(dir.path()).unwrap().len(), 3);
+    }
+    
+    #[test]
+    fn test_tags_are_immutable() {
+        let mut store = Store::new();
+        let type_hash = Hash256::hash(b"Release");
+        let v1 = store.put(&Envelope::builder(type_hash, b"v1".to_vec()).build()).unwrap();
+        let v2 = store.put(&Envelope::builder(type_hash, b"v2".to_vec()).build()).unwrap();
+        
+        store.tag("v1.0", v1).unwrap();
+        store.tag("v1.0", v1).unwrap();
+        assert!(matches!(store.tag("v1.0", v2), Err(Error::TagExists(_))));
+        store.tag("v2.0", v2).unwrap();
+        
+        assert_eq!(store.get_tag("v1.0"), Some(v1));
+        assert_eq!(store.tags(), vec![("v1.0", v1), ("v2.0", v2)]);
     }
 }
@@ -0,0 +1,315 @@
+//! Pulling object graphs from another store
+//!
+//! A `Pull` walks the closure of some roots in the source and hands the
+//! receiver objects it lacks in bounded batches. The receiver asks for
+//! each batch when it's ready, so a slow receiver simply pulls less
+//! often; nothing is buffered beyond the batches in flight. Objects come
+//! children first (strong targets and parents before the objects that
+//! link them), so a receiver that has an object has its closure too:
+//! objects it already has aren't descended into, and a pull interrupted
+//! part way resumes where it stopped when started again.
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::store::Store;
+use crate::trace::span;
+use crate::Result;
+use std::collections::{HashSet, VecDeque};
+use crate::clock::{self, Stopwatch};
+use std::time::Duration;
+
+/// Limits on a pull
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncOptions {
+    max_batch_objects: usize,
+    max_batch_bytes: usize,
+    bytes_per_second: Option<u64>,
+    max_in_flight: usize,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self { max_batch_objects: 256, max_batch_bytes: 1 << 20, bytes_per_second: None, max_in_flight: 1 }
+    }
+}
+
+impl SyncOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Objects per batch (at least one)
+    pub fn max_batch_objects(mut self, n: usize) -> Self {
+        self.max_batch_objects = n.max(1);
+        self
+    }
+    
+    /// Serialized bytes per batch; a single larger object still gets a
+    /// batch of its own
+    pub fn max_batch_bytes(mut self, n: usize) -> Self {
+        self.max_batch_bytes = n;
+        self
+    }
+    
+    /// Throttle to an average transfer rate, sleeping between batches
+    pub fn bytes_per_second(mut self, rate: u64) -> Self {
+        self.bytes_per_second = Some(rate.max(1));
+        self
+    }
+    
+    /// Batches handed out before the receiver has stored them (at least
+    /// one, the default), e.g. for receivers storing on several threads
+    pub fn max_in_flight(mut self, n: usize) -> Self {
+        self.max_in_flight = n.max(1);
+        self
+    }
+}
+
+/// A step of the post-order walk
+#[derive(Debug)]
+enum Visit {
+    /// Descend into an object's links
+    Expand(Hash256),
+    /// Hand out an object whose links have been handed out
+    Emit(Hash256, Box<Envelope>),
+}
+
+/// A pull in progress, from `Store::pull`
+#[derive(Debug)]
+pub struct Pull<'a> {
+    source: &'a Store,
+    options: SyncOptions,
+    stack: Vec<Visit>,
+    seen: HashSet<Hash256>,
+    /// Hashes of handed-out batches the receiver may not have stored yet
+    in_flight: VecDeque<Vec<Hash256>>,
+    /// Set by the first throttled batch, so unthrottled pulls never read
+    /// the clock
+    started: Option<Stopwatch>,
+    transferred: u64,
+}
+
+impl Pull<'_> {
+    /// The next objects `receiver` lacks, or `None` when done
+    ///
+    /// Store each batch in order: objects already in the receiver are
+    /// skipped along with their closure. At most `max_in_flight` batches
+    /// may be handed out and not yet stored; asking for another fails
+    /// with `Error::InvalidArgument`.
+    pub fn next_batch(&mut self, receiver: &Store) -> Result<Option<Vec<Envelope>>> {
+        let span = span!("Pull::next_batch", pending = self.stack.len(), objects = Empty, bytes = Empty);
+        self.in_flight.retain(|batch| !batch.iter().all(|hash| receiver.contains(hash)));
+        if self.in_flight.len() >= self.options.max_in_flight {
+            return Err(Error::InvalidArgument(format!(
+                "{} batches in flight; store them before asking for more",
+                self.in_flight.len(),
+            )));
+        }
+        self.throttle();
+        let mut batch = Vec::new();
+        let mut hashes = Vec::new();
+        let mut bytes = 0;
+        while let Some(visit) = self.stack.pop() {
+            match visit {
+                Visit::Expand(hash) => {
+                    if !self.seen.insert(hash) || receiver.contains(&hash) || !self.source.contains(&hash) {
+                        continue;
+                    }
+                    let envelope = self.source.get(&hash)?;
+                    let links: Vec<Hash256> = envelope.relationships.iter()
+                        .filter(|r| r.is_strong())
+                        .map(|r| r.target)
+                        .chain(envelope.parents().copied())
+                        .collect();
+                    self.stack.push(Visit::Emit(hash, Box::new(envelope)));
+                    // Reversed, so links come out in the order listed
+                    self.stack.extend(links.into_iter().rev().map(Visit::Expand));
+                }
+                Visit::Emit(hash, envelope) => {
+                    let size = self.source.stored_size(&hash).unwrap_or(0);
+                    if !batch.is_empty() && (batch.len() >= self.options.max_batch_objects || bytes + size > self.options.max_batch_bytes) {
+                        // Leave it for the next batch
+                        self.stack.push(Visit::Emit(hash, envelope));
+                        break;
+                    }
+                    bytes += size;
+                    hashes.push(hash);
+                    batch.push(*envelope);
+                }
+            }
+        }
+        self.transferred += bytes as u64;
+        span.record("objects", batch.len());
+        span.record("bytes", bytes);
+        if batch.is_empty() {
+            return Ok(None);
+        }
+        self.in_flight.push_back(hashes);
+        Ok(Some(batch))
+    }
+    
+    /// Sleep until the bytes sent so far fit the configured rate
+    fn throttle(&mut self) {
+        let Some(rate) = self.options.bytes_per_second else {
+            return;
+        };
+        let started = *self.started.get_or_insert_with(Stopwatch::start);
+        let due = Duration::from_secs_f64(self.transferred as f64 / rate as f64);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            clock::sleep(wait);
+        }
+    }
+    
+    /// Serialized bytes handed out so far
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+}
+
+impl Store {
+    /// Start serving the closure of `roots` to another store
+    ///
+    /// The receiver calls `next_batch` and stores each batch, or uses
+    /// `pull_from` to do both until done.
+    pub fn pull(&self, roots: &[Hash256], options: SyncOptions) -> Pull<'_> {
+        Pull {
+            source: self,
+            options,
+            stack: roots.iter().rev().copied().map(Visit::Expand).collect(),
+            seen: HashSet::new(),
+            in_flight: VecDeque::new(),
+            started: None,
+            transferred: 0,
+        }
+    }
+    
+    /// Copy the closure of `roots` from `source`, batch by batch;
+    /// returns the number of objects stored
+    pub fn pull_from(&mut self, source: &Store, roots: &[Hash256], options: SyncOptions) -> Result<usize> {
+        let span = span!("Store::pull_from", roots = roots.len(), objects = Empty, bytes = Empty);
+        let mut pull = source.pull(roots, options);
+        let mut stored = 0;
+        while let Some(batch) = pull.next_batch(self)? {
+            for envelope in &batch {
+                self.put(envelope)?;
+            }
+            stored += batch.len();
+        }
+        span.record("objects", stored);
+        span.record("bytes", pull.transferred());
+        Ok(stored)
+    }
+}
+
+impl IndexedStore {
+    /// `Store::pull_from`, indexing each object as it's stored
+    pub fn pull_from(&mut self, source: &Store, roots: &[Hash256], options: SyncOptions) -> Result<usize> {
+        let span = span!("IndexedStore::pull_from", roots = roots.len(), objects = Empty, bytes = Empty);
+        #[cfg(feature = "metrics")]
+        let started = Stopwatch::start();
+        let mut pull = source.pull(roots, options);
+        let mut stored = 0;
+        while let Some(batch) = pull.next_batch(self.store())? {
+            for envelope in &batch {
+                self.put(envelope)?;
+            }
+            stored += batch.len();
+        }
+        span.record("objects", stored);
+        span.record("bytes", pull.transferred());
+        #[cfg(feature = "metrics")]
+        self.metrics().record_sync(started, stored, pull.transferred());
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_pull_in_batches() {
+        let mut source = Store::new();
+        let node = Hash256::hash(b"Node");
+        let leaves: Vec<_> = (0..5u8)
+            .map(|i| source.put(&Envelope::builder(node, vec![i; 100]).build()).unwrap())
+            .collect();
+        let mut builder = Envelope::builder(node, b"root".to_vec());
+        for leaf in &leaves {
+            builder = builder.relationship("leaf", *leaf);
+        }
+        let root = source.put(&builder.build()).unwrap();
+        source.put(&Envelope::builder(node, b"unrelated".to_vec()).build()).unwrap();
+        
+        let mut receiver = Store::new();
+        receiver.put(&source.get(&leaves[0]).unwrap()).unwrap();
+        let mut pull = source.pull(&[root], SyncOptions::new().max_batch_objects(2));
+        let mut sizes = Vec::new();
+        while let Some(batch) = pull.next_batch(&receiver).unwrap() {
+            sizes.push(batch.len());
+            batch.iter().for_each(|e| { receiver.put(e).unwrap(); });
+        }
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(receiver.len(), 6);
+        
+        let mut copy = Store::new();
+        assert_eq!(copy.pull_from(&source, &[root], SyncOptions::new().max_batch_bytes(250)).unwrap(), 6);
+        assert_eq!(copy.pull_from(&source, &[root], SyncOptions::new()).unwrap(), 0);
+    }
+    
+    #[test]
+    fn test_interrupted_pull_resumes() {
+        let mut source = Store::new();
+        let node = Hash256::hash(b"Node");
+        let leaf = source.put(&Envelope::builder(node, b"leaf".to_vec()).build()).unwrap();
+        let middle = source.put(&Envelope::builder(node, b"middle".to_vec()).relationship("leaf", leaf).build()).unwrap();
+        let v1 = Envelope::builder(node, b"v1".to_vec()).relationship("middle", middle).build();
+        source.put(&v1).unwrap();
+        let other = source.put(&Envelope::builder(node, b"other".to_vec()).build()).unwrap();
+        let v2 = source.put(&v1.derive().relationship("other", other).build()).unwrap();
+        
+        // Children come before whatever links them
+        let mut receiver = Store::new();
+        let mut pull = source.pull(&[v2], SyncOptions::new().max_batch_objects(2));
+        let first = pull.next_batch(&receiver).unwrap().unwrap();
+        assert_eq!(first.iter().map(Envelope::hash).collect::<Vec<_>>(), vec![leaf, middle]);
+        first.iter().for_each(|e| { receiver.put(e).unwrap(); });
+        drop(pull);
+        
+        // Starting over after the interruption fetches only the rest
+        assert_eq!(receiver.pull_from(&source, &[v2], SyncOptions::new()).unwrap(), 3);
+        assert_eq!(receiver.len(), 5);
+    }
+    
+    #[test]
+    fn test_batches_in_flight() {
+        let mut source = Store::new();
+        let node = Hash256::hash(b"Node");
+        let roots: Vec<_> = (0..3u8).map(|i| source.put(&Envelope::builder(node, vec![i]).build()).unwrap()).collect();
+        let mut receiver = Store::new();
+        let mut pull = source.pull(&roots, SyncOptions::new().max_batch_objects(1).max_in_flight(2));
+        let first = pull.next_batch(&receiver).unwrap().unwrap();
+        let second = pull.next_batch(&receiver).unwrap().unwrap();
+        assert!(matches!(pull.next_batch(&receiver), Err(Error::InvalidArgument(_))));
+        receiver.put(&first[0]).unwrap();
+        assert_eq!(pull.next_batch(&receiver).unwrap().unwrap()[0].hash(), roots[2]);
+        receiver.put(&second[0]).unwrap();
+    }
+    
+    #[test]
+    fn test_pull_rate_limit() {
+        let mut source = Store::new();
+        let node = Hash256::hash(b"Node");
+        let roots: Vec<_> = (0..4u8)
+            .map(|i| source.put(&Envelope::builder(node, vec![i; 1000]).build()).unwrap())
+            .collect();
+        let started = Stopwatch::start();
+        let options = SyncOptions::new().max_batch_objects(1).bytes_per_second(20_000);
+        let mut receiver = Store::new();
+        assert_eq!(receiver.pull_from(&source, &roots, options).unwrap(), 4);
+        // Three batches have to wait for the ~1 KB sent before each
+        assert!(started.elapsed() >= Duration::from_millis(140));
+    }
+}
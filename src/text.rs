@@ -0,0 +1,273 @@
+//! Full-text search over designated string fields
+//!
+//! Text is split into lowercase alphanumeric tokens; each (field, term)
+//! keeps a posting list of envelopes with term frequencies. Results are
+//! ranked by TF-IDF.
+
+use crate::envelope::{Envelope, IndexValue};
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use crate::memory::HeapSize;
+use crate::wire::{self, Reader};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Lowercase alphanumeric words of `text`
+pub(crate) fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Edit distance between `a` and `b`, or `None` if it exceeds `max`
+///
+/// Counts insertions, deletions, substitutions and transpositions of
+/// adjacent characters (optimal string alignment), so "Alcie" is one
+/// edit from "Alice".
+pub(crate) fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j - 1] + cost).min(previous[j] + 1).min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        // Later rows can't drop below the last two rows' minimum
+        let floor = |row: &[usize]| row.iter().copied().min().unwrap_or(0);
+        if floor(&current) > max && floor(&previous) > max {
+            return None;
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    Some(previous[b.len()]).filter(|&d| d <= max)
+}
+
+/// Posting lists for the fields with full-text indexing enabled
+#[derive(Debug, Clone, Default)]
+pub struct TextIndex {
+    fields: HashSet<String>,
+    /// (field, term) -> envelope -> occurrences
+    postings: HashMap<(String, String), HashMap<Hash256, u32>>,
+    /// field -> number of envelopes with indexed text in it
+    documents: HashMap<String, usize>,
+}
+
+impl HeapSize for TextIndex {
+    fn heap_size(&self) -> usize {
+        self.fields.heap_size() + self.postings.heap_size() + self.documents.heap_size()
+    }
+}
+
+impl TextIndex {
+    /// A text index with the same fields enabled but no entries
+    pub(crate) fn empty_like(&self) -> Self {
+        Self { fields: self.fields.clone(), ..Self::default() }
+    }
+    
+    pub(crate) fn enable(&mut self, field: String) {
+        self.fields.insert(field);
+    }
+    
+    /// Whether `field` is indexed for full-text search
+    pub fn is_enabled(&self, field: &str) -> bool {
+        self.fields.contains(field)
+    }
+    
+    /// Index an envelope's text; adding one already indexed does nothing
+    pub(crate) fn add(&mut self, hash: Hash256, envelope: &Envelope) {
+        for (field, text) in self.texts(envelope) {
+            let terms: Vec<String> = tokenize(text).collect();
+            if terms.is_empty() || self.has_posting(&field, &terms[0], &hash) {
+                continue;
+            }
+            *self.documents.entry(field.clone()).or_default() += 1;
+            for term in terms {
+                *self.postings
+                    .entry((field.clone(), term))
+                    .or_default()
+                    .entry(hash)
+                    .or_default() += 1;
+            }
+        }
+    }
+    
+    pub(crate) fn remove(&mut self, hash: &Hash256, envelope: &Envelope) {
+        for (field, text) in self.texts(envelope) {
+            let terms: Vec<String> = tokenize(text).collect();
+            if terms.is_empty() || !self.has_posting(&field, &terms[0], hash) {
+                continue;
+            }
+            if let Some(count) = self.documents.get_mut(&field) {
+                *count = count.saturating_sub(1);
+            }
+            for term in terms {
+                if let Some(posting) = self.postings.get_mut(&(field.clone(), term)) {
+                    posting.remove(hash);
+                }
+            }
+        }
+    }
+    
+    /// Whether `hash` is posted under a term of `field`; text without
+    /// terms is never indexed, so this tells whether a text was added
+    fn has_posting(&self, field: &str, term: &str, hash: &Hash256) -> bool {
+        self.postings.get(&(field.to_string(), term.to_string())).is_some_and(|p| p.contains_key(hash))
+    }
+    
+    fn texts<'e>(&self, envelope: &'e Envelope) -> Vec<(String, &'e str)> {
+        envelope.index.iter()
+            .filter(|(key, _)| self.fields.contains(*key))
+            .filter_map(|(key, value)| match value {
+                IndexValue::String(s) => Some((key.clone(), s.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let mut fields: Vec<_> = self.fields.iter().collect();
+        fields.sort();
+        wire::put_u32(buf, fields.len() as u32);
+        fields.into_iter().for_each(|field| wire::put_str(buf, field));
+        
+        let mut postings: Vec<_> = self.postings.iter().filter(|(_, p)| !p.is_empty()).collect();
+        postings.sort_by(|a, b| a.0.cmp(b.0));
+        wire::put_u32(buf, postings.len() as u32);
+        for ((field, term), posting) in postings {
+            wire::put_str(buf, field);
+            wire::put_str(buf, term);
+            let mut entries: Vec<_> = posting.iter().collect();
+            entries.sort();
+            wire::put_u32(buf, entries.len() as u32);
+            for (hash, count) in entries {
+                wire::put_hash(buf, hash);
+                wire::put_u32(buf, *count);
+            }
+        }
+        
+        let mut documents: Vec<_> = self.documents.iter().collect();
+        documents.sort();
+        wire::put_u32(buf, documents.len() as u32);
+        for (field, count) in documents {
+            wire::put_str(buf, field);
+            wire::put_u32(buf, *count as u32);
+        }
+    }
+    
+    pub(crate) fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let mut index = Self::default();
+        for _ in 0..reader.u32()? {
+            index.fields.insert(reader.string()?);
+        }
+        for _ in 0..reader.u32()? {
+            let key = (reader.string()?, reader.string()?);
+            let mut posting = HashMap::new();
+            for _ in 0..reader.u32()? {
+                posting.insert(reader.hash()?, reader.u32()?);
+            }
+            index.postings.insert(key, posting);
+        }
+        for _ in 0..reader.u32()? {
+            let field = reader.string()?;
+            index.documents.insert(field, reader.u32()? as usize);
+        }
+        Ok(index)
+    }
+    
+    /// Envelopes whose `field` contains any of the query's terms, best first
+    ///
+    /// Scores sum term frequency times inverse document frequency, so
+    /// rare terms weigh more; ties are broken by hash.
+    pub fn search(&self, field: &str, query: &str) -> Vec<(Hash256, f64)> {
+        let documents = self.documents.get(field).copied().unwrap_or(0) as f64;
+        let mut scores: HashMap<Hash256, f64> = HashMap::new();
+        let terms: HashSet<String> = tokenize(query).collect();
+        for term in terms {
+            let Some(posting) = self.postings.get(&(field.to_string(), term)) else {
+                continue;
+            };
+            let idf = (1.0 + documents / posting.len().max(1) as f64).ln();
+            for (hash, count) in posting {
+                *scores.entry(*hash).or_default() += *count as f64 * idf;
+            }
+        }
+        let mut ranked: Vec<_> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+    }
+}
+
+impl IndexedStore {
+    /// Enable full-text search on a string field, indexing existing envelopes
+    pub fn enable_text_index(&mut self, field: impl Into<String>) -> Result<()> {
+        let field = field.into();
+        if self.index().text().is_enabled(&field) {
+            return Ok(());
+        }
+        self.reindex(|index| index.text_mut().enable(field))
+    }
+    
+    /// Ranked full-text search, e.g. `query_text("body", "zero copy serialization")`
+    pub fn query_text(&self, field: &str, query: &str) -> Vec<Hash256> {
+        self.index().text().search(field, query).into_iter().map(|(hash, _)| hash).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_query_text() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let mut put = |body: &str| store.put(&Envelope::builder(post, body.into()).index("body", body).build()).unwrap();
+        let both = put("Zero-copy serialization: zero parsing, zero copies");
+        let serde = put("Serialization with serde");
+        let other = put("Gardening tips");
+        
+        store.enable_text_index("body").unwrap();
+        let hits = store.query_text("body", "zero copy serialization");
+        assert_eq!(hits, vec![both, serde]);
+        assert!(!hits.contains(&other));
+        assert!(store.query_text("title", "zero").is_empty());
+        
+        // New envelopes are indexed as they're stored
+        let late = store.put(&Envelope::builder(post, vec![]).index("body", "GARDENING").build()).unwrap();
+        let mut gardening = store.query_text("body", "gardening");
+        gardening.sort();
+        let mut expected = vec![other, late];
+        expected.sort();
+        assert_eq!(gardening, expected);
+    }
+    
+    #[test]
+    fn test_add_is_idempotent() {
+        let mut index = TextIndex::default();
+        index.enable("body".to_string());
+        let envelope = Envelope::builder(Hash256::hash(b"Post"), vec![]).index("body", "zero copy zero").build();
+        let other = Envelope::builder(Hash256::hash(b"Post"), vec![]).index("body", "copy").build();
+        index.add(other.hash(), &other);
+        index.add(envelope.hash(), &envelope);
+        let once = index.search("body", "zero copy");
+        
+        // Re-putting an envelope must not double its term counts or the document count
+        index.add(envelope.hash(), &envelope);
+        assert_eq!(index.search("body", "zero copy"), once);
+        assert_eq!(index.documents["body"], 2);
+        
+        index.remove(&envelope.hash(), &envelope);
+        index.remove(&envelope.hash(), &envelope);
+        assert_eq!(index.documents["body"], 1);
+        assert_eq!(index.search("body", "zero"), Vec::new());
+    }
+}
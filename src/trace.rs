@@ -0,0 +1,177 @@
+//! Spans and events through `tracing`, behind the `tracing` feature
+//!
+//! A span covers one operation (a put, a pull batch, a garbage
+//! collection) and carries its inputs and results as structured fields,
+//! such as the hash, object counts and byte sizes, plus `elapsed_us` once
+//! it ends. Spans are at info level and events at their own, all with the
+//! `envelope` target. The `log` feature also forwards them to the `log`
+//! facade when no subscriber is installed. Without either feature this
+//! module compiles to nothing.
+
+#[cfg(feature = "tracing")]
+use crate::clock::Stopwatch;
+
+/// Emit an event at `level`, e.g. `event!(debug, hash = %hash, "put")`
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!(target: "envelope", $($arg)+);
+    };
+}
+
+pub(crate) use event;
+
+/// Enter a span, e.g. `span!("Store::put", hash = %hash, bytes = Empty)`;
+/// fields recorded later are declared `Empty`, and none are evaluated
+/// unless a subscriber wants the span
+macro_rules! span {
+    ($name:literal $(, $($field:tt)+)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = {
+            #[allow(unused_imports)]
+            use tracing::field::Empty;
+            $crate::trace::Span::enter(tracing::info_span!(
+                target: "envelope",
+                $name,
+                elapsed_us = Empty
+                $(, $($field)+)?
+            ))
+        };
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span {};
+        span
+    }};
+}
+
+pub(crate) use span;
+
+/// An operation in progress; records its duration when dropped
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    started: Stopwatch,
+}
+
+impl Span {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(span: tracing::Span) -> Self {
+        Self { span: span.entered(), started: Stopwatch::start() }
+    }
+    
+    /// Fill in a field declared `Empty`, known only once the operation
+    /// has run
+    #[cfg(feature = "tracing")]
+    pub(crate) fn record(&self, key: &str, value: impl tracing::Value) {
+        self.span.record(key, value);
+    }
+    
+    /// `record` for a value shown through `Display`, like a hash
+    #[cfg(feature = "tracing")]
+    pub(crate) fn record_display(&self, key: &str, value: impl std::fmt::Display) {
+        self.span.record(key, tracing::field::display(value));
+    }
+    
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn record<T>(&self, _key: &str, _value: T) {}
+    
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn record_display<T>(&self, _key: &str, _value: T) {}
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.span.record("elapsed_us", self.started.elapsed().as_micros() as u64);
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::envelope::Envelope;
+    use crate::hash::Hash256;
+    use crate::index::IndexedStore;
+    use std::fmt::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+    
+    /// `name=value` pairs, in the order recorded
+    #[derive(Default)]
+    struct Fields(String);
+    
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+        
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+    
+    /// Closed spans as `<name><fields>` and events as
+    /// `<level> <span>:<fields>`
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+    
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            ctx.span(id).unwrap().extensions_mut().insert(fields);
+        }
+        
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            values.record(ctx.span(id).unwrap().extensions_mut().get_mut::<Fields>().unwrap());
+        }
+        
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let span = ctx.event_span(event).map_or("", |span| span.name());
+            self.0.lock().unwrap().push(format!("{} {}:{}", event.metadata().level(), span, fields.0));
+        }
+        
+        fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+            let span = ctx.span(&id).unwrap();
+            let extensions = span.extensions();
+            let fields = &extensions.get::<Fields>().unwrap().0;
+            self.0.lock().unwrap().push(format!("{}{}", span.name(), fields));
+        }
+    }
+    
+    #[test]
+    fn test_spans_and_events() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        let (hash, size) = tracing::subscriber::with_default(subscriber, || {
+            let mut store = IndexedStore::new();
+            let envelope = Envelope::builder(Hash256::hash(b"Traced"), b"traced".to_vec()).build();
+            let hash = store.put(&envelope).unwrap();
+            store.get(&hash).unwrap();
+            let size = store.store().stored_size(&hash).unwrap();
+            let mut copy = IndexedStore::new();
+            copy.pull_from(store.store(), &[hash], Default::default()).unwrap();
+            store.collect_garbage(&[]).unwrap();
+            (hash, size)
+        });
+        
+        // Spans end with their duration
+        let records = capture.0.lock().unwrap();
+        let has = |record: &str| records.iter().any(|r| r == record || r.starts_with(&format!("{} elapsed_us=", record)));
+        assert!(has(&format!("DEBUG Store::put: message=put hash={} bytes={}", hash, size)));
+        assert!(has(&format!("Store::put hash={} bytes={}", hash, size)));
+        assert!(has(&format!("IndexedStore::put type_hash={} hash={} new=true", Hash256::hash(b"Traced"), hash)));
+        assert!(has(&format!("IndexedStore::get hash={} found=true", hash)));
+        assert!(has(&format!("Pull::next_batch pending=1 objects=1 bytes={}", size)));
+        assert!(has(&format!("IndexedStore::pull_from roots=1 objects=1 bytes={}", size)));
+        assert!(has(&format!("Store::gc_plan roots=0 objects=1 garbage=1 bytes={}", size)));
+        assert!(has(&format!("DEBUG IndexedStore::collect_garbage: message=removed hash={}", hash)));
+        assert!(has(&format!("IndexedStore::collect_garbage roots=0 collected=1 bytes={}", size)));
+    }
+}
@@ -0,0 +1,113 @@
+//! Triggers: callbacks run when a newly stored envelope matches a query
+//!
+//! Like views, triggers are evaluated once per put against the freshly
+//! updated indexes. Each filter looks up only the new envelope's entries,
+//! so a trigger's cost doesn't grow with the store.
+
+use crate::envelope::Envelope;
+use crate::hash::Hash256;
+use crate::index::{Index, IndexedStore};
+use crate::query::Query;
+use std::sync::Arc;
+
+type Callback = Arc<dyn Fn(&Hash256, &Envelope) + Send + Sync>;
+
+/// Registered triggers, in registration order
+#[derive(Default)]
+pub(crate) struct Triggers {
+    triggers: Vec<(String, Query, Callback)>,
+}
+
+impl Triggers {
+    pub(crate) fn fire(&self, index: &Index, hash: &Hash256, envelope: &Envelope) {
+        for (_, query, callback) in &self.triggers {
+            if query.matches(index, hash) {
+                callback(hash, envelope);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Triggers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Triggers")
+            .field("names", &self.triggers.iter().map(|(name, ..)| name).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl IndexedStore {
+    /// Call `callback` whenever a put stores a new envelope matching `query`
+    ///
+    /// Replaces any trigger with the same name. Re-putting an object
+    /// that's already stored doesn't fire, and neither do existing
+    /// envelopes; ordering and paging on the query are ignored.
+    pub fn add_trigger<F>(&mut self, name: impl Into<String>, query: Query, callback: F)
+    where
+        F: Fn(&Hash256, &Envelope) + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.remove_trigger(&name);
+        self.triggers_mut().triggers.push((name, query, Arc::new(callback)));
+    }
+    
+    /// Unregister a trigger; returns whether it existed
+    pub fn remove_trigger(&mut self, name: &str) -> bool {
+        let triggers = &mut self.triggers_mut().triggers;
+        let before = triggers.len();
+        triggers.retain(|(existing, ..)| existing != name);
+        triggers.len() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::field_eq;
+    use std::sync::Mutex;
+    
+    #[test]
+    fn test_trigger() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let log = fired.clone();
+        store.add_trigger("published", Query::type_is(post).and(field_eq("status", "published")), move |hash, _| {
+            log.lock().unwrap().push(*hash);
+        });
+        
+        let draft = Envelope::builder(post, b"a".to_vec()).index("status", "draft").build();
+        let published = Envelope::builder(post, b"b".to_vec()).index("status", "published").build();
+        let comment = Envelope::builder(Hash256::hash(b"Comment"), vec![]).index("status", "published").build();
+        store.put(&draft).unwrap();
+        let hash = store.put(&published).unwrap();
+        store.put(&published).unwrap();
+        store.put(&comment).unwrap();
+        assert_eq!(*fired.lock().unwrap(), vec![hash]);
+        
+        assert!(store.remove_trigger("published"));
+        assert!(!store.remove_trigger("published"));
+        store.put(&Envelope::builder(post, b"c".to_vec()).index("status", "published").build()).unwrap();
+        assert_eq!(fired.lock().unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_range_trigger() {
+        let mut store = IndexedStore::new();
+        let reading = Hash256::hash(b"Reading");
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let log = fired.clone();
+        store.add_trigger("hot", Query::range("celsius", 30i64..), move |hash, _| {
+            log.lock().unwrap().push(*hash);
+        });
+        
+        let mut put = |celsius: i64| store.put(&Envelope::builder(reading, celsius.to_be_bytes().to_vec()).index("celsius", celsius).build()).unwrap();
+        for celsius in 0..30 {
+            put(celsius);
+        }
+        let hot = put(31);
+        put(-5);
+        let boundary = put(30);
+        assert_eq!(*fired.lock().unwrap(), vec![hot, boundary]);
+    }
+}
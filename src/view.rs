@@ -0,0 +1,225 @@
+//! Materialized views: named queries kept current as envelopes change
+//!
+//! Most query predicates depend only on the envelope being tested, so a
+//! view is maintained by testing each added envelope once and dropping
+//! removed ones. Relationship filters (`Query::related`) also depend on
+//! the targets, so adding or removing an envelope re-tests the envelopes
+//! linking to it, through the reverse relationship index, as many hops
+//! back as a view's filters reach.
+
+use crate::hash::Hash256;
+use crate::index::{Index, IndexedStore};
+use crate::memory::HeapSize;
+use crate::query::Query;
+use crate::wire::{self, Reader};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+
+/// Registered views and their current members
+#[derive(Debug, Clone, Default)]
+pub struct Views {
+    views: HashMap<String, (Query, HashSet<Hash256>)>,
+}
+
+impl HeapSize for Views {
+    /// Member sets only; query definitions are small and not counted
+    fn heap_size(&self) -> usize {
+        self.views.iter()
+            .map(|(name, (_, members))| name.heap_size() + size_of::<(String, Query)>() + members.heap_size())
+            .sum()
+    }
+}
+
+impl Views {
+    /// The same views with no members
+    pub(crate) fn empty_like(&self) -> Self {
+        let views = self.views.iter()
+            .map(|(name, (query, _))| (name.clone(), (query.clone(), HashSet::new())))
+            .collect();
+        Self { views }
+    }
+    
+    /// Append the view definitions; members are recomputed on decode
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let mut views: Vec<_> = self.views.iter().collect();
+        views.sort_by(|a, b| a.0.cmp(b.0));
+        wire::put_u32(buf, views.len() as u32);
+        for (name, (query, _)) in views {
+            wire::put_str(buf, name);
+            query.encode(buf);
+        }
+    }
+    
+    /// Decode view definitions and fill them from `index`
+    pub(crate) fn decode(reader: &mut Reader<'_>, index: &Index) -> Result<Self> {
+        let mut views = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let query = Query::decode(reader)?;
+            let members = query.evaluate(index).collect();
+            views.insert(name, (query, members));
+        }
+        Ok(Self { views })
+    }
+    
+    pub(crate) fn add(&mut self, hash: Hash256, index: &Index) {
+        for (query, members) in self.views.values_mut() {
+            if query.matches(index, &hash) {
+                members.insert(hash);
+            }
+        }
+        self.retest_referrers(&hash, index);
+    }
+    
+    /// Re-test the envelopes linking to `hash`, up to the deepest view's
+    /// relationship hops back, after it was added or removed
+    pub(crate) fn retest_referrers(&mut self, hash: &Hash256, index: &Index) {
+        let depth = self.views.values().map(|(query, _)| query.relationship_depth()).max().unwrap_or(0);
+        let mut seen = HashSet::from([*hash]);
+        let mut frontier = vec![*hash];
+        for hop in 1..=depth {
+            frontier = frontier.iter()
+                .flat_map(|target| index.references_to(target))
+                .filter(|source| seen.insert(**source))
+                .copied()
+                .collect();
+            for (query, members) in self.views.values_mut().filter(|(query, _)| query.relationship_depth() >= hop) {
+                for source in &frontier {
+                    if query.matches(index, source) {
+                        members.insert(*source);
+                    } else {
+                        members.remove(source);
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Recompute every view's members from `index`
+    pub(crate) fn refill(&mut self, index: &Index) {
+        for (query, members) in self.views.values_mut() {
+            *members = query.evaluate(index).collect();
+        }
+    }
+    
+    pub(crate) fn remove(&mut self, hash: &Hash256) {
+        for (_, members) in self.views.values_mut() {
+            members.remove(hash);
+        }
+    }
+    
+    /// Current members of a view
+    pub fn get(&self, name: &str) -> Option<&HashSet<Hash256>> {
+        self.views.get(name).map(|(_, members)| members)
+    }
+    
+    /// The query behind a view
+    pub fn query(&self, name: &str) -> Option<&Query> {
+        self.views.get(name).map(|(query, _)| query)
+    }
+}
+
+impl IndexedStore {
+    /// Register `query` as a view named `name`, replacing any previous one
+    ///
+    /// The view is filled from the indexes now and updated on every put
+    /// and removal. Ordering and paging on the query are ignored.
+    pub fn create_view(&mut self, name: impl Into<String>, query: Query) {
+        let members = query.evaluate(self.index()).collect();
+        self.index_mut().views_mut().views.insert(name.into(), (query, members));
+    }
+    
+    /// Unregister a view; returns whether it existed
+    pub fn drop_view(&mut self, name: &str) -> bool {
+        self.index_mut().views_mut().views.remove(name).is_some()
+    }
+    
+    /// Current members of a view, e.g. `view("published_posts")`
+    pub fn view(&self, name: &str) -> Option<&HashSet<Hash256>> {
+        self.index().views().get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{Envelope, IndexValue};
+    use crate::query::field_eq;
+    
+    #[test]
+    fn test_materialized_view() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let draft = store.put(&Envelope::builder(post, b"1".to_vec()).index("status", "draft").build()).unwrap();
+        let first = store.put(&Envelope::builder(post, b"2".to_vec()).index("status", "published").build()).unwrap();
+        
+        store.create_view("published_posts", Query::type_is(post).and(field_eq("status", "published")));
+        assert_eq!(store.view("published_posts"), Some(&HashSet::from([first])));
+        
+        // Publishing the draft adds the new version; the draft stays out
+        let published = store.get(&draft).unwrap().derive().index("status", "published").build();
+        let second = store.put(&published).unwrap();
+        store.put(&Envelope::builder(Hash256::hash(b"Page"), vec![]).index("status", "published").build()).unwrap();
+        assert_eq!(store.view("published_posts"), Some(&HashSet::from([first, second])));
+        
+        store.remove_object(&first).unwrap();
+        assert_eq!(store.view("published_posts"), Some(&HashSet::from([second])));
+        
+        assert!(store.drop_view("published_posts"));
+        assert!(store.view("published_posts").is_none());
+    }
+    
+    #[test]
+    fn test_view_over_relationships() {
+        let mut store = IndexedStore::new();
+        let person = Hash256::hash(b"Person");
+        let post = Hash256::hash(b"Post");
+        let alice = Envelope::builder(person, vec![]).index("name", "Alice").build();
+        store.create_view("by_alice", Query::parse(r#"author->name = "Alice""#).unwrap());
+        
+        // The post arrives before its author
+        let first = store.put(&Envelope::builder(post, b"1".to_vec()).relationship("author", alice.hash()).build()).unwrap();
+        assert_eq!(store.view("by_alice"), Some(&HashSet::new()));
+        store.put(&alice).unwrap();
+        assert_eq!(store.view("by_alice"), Some(&HashSet::from([first])));
+        assert_eq!(store.query(&Query::parse(r#"author->name = "Alice""#).unwrap()), vec![first]);
+        
+        // Two hops: comments on Alice's posts
+        store.create_view("on_alice", Query::related("on", Query::related("author", field_eq("name", "Alice"))));
+        let comment = store.put(&Envelope::builder(post, b"c".to_vec()).relationship("on", first).build()).unwrap();
+        assert_eq!(store.view("on_alice"), Some(&HashSet::from([comment])));
+        
+        // Removing the author drops what matched through it
+        store.remove_object(&alice.hash()).unwrap();
+        assert_eq!(store.view("by_alice"), Some(&HashSet::new()));
+        assert_eq!(store.view("on_alice"), Some(&HashSet::new()));
+    }
+    
+    #[test]
+    fn test_view_over_range() {
+        let mut store = IndexedStore::new();
+        let post = Hash256::hash(b"Post");
+        let range = Query::range("score", 10i64..20);
+        store.create_view("mid", range.clone());
+        let put = |store: &mut IndexedStore, score: IndexValue| {
+            store.put(&Envelope::builder(post, format!("{:?}", score).into_bytes()).index("score", score).build()).unwrap()
+        };
+        
+        let low = put(&mut store, IndexValue::Int64(9));
+        let ten = put(&mut store, IndexValue::Int64(10));
+        let mid = put(&mut store, IndexValue::Float64(15.5));
+        let twenty = put(&mut store, IndexValue::Int64(20));
+        let text = put(&mut store, IndexValue::String("15".to_string()));
+        assert_eq!(store.view("mid"), Some(&HashSet::from([ten, mid])));
+        assert!(![low, twenty, text].iter().any(|h| store.view("mid").unwrap().contains(h)));
+        
+        // Moving a value across a bound, and removing, keep the view in step
+        let moved = store.get(&low).unwrap().derive().index("score", 19i64).build();
+        let moved = store.put(&moved).unwrap();
+        store.remove_object(&ten).unwrap();
+        let expected = HashSet::from([mid, moved]);
+        assert_eq!(store.view("mid"), Some(&expected));
+        assert_eq!(store.query(&range).into_iter().collect::<HashSet<_>>(), expected);
+    }
+}
@@ -0,0 +1,108 @@
+//! Change notifications for subscribers of an `IndexedStore`
+//!
+//! Every put and removal is broadcast over `std::sync::mpsc` channels;
+//! subscribers whose receiver was dropped are pruned on the next send.
+
+use crate::envelope::Envelope;
+use crate::hash::Hash256;
+use crate::index::IndexedStore;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// What happened to an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Put,
+    Delete,
+}
+
+/// A store mutation, as seen by subscribers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub hash: Hash256,
+    pub type_hash: Hash256,
+    /// Type name (or short type hash) and payload size, e.g. `Post (42 bytes)`
+    pub summary: String,
+}
+
+impl Event {
+    pub(crate) fn new(kind: EventKind, hash: Hash256, envelope: &Envelope) -> Self {
+        let name = envelope.type_name.clone().unwrap_or_else(|| envelope.type_hash.short());
+        Self {
+            kind,
+            hash,
+            type_hash: envelope.type_hash,
+            summary: format!("{} ({} bytes)", name, envelope.payload.len()),
+        }
+    }
+}
+
+/// Open subscription channels
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    senders: Vec<Sender<Event>>,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+    
+    pub(crate) fn notify(&mut self, event: Event) {
+        self.senders.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+    
+    pub(crate) fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+}
+
+impl IndexedStore {
+    /// Receive an `Event` for every later put and delete
+    ///
+    /// Puts are reported even when the object was already stored. Events
+    /// queue up until received; drop the receiver to unsubscribe.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        self.subscribers_mut().subscribe()
+    }
+    
+    pub(crate) fn notify(&mut self, kind: EventKind, hash: Hash256, envelope: &Envelope) {
+        if !self.subscribers_mut().is_empty() {
+            self.subscribers_mut().notify(Event::new(kind, hash, envelope));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_subscribe() {
+        let mut store = IndexedStore::new();
+        let events = store.subscribe();
+        let post = Hash256::hash(b"Post");
+        let hash = store.put(&Envelope::builder(post, b"hello".to_vec()).type_name("Post").build()).unwrap();
+        let untyped = store.put(&Envelope::builder(post, vec![]).index("n", 1i64).build()).unwrap();
+        assert!(store.delete(&hash, false).unwrap().removed);
+        
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], Event {
+            kind: EventKind::Put,
+            hash,
+            type_hash: post,
+            summary: "Post (5 bytes)".into(),
+        });
+        assert_eq!(received[1].hash, untyped);
+        assert_eq!(received[1].summary, format!("{} (0 bytes)", post.short()));
+        assert_eq!((received[2].kind, received[2].hash), (EventKind::Delete, hash));
+        
+        // Dropped receivers are forgotten
+        drop(events);
+        store.put(&Envelope::builder(post, vec![1]).build()).unwrap();
+        assert!(store.subscribers_mut().is_empty());
+    }
+}
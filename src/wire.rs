@@ -0,0 +1,165 @@
+//! Low-level encoding helpers shared by hashing and serialization
+//!
+//! All integers are little-endian; variable-length data is prefixed
+//! with a u32 length.
+
+use crate::error::Error;
+use crate::hash::Hash256;
+use crate::Result;
+
+pub(crate) fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+pub(crate) fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(buf, bytes.len() as u32);
+    buf.extend_from_slice(bytes);
+}
+
+pub(crate) fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_bytes(buf, s.as_bytes());
+}
+
+pub(crate) fn put_hash(buf: &mut Vec<u8>, hash: &Hash256) {
+    buf.extend_from_slice(hash.as_bytes());
+}
+
+pub(crate) fn put_opt_hash(buf: &mut Vec<u8>, hash: Option<&Hash256>) {
+    match hash {
+        Some(hash) => {
+            buf.push(1);
+            put_hash(buf, hash);
+        }
+        None => buf.push(0),
+    }
+}
+
+pub(crate) fn put_i64(buf: &mut Vec<u8>, v: i64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+pub(crate) fn put_opt_i64(buf: &mut Vec<u8>, v: Option<i64>) {
+    match v {
+        Some(v) => {
+            buf.push(1);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+}
+
+/// Cursor over an encoded buffer that fails instead of panicking
+///
+/// Errors give the byte offset and the sections being parsed, e.g.
+/// `relationship 3: target: expected 32 bytes at offset 120, 5 available`.
+/// Sections are only formatted into an error, so naming them costs
+/// nothing on the success path.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Sections being parsed, outermost first, with the item number of
+    /// those that are one of several
+    sections: Vec<(&'static str, Option<usize>)>,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0, sections: Vec::new() }
+    }
+    
+    /// Parse with `section` named in any error
+    pub(crate) fn section<T>(&mut self, section: &'static str, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.parse_in((section, None), parse)
+    }
+    
+    /// Parse the `n`th of several `section`s, named e.g. `relationship 3`
+    /// in any error
+    pub(crate) fn item<T>(&mut self, section: &'static str, n: usize, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.parse_in((section, Some(n)), parse)
+    }
+    
+    fn parse_in<T>(&mut self, section: (&'static str, Option<usize>), parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.sections.push(section);
+        let parsed = parse(self);
+        self.sections.pop();
+        parsed
+    }
+    
+    /// A decode error in the current sections
+    pub(crate) fn error(&self, message: impl std::fmt::Display) -> Error {
+        let mut context = String::new();
+        for (section, n) in &self.sections {
+            match n {
+                Some(n) => context.push_str(&format!("{} {}: ", section, n)),
+                None => context.push_str(&format!("{}: ", section)),
+            }
+        }
+        context.push_str(&message.to_string());
+        Error::Serialization(context)
+    }
+    
+    pub(crate) fn offset(&self) -> usize {
+        self.pos
+    }
+    
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+    
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| self.error(format!(
+                "expected {} bytes at offset {}, {} available", len, self.pos, self.bytes.len().saturating_sub(self.pos)
+            )))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+    
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    
+    pub(crate) fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    
+    pub(crate) fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    
+    pub(crate) fn hash(&mut self) -> Result<Hash256> {
+        Ok(Hash256::from_bytes(self.take(32)?.try_into().unwrap()))
+    }
+    
+    pub(crate) fn opt_hash(&mut self) -> Result<Option<Hash256>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.hash()?)),
+        }
+    }
+    
+    pub(crate) fn opt_i64(&mut self) -> Result<Option<i64>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.i64()?)),
+        }
+    }
+    
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+    
+    pub(crate) fn string(&mut self) -> Result<String> {
+        let start = self.pos;
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|e| self.error(format!("invalid UTF-8 at offset {}: {}", start, e)))
+    }
+}
@@ -0,0 +1,39 @@
+//! Round trip through IndexedDB, in a headless browser: with
+//! `wasm-bindgen-test-runner` as the wasm32 runner and a WebDriver such
+//! as geckodriver installed, run
+//! `cargo test --target wasm32-unknown-unknown --features js --test idb`
+#![cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "js"))]
+
+use envelope::idb::IdbStore;
+use envelope::{Envelope, Hash256};
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn test_idb_roundtrip() {
+    let note = Hash256::hash(b"Note");
+    let mut db = IdbStore::open("envelope-test-roundtrip").await.unwrap();
+    let mut store = db.load().await.unwrap();
+    assert!(store.is_empty());
+    let leaf = store.put(&Envelope::builder(note, b"leaf".to_vec()).build()).unwrap();
+    let root = store.put(&Envelope::builder(note, b"root".to_vec()).relationship("child", leaf).build()).unwrap();
+    let draft = store.put(&Envelope::builder(note, b"draft".to_vec()).build()).unwrap();
+    store.set_ref("main", root).unwrap();
+    store.tag("v1", leaf).unwrap();
+    db.save(&store).await.unwrap();
+    
+    // Only what changed since is written back
+    store.remove(&draft).unwrap();
+    store.set_ref("main", leaf).unwrap();
+    db.save(&store).await.unwrap();
+    drop(db);
+    
+    let mut db = IdbStore::open("envelope-test-roundtrip").await.unwrap();
+    let loaded = db.load().await.unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert!(!loaded.contains(&draft));
+    assert_eq!(loaded.get(&root).unwrap().relationships[0].target, leaf);
+    assert_eq!(loaded.get_ref("main"), Some(leaf));
+    assert_eq!(loaded.get_tag("v1"), Some(leaf));
+}